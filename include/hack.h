@@ -0,0 +1,71 @@
+/*
+ * The Hack assembler and CPU emulator, for embedding in C, C++ or Swift.
+ * Build the library with `cargo build --release --features ffi` and link
+ * against target/release/libhack.a or libhack.so. This header is kept in
+ * step with src/ffi.rs by hand.
+ *
+ * Whatever the library hands out belongs to it until it's given back to the
+ * matching `_free` function. Any pointer may be null, which is ignored or
+ * treated as empty. Otherwise buffers must be valid for the length given
+ * alongside them, and everything else must have come from the library and
+ * not yet been freed. Any fields the structs gain will go on the end.
+ */
+
+#ifndef HACK_H
+#define HACK_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct HackDiagnostic {
+    /* 0 for a warning, 1 for an error */
+    uint32_t severity;
+    /* 1-based, or 0 if the problem isn't with any one line */
+    uint32_t line;
+    /* the byte range of the line at fault */
+    uint32_t start;
+    uint32_t end;
+    /* NUL-terminated UTF-8 */
+    char *message;
+} HackDiagnostic;
+
+typedef struct HackAssembly {
+    /* one word per instruction; null if there are none, which is also the
+     * case for a program with no instructions, so check `ok` */
+    uint16_t *words;
+    size_t length;
+    HackDiagnostic *diagnostics;
+    size_t diagnostic_count;
+    /* whether the program assembled */
+    bool ok;
+} HackAssembly;
+
+/* an emulator, only ever seen through a pointer */
+typedef struct HackEmulator HackEmulator;
+
+/* assembles `length` bytes of Hack assembly at `source`, never returning
+ * null. Free the result with `hack_assembly_free` */
+HackAssembly *hack_assemble_buffer(const uint8_t *source, size_t length);
+void hack_assembly_free(HackAssembly *assembly);
+
+/* a machine with `length` words of program at `words` in ROM, or null if
+ * they won't fit. Free it with `hack_emulator_free` */
+HackEmulator *hack_emulator_new(const uint16_t *words, size_t length);
+/* runs at most `count` instructions, stopping early if the program finishes
+ * or halts, and returns how many ran */
+uint64_t hack_emulator_step(HackEmulator *emulator, uint64_t count);
+uint16_t hack_emulator_read_ram(const HackEmulator *emulator, uint16_t address);
+void hack_emulator_write_ram(HackEmulator *emulator, uint16_t address, uint16_t value);
+uint16_t hack_emulator_pc(const HackEmulator *emulator);
+void hack_emulator_free(HackEmulator *emulator);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,159 @@
+use crate::diagnostic::Diagnostic;
+
+// the order variables are given addresses in, counting up from 16:
+// first-use (the default, and the course's), or alphabetical, where using a
+// new variable earlier in the program doesn't move every one after it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    FirstUse,
+    Alphabetical,
+}
+
+impl Order {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "first-use" => Ok(Order::FirstUse),
+            "alphabetical" => Ok(Order::Alphabetical),
+            other => Err(format!(
+                "invalid allocation order `{}` (expected first-use or alphabetical)",
+                other
+            )),
+        }
+    }
+}
+
+// a variable held at a fixed address by a comment, as in
+//
+//     // @pin counter 20
+//
+// so that hardware tests know where to find it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub name: String,
+    pub address: u16,
+}
+
+impl Pin {
+    // the pin on line `number` of a program, if it has one
+    pub fn scan(number: usize, text: &str) -> Result<Option<Self>, Diagnostic> {
+        let Some(comment) = crate::split_comment(text).1 else {
+            return Ok(None);
+        };
+        let Some(directive) = comment[2..].trim_start().strip_prefix("@pin") else {
+            return Ok(None);
+        };
+        // `// @pinned down` is just a comment
+        if directive.starts_with(|c: char| !c.is_whitespace()) {
+            return Ok(None);
+        }
+        let error = |message: String| {
+            Diagnostic::error(message)
+                .at(text, comment.trim_end())
+                .on_line(number, text)
+        };
+        let [name, address] = directive.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(error("expected `@pin NAME ADDRESS`".to_owned()));
+        };
+        if !crate::is_symbol(name) {
+            return Err(error(format!("invalid symbol: {}", name)));
+        }
+        match address.parse() {
+            Ok(address @ 16..=16383) => Ok(Some(Self {
+                name: name.to_owned(),
+                address,
+            })),
+            _ => Err(error(format!(
+                "can't pin `{}` to {}: variables live from 16 to 16383",
+                name, address
+            ))),
+        }
+    }
+}
+
+// where a program's variables go in RAM. Either way, the addresses only
+// depend on the program, so the same source always gets the same layout
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    pub order: Order,
+    pub pins: Vec<Pin>,
+}
+
+impl Layout {
+    // `order`, along with whatever `source` pins
+    pub fn new(order: Order, source: &str) -> Result<Self, Diagnostic> {
+        let mut pins = Vec::new();
+        for (number, text) in (1..).zip(crate::strip_bom(source).lines()) {
+            pins.extend(Pin::scan(number, text)?);
+        }
+        Ok(Self { order, pins })
+    }
+
+    // that the pins agree with each other, and only pin variables
+    pub fn check(&self, is_label: impl Fn(&str) -> bool) -> Result<(), String> {
+        for (index, pin) in self.pins.iter().enumerate() {
+            if is_label(&pin.name) {
+                return Err(format!("`{}` is pinned, but it isn't a variable", pin.name));
+            }
+            for other in &self.pins[..index] {
+                if other.name == pin.name && other.address != pin.address {
+                    return Err(format!(
+                        "`{}` is pinned to both {} and {}",
+                        pin.name, other.address, pin.address
+                    ));
+                }
+                if other.name != pin.name && other.address == pin.address {
+                    return Err(format!(
+                        "`{}` and `{}` are both pinned to {}",
+                        other.name, pin.name, pin.address
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the first address from `next` on that nothing is pinned to
+pub fn unpinned(pins: &[Pin], mut next: u16) -> u16 {
+    while pins.iter().any(|pin| pin.address == next) {
+        next = next.saturating_add(1);
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins() {
+        let source = "// @pin counter 20\n@counter // @pin  flag 16\n// @pinned down\n";
+        let layout = Layout::new(Order::Alphabetical, source).unwrap();
+        let pins: Vec<_> = layout
+            .pins
+            .iter()
+            .map(|pin| (pin.name.as_str(), pin.address))
+            .collect();
+        assert_eq!(pins, [("counter", 20), ("flag", 16)]);
+        assert_eq!(unpinned(&layout.pins, 16), 17);
+        assert!(layout.check(|_| false).is_ok());
+        assert!(layout.check(|name| name == "flag").is_err());
+
+        let clash = Layout::new(Order::FirstUse, "// @pin a 20\n// @pin b 20\n").unwrap();
+        assert_eq!(
+            clash.check(|_| false).unwrap_err(),
+            "`a` and `b` are both pinned to 20"
+        );
+        for bad in [
+            "// @pin a",
+            "// @pin 1a 20",
+            "// @pin a 5",
+            "// @pin a SCREEN",
+        ] {
+            assert!(Layout::new(Order::FirstUse, bad).is_err(), "{}", bad);
+        }
+        assert_eq!(Order::parse("alphabetical"), Ok(Order::Alphabetical));
+        assert!(Order::parse("random").is_err());
+    }
+}
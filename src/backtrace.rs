@@ -0,0 +1,110 @@
+use crate::debug::Symbols;
+
+// the words of a stack frame the standard calling convention saves below
+// a function's locals, counting back from LCL
+const RETURN_ADDRESS: u16 = 5;
+const SAVED_LCL: u16 = 4;
+
+// the functions of a translated VM program, by where they start in ROM.
+// Translators label each function's entry point with its `Class.function`
+// name, and everything inside it with a `$`-qualified one. Any other
+// unqualified label starts code that isn't part of a function
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Functions {
+    // sorted by address
+    entries: Vec<(u16, Option<String>)>,
+}
+
+impl Functions {
+    pub fn new(symbols: &Symbols) -> Self {
+        let mut entries: Vec<(u16, Option<String>)> = symbols
+            .labels
+            .iter()
+            .filter(|(name, _)| !name.contains('$'))
+            .map(|(name, address)| (*address, name.contains('.').then(|| name.clone())))
+            .collect();
+        // a function's label wins over anything else at the same address
+        entries.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.is_some().cmp(&b.1.is_some())));
+        Self { entries }
+    }
+
+    // the function whose code includes a ROM address
+    pub fn containing(&self, address: u16) -> Option<&str> {
+        let index = self
+            .entries
+            .partition_point(|(start, _)| *start <= address)
+            .checked_sub(1)?;
+        self.entries[index].1.as_deref()
+    }
+
+    // the calls in progress, outermost first, worked out by following the
+    // saved LCL pointers back through the stack: each frame holds the
+    // address its caller returns to, and so which function the caller is
+    pub fn backtrace(&self, read: impl Fn(u16) -> u16, pc: u16) -> Vec<String> {
+        let Some(current) = self.containing(pc) else {
+            return Vec::new();
+        };
+        let mut names = vec![current.to_owned()];
+        let mut lcl = read(crate::vm::LCL);
+        while lcl >= RETURN_ADDRESS {
+            let caller = read(lcl - RETURN_ADDRESS);
+            let saved = read(lcl - SAVED_LCL);
+            let Some(name) = self.containing(caller) else {
+                break;
+            };
+            names.push(name.to_owned());
+            // frames only ever get pushed further up the stack, so anything
+            // else is garbage rather than a caller
+            if saved >= lcl {
+                break;
+            }
+            lcl = saved;
+        }
+        names.reverse();
+        names
+    }
+}
+
+pub fn render(names: &[String]) -> String {
+    names.join(" > ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_frames() {
+        let symbols = Symbols {
+            labels: [
+                ("Sys.init", 10),
+                ("Sys.init$ret.0", 15),
+                ("Main.main", 20),
+                ("Math.multiply", 30),
+                ("__stub", 40),
+            ]
+            .into_iter()
+            .map(|(name, address)| (name.to_owned(), address))
+            .collect(),
+            ..Symbols::default()
+        };
+        let functions = Functions::new(&symbols);
+        assert_eq!(functions.containing(5), None);
+        assert_eq!(functions.containing(15), Some("Sys.init"));
+        assert_eq!(functions.containing(35), Some("Math.multiply"));
+        assert_eq!(functions.containing(45), None);
+
+        // bootstrap code at 2 called Sys.init, which called Main.main at 12,
+        // which called Math.multiply at 25
+        let mut ram = vec![0; 512];
+        ram[1] = 271;
+        ram[256..261].copy_from_slice(&[2, 0, 0, 0, 0]);
+        ram[261..266].copy_from_slice(&[12, 261, 256, 0, 0]);
+        ram[266..271].copy_from_slice(&[25, 266, 261, 0, 0]);
+        let backtrace = functions.backtrace(|address| ram[address as usize], 33);
+        assert_eq!(render(&backtrace), "Sys.init > Main.main > Math.multiply");
+        assert!(functions
+            .backtrace(|address| ram[address as usize], 3)
+            .is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::borrow::Cow;
+
+use crate::{Computation, Destination, HackLine, Jump};
+
+// builds a program out of instructions rather than strings of them, for
+// code generators like the VM translator, so that nothing has to be
+// formatted only to be parsed again. Comments come along into the assembly
+// text, but of course not into the binary
+// the biggest value an A-instruction can load
+pub const MAX_VALUE: u16 = 32767;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Builder {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Code(HackLine<'static>),
+    Comment(String),
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, line: HackLine<'static>) -> &mut Self {
+        self.lines.push(Line::Code(line));
+        self
+    }
+
+    pub fn label(&mut self, name: impl Into<String>) -> &mut Self {
+        self.line(HackLine::Label(Cow::Owned(name.into())))
+    }
+
+    // `@value`, which can't have its top bit set, or it'd be a C-instruction
+    pub fn a(&mut self, value: u16) -> Result<&mut Self, String> {
+        if value > MAX_VALUE {
+            return Err(format!(
+                "@{} won't fit in an A-instruction (the most is {})",
+                value, MAX_VALUE
+            ));
+        }
+        Ok(self.line(HackLine::AImmediate(value)))
+    }
+
+    pub fn a_sym(&mut self, name: impl Into<String>) -> &mut Self {
+        self.line(HackLine::ALocation(Cow::Owned(name.into())))
+    }
+
+    pub fn c(&mut self, dest: Destination, comp: Computation, jump: Jump) -> &mut Self {
+        self.line(HackLine::C(comp, dest, jump))
+    }
+
+    // `dest=comp`
+    pub fn assign(&mut self, dest: Destination, comp: Computation) -> &mut Self {
+        self.c(dest, comp, Jump::Null)
+    }
+
+    // `comp;jump`
+    pub fn jump(&mut self, comp: Computation, jump: Jump) -> &mut Self {
+        self.c(Destination::Null, comp, jump)
+    }
+
+    pub fn comment(&mut self, text: impl Into<String>) -> &mut Self {
+        self.lines.push(Line::Comment(text.into()));
+        self
+    }
+
+    // the program's labels and instructions, without its comments
+    pub fn code(&self) -> impl Iterator<Item = &HackLine<'static>> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Code(code) => Some(code),
+            Line::Comment(_) => None,
+        })
+    }
+
+    // how many words of ROM the program takes
+    pub fn instructions(&self) -> usize {
+        self.code()
+            .filter(|line| !matches!(line, HackLine::Label(_)))
+            .count()
+    }
+
+    // the program as assembly, one line each
+    pub fn to_asm(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Code(code) => out.push_str(&code.to_string()),
+                Line::Comment(text) => {
+                    out.push_str("// ");
+                    out.push_str(text);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // the program as a `.hack` binary
+    pub fn to_hack(&self) -> String {
+        let code: Vec<HackLine> = self.code().cloned().collect();
+        let mut out = Vec::new();
+        crate::assemble_lines(&code, &mut out).expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("binaries are ASCII")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AM;
+
+    #[test]
+    fn emits_both_forms() {
+        let mut program = Builder::new();
+        program
+            .comment("count up forever")
+            .a_sym("i")
+            .assign(Destination::M, Computation::Zero)
+            .label("LOOP")
+            .a_sym("i")
+            .assign(Destination::M, Computation::XPlusOne(AM::M))
+            .a_sym("LOOP")
+            .jump(Computation::Zero, Jump::JMP);
+        assert_eq!(
+            program.to_asm(),
+            "// count up forever\n@i\nM=0\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n"
+        );
+        assert_eq!(program.instructions(), 6);
+        let asm = program.to_asm();
+        let expected = crate::parse(&asm).unwrap();
+        let mut binary = Vec::new();
+        crate::assemble_lines(&expected, &mut binary).unwrap();
+        assert_eq!(program.to_hack().as_bytes(), binary);
+        assert!(program.to_hack().starts_with("0000000000010000\n"));
+
+        assert!(program.a(MAX_VALUE).is_ok());
+        assert!(program.a(MAX_VALUE + 1).is_err());
+        assert_eq!(program.instructions(), 7);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::json::Json;
+use crate::vm::{self, Command};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    // false for the OS functions the emulator has built in
+    pub defined: bool,
+    // the functions this one calls, each once, in the order first called
+    pub calls: Vec<usize>,
+    // whether running the program could ever call it
+    pub reachable: bool,
+}
+
+// which functions of a VM program call which. Programs too big for ROM
+// can drop what's unreachable, and the cycles are where the stack can grow
+// without bound
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallGraph {
+    // the program's own functions in the order they're defined, then the
+    // built-in ones in the order they're first called
+    pub functions: Vec<Function>,
+    // groups of functions that call each other, directly or not, including
+    // any function that calls itself
+    pub cycles: Vec<Vec<usize>>,
+}
+
+impl CallGraph {
+    pub fn new(program: &vm::Program) -> Self {
+        let mut functions: Vec<Function> = Vec::new();
+        let mut by_name: HashMap<&str, usize> = HashMap::new();
+        for line in &program.lines {
+            if let Command::Function(name, _) = &line.command {
+                by_name.insert(name, functions.len());
+                functions.push(Function {
+                    name: name.clone(),
+                    defined: true,
+                    calls: Vec::new(),
+                    reachable: false,
+                });
+            }
+        }
+
+        // anything before the first `function` isn't in one, and runs
+        // straight into it anyway
+        let mut caller = (!functions.is_empty()).then_some(0);
+        for line in &program.lines {
+            match &line.command {
+                Command::Function(name, _) => caller = Some(by_name[name.as_str()]),
+                Command::Call(name, _) => {
+                    let Some(caller) = caller else {
+                        continue;
+                    };
+                    let callee = *by_name.entry(name).or_insert_with(|| {
+                        functions.push(Function {
+                            name: name.clone(),
+                            defined: false,
+                            calls: Vec::new(),
+                            reachable: false,
+                        });
+                        functions.len() - 1
+                    });
+                    let caller = &mut functions[caller];
+                    if !caller.calls.contains(&callee) {
+                        caller.calls.push(callee);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // translated programs start at `Sys.init`; single files without one
+        // just run from the top
+        let mut stack: Vec<usize> = by_name
+            .get(vm::ENTRY)
+            .copied()
+            .or((!functions.is_empty()).then_some(0))
+            .into_iter()
+            .collect();
+        while let Some(index) = stack.pop() {
+            if !std::mem::replace(&mut functions[index].reachable, true) {
+                stack.extend(&functions[index].calls);
+            }
+        }
+
+        let cycles = cycles(&functions);
+        Self { functions, cycles }
+    }
+
+    pub fn unreachable(&self) -> impl Iterator<Item = &Function> {
+        self.functions
+            .iter()
+            .filter(|function| function.defined && !function.reachable)
+    }
+
+    fn recursive(&self, index: usize) -> bool {
+        self.cycles.iter().any(|cycle| cycle.contains(&index))
+    }
+
+    // the graph in Graphviz's DOT language. Unreachable functions are
+    // dashed, built-in ones grey, and recursive ones red
+    pub fn dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n    node [shape=box, fontname=monospace];\n");
+        for (index, function) in self.functions.iter().enumerate() {
+            let mut style = Vec::new();
+            if !function.reachable {
+                style.push("style=dashed");
+            }
+            if !function.defined {
+                style.push("color=grey");
+            }
+            if self.recursive(index) {
+                style.push("color=red");
+            }
+            let style: String = style.iter().map(|style| format!(", {}", style)).collect();
+            let _ = writeln!(
+                out,
+                "    f{} [label=\"{}\"{}];",
+                index, function.name, style
+            );
+        }
+        for (index, function) in self.functions.iter().enumerate() {
+            for callee in &function.calls {
+                let _ = writeln!(out, "    f{} -> f{};", index, callee);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn json(&self) -> Json {
+        let name = |index: &usize| Json::from(self.functions[*index].name.as_str());
+        let functions = self.functions.iter().enumerate().map(|(index, function)| {
+            Json::object([
+                ("name", function.name.as_str().into()),
+                ("defined", function.defined.into()),
+                ("reachable", function.reachable.into()),
+                ("recursive", self.recursive(index).into()),
+                ("calls", function.calls.iter().map(name).collect()),
+            ])
+        });
+        Json::object([
+            ("functions", functions.collect()),
+            (
+                "cycles",
+                self.cycles
+                    .iter()
+                    .map(|cycle| cycle.iter().map(name).collect::<Json>())
+                    .collect(),
+            ),
+        ])
+    }
+}
+
+// the strongly connected components that make up cycles (Tarjan's
+// algorithm), each listed from the function first defined
+fn cycles(functions: &[Function]) -> Vec<Vec<usize>> {
+    struct Search<'a> {
+        functions: &'a [Function],
+        next: usize,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        cycles: Vec<Vec<usize>>,
+    }
+
+    impl Search<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in &self.functions[v].calls {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    Some(_) => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                let mut component = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                if component.len() > 1 || self.functions[v].calls.contains(&v) {
+                    component.sort();
+                    self.cycles.push(component);
+                }
+            }
+        }
+    }
+
+    let mut search = Search {
+        functions,
+        next: 0,
+        index: vec![None; functions.len()],
+        low: vec![0; functions.len()],
+        stack: Vec::new(),
+        on_stack: vec![false; functions.len()],
+        cycles: Vec::new(),
+    };
+    for v in 0..functions.len() {
+        if search.index[v].is_none() {
+            search.visit(v);
+        }
+    }
+    search.cycles.sort();
+    search.cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn calls_reachability_and_cycles() {
+        let source = "function Sys.init 0\ncall Main.even 1\ncall Math.multiply 2\n\
+                      function Main.even 0\ncall Main.odd 1\nreturn\n\
+                      function Main.odd 0\ncall Main.even 1\nreturn\n\
+                      function Main.unused 0\ncall Main.unused 0\nreturn\n";
+        let program = vm::Program::new(&[(PathBuf::from("Main.vm"), source.to_owned())]).unwrap();
+        let graph = CallGraph::new(&program);
+        let summary: Vec<_> = graph
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.defined, f.reachable, f.calls.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Sys.init", true, true, vec![1, 4]),
+                ("Main.even", true, true, vec![2]),
+                ("Main.odd", true, true, vec![1]),
+                ("Main.unused", true, false, vec![3]),
+                ("Math.multiply", false, true, vec![]),
+            ]
+        );
+        assert_eq!(graph.cycles, [vec![1, 2], vec![3]]);
+        assert_eq!(
+            graph.unreachable().map(|f| &f.name).collect::<Vec<_>>(),
+            ["Main.unused"]
+        );
+        assert!(graph
+            .dot()
+            .contains("f3 [label=\"Main.unused\", style=dashed, color=red];"));
+        assert_eq!(
+            graph.json().get("cycles").unwrap().to_string(),
+            r#"[["Main.even","Main.odd"],["Main.unused"]]"#
+        );
+    }
+}
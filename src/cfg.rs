@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::{HackLine, Jump};
+
+// a straight-line run of instructions: control only ever enters at the top
+// and leaves at the bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    // labels pointing at the start of the block
+    pub labels: Vec<String>,
+    // ROM addresses of the block's instructions, `start..end`
+    pub start: usize,
+    pub end: usize,
+    // indices of the lines (including labels) making up the block
+    pub lines: std::ops::Range<usize>,
+    // blocks control can pass to when this one finishes
+    pub successors: Vec<usize>,
+    // whether the block ends in a jump whose target we can't work out
+    pub computed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    // blocks that can be entered from somewhere other than a successor
+    // edge: the start of the program, and any label whose address is loaded
+    // without being jumped to straight away (e.g. a return address pushed by
+    // VM-translated code), since it could be jumped to from anywhere later
+    pub roots: Vec<usize>,
+}
+
+impl Cfg {
+    pub fn new<'a>(lines: &[impl AsRef<HackLine<'a>>]) -> Self {
+        let lines: Vec<&HackLine> = lines.iter().map(AsRef::as_ref).collect();
+
+        // split the program into blocks, starting a new one at every label
+        // and after every jump
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut by_label: HashMap<&str, usize> = HashMap::new();
+        let mut address = 0;
+        let mut open = false;
+        for (index, &line) in lines.iter().enumerate() {
+            if !open
+                || matches!(line, HackLine::Label(_))
+                    && blocks.last().is_some_and(|b| b.end > b.start)
+            {
+                blocks.push(Block {
+                    labels: Vec::new(),
+                    start: address,
+                    end: address,
+                    lines: index..index,
+                    successors: Vec::new(),
+                    computed: false,
+                });
+                open = true;
+            }
+            let block = blocks.last_mut().expect("a block was just opened");
+            block.lines.end = index + 1;
+            match line {
+                HackLine::Label(label) => {
+                    block.labels.push(label.to_string());
+                    by_label.insert(label, blocks.len() - 1);
+                }
+                HackLine::C(_, _, jump) => {
+                    address += 1;
+                    block.end = address;
+                    if !matches!(jump, Jump::Null) {
+                        open = false;
+                    }
+                }
+                _ => {
+                    address += 1;
+                    block.end = address;
+                }
+            }
+        }
+
+        let mut roots = Vec::new();
+        if !blocks.is_empty() {
+            roots.push(0);
+        }
+
+        let count = blocks.len();
+        for (index, block) in blocks.iter_mut().enumerate() {
+            let instructions = &lines[block.lines.clone()];
+            let code: Vec<_> = instructions
+                .iter()
+                .copied()
+                .filter(|line| !matches!(line, HackLine::Label(_)))
+                .collect();
+
+            // labels loaded for any reason other than an immediate jump
+            for (position, line) in code.iter().enumerate() {
+                if let HackLine::ALocation(name) = line {
+                    let jumped_to = position + 2 == code.len()
+                        && matches!(code.last(), Some(HackLine::C(_, _, jump)) if !matches!(jump, Jump::Null));
+                    if let Some(&target) = by_label.get(name.as_ref()).filter(|_| !jumped_to) {
+                        if !roots.contains(&target) {
+                            roots.push(target);
+                        }
+                    }
+                }
+            }
+
+            let jump = match code.last() {
+                Some(HackLine::C(_, _, jump)) => *jump,
+                _ => Jump::Null,
+            };
+            if !matches!(jump, Jump::JMP) && index + 1 < count {
+                block.successors.push(index + 1);
+            }
+            if !matches!(jump, Jump::Null) {
+                let target = match code.len().checked_sub(2).map(|position| code[position]) {
+                    Some(HackLine::ALocation(name)) => by_label.get(name.as_ref()).copied(),
+                    _ => None,
+                };
+                match target {
+                    Some(target) if !block.successors.contains(&target) => {
+                        block.successors.push(target)
+                    }
+                    Some(_) => {}
+                    None => block.computed = true,
+                }
+            }
+        }
+
+        Self { blocks, roots }
+    }
+
+    // which blocks can ever be executed
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = self.roots.clone();
+        while let Some(index) = stack.pop() {
+            if !std::mem::replace(&mut reachable[index], true) {
+                stack.extend(&self.blocks[index].successors);
+            }
+        }
+        reachable
+    }
+
+    // the graph in Graphviz's DOT language, a node per block giving its
+    // labels and the ROM addresses it covers. Blocks nothing reaches are
+    // dashed, and those ending in a computed jump point at a `?` node
+    pub fn dot(&self) -> String {
+        let reachable = self.reachable();
+        let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = block.labels.join("\\n");
+            if !label.is_empty() {
+                label.push_str("\\n");
+            }
+            label.push_str(&match block.end - block.start {
+                0 => format!("{} (empty)", block.start),
+                1 => block.start.to_string(),
+                _ => format!("{}..{}", block.start, block.end - 1),
+            });
+            let style = if reachable[index] {
+                ""
+            } else {
+                ", style=dashed"
+            };
+            let _ = writeln!(out, "    b{} [label=\"{}\"{}];", index, label, style);
+        }
+        if self.blocks.iter().any(|block| block.computed) {
+            let _ = writeln!(out, "    computed [label=\"?\", shape=circle];");
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                let _ = writeln!(out, "    b{} -> b{};", index, successor);
+            }
+            if block.computed {
+                let _ = writeln!(out, "    b{} -> computed [style=dotted];", index);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn blocks_and_edges() {
+        let lines =
+            parse("@i\nM=0\n(LOOP)\n@i\nD=M\n@END\nD;JGT\n@LOOP\n0;JMP\n(END)\n@END\n0;JMP\n")
+                .unwrap();
+        let cfg = Cfg::new(&lines);
+        let summary: Vec<_> = cfg
+            .blocks
+            .iter()
+            .map(|block| {
+                (
+                    block.labels.clone(),
+                    block.start,
+                    block.end,
+                    block.successors.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (vec![], 0, 2, vec![1]),
+                (vec!["LOOP".to_owned()], 2, 6, vec![2, 3]),
+                (vec![], 6, 8, vec![1]),
+                (vec!["END".to_owned()], 8, 10, vec![3]),
+            ]
+        );
+        assert_eq!(cfg.roots, [0]);
+        assert_eq!(cfg.reachable(), [true; 4]);
+    }
+
+    #[test]
+    fn computed_jumps() {
+        // a return address pushed as data makes its label a root
+        let lines = parse("@RET\nD=A\n@R13\nA=M\n0;JMP\n(RET)\n0\n").unwrap();
+        let cfg = Cfg::new(&lines);
+        assert!(cfg.blocks[0].computed);
+        assert_eq!(cfg.roots, [0, 1]);
+    }
+
+    #[test]
+    fn dot() {
+        let lines = parse("@END\n0;JMP\n(DEAD)\n@DEAD\n0;JMP\n(END)\n@R13\nA=M\n0;JMP\n").unwrap();
+        assert_eq!(
+            Cfg::new(&lines).dot(),
+            "digraph cfg {\n    \
+             node [shape=box, fontname=monospace];\n    \
+             b0 [label=\"0..1\"];\n    \
+             b1 [label=\"DEAD\\n2..3\", style=dashed];\n    \
+             b2 [label=\"END\\n4..6\"];\n    \
+             computed [label=\"?\", shape=circle];\n    \
+             b0 -> b2;\n    \
+             b1 -> b1;\n    \
+             b2 -> computed [style=dotted];\n\
+             }\n"
+        );
+    }
+}
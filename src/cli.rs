@@ -0,0 +1,683 @@
+use std::fmt::Write as _;
+
+// the whole command-line interface is described by these tables, so that
+// parsing, `--help` output, and anything else that needs to know about our
+// flags all agree with each other
+
+pub struct Flag {
+    pub long: &'static str,
+    pub short: Option<char>,
+    // name of the flag's argument, if it takes one
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub about: &'static str,
+    pub flags: &'static [Flag],
+}
+
+const HELP: Flag = Flag {
+    long: "help",
+    short: Some('h'),
+    value: None,
+    help: "print this help message",
+};
+
+const COLOR: Flag = Flag {
+    long: "color",
+    short: None,
+    value: Some("WHEN"),
+    help: "colorize diagnostics: auto, always, or never",
+};
+
+const JOBS: Flag = Flag {
+    long: "jobs",
+    short: Some('j'),
+    value: Some("N"),
+    help: "work on up to N files at once (default: one per CPU)",
+};
+
+const QUIET: Flag = Flag {
+    long: "quiet",
+    short: Some('q'),
+    value: None,
+    help: "only report errors",
+};
+
+const VERBOSE: Flag = Flag {
+    long: "verbose",
+    short: Some('v'),
+    value: None,
+    help: "also report sizes and symbol counts; twice (-vv) for how long each pass took",
+};
+
+const LOG_FORMAT: Flag = Flag {
+    long: "log-format",
+    short: None,
+    value: Some("FORMAT"),
+    help: "text (default), or json for an object per line",
+};
+
+const ALLOCATE: Flag = Flag {
+    long: "allocate",
+    short: None,
+    value: Some("ORDER"),
+    help: "the order variables get addresses in: first-use (default) or alphabetical; \
+           a `// @pin NAME ADDRESS` comment fixes one wherever it's used",
+};
+
+const SYM: Flag = Flag {
+    long: "sym",
+    short: None,
+    value: None,
+    help: "also write a .sym file with the address of every label and variable",
+};
+
+const BUILTINS: Flag = Flag {
+    long: "builtins",
+    short: None,
+    value: None,
+    help: "run the OS's functions natively, in place of the program's own versions",
+};
+
+pub const BINARY: &str = "hack";
+
+// the first entry is the default command, used when the first argument
+// isn't the name of a command
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "asm",
+        args: "<FILE|DIR>...",
+        about: "assemble .asm files into .hack binaries",
+        flags: &[
+            Flag {
+                long: "object",
+                short: Some('c'),
+                value: None,
+                help: "emit relocatable .hobj objects for `hack link` instead of .hack files",
+            },
+            Flag {
+                long: "optimize",
+                short: Some('O'),
+                value: None,
+                help: "shrink the program with peephole optimizations and dead code removal",
+            },
+            Flag {
+                long: "source-map",
+                short: Some('m'),
+                value: None,
+                help: "also write a .map file relating ROM addresses to source lines",
+            },
+            Flag {
+                long: "emit",
+                short: None,
+                value: Some("FORMAT"),
+                help: "what to write for each file: hack (default), or ast-json for the \
+                       parsed program as a .json file",
+            },
+            Flag {
+                long: "relaxed-case",
+                short: None,
+                value: None,
+                help: "accept lowercase mnemonics, as in `d=m+1;jmp`",
+            },
+            Flag {
+                long: "symbols",
+                short: None,
+                value: Some("FILE"),
+                help: "take the predefined symbols from a JSON file, as in \
+                       {\"symbols\": {\"LED\": 24577, \"R15\": null}}",
+            },
+            ALLOCATE,
+            SYM,
+            Flag {
+                long: "target",
+                short: None,
+                value: Some("TARGET"),
+                help: "the machine to assemble for: hack (default), or hack-ext to allow \
+                       the shift and multiply instructions D<<, A>>, D*M and the like",
+            },
+            Flag {
+                long: "compat",
+                short: None,
+                value: Some("MODE"),
+                help: "match another assembler exactly; the only MODE is nand2tetris, for the \
+                       course's own",
+            },
+            Flag {
+                long: "stream",
+                short: None,
+                value: None,
+                help: "read each file twice rather than holding it in memory, for huge programs",
+            },
+            Flag {
+                long: "watch",
+                short: Some('w'),
+                value: None,
+                help: "keep running, reassembling inputs whenever they change",
+            },
+            JOBS,
+            QUIET,
+            VERBOSE,
+            LOG_FORMAT,
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "check",
+        args: "<FILE|DIR>...",
+        about: "check .asm files for errors without writing any output",
+        flags: &[JOBS, COLOR, HELP],
+    },
+    Command {
+        name: "debug",
+        args: "<FILE|DIR>",
+        about: "step through a .asm, .hack, .snap, or .vm program in an interactive debugger",
+        flags: &[BUILTINS, COLOR, HELP],
+    },
+    Command {
+        name: "repl",
+        args: "",
+        about: "type assembly a line at a time, running each one as it's entered",
+        flags: &[HELP],
+    },
+    Command {
+        name: "run",
+        args: "<FILE|DIR>",
+        about: "run a .asm, .hack, or .snap program in the emulator, or .vm files in the VM",
+        flags: &[
+            Flag {
+                long: "trace",
+                short: Some('t'),
+                value: Some("FILE"),
+                help: "log every executed instruction with the cycle, PC, A and D",
+            },
+            Flag {
+                long: "vcd",
+                short: None,
+                value: Some("FILE"),
+                help: "record the registers' values every cycle as a waveform, in a .vcd \
+                       file for GTKWave",
+            },
+            Flag {
+                long: "signals",
+                short: None,
+                value: Some("LIST"),
+                help: "what `--vcd` records, separated by commas: registers (A, D, PC), \
+                       RAM[N], or RAM symbols",
+            },
+            Flag {
+                long: "max-cycles",
+                short: None,
+                value: Some("N"),
+                help: "give up, and fail, if the program hasn't halted after N cycles",
+            },
+            Flag {
+                long: "hz",
+                short: None,
+                value: Some("N"),
+                help: "run at most N instructions a second, rather than flat out",
+            },
+            Flag {
+                long: "profile",
+                short: Some('p'),
+                value: None,
+                help: "report how many cycles each instruction and label took",
+            },
+            Flag {
+                long: "format",
+                short: None,
+                value: Some("FORMAT"),
+                help: "how to write the profile: text (default) or json",
+            },
+            Flag {
+                long: "coverage",
+                short: None,
+                value: Some("FILE"),
+                help: "report which instructions ran, writing an annotated listing to FILE",
+            },
+            Flag {
+                long: "screen",
+                short: Some('s'),
+                value: None,
+                help: "show the screen in the terminal while the program runs, \
+                       feeding it keystrokes (ctrl-c stops)",
+            },
+            Flag {
+                long: "device",
+                short: None,
+                value: Some("NAME@ADDR"),
+                help: "map a device into RAM (may be repeated): a `timer` counting \
+                       thousands of cycles since it was written, or a `console` printing \
+                       what's written to it",
+            },
+            Flag {
+                long: "png",
+                short: None,
+                value: Some("FILE"),
+                help: "save the screen as a PNG once the program stops",
+            },
+            Flag {
+                long: "gif",
+                short: None,
+                value: Some("FILE"),
+                help: "save an animated GIF of the screen as the program runs",
+            },
+            Flag {
+                long: "frames",
+                short: None,
+                value: Some("N"),
+                help: "the most frames to put in a --gif (default 100)",
+            },
+            Flag {
+                long: "script",
+                short: None,
+                value: Some("FILE"),
+                help: "drive the program with a script of keypresses and expectations; \
+                       one with an `output-list` also writes a .out file beside it",
+            },
+            Flag {
+                long: "ram",
+                short: None,
+                value: Some("FILE"),
+                help: "set up RAM before running from lines of `ADDRESS VALUE`",
+            },
+            Flag {
+                long: "save",
+                short: None,
+                value: Some("FILE"),
+                help: "save a .snap snapshot of the machine when the program stops",
+            },
+            BUILTINS,
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "translate",
+        args: "<FILE|DIR>...",
+        about: "translate .vm files, or directories of them, into .asm programs",
+        flags: &[
+            Flag {
+                long: "inline",
+                short: None,
+                value: None,
+                help: "expand every call, return and comparison in place rather than \
+                       sharing one copy of each (bigger, but slightly faster)",
+            },
+            Flag {
+                long: "annotate",
+                short: Some('a'),
+                value: None,
+                help: "comment the output with the VM commands it came from",
+            },
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "compile",
+        args: "<FILE|DIR>...",
+        about: "compile .jack files, or directories of them, into .vm files",
+        flags: &[
+            Flag {
+                long: "verbose",
+                short: Some('v'),
+                value: None,
+                help: "list the expressions and statements worked out at compile time",
+            },
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "fmt",
+        args: "<FILE|DIR>...",
+        about: "reformat .asm files in place",
+        flags: &[
+            Flag {
+                long: "check",
+                short: None,
+                value: None,
+                help: "don't write anything; fail if any file isn't already formatted",
+            },
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "lint",
+        args: "<FILE|DIR>...",
+        about: "warn about common mistakes in .asm files",
+        flags: &[
+            Flag {
+                long: "allow",
+                short: Some('A'),
+                value: Some("LINT"),
+                help: "silence a lint (may be repeated); see --list",
+            },
+            Flag {
+                long: "list",
+                short: None,
+                value: None,
+                help: "list the available lints",
+            },
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "stats",
+        args: "<FILE|DIR>...",
+        about: "report instruction counts and memory usage of .asm files",
+        flags: &[COLOR, HELP],
+    },
+    Command {
+        name: "xref",
+        args: "<FILE|DIR>...",
+        about: "list where each symbol of .asm files is defined and every line using it",
+        flags: &[COLOR, HELP],
+    },
+    Command {
+        name: "verify",
+        args: "<FILE|DIR>...",
+        about: "check that .asm files survive assembly, disassembly and reassembly unchanged",
+        flags: &[COLOR, HELP],
+    },
+    Command {
+        name: "diff",
+        args: "<A> <B>",
+        about: "compare two .hack, .asm, or .snap programs instruction by instruction",
+        flags: &[COLOR, HELP],
+    },
+    Command {
+        name: "disasm",
+        args: "<FILE>",
+        about: "turn a .hack binary back into assembly, naming the labels and variables it uses",
+        flags: &[
+            Flag {
+                long: "sym",
+                short: None,
+                value: Some("FILE"),
+                help: "take names from a .sym file of `(LABEL) address` and `variable address` \
+                       lines",
+            },
+            Flag {
+                long: "output",
+                short: Some('o'),
+                value: Some("FILE"),
+                help: "where to write the assembly (default: standard output)",
+            },
+            HELP,
+        ],
+    },
+    Command {
+        name: "cfg",
+        args: "<FILE>",
+        about: "draw an .asm program's control-flow graph, as Graphviz DOT",
+        flags: &[
+            Flag {
+                long: "dot",
+                short: None,
+                value: Some("FILE"),
+                help: "where to write the graph (default: standard output)",
+            },
+            HELP,
+        ],
+    },
+    Command {
+        name: "callgraph",
+        args: "<FILE|DIR>",
+        about: "draw which functions of a .vm or .jack program call which, as Graphviz DOT",
+        flags: &[
+            Flag {
+                long: "dot",
+                short: None,
+                value: Some("FILE"),
+                help: "where to write the graph (default: standard output)",
+            },
+            Flag {
+                long: "json",
+                short: None,
+                value: Some("FILE"),
+                help: "write the graph as JSON too, or instead",
+            },
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "selftest",
+        args: "<FILE|DIR>...",
+        about: "assemble .asm fixtures and compare each with the .hack file beside it",
+        flags: &[COLOR, HELP],
+    },
+    Command {
+        name: "link",
+        args: "<FILE>...",
+        about: "link .asm modules and .hobj objects into a single .hack binary",
+        flags: &[
+            Flag {
+                long: "output",
+                short: Some('o'),
+                value: Some("FILE"),
+                help: "where to write the linked binary (default: a.hack)",
+            },
+            ALLOCATE,
+            SYM,
+            COLOR,
+            HELP,
+        ],
+    },
+    Command {
+        name: "completions",
+        args: "<SHELL>",
+        about: "print a completion script for bash, zsh or fish",
+        flags: &[HELP],
+    },
+    Command {
+        name: "man",
+        args: "",
+        about: "print a man page for every command, in roff",
+        flags: &[HELP],
+    },
+    Command {
+        name: "lsp",
+        args: "",
+        about: "serve the Language Server Protocol over stdin and stdout, for editors",
+        flags: &[HELP],
+    },
+    Command {
+        name: "dap",
+        args: "",
+        about: "serve the Debug Adapter Protocol over stdin and stdout, for editors' debuggers",
+        flags: &[HELP],
+    },
+];
+
+pub struct Matches {
+    pub command: &'static Command,
+    flags: Vec<(&'static str, Option<String>)>,
+    pub positionals: Vec<String>,
+}
+
+impl Matches {
+    pub fn flag(&self, long: &str) -> bool {
+        self.flags.iter().any(|(name, _)| *name == long)
+    }
+
+    // every value given for a flag that can be repeated
+    pub fn values(&self, long: &str) -> Vec<&str> {
+        self.flags
+            .iter()
+            .filter(|(name, _)| *name == long)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    // how many times a flag was given, as in `-vv`
+    pub fn count(&self, long: &str) -> usize {
+        self.flags.iter().filter(|(name, _)| *name == long).count()
+    }
+
+    // the last occurrence of a flag wins
+    pub fn value(&self, long: &str) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == long)
+            .and_then(|(_, value)| value.as_deref())
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Matches, String> {
+    let mut args = args.into_iter().peekable();
+
+    let command = match args
+        .peek()
+        .and_then(|first| COMMANDS.iter().find(|command| command.name == first))
+    {
+        Some(command) => {
+            args.next();
+            command
+        }
+        None => &COMMANDS[0],
+    };
+
+    let mut matches = Matches {
+        command,
+        flags: Vec::new(),
+        positionals: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = if arg == "--" {
+            matches.positionals.extend(args.by_ref());
+            break;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (long, None),
+            };
+            let flag = command
+                .flags
+                .iter()
+                .find(|flag| flag.long == name)
+                .ok_or_else(|| format!("unknown flag `--{}` for `{}`", name, command.name))?;
+            (flag, value)
+        } else if let Some(short) = arg.strip_prefix('-').filter(|short| !short.is_empty()) {
+            // flags without values can share a dash, as in `-vv` or `-cO`,
+            // and the first one with a value takes the rest of the argument
+            let mut chars = short.chars();
+            let flag = loop {
+                let c = chars.next().unwrap_or_default();
+                let flag = command
+                    .flags
+                    .iter()
+                    .find(|flag| flag.short == Some(c))
+                    .ok_or_else(|| format!("unknown flag `-{}` for `{}`", c, command.name))?;
+                if flag.value.is_some() || chars.as_str().is_empty() {
+                    break flag;
+                }
+                matches.flags.push((flag.long, None));
+            };
+            let rest = chars.as_str();
+            (flag, (!rest.is_empty()).then(|| rest.to_owned()))
+        } else {
+            matches.positionals.push(arg);
+            continue;
+        };
+
+        let value = match (flag.value, inline_value) {
+            (None, None) => None,
+            (None, Some(_)) => Err(format!("flag `--{}` doesn't take a value", flag.long))?,
+            (Some(_), Some(value)) => Some(value),
+            (Some(name), None) => Some(
+                args.next()
+                    .ok_or_else(|| format!("flag `--{}` expects a {}", flag.long, name))?,
+            ),
+        };
+        matches.flags.push((flag.long, value));
+    }
+
+    Ok(matches)
+}
+
+pub fn help(command: &Command) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", command.about);
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "usage: {} {} [OPTIONS] {}",
+        BINARY, command.name, command.args
+    );
+    let _ = writeln!(out);
+    let _ = writeln!(out, "options:");
+    for flag in command.flags {
+        let mut spec = match flag.short {
+            Some(short) => format!("-{}, --{}", short, flag.long),
+            None => format!("    --{}", flag.long),
+        };
+        if let Some(value) = flag.value {
+            let _ = write!(spec, " <{}>", value);
+        }
+        let _ = writeln!(out, "  {:<28} {}", spec, flag.help);
+    }
+    if command.name == COMMANDS[0].name {
+        let _ = writeln!(out);
+        let _ = writeln!(out, "commands:");
+        let width = COMMANDS.iter().map(|command| command.name.len()).max();
+        for command in COMMANDS {
+            let _ = writeln!(
+                out,
+                "  {:<width$} {}",
+                command.name,
+                command.about,
+                width = width.unwrap_or(0)
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn default_command() {
+        let matches = parse(args(&["Rect.asm", "-c"])).unwrap();
+        assert_eq!(matches.command.name, "asm");
+        assert!(matches.flag("object"));
+        assert_eq!(matches.positionals, ["Rect.asm"]);
+    }
+
+    #[test]
+    fn flag_values() {
+        let matches = parse(args(&["link", "-o", "out.hack", "a.asm"])).unwrap();
+        assert_eq!(matches.command.name, "link");
+        assert_eq!(matches.value("output"), Some("out.hack"));
+
+        let matches = parse(args(&["link", "--output=b.hack", "-oc.hack"])).unwrap();
+        assert_eq!(matches.value("output"), Some("c.hack"));
+
+        assert!(parse(args(&["link", "-o"])).is_err());
+
+        let matches = parse(args(&["-cOj4", "-vv"])).unwrap();
+        assert!(matches.flag("object") && matches.flag("optimize"));
+        assert_eq!(
+            (matches.value("jobs"), matches.count("verbose")),
+            (Some("4"), 2)
+        );
+        assert!(parse(args(&["link", "--frobnicate"])).is_err());
+    }
+}
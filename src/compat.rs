@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use crate::diagnostic::Diagnostic;
+use crate::predefined::SymbolSet;
+use crate::{split_comment, HackLine, SourceLine};
+
+// `--compat nand2tetris`: the course's own assembler, which graders compare
+// our output against. It ignores whitespace anywhere in a line, won't have
+// a label defined twice or named after a predefined symbol, where we'd
+// quietly take the last definition or shadow the symbol, and words its
+// errors its own way, as `In line N, ...`
+
+// the program with all whitespace taken out of every line, and comments
+// dropped, keeping the lines where they were so that errors still point at
+// the right one
+pub fn normalize(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    for line in crate::strip_bom(source).lines() {
+        let (code, _) = split_comment(line);
+        out.extend(code.chars().filter(|c| !c.is_whitespace()));
+        out.push('\n');
+    }
+    out
+}
+
+pub fn check(lines: &[SourceLine]) -> Result<(), Diagnostic> {
+    let mut defined: HashSet<&str> = HashSet::new();
+    for source in lines {
+        let HackLine::Label(label) = &source.line else {
+            continue;
+        };
+        let error = |message: String| {
+            Diagnostic::error(in_line(source.number, &message))
+                .at(source.text, source.code())
+                .on_line(source.number, source.text)
+        };
+        if SymbolSet::standard().get(label).is_some() {
+            return Err(error(format!("Label {} is a predefined symbol", label)));
+        }
+        if !defined.insert(label) {
+            return Err(error(format!("Label {} is already defined", label)));
+        }
+    }
+    Ok(())
+}
+
+fn in_line(line: usize, message: &str) -> String {
+    format!("In line {}, {}", line, message)
+}
+
+// one of our parsing errors as the course assembler words it, which only
+// says what kind of thing was wrong, where ours say how
+pub fn reword(err: Diagnostic) -> Diagnostic {
+    let kind = err.message.split(':').next().unwrap_or_default();
+    let message = match kind {
+        "Invalid label" => "Illegal label",
+        "Invalid address" => "Expected a number between 0 and 32767 after @",
+        "Invalid symbol" => "Illegal symbol after @",
+        "Invalid dest" => "Illegal destination",
+        "Invalid comp" => "Illegal computation",
+        "Invalid jump" => "Illegal jump",
+        _ => "Illegal instruction",
+    };
+    Diagnostic {
+        message: in_line(err.line, message),
+        ..err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_source;
+
+    #[test]
+    fn matches_the_course_assembler() {
+        let source = normalize("  D = M + 1 ; JGT // go\n@ R0\n( LOOP )\n");
+        assert_eq!(source, "D=M+1;JGT\n@R0\n(LOOP)\n");
+        assert!(check(&parse_source(&source).unwrap()).is_ok());
+
+        let twice = parse_source("(A)\n@0\n(A)\n").unwrap();
+        let err = check(&twice).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "In line 3, Label A is already defined");
+        let predefined = parse_source("(SCREEN)\n").unwrap();
+        assert_eq!(
+            check(&predefined).unwrap_err().message,
+            "In line 1, Label SCREEN is a predefined symbol"
+        );
+    }
+
+    #[test]
+    fn words_errors_like_the_course_assembler() {
+        let message = |source: &str| reword(parse_source(&normalize(source)).unwrap_err()).message;
+        assert_eq!(message("@0\n(1A)\n"), "In line 2, Illegal label");
+        assert_eq!(
+            message("@40000\n"),
+            "In line 1, Expected a number between 0 and 32767 after @"
+        );
+        assert_eq!(message("@a-b\n"), "In line 1, Illegal symbol after @");
+        assert_eq!(message("X=D\n"), "In line 1, Illegal destination");
+        assert_eq!(message("D=Q\n"), "In line 1, Illegal computation");
+        assert_eq!(message("D;JMQ\n"), "In line 1, Illegal jump");
+        assert_eq!(message("A=D=M\n"), "In line 1, Illegal instruction");
+    }
+}
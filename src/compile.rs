@@ -0,0 +1,890 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::Diagnostic;
+use crate::jack::{
+    self, BinaryOp, Call, Class, Expr, Statement, StatementKind, Subroutine, SubroutineKind, Type,
+    UnaryOp, VarKind,
+};
+use crate::vm::{self, Segment};
+
+// compiles Jack classes into VM code, the way the course's compiler does,
+// except that it works out whatever it can at compile time: constant
+// expressions, `if`s and `while`s with constant conditions, and
+// multiplications by powers of two, which would otherwise all be pushed
+// and evaluated (or called out to Math.multiply for) at run time
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compiled {
+    pub code: Vec<String>,
+    // what got simplified, by line
+    pub notes: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    segment: Segment,
+    index: u16,
+    ty: Type,
+}
+
+// the value of an expression, if it's the same every time. Like the
+// machine, this works in 16 bits and wraps around
+fn constant(expr: &Expr) -> Option<i16> {
+    Some(match expr {
+        Expr::Int(value) => *value as i16,
+        Expr::True => -1,
+        Expr::False | Expr::Null => 0,
+        Expr::Unary(UnaryOp::Neg, term) => constant(term)?.wrapping_neg(),
+        Expr::Unary(UnaryOp::Not, term) => !constant(term)?,
+        Expr::Binary(left, op, right) => {
+            let (x, y) = (constant(left)?, constant(right)?);
+            match op {
+                BinaryOp::Add => x.wrapping_add(y),
+                BinaryOp::Sub => x.wrapping_sub(y),
+                BinaryOp::Mul => x.wrapping_mul(y),
+                // leave dividing by zero for Math.divide to complain about
+                BinaryOp::Div if y == 0 => return None,
+                BinaryOp::Div => x.wrapping_div(y),
+                BinaryOp::And => x & y,
+                BinaryOp::Or => x | y,
+                BinaryOp::Lt => -((x < y) as i16),
+                BinaryOp::Gt => -((x > y) as i16),
+                BinaryOp::Eq => -((x == y) as i16),
+            }
+        }
+        _ => return None,
+    })
+}
+
+// which way a constant condition goes, if it can be folded. Compiled
+// conditions only count -1 as true, since `if-goto` follows a `not`, so any
+// other constant is left to the code that tests it at runtime
+fn decided(condition: &Expr) -> Option<bool> {
+    match constant(condition)? {
+        -1 => Some(true),
+        0 => Some(false),
+        _ => None,
+    }
+}
+
+// whether an expression is already as simple as it gets, so folding it
+// isn't worth mentioning
+fn literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Int(_) | Expr::True | Expr::False | Expr::Null => true,
+        Expr::Unary(UnaryOp::Neg, term) => matches!(**term, Expr::Int(_)),
+        _ => false,
+    }
+}
+
+// whether evaluating an expression can't do anything but produce a value,
+// so that we can leave it out when its value doesn't matter
+fn pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_) => false,
+        Expr::Index(_, index) => pure(index),
+        Expr::Unary(_, term) => pure(term),
+        Expr::Binary(left, _, right) => pure(left) && pure(right),
+        _ => true,
+    }
+}
+
+struct Compiler<'a> {
+    source: &'a str,
+    class: &'a Class,
+    fields: u16,
+    class_vars: HashMap<&'a str, Variable>,
+    vars: HashMap<&'a str, Variable>,
+    kind: SubroutineKind,
+    labels: usize,
+    // the labels the subroutine's asm blocks define
+    asm_labels: HashSet<&'a str>,
+    out: Compiled,
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, command: String) {
+        self.out.code.push(command);
+    }
+
+    fn note(&mut self, line: usize, note: String) {
+        self.out.notes.push((line, note));
+    }
+
+    fn label(&mut self, name: &str) -> String {
+        let label = format!("{}{}", name, self.labels);
+        self.labels += 1;
+        label
+    }
+
+    // an error on `line`, pointing at `name` if it's there
+    fn error(&self, line: usize, name: &str, message: String) -> Diagnostic {
+        let text = self.source.lines().nth(line - 1).unwrap_or_default();
+        let word = |&(start, _): &(usize, &str)| {
+            let around = [
+                text[..start].chars().next_back(),
+                text[start + name.len()..].chars().next(),
+            ];
+            !around
+                .iter()
+                .flatten()
+                .any(|c| c.is_ascii_alphanumeric() || *c == '_')
+        };
+        let span = match text.match_indices(name).find(word) {
+            Some((start, _)) if !name.is_empty() => start..start + name.len(),
+            _ => {
+                let start = text.len() - text.trim_start().len();
+                start..text.trim_end().len()
+            }
+        };
+        jack::error(self.source, line, span, message)
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name).or_else(|| self.class_vars.get(name))
+    }
+
+    fn variable(&self, name: &str, line: usize) -> Result<Variable, Diagnostic> {
+        let variable = self
+            .lookup(name)
+            .ok_or_else(|| self.error(line, name, format!("no variable called `{}`", name)))?;
+        if variable.segment == Segment::This && self.kind == SubroutineKind::Function {
+            Err(self.error(
+                line,
+                name,
+                format!("functions can't use the field `{}`", name),
+            ))?;
+        }
+        Ok(variable.clone())
+    }
+
+    fn declare(
+        vars: &mut HashMap<&'a str, Variable>,
+        var: &'a jack::Var,
+        segment: Segment,
+        index: u16,
+        source: &str,
+    ) -> Result<(), Diagnostic> {
+        let variable = Variable {
+            segment,
+            index,
+            ty: var.ty.clone(),
+        };
+        if vars.insert(&var.name, variable).is_some() {
+            let text = source.lines().nth(var.line - 1).unwrap_or_default();
+            let start = text.rfind(var.name.as_str()).unwrap_or(0);
+            Err(jack::error(
+                source,
+                var.line,
+                start..start + var.name.len(),
+                format!("`{}` is already declared", var.name),
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn subroutine(&mut self, subroutine: &'a Subroutine) -> Result<(), Diagnostic> {
+        self.vars.clear();
+        self.kind = subroutine.kind;
+        self.labels = 0;
+        // a method's object comes in as its first argument
+        let first = (subroutine.kind == SubroutineKind::Method) as u16;
+        for (index, param) in subroutine.params.iter().enumerate() {
+            let index = first + index as u16;
+            Self::declare(&mut self.vars, param, Segment::Argument, index, self.source)?;
+        }
+        for (index, local) in subroutine.locals.iter().enumerate() {
+            Self::declare(
+                &mut self.vars,
+                local,
+                Segment::Local,
+                index as u16,
+                self.source,
+            )?;
+        }
+
+        self.asm_labels.clear();
+        self.find_asm_labels(&subroutine.body)?;
+
+        self.emit(format!(
+            "function {}.{} {}",
+            self.class.name,
+            subroutine.name,
+            subroutine.locals.len()
+        ));
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                self.emit(format!("push constant {}", self.fields));
+                self.emit("call Memory.alloc 1".to_owned());
+                self.emit("pop pointer 0".to_owned());
+            }
+            SubroutineKind::Method => {
+                self.emit("push argument 0".to_owned());
+                self.emit("pop pointer 0".to_owned());
+            }
+            SubroutineKind::Function => {}
+        }
+        self.statements(&subroutine.body)
+    }
+
+    // labels are shared by all of a subroutine's asm blocks, so that they
+    // can jump between them
+    fn find_asm_labels(&mut self, statements: &'a [Statement]) -> Result<(), Diagnostic> {
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Asm(code) => {
+                    for (line, instruction) in code {
+                        let Some(label) = vm::asm_label(instruction) else {
+                            continue;
+                        };
+                        if !self.asm_labels.insert(label) {
+                            Err(self.error(
+                                *line,
+                                label,
+                                format!("label {} defined twice", label),
+                            ))?;
+                        }
+                    }
+                }
+                // only the code that's kept after folding, so that nothing
+                // can jump into a dropped branch
+                StatementKind::If {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    if decided(condition) != Some(false) {
+                        self.find_asm_labels(then)?;
+                    }
+                    if decided(condition) != Some(true) {
+                        self.find_asm_labels(otherwise)?;
+                    }
+                }
+                StatementKind::While { condition, body } if decided(condition) != Some(false) => {
+                    self.find_asm_labels(body)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), Diagnostic> {
+        statements
+            .iter()
+            .try_for_each(|statement| self.statement(statement))
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Diagnostic> {
+        let line = statement.line;
+        match &statement.kind {
+            StatementKind::Let {
+                name,
+                index: None,
+                value,
+            } => {
+                let variable = self.variable(name, line)?;
+                self.expr(value, line)?;
+                self.emit(format!("pop {} {}", variable.segment, variable.index));
+            }
+            StatementKind::Let {
+                name,
+                index: Some(index),
+                value,
+            } => {
+                let variable = self.variable(name, line)?;
+                self.emit(format!("push {} {}", variable.segment, variable.index));
+                self.expr(index, line)?;
+                self.emit("add".to_owned());
+                // the value might index arrays itself, so it has to be
+                // worked out before we point THAT at the element
+                self.expr(value, line)?;
+                self.emit("pop temp 0".to_owned());
+                self.emit("pop pointer 1".to_owned());
+                self.emit("push temp 0".to_owned());
+                self.emit("pop that 0".to_owned());
+            }
+            StatementKind::If {
+                condition,
+                then,
+                otherwise,
+            } => match decided(condition) {
+                Some(value) => {
+                    let (taken, dropped) = if value {
+                        (then, "else")
+                    } else {
+                        (otherwise, "then")
+                    };
+                    self.note(
+                        line,
+                        format!("dropped the {} branch of `if ({})`", dropped, condition),
+                    );
+                    self.statements(taken)?;
+                }
+                None => {
+                    let (otherwise_label, end) = (self.label("IF_ELSE"), self.label("IF_END"));
+                    self.expr(condition, line)?;
+                    self.emit("not".to_owned());
+                    self.emit(format!("if-goto {}", otherwise_label));
+                    self.statements(then)?;
+                    if otherwise.is_empty() {
+                        self.emit(format!("label {}", otherwise_label));
+                    } else {
+                        self.emit(format!("goto {}", end));
+                        self.emit(format!("label {}", otherwise_label));
+                        self.statements(otherwise)?;
+                        self.emit(format!("label {}", end));
+                    }
+                }
+            },
+            StatementKind::While { condition, body } => {
+                let value = decided(condition);
+                if value == Some(false) {
+                    self.note(line, format!("dropped `while ({})`", condition));
+                    return Ok(());
+                }
+                let (top, end) = (self.label("WHILE_EXP"), self.label("WHILE_END"));
+                self.emit(format!("label {}", top));
+                if value.is_some() {
+                    self.note(line, format!("dropped the test of `while ({})`", condition));
+                } else {
+                    self.expr(condition, line)?;
+                    self.emit("not".to_owned());
+                    self.emit(format!("if-goto {}", end));
+                }
+                self.statements(body)?;
+                self.emit(format!("goto {}", top));
+                if value.is_none() {
+                    self.emit(format!("label {}", end));
+                }
+            }
+            StatementKind::Do(call) => {
+                self.call(call, line)?;
+                self.emit("pop temp 0".to_owned());
+            }
+            StatementKind::Asm(code) => {
+                for (line, instruction) in code {
+                    // the same rule the VM program's loader enforces, but
+                    // pointing at the Jack
+                    if let Some(symbol) = vm::asm_symbol(instruction) {
+                        if !vm::asm_builtin(symbol) && !self.asm_labels.contains(symbol) {
+                            Err(self.error(
+                                *line,
+                                symbol,
+                                format!(
+                                    "asm can only use built-in symbols and its subroutine's \
+                                     own labels, not `{}`",
+                                    symbol
+                                ),
+                            ))?;
+                        }
+                    }
+                    self.emit(format!("asm {}", instruction));
+                }
+            }
+            StatementKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value, line)?,
+                    None => self.emit("push constant 0".to_owned()),
+                }
+                self.emit("return".to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    fn push_constant(&mut self, value: i16) {
+        match value {
+            0.. => self.emit(format!("push constant {}", value)),
+            // true, and the one number whose negation doesn't fit
+            -1 => {
+                self.emit("push constant 0".to_owned());
+                self.emit("not".to_owned());
+            }
+            i16::MIN => {
+                self.emit("push constant 32767".to_owned());
+                self.emit("not".to_owned());
+            }
+            _ => {
+                self.emit(format!("push constant {}", -value));
+                self.emit("neg".to_owned());
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, line: usize) -> Result<(), Diagnostic> {
+        if let Some(value) = constant(expr) {
+            if !literal(expr) {
+                self.note(line, format!("folded `{}` to {}", expr, value));
+            }
+            self.push_constant(value);
+            return Ok(());
+        }
+
+        match expr {
+            Expr::Str(text) => {
+                self.emit(format!("push constant {}", text.len()));
+                self.emit("call String.new 1".to_owned());
+                for c in text.chars() {
+                    self.emit(format!("push constant {}", c as u32));
+                    self.emit("call String.appendChar 2".to_owned());
+                }
+            }
+            Expr::This => {
+                if self.kind == SubroutineKind::Function {
+                    Err(self.error(line, "this", "functions have no `this`".to_owned()))?;
+                }
+                self.emit("push pointer 0".to_owned());
+            }
+            Expr::Var(name) => {
+                let variable = self.variable(name, line)?;
+                self.emit(format!("push {} {}", variable.segment, variable.index));
+            }
+            Expr::Index(name, index) => {
+                let variable = self.variable(name, line)?;
+                self.emit(format!("push {} {}", variable.segment, variable.index));
+                self.expr(index, line)?;
+                self.emit("add".to_owned());
+                self.emit("pop pointer 1".to_owned());
+                self.emit("push that 0".to_owned());
+            }
+            Expr::Call(call) => self.call(call, line)?,
+            Expr::Unary(op, term) => {
+                self.expr(term, line)?;
+                self.emit(
+                    match op {
+                        UnaryOp::Neg => "neg",
+                        UnaryOp::Not => "not",
+                    }
+                    .to_owned(),
+                );
+            }
+            Expr::Binary(left, op, right) => self.binary(expr, left, *op, right, line)?,
+            Expr::Int(_) | Expr::True | Expr::False | Expr::Null => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        expr: &Expr,
+        left: &Expr,
+        op: BinaryOp,
+        right: &Expr,
+        line: usize,
+    ) -> Result<(), Diagnostic> {
+        let (x, y) = (constant(left), constant(right));
+        // the other operand, when one of them is this constant
+        let other = |value: i16, commutes: bool| match (x, y) {
+            (_, Some(y)) if y == value => Some(left),
+            (Some(x), _) if x == value && commutes => Some(right),
+            _ => None,
+        };
+
+        let identity = match op {
+            BinaryOp::Add | BinaryOp::Or => other(0, true),
+            BinaryOp::Sub => other(0, false),
+            BinaryOp::Mul => other(1, true),
+            BinaryOp::Div => other(1, false),
+            BinaryOp::And => other(-1, true),
+            _ => None,
+        };
+        if let Some(operand) = identity {
+            self.note(line, format!("simplified `{}` to `{}`", expr, operand));
+            return self.expr(operand, line);
+        }
+
+        if op == BinaryOp::Mul {
+            if other(0, true).is_some_and(pure) {
+                self.note(line, format!("folded `{}` to 0", expr));
+                self.push_constant(0);
+                return Ok(());
+            }
+            let power =
+                |value: Option<i16>| value.filter(|value| *value > 0 && value.count_ones() == 1);
+            let doubled = match (power(x), power(y)) {
+                (_, Some(y)) => Some((left, y.trailing_zeros())),
+                (Some(x), _) => Some((right, x.trailing_zeros())),
+                _ => None,
+            };
+            if let Some((operand, shifts)) = doubled {
+                self.note(
+                    line,
+                    format!(
+                        "strength-reduced `{}` to {} doubling{}",
+                        expr,
+                        shifts,
+                        if shifts == 1 { "" } else { "s" }
+                    ),
+                );
+                return self.doubled(operand, shifts, line);
+            }
+        }
+
+        self.expr(left, line)?;
+        self.expr(right, line)?;
+        self.emit(
+            match op {
+                BinaryOp::Add => "add",
+                BinaryOp::Sub => "sub",
+                BinaryOp::Mul => "call Math.multiply 2",
+                BinaryOp::Div => "call Math.divide 2",
+                BinaryOp::And => "and",
+                BinaryOp::Or => "or",
+                BinaryOp::Lt => "lt",
+                BinaryOp::Gt => "gt",
+                BinaryOp::Eq => "eq",
+            }
+            .to_owned(),
+        );
+        Ok(())
+    }
+
+    // `operand` shifted left: each doubling adds the value to itself, via
+    // temp 0 unless it's just a variable we can push twice
+    fn doubled(&mut self, operand: &Expr, shifts: u32, line: usize) -> Result<(), Diagnostic> {
+        self.expr(operand, line)?;
+        for shift in 0..shifts {
+            if shift == 0 && matches!(operand, Expr::Var(_)) {
+                self.expr(operand, line)?;
+            } else {
+                self.emit("pop temp 0".to_owned());
+                self.emit("push temp 0".to_owned());
+                self.emit("push temp 0".to_owned());
+            }
+            self.emit("add".to_owned());
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, call: &Call, line: usize) -> Result<(), Diagnostic> {
+        let (class, method) = match &call.receiver {
+            // our own subroutines: methods get called on `this`
+            None => {
+                let own = self
+                    .class
+                    .subroutines
+                    .iter()
+                    .find(|subroutine| subroutine.name == call.name);
+                let method = own.is_none_or(|own| own.kind == SubroutineKind::Method);
+                if method {
+                    if self.kind == SubroutineKind::Function {
+                        Err(self.error(
+                            line,
+                            &call.name,
+                            format!(
+                                "functions can't call the method `{}` without an object",
+                                call.name
+                            ),
+                        ))?;
+                    }
+                    self.emit("push pointer 0".to_owned());
+                }
+                (self.class.name.clone(), method)
+            }
+            Some(receiver) => match self.lookup(receiver) {
+                Some(_) => {
+                    let variable = self.variable(receiver, line)?;
+                    let Type::Class(class) = variable.ty else {
+                        return Err(self.error(
+                            line,
+                            receiver,
+                            format!("`{}` is {}, not an object", receiver, variable.ty),
+                        ));
+                    };
+                    self.emit(format!("push {} {}", variable.segment, variable.index));
+                    (class, true)
+                }
+                None => (receiver.clone(), false),
+            },
+        };
+        for arg in &call.args {
+            self.expr(arg, line)?;
+        }
+        self.emit(format!(
+            "call {}.{} {}",
+            class,
+            call.name,
+            call.args.len() + method as usize
+        ));
+        Ok(())
+    }
+}
+
+pub fn compile(source: &str) -> Result<Compiled, Diagnostic> {
+    let class = jack::parse(source)?;
+    let mut class_vars = HashMap::new();
+    let (mut statics, mut fields) = (0, 0);
+    for (kind, var) in &class.vars {
+        let (segment, count) = match kind {
+            VarKind::Static => (Segment::Static, &mut statics),
+            VarKind::Field => (Segment::This, &mut fields),
+        };
+        Compiler::declare(&mut class_vars, var, segment, *count, source)?;
+        *count += 1;
+    }
+
+    let mut compiler = Compiler {
+        source,
+        class: &class,
+        fields,
+        class_vars,
+        vars: HashMap::new(),
+        kind: SubroutineKind::Function,
+        labels: 0,
+        asm_labels: HashSet::new(),
+        out: Compiled {
+            code: Vec::new(),
+            notes: Vec::new(),
+        },
+    };
+    for subroutine in &class.subroutines {
+        compiler.subroutine(subroutine)?;
+    }
+    Ok(compiler.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{Program, Vm};
+    use std::path::PathBuf;
+
+    // runs compiled classes until Sys.init halts, returning static 0 of the
+    // first class
+    fn run(classes: &[(&str, &str)]) -> u16 {
+        let sources: Vec<(PathBuf, String)> = classes
+            .iter()
+            .map(|(name, source)| {
+                let code = compile(source).unwrap().code;
+                (PathBuf::from(format!("{}.vm", name)), code.join("\n"))
+            })
+            .collect();
+        let mut vm = Vm::new(Program::new(&sources).unwrap());
+        while !vm.halted() && vm.cycles < 100_000 {
+            vm.step();
+        }
+        assert!(vm.halted());
+        vm.read(16)
+    }
+
+    const SYS: &str = "class Sys {
+        function void init() { do Main.main(); while (true) {} }
+    }";
+
+    #[test]
+    fn folds_constants() {
+        let source = "class Main {
+            static int x;
+            function void main() {
+                let x = 2 * 8 + 1;
+                if (1 < 2) { let x = x + 0; } else { let x = 99; }
+                while (false) { let x = 0; }
+                return;
+            }
+        }";
+        let compiled = compile(source).unwrap();
+        assert_eq!(
+            compiled.code,
+            [
+                "function Main.main 0",
+                "push constant 17",
+                "pop static 0",
+                "push static 0",
+                "pop static 0",
+                "push constant 0",
+                "return",
+            ]
+        );
+        let notes: Vec<_> = compiled
+            .notes
+            .iter()
+            .map(|(_, note)| note.as_str())
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                "folded `2 * 8 + 1` to 17",
+                "dropped the else branch of `if (1 < 2)`",
+                "simplified `x + 0` to `x`",
+                "dropped `while (false)`",
+            ]
+        );
+        assert_eq!(compiled.notes[0].0, 4);
+        assert_eq!(run(&[("Main", source), ("Sys", SYS)]), 17);
+
+        // only -1 is true once compiled, so other constants aren't folded
+        let source = "class Main {
+            static int x;
+            function void main() {
+                if (1) { let x = 5; } else { let x = 6; }
+                while (2) { let x = 0; }
+                return;
+            }
+        }";
+        assert!(compile(source).unwrap().notes.is_empty());
+        assert_eq!(run(&[("Main", source), ("Sys", SYS)]), 6);
+    }
+
+    #[test]
+    fn doubles_instead_of_multiplying() {
+        let source = "class Main {
+            static int x;
+            function void main() {
+                var int y;
+                let y = 3;
+                let x = y * 4 + (8 * (y + 1));
+                let x = x - (-32767 - 1);
+                return;
+            }
+        }";
+        let compiled = compile(source).unwrap();
+        assert!(!compiled
+            .code
+            .iter()
+            .any(|line| line.contains("Math.multiply")));
+        assert_eq!(
+            compiled.notes[0].1,
+            "strength-reduced `y * 4` to 2 doublings"
+        );
+        // 12 + 32 - -32768, wrapping around
+        assert_eq!(
+            run(&[("Main", source), ("Sys", SYS)]),
+            44u16.wrapping_add(32768)
+        );
+    }
+
+    #[test]
+    fn objects_and_arrays() {
+        let point = "class Point {
+            field int x, y;
+            constructor Point new(int ax, int ay) { let x = ax; let y = ay; return this; }
+            method int sum() { return x + y; }
+        }";
+        let main = "class Main {
+            static int result;
+            function void main() {
+                var Point p;
+                var Array a;
+                let a = 2048;
+                let p = Point.new(3, 4);
+                let a[1] = p.sum();
+                let a[a[1] - 7] = 5;
+                let result = a[1] + a[0];
+                return;
+            }
+        }";
+        // a bump allocator is all Point.new needs
+        let memory = "class Memory {
+            static int next;
+            function int alloc(int size) {
+                var int block;
+                if (next = 0) { let next = 3000; }
+                let block = next;
+                let next = next + size;
+                return block;
+            }
+        }";
+        assert_eq!(
+            run(&[
+                ("Main", main),
+                ("Memory", memory),
+                ("Point", point),
+                ("Sys", SYS)
+            ]),
+            12
+        );
+    }
+
+    #[test]
+    fn passes_asm_through() {
+        // blacken the first `n` words of the screen, with labels that
+        // translated code mustn't confuse with anyone else's, spaced out
+        // in places as the assembler allows
+        let main = "class Main {
+            function void fill(int n) {
+                asm {
+                    @ARG
+                    A=M
+                    D=M
+                    ( LOOP )
+                    @ DONE
+                    D;JEQ
+                    D=D-1
+                    @SCREEN
+                    A=D+A
+                    M=-1
+                    @LOOP
+                    0;JMP
+                }
+                asm { (DONE) }
+                return;
+            }
+        }";
+        let sys = "class Sys {
+            function void init() {
+                do Main.fill(3);
+                asm { (LOOP) }
+                while (true) {}
+            }
+        }";
+        let sources: Vec<(PathBuf, String)> = [("Main", main), ("Sys", sys)]
+            .iter()
+            .map(|(name, source)| {
+                let code = compile(source).unwrap().code;
+                (PathBuf::from(format!("{}.vm", name)), code.join("\n"))
+            })
+            .collect();
+        let program = Program::new(&sources).unwrap();
+        assert!(program.has_asm());
+        let asm = crate::translate::translate(&program, Default::default());
+        let text = asm.to_asm();
+        assert!(text.contains("\n(Main.fill$asm.LOOP)\n"));
+        assert!(text.contains("\n(Sys.init$asm.LOOP)\n"));
+
+        let binary = asm.to_hack();
+        let mut cpu = crate::emulator::Cpu::new(&crate::emulator::load(binary.as_bytes()).unwrap());
+        while !cpu.halted() && cpu.cycles < 100_000 {
+            cpu.step();
+        }
+        assert_eq!(&cpu.ram[16384..16388], [0xFFFF, 0xFFFF, 0xFFFF, 0]);
+
+        // a label in a branch that's folded away isn't there to jump to
+        let dropped = "class Main {
+            function void f() {
+                if (false) { asm { (GONE) } }
+                asm { @GONE }
+                return;
+            }
+        }";
+        assert!(compile(dropped).is_err());
+        let spaced = "class Main { function void f() { asm { @ x } return; } }";
+        assert!(compile(spaced).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_classes() {
+        let err = |source: &str| compile(source).unwrap_err();
+        let undefined = err("class Main {\n function void f() {\n  let y = 1;\n }\n}");
+        assert_eq!(undefined.message, "no variable called `y`");
+        assert_eq!((undefined.line, undefined.span.clone()), (3, 6..7));
+        assert_eq!(
+            err("class Main { field int x; function int f() { return x; } }").message,
+            "functions can't use the field `x`"
+        );
+        assert_eq!(
+            err("class Main { function void f() { var int n; do n.g(); return; } }").message,
+            "`n` is int, not an object"
+        );
+        assert_eq!(
+            err("class Main { function void f(int a, int a) { return; } }").message,
+            "`a` is already declared"
+        );
+        let asm = err("class Main {\n function void f() {\n  asm {\n   @x\n  }\n }\n}");
+        assert_eq!(
+            asm.message,
+            "asm can only use built-in symbols and its subroutine's own labels, not `x`"
+        );
+        assert_eq!((asm.line, asm.span.clone()), (4, 4..5));
+    }
+}
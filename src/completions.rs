@@ -0,0 +1,275 @@
+use std::fmt::Write as _;
+
+use crate::cli::{Command, Flag, BINARY, COMMANDS};
+
+// shell completions and the man page, written from the same tables as
+// `--help` so that they can't fall behind it
+
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+pub fn completions(shell: &str) -> Option<String> {
+    match shell {
+        "bash" => Some(bash()),
+        "zsh" => Some(zsh()),
+        "fish" => Some(fish()),
+        _ => None,
+    }
+}
+
+// whether a flag's value is a path, and so worth completing as one
+fn takes_path(flag: &Flag) -> bool {
+    matches!(flag.value, Some("FILE" | "DIR"))
+}
+
+fn spellings(flag: &Flag) -> Vec<String> {
+    let mut spellings = vec![format!("--{}", flag.long)];
+    if let Some(short) = flag.short {
+        spellings.push(format!("-{}", short));
+    }
+    spellings
+}
+
+fn bash() -> String {
+    let names: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+    let mut out = format!(
+        "_{bin}() {{\n    \
+         local cur=${{COMP_WORDS[COMP_CWORD]}} prev=${{COMP_WORDS[COMP_CWORD-1]}}\n    \
+         local command={default} flags paths\n    \
+         case ${{COMP_WORDS[1]}} in\n        \
+         {names}) command=${{COMP_WORDS[1]}} ;;\n    \
+         esac\n    \
+         case $command in\n",
+        bin = BINARY,
+        default = COMMANDS[0].name,
+        names = names.join("|"),
+    );
+    for command in COMMANDS {
+        let flags: Vec<String> = command.flags.iter().flat_map(spellings).collect();
+        let paths: Vec<String> = command
+            .flags
+            .iter()
+            .filter(|flag| takes_path(flag))
+            .flat_map(spellings)
+            .collect();
+        let _ = writeln!(
+            out,
+            "        {}) flags=\"{}\" paths=\"{}\" ;;",
+            command.name,
+            flags.join(" "),
+            paths.join(" ")
+        );
+    }
+    let _ = write!(
+        out,
+        "    esac\n    \
+         if [[ \" $paths \" == *\" $prev \"* ]]; then\n        \
+         COMPREPLY=($(compgen -f -- \"$cur\"))\n    \
+         elif [[ $cur == -* ]]; then\n        \
+         COMPREPLY=($(compgen -W \"$flags\" -- \"$cur\"))\n    \
+         elif ((COMP_CWORD == 1)); then\n        \
+         COMPREPLY=($(compgen -W \"{names}\" -- \"$cur\") $(compgen -f -- \"$cur\"))\n    \
+         else\n        \
+         COMPREPLY=($(compgen -f -- \"$cur\"))\n    \
+         fi\n\
+         }}\n\
+         complete -o filenames -F _{bin} {bin}\n",
+        names = names.join(" "),
+        bin = BINARY,
+    );
+    out
+}
+
+// text for inside single quotes, in a zsh `_arguments` description
+fn zsh_quote(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+fn zsh_arguments(command: &Command) -> String {
+    let mut specs = Vec::new();
+    for flag in command.flags {
+        let value = match flag.value {
+            Some(name) if takes_path(flag) => format!(":{}:_files", name),
+            Some(name) => format!(":{}: ", name),
+            None => String::new(),
+        };
+        let help = zsh_quote(flag.help);
+        match flag.short {
+            Some(short) => specs.push(format!(
+                "'(-{short} --{long})'{{-{short},--{long}}}'[{help}]{value}'",
+                short = short,
+                long = flag.long,
+                help = help,
+                value = value
+            )),
+            None => specs.push(format!("'--{}[{}]{}'", flag.long, help, value)),
+        }
+    }
+    if !command.args.is_empty() {
+        specs.push("'*:file:_files'".to_owned());
+    }
+    format!("_arguments -s {}", specs.join(" \\\n                "))
+}
+
+fn zsh() -> String {
+    let mut out = format!(
+        "#compdef {bin}\n\n_{bin}() {{\n    local -a commands\n    commands=(\n",
+        bin = BINARY
+    );
+    for command in COMMANDS {
+        let _ = writeln!(
+            out,
+            "        '{}:{}'",
+            command.name,
+            zsh_quote(command.about)
+        );
+    }
+    out.push_str(
+        "    )\n    \
+         if ((CURRENT == 2)) && [[ $words[2] != -* ]]; then\n        \
+         _describe command commands\n    \
+         fi\n    \
+         case $words[2] in\n",
+    );
+    for command in &COMMANDS[1..] {
+        let _ = writeln!(
+            out,
+            "        {})\n            \
+             shift words\n            \
+             ((CURRENT--))\n            \
+             {} ;;",
+            command.name,
+            zsh_arguments(command)
+        );
+    }
+    let _ = write!(
+        out,
+        "        *)\n            {} ;;\n    esac\n}}\n\n_{bin} \"$@\"\n",
+        zsh_arguments(&COMMANDS[0]),
+        bin = BINARY
+    );
+    out
+}
+
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish() -> String {
+    let mut out = String::new();
+    for command in COMMANDS {
+        let _ = writeln!(
+            out,
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}",
+            BINARY,
+            command.name,
+            fish_quote(command.about)
+        );
+    }
+    for command in COMMANDS {
+        // the default command's flags work without naming it
+        let condition = if command.name == COMMANDS[0].name {
+            format!(
+                "'__fish_use_subcommand; or __fish_seen_subcommand_from {}'",
+                command.name
+            )
+        } else {
+            format!("'__fish_seen_subcommand_from {}'", command.name)
+        };
+        for flag in command.flags {
+            let _ = write!(
+                out,
+                "complete -c {} -n {} -l {}",
+                BINARY, condition, flag.long
+            );
+            if let Some(short) = flag.short {
+                let _ = write!(out, " -s {}", short);
+            }
+            match flag.value {
+                Some(_) if takes_path(flag) => out.push_str(" -r -F"),
+                Some(_) => out.push_str(" -r -f"),
+                None => {}
+            }
+            let _ = writeln!(out, " -d {}", fish_quote(flag.help));
+        }
+    }
+    let _ = writeln!(
+        out,
+        "complete -c {} -n '__fish_seen_subcommand_from completions' -f -a '{}'",
+        BINARY,
+        SHELLS.join(" ")
+    );
+    out
+}
+
+// text for a roff page, which would take a `-` for a hyphen rather than a
+// minus, and a line starting with `.` or `'` for a request
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+pub fn man() -> String {
+    let mut out = format!(
+        ".TH {upper} 1\n\
+         .SH NAME\n\
+         {bin} \\- an assembler, VM translator, Jack compiler and emulator for the Hack computer\n\
+         .SH SYNOPSIS\n\
+         .B {bin}\n\
+         [\\fICOMMAND\\fR] [\\fIOPTIONS\\fR] [\\fIARGS\\fR]\n\
+         .SH DESCRIPTION\n\
+         Without a command, \\fB{bin}\\fR runs \\fB{default}\\fR.\n\
+         .SH COMMANDS\n",
+        upper = BINARY.to_uppercase(),
+        bin = BINARY,
+        default = COMMANDS[0].name,
+    );
+    for command in COMMANDS {
+        let _ = writeln!(
+            out,
+            ".SS {} {}\n{}",
+            command.name,
+            roff(command.args),
+            roff(command.about)
+        );
+        for flag in command.flags {
+            let mut spec = spellings(flag)
+                .iter()
+                .rev()
+                .map(|spelling| format!("\\fB{}\\fR", roff(spelling)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            if let Some(value) = flag.value {
+                let _ = write!(spec, " \\fI{}\\fR", value);
+            }
+            let _ = writeln!(out, ".TP\n{}\n{}", spec, roff(flag.help));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cover_every_command_and_flag() {
+        for text in SHELLS
+            .iter()
+            .map(|shell| completions(shell).unwrap())
+            .chain([man()])
+        {
+            for command in COMMANDS {
+                assert!(text.contains(command.name), "{}", command.name);
+            }
+            assert!(text.contains("relaxed") && text.contains("jobs"));
+        }
+        assert!(completions("powershell").is_none());
+        assert!(man().contains(".TP\n\\fB\\-j\\fR, \\fB\\-\\-jobs\\fR \\fIN\\fR\n"));
+    }
+}
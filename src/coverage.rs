@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+
+use crate::disassemble::describe;
+use crate::sourcemap::SourceMap;
+
+// how a never-executed line is marked in listings
+const UNEXECUTED: &str = "#####";
+
+// how many of the program's instructions ran at least once, given execution
+// counts indexed by PC
+pub fn covered(counts: &[u64], program_length: usize) -> usize {
+    counts
+        .iter()
+        .take(program_length)
+        .filter(|count| **count > 0)
+        .count()
+}
+
+pub fn summary(counts: &[u64], program_length: usize) -> String {
+    let covered = covered(counts, program_length);
+    let percent = if program_length == 0 {
+        100.0
+    } else {
+        covered as f64 / program_length as f64 * 100.0
+    };
+    format!(
+        "coverage: {} of {} instructions executed ({:.1}%)",
+        covered, program_length, percent
+    )
+}
+
+fn mark(count: Option<u64>) -> String {
+    match count {
+        Some(0) => UNEXECUTED.to_owned(),
+        Some(count) => count.to_string(),
+        None => "-".to_owned(),
+    }
+}
+
+// the program's source with every line prefixed by how many times it ran,
+// in the style of gcov: `#####` for code that never ran, and `-` for lines
+// with no code. Without a source map, we list the disassembled ROM instead.
+// `read` fetches a source file by the name the map gives it
+pub fn listing(
+    counts: &[u64],
+    rom: &[u16],
+    program_length: usize,
+    map: Option<&SourceMap>,
+    read: impl Fn(&str) -> io::Result<String>,
+) -> io::Result<String> {
+    let count = |pc: usize| counts.get(pc).copied().unwrap_or(0);
+    let mut out = String::new();
+
+    let Some(map) = map else {
+        for (pc, word) in rom.iter().enumerate().take(program_length) {
+            let _ = writeln!(
+                out,
+                "{:>9} | {:>5}: {}",
+                mark(Some(count(pc))),
+                pc,
+                describe(*word)
+            );
+        }
+        return Ok(out);
+    };
+
+    // per file, the count of each line that has code on it; lines with
+    // several instructions report the busiest
+    let mut files: BTreeMap<&str, BTreeMap<usize, u64>> = BTreeMap::new();
+    for (pc, location) in map.locations.iter().enumerate() {
+        let line = files
+            .entry(&location.file)
+            .or_default()
+            .entry(location.line)
+            .or_default();
+        *line = (*line).max(count(pc));
+    }
+
+    for (file, lines) in files {
+        let _ = writeln!(out, "-- {}", file);
+        for (number, text) in (1..).zip(read(file)?.lines()) {
+            let _ = writeln!(out, "{:>9} | {}", mark(lines.get(&number).copied()), text);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_source;
+
+    #[test]
+    fn marks_unexecuted_lines() {
+        let source = "@i\nD=M\n@END\nD;JGT\n// skipped\n@i\nM=1\n(END)\n@END\n0;JMP\n";
+        let map = SourceMap::new("Prog.asm", &parse_source(source).unwrap());
+        let counts = [1, 1, 1, 1, 0, 0, 5, 5];
+        assert_eq!(covered(&counts, 8), 6);
+        assert_eq!(
+            summary(&counts, 8),
+            "coverage: 6 of 8 instructions executed (75.0%)"
+        );
+
+        let listing = listing(&counts, &[], 8, Some(&map), |_| Ok(source.to_owned())).unwrap();
+        let expected = "\
+-- Prog.asm
+        1 | @i
+        1 | D=M
+        1 | @END
+        1 | D;JGT
+        - | // skipped
+    ##### | @i
+    ##### | M=1
+        - | (END)
+        5 | @END
+        5 | 0;JMP
+";
+        assert_eq!(listing, expected);
+    }
+}
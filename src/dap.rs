@@ -0,0 +1,802 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use crate::backtrace::Functions;
+use crate::debug::{parse_value, Symbols};
+use crate::emulator::{self, Cpu, Journal};
+use crate::error::HackError;
+use crate::json::Json;
+use crate::lsp::{read_message, write_message};
+use crate::os;
+use crate::sourcemap::SourceMap;
+use crate::PREDEFINED_SYMBOLS;
+use crate::{translate, vm};
+
+// a debug adapter, so that editors' debuggers can drive the emulator: it
+// speaks the Debug Adapter Protocol over stdin and stdout, with the same
+// framing as the language server. Lines are 1-based, the protocol's default
+
+// how many instructions we run between checking for requests, e.g. to pause
+const CHUNK: u64 = 10_000;
+// how many instructions we can step back through
+const JOURNAL_LENGTH: usize = 100_000;
+
+// the scopes a stopped program's variables come in
+const REGISTERS: u64 = 1;
+const VARIABLES: u64 = 2;
+const POINTERS: u64 = 3;
+
+struct Session {
+    cpu: Cpu,
+    map: Option<SourceMap>,
+    symbols: Symbols,
+    functions: Functions,
+    // the program's file, for naming it
+    name: String,
+}
+
+impl Session {
+    // the calls in progress, innermost first; if the program doesn't follow
+    // the VM's conventions, just the label we're under
+    fn frames(&self) -> Vec<String> {
+        let mut frames = self
+            .functions
+            .backtrace(|address| self.cpu.read(address), self.cpu.pc);
+        frames.reverse();
+        if frames.is_empty() {
+            let label = self
+                .symbols
+                .labels
+                .iter()
+                .filter(|(_, address)| **address <= self.cpu.pc)
+                .max_by_key(|(name, address)| (**address, std::cmp::Reverse(*name)));
+            frames.push(label.map_or_else(|| self.name.clone(), |(name, _)| name.clone()));
+        }
+        frames
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Running {
+    Continue,
+    // until we've returned from a frame at this depth
+    StepOut(usize),
+}
+
+#[derive(Default)]
+pub struct Adapter {
+    seq: u64,
+    session: Option<Session>,
+    stop_on_entry: bool,
+    // whether the client has sent its breakpoints yet
+    configured: bool,
+    // ROM addresses to stop at, by source file and by function name
+    lines: HashMap<String, Vec<u16>>,
+    functions: Vec<u16>,
+    running: Option<Running>,
+    done: bool,
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    a == b
+        || match (fs::canonicalize(a), fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+}
+
+fn variable(name: &str, value: u16) -> Json {
+    Json::object([
+        ("name", name.into()),
+        ("value", (value as i16).to_string().into()),
+        ("variablesReference", 0u64.into()),
+    ])
+}
+
+// a VM program, translated to run on the CPU like any other. There's no
+// source map back to the VM code, but its functions are all labelled
+fn translated(path: &Path) -> Result<crate::LoadedProgram, HackError> {
+    let program = vm::load(path)?;
+    if let Some(line) = program.builtins().next() {
+        Err(HackError::Usage(format!(
+            "{} needs the OS's own code to be debugged, not the emulator's",
+            line.command
+        )))?;
+    }
+    let asm = translate::translate(&program, translate::Options::default());
+    let rom = emulator::load(asm.to_hack().as_bytes()).map_err(|err| HackError::new(path, err))?;
+    let code: Vec<_> = asm.code().cloned().collect();
+    Ok(crate::LoadedProgram {
+        cpu: Cpu::new(&rom),
+        map: None,
+        symbols: crate::symbols(&code),
+    })
+}
+
+impl Adapter {
+    fn send(&mut self, mut fields: Vec<(&str, Json)>, out: &mut impl Write) -> io::Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq", self.seq.into()));
+        write_message(out, &Json::object(fields))
+    }
+
+    fn event(&mut self, event: &str, body: Json, out: &mut impl Write) -> io::Result<()> {
+        self.send(
+            vec![
+                ("type", "event".into()),
+                ("event", event.into()),
+                ("body", body),
+            ],
+            out,
+        )
+    }
+
+    fn stopped(&mut self, reason: &str, out: &mut impl Write) -> io::Result<()> {
+        self.running = None;
+        let body = Json::object([
+            ("reason", reason.into()),
+            ("threadId", 1u64.into()),
+            ("allThreadsStopped", true.into()),
+        ]);
+        self.event("stopped", body, out)
+    }
+
+    // reports a program that can't go any further, if this one can't
+    fn ended(&mut self, out: &mut impl Write) -> io::Result<bool> {
+        let Some(session) = &self.session else {
+            return Ok(false);
+        };
+        let cpu = &session.cpu;
+        let how = if cpu.finished() {
+            "finished"
+        } else if cpu.halted() {
+            "halted"
+        } else {
+            return Ok(false);
+        };
+        let message = format!("the program has {} after {} cycles\n", how, cpu.cycles);
+        self.running = None;
+        let console = Json::object([("category", "console".into()), ("output", message.into())]);
+        self.event("output", console, out)?;
+        self.event("exited", Json::object([("exitCode", 0u64.into())]), out)?;
+        self.event("terminated", Json::object([]), out)?;
+        Ok(true)
+    }
+
+    fn breakpoint(&self, pc: u16) -> bool {
+        self.functions.contains(&pc) || self.lines.values().any(|lines| lines.contains(&pc))
+    }
+
+    // runs the program on for a while, if it's running, saying so if it
+    // stops
+    pub fn advance(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let Some(running) = self.running else {
+            return Ok(());
+        };
+        for _ in 0..CHUNK {
+            if self.ended(out)? {
+                return Ok(());
+            }
+            let Some(session) = &mut self.session else {
+                return Ok(());
+            };
+            session.cpu.step();
+            let pc = session.cpu.pc;
+            if self.breakpoint(pc) {
+                return self.stopped("breakpoint", out);
+            }
+            if let Running::StepOut(depth) = running {
+                let session = self.session.as_ref().expect("we just stepped it");
+                if session.frames().len() < depth {
+                    return self.stopped("step", out);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // starts the program once it's both launched and has its breakpoints
+    fn start(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.session.is_none() || !self.configured {
+            return Ok(());
+        }
+        if self.stop_on_entry {
+            self.stopped("entry", out)
+        } else {
+            self.running = Some(Running::Continue);
+            Ok(())
+        }
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| "no program has been launched".to_owned())
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        let program = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch needs the `program` to debug")?;
+        let path = fs::canonicalize(program).map_err(|err| format!("{}: {}", program, err))?;
+        let render = |err: HackError| err.render(false).trim_end().to_owned();
+        let mut loaded = if vm::is_vm(&path) {
+            translated(&path).map_err(render)?
+        } else {
+            crate::load_program(&path).map_err(render)?
+        };
+        let flag = |name: &str| arguments.get(name).and_then(Json::as_bool) == Some(true);
+        if flag("builtins") {
+            loaded.cpu.traps = Some(os::Traps::new(&loaded.symbols));
+        }
+        self.stop_on_entry = flag("stopOnEntry");
+        loaded.cpu.journal = Journal::new(JOURNAL_LENGTH);
+        self.session = Some(Session {
+            cpu: loaded.cpu,
+            map: loaded.map,
+            functions: Functions::new(&loaded.symbols),
+            symbols: loaded.symbols,
+            name: path
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        });
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let file = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .ok_or("setBreakpoints needs the source's `path`")?
+            .to_owned();
+        let requested: Vec<usize> = arguments
+            .get("breakpoints")
+            .map_or(&[][..], Json::as_array)
+            .iter()
+            .filter_map(|breakpoint| Some(breakpoint.get("line")?.as_f64()? as usize))
+            .collect();
+        let locations = self
+            .session
+            .as_ref()
+            .and_then(|session| session.map.as_ref())
+            .map_or(&[][..], |map| &map.locations[..]);
+        let mut files: HashMap<&str, bool> = HashMap::new();
+        let mut addresses = Vec::new();
+        let breakpoints = requested
+            .iter()
+            .map(|&line| {
+                // the first instruction at or after the line
+                let found = locations
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, location)| {
+                        *files
+                            .entry(&location.file)
+                            .or_insert_with(|| same_file(&location.file, &file))
+                    })
+                    .filter(|(_, location)| location.line >= line)
+                    .min_by_key(|(address, location)| (location.line, *address));
+                match found {
+                    Some((address, location)) => {
+                        addresses.push(address as u16);
+                        Json::object([("verified", true.into()), ("line", location.line.into())])
+                    }
+                    None => Json::object([
+                        ("verified", false.into()),
+                        ("line", line.into()),
+                        ("message", "there's no code here".into()),
+                    ]),
+                }
+            })
+            .collect();
+        self.lines.insert(file, addresses);
+        Ok(Json::object([("breakpoints", breakpoints)]))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let mut addresses = Vec::new();
+        let breakpoints = arguments
+            .get("breakpoints")
+            .map_or(&[][..], Json::as_array)
+            .iter()
+            .map(|breakpoint| {
+                let name = breakpoint.get("name").and_then(Json::as_str).unwrap_or("");
+                match session.symbols.labels.get(name) {
+                    Some(&address) => {
+                        addresses.push(address);
+                        Json::object([("verified", true.into())])
+                    }
+                    None => Json::object([
+                        ("verified", false.into()),
+                        ("message", format!("no label called `{}`", name).into()),
+                    ]),
+                }
+            })
+            .collect();
+        self.functions = addresses;
+        Ok(Json::object([("breakpoints", breakpoints)]))
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let session = self.session()?;
+        let location = session.map.as_ref().and_then(|map| map.get(session.cpu.pc));
+        let frames: Json = session
+            .frames()
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| {
+                let mut frame = vec![
+                    ("id", id.into()),
+                    ("name", name.into()),
+                    ("line", 0u64.into()),
+                    ("column", 0u64.into()),
+                ];
+                // we only know where the innermost frame is
+                if let Some(location) = location.filter(|_| id == 0) {
+                    let file = Path::new(&location.file);
+                    let name = file.file_name().map(|name| name.to_string_lossy());
+                    frame[2].1 = location.line.into();
+                    frame[3].1 = 1u64.into();
+                    frame.push((
+                        "source",
+                        Json::object([
+                            ("name", name.unwrap_or_default().into_owned().into()),
+                            ("path", location.file.clone().into()),
+                        ]),
+                    ));
+                }
+                if id == 0 {
+                    frame.push((
+                        "instructionPointerReference",
+                        session.cpu.pc.to_string().into(),
+                    ));
+                }
+                Json::object(frame)
+            })
+            .collect();
+        let total = frames.as_array().len();
+        Ok(Json::object([
+            ("stackFrames", frames),
+            ("totalFrames", total.into()),
+        ]))
+    }
+
+    fn variables(&mut self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let cpu = &session.cpu;
+        let reference = arguments
+            .get("variablesReference")
+            .and_then(Json::as_f64)
+            .unwrap_or_default() as u64;
+        let variables: Json = match reference {
+            REGISTERS => vec![
+                variable("A", cpu.a),
+                variable("D", cpu.d),
+                variable("PC", cpu.pc),
+                Json::object([
+                    ("name", "cycles".into()),
+                    ("value", cpu.cycles.to_string().into()),
+                    ("variablesReference", 0u64.into()),
+                ]),
+            ]
+            .into_iter()
+            .collect(),
+            VARIABLES => {
+                let mut variables: Vec<_> = session.symbols.variables.iter().collect();
+                variables.sort_by_key(|(name, address)| (**address, *name));
+                variables
+                    .into_iter()
+                    .map(|(name, address)| variable(name, cpu.read(*address)))
+                    .collect()
+            }
+            POINTERS => PREDEFINED_SYMBOLS[..5]
+                .iter()
+                .map(|(name, address)| variable(name, cpu.read(*address)))
+                .collect(),
+            _ => Json::Array(Vec::new()),
+        };
+        Ok(Json::object([("variables", variables)]))
+    }
+
+    fn set_variable(&mut self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let name = arguments.get("name").and_then(Json::as_str).unwrap_or("");
+        let value = arguments.get("value").and_then(Json::as_str).unwrap_or("");
+        let value = parse_value(value.trim())?;
+        let cpu = &mut session.cpu;
+        match name {
+            "A" => cpu.a = value,
+            "D" => cpu.d = value,
+            "PC" => cpu.pc = value,
+            _ => {
+                let address = session.symbols.ram(name)?;
+                cpu.write(address, value);
+            }
+        }
+        Ok(Json::object([("value", (value as i16).to_string().into())]))
+    }
+
+    // a register, or a word of RAM by address or name
+    fn evaluate(&mut self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        let cpu = &session.cpu;
+        let expression = arguments
+            .get("expression")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .trim();
+        let value = match expression {
+            "A" => cpu.a,
+            "D" => cpu.d,
+            "PC" => cpu.pc,
+            _ => {
+                let address = session.symbols.ram(expression)?;
+                cpu.read(address)
+            }
+        };
+        Ok(Json::object([
+            ("result", (value as i16).to_string().into()),
+            ("variablesReference", 0u64.into()),
+        ]))
+    }
+
+    // answers a request; anything it sets in motion happens once we've
+    // responded
+    fn request(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => Ok(Json::object([
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsFunctionBreakpoints", true.into()),
+                ("supportsStepBack", true.into()),
+                ("supportsSetVariable", true.into()),
+                ("supportsTerminateRequest", true.into()),
+            ])),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setExceptionBreakpoints" => {
+                Ok(Json::object([("breakpoints", Json::Array(Vec::new()))]))
+            }
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::Array(vec![Json::object([
+                    ("id", 1u64.into()),
+                    ("name", "main".into()),
+                ])]),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Json::object([(
+                "scopes",
+                [
+                    ("Registers", REGISTERS),
+                    ("Variables", VARIABLES),
+                    ("Pointers", POINTERS),
+                ]
+                .into_iter()
+                .map(|(name, reference)| {
+                    Json::object([
+                        ("name", name.into()),
+                        ("variablesReference", reference.into()),
+                        ("expensive", false.into()),
+                    ])
+                })
+                .collect(),
+            )])),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                self.session()?;
+                self.running = Some(Running::Continue);
+                Ok(Json::object([("allThreadsContinued", true.into())]))
+            }
+            "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" | "pause" => {
+                self.session()?;
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Json::Null)
+            }
+            _ => Err(format!("unsupported request {}", command)),
+        }
+    }
+
+    // moves the program along for a stepping request, once it's been
+    // acknowledged
+    fn step(&mut self, command: &str, out: &mut impl Write) -> io::Result<()> {
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        match command {
+            "next" | "stepIn" => {
+                session.cpu.step();
+                if !self.ended(out)? {
+                    self.stopped("step", out)?;
+                }
+            }
+            "stepOut" => self.running = Some(Running::StepOut(session.frames().len())),
+            "stepBack" => {
+                session.cpu.back();
+                self.stopped("step", out)?;
+            }
+            "reverseContinue" => {
+                let mut reason = "step";
+                while let Some(undo) = session.cpu.back() {
+                    if self.functions.contains(&undo.pc)
+                        || self.lines.values().any(|lines| lines.contains(&undo.pc))
+                    {
+                        reason = "breakpoint";
+                        break;
+                    }
+                }
+                self.stopped(reason, out)?;
+            }
+            "pause" if self.running.is_some() => self.stopped("pause", out)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // handles one message from the client
+    pub fn handle(&mut self, message: &Json, out: &mut impl Write) -> io::Result<()> {
+        if message.get("type").and_then(Json::as_str) != Some("request") {
+            return Ok(());
+        }
+        let command = message.get("command").and_then(Json::as_str).unwrap_or("");
+        let arguments = message.get("arguments").cloned().unwrap_or(Json::Null);
+        let result = self.request(command, &arguments);
+        let succeeded = result.is_ok();
+        let mut fields = vec![
+            ("type", "response".into()),
+            (
+                "request_seq",
+                message.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", succeeded.into()),
+            ("command", command.into()),
+        ];
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => fields.push(("body", body)),
+            Err(error) => fields.push(("message", error.into())),
+        }
+        self.send(fields, out)?;
+        if !succeeded {
+            return Ok(());
+        }
+        match command {
+            "initialize" => self.event("initialized", Json::object([]), out),
+            "launch" | "configurationDone" => self.start(out),
+            _ => self.step(command, out),
+        }
+    }
+}
+
+pub fn serve(input: impl BufRead + Send + 'static, mut out: impl Write) -> io::Result<()> {
+    // read requests as they come, so that we can hear `pause` while the
+    // program's running
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        loop {
+            let message = read_message(&mut input);
+            let last = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || last {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter::default();
+    while !adapter.done {
+        let message = if adapter.running.is_some() {
+            match receiver.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => {
+                    adapter.advance(&mut out)?;
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            }
+        };
+        match message? {
+            Some(message) => adapter.handle(&message, &mut out)?,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs a session one request at a time, as a client waiting on each
+    // would, returning what the adapter said
+    fn session(requests: &[(&str, &str)]) -> Vec<Json> {
+        let mut adapter = Adapter::default();
+        let mut out = Vec::new();
+        for (seq, (command, arguments)) in requests.iter().enumerate() {
+            let request = format!(
+                r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+                seq + 1,
+                command,
+                arguments
+            );
+            adapter
+                .handle(&Json::parse(&request).unwrap(), &mut out)
+                .unwrap();
+            while adapter.running.is_some() {
+                adapter.advance(&mut out).unwrap();
+            }
+        }
+        let mut messages = Vec::new();
+        let mut out = &out[..];
+        while let Some(message) = read_message(&mut out).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn find<'a>(messages: &'a [Json], kind: &str, name: &str) -> Vec<&'a Json> {
+        messages
+            .iter()
+            .filter(|message| {
+                let key = if kind == "event" { "event" } else { "command" };
+                message.get("type").and_then(Json::as_str) == Some(kind)
+                    && message.get(key).and_then(Json::as_str) == Some(name)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let dir = std::env::temp_dir().join(format!("hack-dap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Count.asm");
+        fs::write(
+            &path,
+            "@i\nM=0\n(LOOP)\n@i\nM=M+1\nD=M\n@3\nD=D-A\n@LOOP\nD;JLT\n(END)\n@END\n0;JMP\n",
+        )
+        .unwrap();
+        let program = path.to_string_lossy().replace('\\', "\\\\");
+        let launch = format!(r#"{{"program":"{}"}}"#, program);
+        let breakpoints = format!(
+            r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":5}},{{"line":30}}]}}"#,
+            program
+        );
+        let messages = session(&[
+            ("initialize", "{}"),
+            ("launch", &launch),
+            ("setBreakpoints", &breakpoints),
+            ("configurationDone", "{}"),
+            ("evaluate", r#"{"expression":"i"}"#),
+            ("stackTrace", "{}"),
+            ("continue", "{}"),
+            ("evaluate", r#"{"expression":"i"}"#),
+            ("next", "{}"),
+            ("evaluate", r#"{"expression":"i"}"#),
+            ("stepBack", "{}"),
+            ("evaluate", r#"{"expression":"i"}"#),
+            (
+                "setBreakpoints",
+                &format!(r#"{{"source":{{"path":"{}"}},"breakpoints":[]}}"#, program),
+            ),
+            ("continue", "{}"),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let set = find(&messages, "response", "setBreakpoints");
+        assert_eq!(
+            set[0].get("body").unwrap().to_string(),
+            r#"{"breakpoints":[{"verified":true,"line":5},{"verified":false,"line":30,"message":"there's no code here"}]}"#
+        );
+        let values: Vec<_> = find(&messages, "response", "evaluate")
+            .iter()
+            .map(|response| {
+                response
+                    .get("body")
+                    .unwrap()
+                    .get("result")
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(values, ["0", "1", "2", "1"]);
+        let frames = find(&messages, "response", "stackTrace")[0]
+            .get("body")
+            .unwrap()
+            .get("stackFrames")
+            .unwrap();
+        assert_eq!(
+            frames.as_array()[0].get("name").and_then(Json::as_str),
+            Some("LOOP")
+        );
+        assert_eq!(
+            frames.as_array()[0].get("line").and_then(Json::as_f64),
+            Some(5.0)
+        );
+        let reasons: Vec<_> = find(&messages, "event", "stopped")
+            .iter()
+            .map(|event| {
+                event
+                    .get("body")
+                    .unwrap()
+                    .get("reason")
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(reasons, ["breakpoint", "breakpoint", "step", "step"]);
+        assert_eq!(find(&messages, "event", "terminated").len(), 1);
+    }
+
+    #[test]
+    fn debugs_translated_vm_programs() {
+        let dir = std::env::temp_dir().join(format!("hack-dap-vm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Sys.vm");
+        fs::write(
+            &path,
+            "function Sys.init 0\npush constant 2\ncall Main.double 1\npop temp 0\n\
+             label END\ngoto END\n\
+             function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn\n",
+        )
+        .unwrap();
+        let launch = format!(
+            r#"{{"program":"{}"}}"#,
+            path.to_string_lossy().replace('\\', "\\\\")
+        );
+        let messages = session(&[
+            ("initialize", "{}"),
+            ("launch", &launch),
+            (
+                "setFunctionBreakpoints",
+                r#"{"breakpoints":[{"name":"Main.double"}]}"#,
+            ),
+            ("configurationDone", "{}"),
+            ("stackTrace", "{}"),
+            ("continue", "{}"),
+            ("evaluate", r#"{"expression":"R5"}"#),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let frames = find(&messages, "response", "stackTrace")[0]
+            .get("body")
+            .unwrap()
+            .get("stackFrames")
+            .unwrap();
+        let names: Vec<_> = frames
+            .as_array()
+            .iter()
+            .filter_map(|frame| frame.get("name").and_then(Json::as_str))
+            .collect();
+        assert_eq!(names[..2], ["Main.double", "Sys.init"]);
+        assert_eq!(find(&messages, "event", "terminated").len(), 1);
+        let result = find(&messages, "response", "evaluate")[0]
+            .get("body")
+            .unwrap();
+        assert_eq!(result.get("result").and_then(Json::as_str), Some("4"));
+    }
+}
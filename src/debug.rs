@@ -0,0 +1,644 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use crate::backtrace::{self, Functions};
+use crate::diagnostic::Diagnostic;
+use crate::disassemble::describe;
+use crate::emulator::{Access, Cpu, Journal, RAM_SIZE};
+use crate::image;
+use crate::run::Throttle;
+use crate::screen;
+use crate::snapshot;
+use crate::sourcemap::SourceMap;
+use crate::PREDEFINED_SYMBOLS;
+
+// how long `continue` runs without being told otherwise
+const DEFAULT_CONTINUE: u64 = 1_000_000;
+// how many instructions we can step back through
+const JOURNAL_LENGTH: usize = 100_000;
+
+const HELP: &str = "\
+commands:
+  s, step [N]           execute N instructions (default 1)
+  c, continue [N]       run until a breakpoint, watchpoint, or the end of the
+                        program, or for at most N cycles
+  back [N]              undo the last N instructions (default 1)
+  rewind                undo as many instructions as we remember
+  last ADDR             show which instruction last wrote a RAM address
+  speed [HZ|max]        show or limit how many instructions `continue` runs
+                        a second
+  r, regs               show A, D, PC and the cycle count
+  set A|D|PC VALUE      change a register
+  x, ram ADDR [COUNT]   show COUNT words of RAM starting at ADDR
+  poke ADDR VALUE       change a word of RAM
+  b, break LOCATION     stop when PC reaches a ROM address or label
+  watch ADDR [r|w|rw]   stop when a RAM address is read and/or written
+  info                  list breakpoints and watchpoints
+  delete N              remove breakpoint or watchpoint number N
+  screen                draw the screen
+  png FILE              save the screen as a PNG
+  save FILE             save a snapshot of the machine
+  load FILE             restore a snapshot saved earlier
+  bt, backtrace         show the functions being called, if the program
+                        follows the VM calling convention
+  l, list               show the current instruction and its source
+  h, help               show this message
+  q, quit               leave the debugger
+values may be decimal (including negative), 0x-prefixed hex, or 0b-prefixed binary;
+addresses may also be symbols: labels for ROM, variables or predefined symbols for RAM";
+
+// the names a program's addresses had before it was assembled
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    // ROM addresses
+    pub labels: HashMap<String, u16>,
+    // RAM addresses
+    pub variables: HashMap<String, u16>,
+}
+
+impl Symbols {
+    // a `.sym` file: a symbol and its address on each line, with labels in
+    // parentheses as they're written in assembly, as in
+    //
+    //     (LOOP) 10
+    //     counter 16
+    pub fn parse(text: &str) -> Result<Self, Diagnostic> {
+        let mut symbols = Symbols::default();
+        for (number, text) in (1..).zip(crate::strip_bom(text).lines()) {
+            let (code, _) = crate::split_comment(text);
+            let error = |message: String| {
+                Diagnostic::error(message)
+                    .at(text, code.trim())
+                    .on_line(number, text)
+            };
+            let (name, address) = match code.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => continue,
+                [name, address] => (name, address),
+                _ => return Err(error("expected a symbol and its address".to_owned())),
+            };
+            let address = address
+                .parse()
+                .map_err(|_| error(format!("invalid address: {}", address)))?;
+            let (name, table) = match name.strip_prefix('(').and_then(|n| n.strip_suffix(')')) {
+                Some(label) => (label, &mut symbols.labels),
+                None => (name, &mut symbols.variables),
+            };
+            if !crate::is_symbol(name) {
+                return Err(error(format!("invalid symbol: {}", name)));
+            }
+            table.insert(name.to_owned(), address);
+        }
+        Ok(symbols)
+    }
+
+    pub fn rom(&self, name: &str) -> Result<u16, String> {
+        self.labels
+            .get(name)
+            .copied()
+            .map_or_else(|| parse_value(name), Ok)
+    }
+
+    // a RAM address, which unlike other values has to be somewhere in RAM
+    pub fn ram(&self, name: &str) -> Result<u16, String> {
+        let address = self
+            .variables
+            .get(name)
+            .copied()
+            .or_else(|| {
+                PREDEFINED_SYMBOLS
+                    .iter()
+                    .find(|(symbol, _)| *symbol == name)
+                    .map(|(_, address)| *address)
+            })
+            .map_or_else(|| parse_value(name), Ok)?;
+        if address as usize >= RAM_SIZE {
+            return Err(format!(
+                "RAM address `{}` is out of range (the most is {})",
+                name,
+                RAM_SIZE - 1
+            ));
+        }
+        Ok(address)
+    }
+
+    // a name for a RAM address, for display
+    pub fn ram_name(&self, address: u16) -> Option<&str> {
+        self.variables
+            .iter()
+            .filter(|(_, value)| **value == address)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+}
+
+// the `.sym` file `parse` reads: labels, then variables, each in order of
+// address, so that the same program always gets the same file
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sorted = |table: &HashMap<String, u16>| {
+            let mut symbols: Vec<(u16, String)> = table
+                .iter()
+                .map(|(name, address)| (*address, name.clone()))
+                .collect();
+            symbols.sort();
+            symbols
+        };
+        for (address, label) in sorted(&self.labels) {
+            writeln!(f, "({}) {}", label, address)?;
+        }
+        for (address, variable) in sorted(&self.variables) {
+            writeln!(f, "{} {}", variable, address)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint {
+        address: u16,
+        read: bool,
+        write: bool,
+    },
+}
+
+impl Stop {
+    // whether breakpoint or watchpoint `number` fires for this step of
+    // execution, and if so why
+    pub fn triggered(&self, number: usize, pc: u16, access: &Access) -> Option<String> {
+        match *self {
+            Stop::Breakpoint(address) => {
+                (pc == address).then(|| format!("breakpoint {}: PC {}", number, pc))
+            }
+            Stop::Watchpoint {
+                address,
+                read,
+                write,
+            } => {
+                if let Some((written, value)) = access.write.filter(|_| write) {
+                    if written == address {
+                        return Some(format!(
+                            "watchpoint {}: RAM[{}] written ({})",
+                            number, address, value as i16
+                        ));
+                    }
+                }
+                (read && access.read == Some(address))
+                    .then(|| format!("watchpoint {}: RAM[{}] read", number, address))
+            }
+        }
+    }
+}
+
+// parses a word typed by the user
+pub fn parse_value(s: &str) -> Result<u16, String> {
+    let parsed = if let Some(hex) = s.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = s.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        s.parse::<u16>()
+            .ok()
+            .or_else(|| s.parse::<i16>().ok().map(|value| value as u16))
+    };
+    parsed.ok_or_else(|| format!("invalid value `{}`", s))
+}
+
+pub struct Debugger {
+    pub cpu: Cpu,
+    map: Option<SourceMap>,
+    symbols: Symbols,
+    functions: Functions,
+    throttle: Option<Throttle>,
+    // numbered from 1, with deleted entries left as holes so numbers are stable
+    stops: Vec<Option<Stop>>,
+    // source files we've had to show lines from, by name
+    sources: HashMap<String, Option<Vec<String>>>,
+}
+
+impl Debugger {
+    pub fn new(mut cpu: Cpu, map: Option<SourceMap>, symbols: Symbols) -> Self {
+        cpu.journal = Journal::new(JOURNAL_LENGTH);
+        Self {
+            cpu,
+            map,
+            functions: Functions::new(&symbols),
+            symbols,
+            throttle: None,
+            stops: Vec::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    // the current instruction, along with the source line it came from if
+    // we know it
+    pub fn location(&mut self) -> String {
+        let pc = self.cpu.pc;
+        let mut out = format!("PC {:>5}: {}", pc, describe(self.cpu.instruction()));
+        if self.cpu.finished() {
+            out.push_str("  (past the end of the program)");
+        }
+
+        let Some(location) = self.map.as_ref().and_then(|map| map.get(pc)) else {
+            return out;
+        };
+        let text = self
+            .sources
+            .entry(location.file.clone())
+            .or_insert_with(|| {
+                fs::read_to_string(&location.file)
+                    .ok()
+                    .map(|source| source.lines().map(str::to_owned).collect())
+            })
+            .as_ref()
+            .and_then(|lines| lines.get(location.line - 1));
+        out.push_str(&format!("    {}:{}", location.file, location.line));
+        if let Some(text) = text {
+            out.push_str(&format!(": {}", text.trim()));
+        }
+        out
+    }
+
+    fn backtrace(&self) -> Vec<String> {
+        self.functions
+            .backtrace(|address| self.cpu.read(address), self.cpu.pc)
+    }
+
+    fn registers(&self) -> String {
+        format!(
+            "A={} D={} PC={} cycles={}",
+            self.cpu.a as i16, self.cpu.d as i16, self.cpu.pc, self.cpu.cycles
+        )
+    }
+
+    // steps at most `count` times, stopping early if the program ends or we
+    // hit a breakpoint or watchpoint, in which case we say why
+    fn run(&mut self, count: u64) -> Option<String> {
+        if let Some(throttle) = &mut self.throttle {
+            // don't count the time spent at the prompt
+            throttle.reset(self.cpu.cycles);
+        }
+        for _ in 0..count {
+            if self.cpu.finished() {
+                return Some("the program has finished".to_owned());
+            }
+            if self.cpu.halted() {
+                return Some("the program has halted".to_owned());
+            }
+            let access = self.cpu.step();
+            if let Some(throttle) = &mut self.throttle {
+                throttle.pace(self.cpu.cycles);
+            }
+            let reason = self.stops.iter().enumerate().find_map(|(index, stop)| {
+                stop.as_ref()?.triggered(index + 1, self.cpu.pc, &access)
+            });
+            if reason.is_some() {
+                return reason;
+            }
+        }
+        None
+    }
+
+    fn add_stop(&mut self, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+        self.stops.push(Some(stop));
+        writeln!(
+            out,
+            "{}: {}",
+            self.stops.len(),
+            self.describe_stop(self.stops.len() - 1)
+        )
+    }
+
+    fn describe_stop(&self, index: usize) -> String {
+        match &self.stops[index] {
+            Some(Stop::Breakpoint(address)) => format!("breakpoint at PC {}", address),
+            Some(Stop::Watchpoint {
+                address,
+                read,
+                write,
+            }) => {
+                let name = self
+                    .symbols
+                    .ram_name(*address)
+                    .map(|name| format!(" ({})", name))
+                    .unwrap_or_default();
+                let kind = match (read, write) {
+                    (true, true) => "read/write",
+                    (true, false) => "read",
+                    _ => "write",
+                };
+                format!("{} watchpoint on RAM[{}]{}", kind, address, name)
+            }
+            None => "deleted".to_owned(),
+        }
+    }
+
+    // runs a single debugger command, returning whether to keep going
+    pub fn command(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = |index: usize, default: u64| -> Result<u64, String> {
+            words.get(index).map_or(Ok(default), |word| {
+                word.parse()
+                    .map_err(|_| format!("invalid count `{}`", word))
+            })
+        };
+
+        match words[..] {
+            [] => {}
+            ["s" | "step", ..] | ["c" | "continue", ..] => {
+                let default = if words[0].starts_with('s') {
+                    1
+                } else {
+                    DEFAULT_CONTINUE
+                };
+                if let Some(reason) = self.run(count(1, default)?) {
+                    writeln!(out, "stopped: {}", reason)?;
+                    let backtrace = self.backtrace();
+                    if !backtrace.is_empty() {
+                        writeln!(out, "in {}", backtrace::render(&backtrace))?;
+                    }
+                }
+                writeln!(out, "{}", self.location())?;
+            }
+            ["back", ..] | ["rewind"] => {
+                let count = if words[0] == "back" {
+                    count(1, 1)?
+                } else {
+                    u64::MAX
+                };
+                let mut undone = 0;
+                while undone < count && self.cpu.back().is_some() {
+                    undone += 1;
+                }
+                if undone < count && count != u64::MAX {
+                    writeln!(out, "stopped: no more history")?;
+                }
+                writeln!(out, "{}", self.location())?;
+            }
+            ["last", address] => {
+                let address = self.symbols.ram(address)?;
+                match self.cpu.journal.last_write(address) {
+                    Some(undo) => writeln!(
+                        out,
+                        "RAM[{}] last written at cycle {} by PC {}: {} (was {})",
+                        address,
+                        undo.cycle,
+                        undo.pc,
+                        describe(self.cpu.rom[undo.pc as usize]),
+                        undo.write.map_or(0, |(_, old)| old) as i16
+                    )?,
+                    None => writeln!(
+                        out,
+                        "RAM[{}] not written in the last {} instructions",
+                        address,
+                        self.cpu.journal.entries.len()
+                    )?,
+                }
+            }
+            ["speed"] => match &self.throttle {
+                Some(throttle) => writeln!(out, "{} Hz", throttle.hz)?,
+                None => writeln!(out, "max")?,
+            },
+            ["speed", "max"] => self.throttle = None,
+            ["speed", hz] => {
+                let hz = hz
+                    .parse()
+                    .ok()
+                    .filter(|hz| *hz > 0)
+                    .ok_or_else(|| format!("invalid speed `{}`", hz))?;
+                self.throttle = Some(Throttle::new(hz, self.cpu.cycles));
+            }
+            ["r" | "regs"] => writeln!(out, "{}", self.registers())?,
+            ["set", register, value] => {
+                let value = parse_value(value)?;
+                match register.to_ascii_uppercase().as_str() {
+                    "A" => self.cpu.a = value,
+                    "D" => self.cpu.d = value,
+                    "PC" => self.cpu.pc = value,
+                    _ => Err(format!("unknown register `{}`", register))?,
+                }
+                writeln!(out, "{}", self.registers())?;
+            }
+            ["x" | "ram", address, ..] => {
+                let address = self.symbols.ram(address)?;
+                let count = count(2, 1)?;
+                // stopping at the end of RAM rather than wrapping around
+                let end = (address as usize)
+                    .saturating_add(count as usize)
+                    .min(RAM_SIZE);
+                for address in address..end as u16 {
+                    let value = self.cpu.read(address);
+                    writeln!(out, "RAM[{}] = {} ({:#06x})", address, value as i16, value)?;
+                }
+            }
+            ["poke", address, value] => {
+                let (address, value) = (self.symbols.ram(address)?, parse_value(value)?);
+                self.cpu.write(address, value);
+            }
+            ["b" | "break", location] => {
+                let address = self.symbols.rom(location)?;
+                self.add_stop(Stop::Breakpoint(address), out)?;
+            }
+            ["watch", address, ..] => {
+                let address = self.symbols.ram(address)?;
+                let (read, write) = match words.get(2).copied().unwrap_or("rw") {
+                    "r" => (true, false),
+                    "w" => (false, true),
+                    "rw" => (true, true),
+                    other => Err(format!(
+                        "invalid watch kind `{}` (expected r, w, or rw)",
+                        other
+                    ))?,
+                };
+                let stop = Stop::Watchpoint {
+                    address,
+                    read,
+                    write,
+                };
+                self.add_stop(stop, out)?;
+            }
+            ["info"] => {
+                for index in 0..self.stops.len() {
+                    if self.stops[index].is_some() {
+                        writeln!(out, "{}: {}", index + 1, self.describe_stop(index))?;
+                    }
+                }
+            }
+            ["delete", number] => {
+                let stop = number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| self.stops.get_mut(number.checked_sub(1)?))
+                    .filter(|stop| stop.is_some())
+                    .ok_or_else(|| format!("no breakpoint or watchpoint number `{}`", number))?;
+                *stop = None;
+            }
+            ["screen"] => write!(out, "{}", screen::render(&self.cpu.ram))?,
+            ["png", path] => fs::write(path, image::png(&self.cpu.ram))
+                .map_err(|err| format!("couldn't write {}: {}", path, err))?,
+            ["save", path] => {
+                let mut saved = Vec::new();
+                snapshot::write(&self.cpu, &mut saved)
+                    .and_then(|()| fs::write(path, saved))
+                    .map_err(|err| format!("couldn't write {}: {}", path, err))?;
+            }
+            ["load", path] => {
+                let file = fs::File::open(path)
+                    .map_err(|err| format!("couldn't read {}: {}", path, err))?;
+                let journal = std::mem::take(&mut self.cpu.journal);
+                self.cpu = snapshot::read(io::BufReader::new(file))
+                    .map_err(|err| format!("couldn't load {}: {}", path, err))?;
+                // the history before the snapshot doesn't lead to it
+                self.cpu.journal = Journal::new(journal.capacity);
+                writeln!(out, "{}", self.location())?;
+            }
+            ["bt" | "backtrace"] => {
+                let backtrace = self.backtrace();
+                if backtrace.is_empty() {
+                    writeln!(out, "not in a function we know of")?;
+                } else {
+                    writeln!(out, "{}", backtrace::render(&backtrace))?;
+                }
+            }
+            ["l" | "list"] => writeln!(out, "{}", self.location())?,
+            ["h" | "help"] => writeln!(out, "{}", HELP)?,
+            ["q" | "quit"] => return Ok(false),
+            _ => Err(format!("unknown command `{}` (try `help`)", line.trim()))?,
+        }
+        Ok(true)
+    }
+
+    // the interactive loop: reads commands from `input` until it runs dry or
+    // the user quits
+    pub fn repl(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.location())?;
+        write!(out, "(hack) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            match self.command(&line?, out) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(err) => writeln!(out, "error: {}", err)?,
+            }
+            write!(out, "(hack) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::load;
+    use std::{
+        fs::{self, File},
+        io::BufReader,
+    };
+
+    fn session(commands: &str) -> String {
+        let rect = File::open("resources/Rect.hack").unwrap();
+        let cpu = Cpu::new(&load(BufReader::new(rect)).unwrap());
+        let source = fs::read_to_string("resources/Rect.asm").unwrap();
+        let map = SourceMap::new("resources/Rect.asm", &crate::parse_source(&source).unwrap());
+        let lines = crate::parse(&source).unwrap();
+        let mut debugger = Debugger::new(cpu, Some(map), crate::symbols(&lines));
+        let mut out = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn stepping_and_inspecting() {
+        let transcript = session("poke 0 2\nstep 2\nregs\nset D -1\nx 0 2\nq\n");
+        let expected = "\
+PC     0: @0    resources/Rect.asm:9: @0
+(hack) (hack) PC     2: @23    resources/Rect.asm:11: @INFINITE_LOOP
+(hack) A=0 D=2 PC=2 cycles=2
+(hack) A=0 D=-1 PC=2 cycles=2
+(hack) RAM[0] = 2 (0x0002)
+RAM[1] = 0 (0x0000)
+(hack) ";
+        assert_eq!(transcript, expected);
+    }
+
+    #[test]
+    fn errors_keep_the_session_going() {
+        let transcript = session("frobnicate\nset Q 1\nx\nspeed 0\nquit\n");
+        assert!(transcript.contains("error: unknown command `frobnicate`"));
+        assert!(transcript.contains("error: unknown register `Q`"));
+        assert!(transcript.contains("error: unknown command `x`"));
+        assert!(transcript.contains("error: invalid speed `0`"));
+
+        let transcript = session("x 40000\nx 0x7fff 3\nwatch 32768\nwatch -1\npoke 32768 1\nq\n");
+        let out_of_range = |address: &str| {
+            format!(
+                "error: RAM address `{}` is out of range (the most is 32767)",
+                address
+            )
+        };
+        assert!(transcript.contains(&out_of_range("40000")));
+        assert!(transcript.contains(&out_of_range("32768")));
+        assert!(transcript.contains(&out_of_range("-1")));
+        assert!(transcript.contains("RAM[32767] = 0 (0x0000)\n(hack)"));
+        assert!(!transcript.contains("watchpoint on"));
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let transcript =
+            session("poke 0 2\nbreak LOOP\nwatch counter w\nc\nc\ndelete 2\nc\ninfo\nq\n");
+        let expected = "\
+PC     0: @0    resources/Rect.asm:9: @0
+(hack) (hack) 1: breakpoint at PC 10
+(hack) 2: write watchpoint on RAM[16] (counter)
+(hack) stopped: watchpoint 2: RAM[16] written (2)
+PC     6: @16384    resources/Rect.asm:15: @SCREEN
+(hack) stopped: breakpoint 1: PC 10
+PC    10: @17    resources/Rect.asm:20: @address
+(hack) (hack) stopped: breakpoint 1: PC 10
+PC    10: @17    resources/Rect.asm:20: @address
+(hack) 1: breakpoint at PC 10
+(hack) ";
+        assert_eq!(transcript, expected);
+    }
+
+    #[test]
+    fn stepping_backwards() {
+        let transcript = session("poke 0 2\nstep 7\nlast counter\nback 2\nregs\nrewind\nback\nq\n");
+        let expected = "\
+PC     0: @0    resources/Rect.asm:9: @0
+(hack) (hack) PC     7: D=A    resources/Rect.asm:16: D=A
+(hack) RAM[16] last written at cycle 6 by PC 5: M=D (was 0)
+(hack) PC     5: M=D    resources/Rect.asm:14: M=D
+(hack) A=16 D=2 PC=5 cycles=5
+(hack) PC     0: @0    resources/Rect.asm:9: @0
+(hack) stopped: no more history
+PC     0: @0    resources/Rect.asm:9: @0
+(hack) ";
+        assert_eq!(transcript, expected);
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse_value("-1"), Ok(0xFFFF));
+        assert_eq!(parse_value("0x4000"), Ok(16384));
+        assert_eq!(parse_value("0b101"), Ok(5));
+        assert!(parse_value("SCREEN").is_err());
+    }
+
+    #[test]
+    fn sym_files() {
+        let text = "(LOOP) 2\n(END) 10\ni 16\nsum 17\n";
+        let symbols = Symbols::parse(text).unwrap();
+        assert_eq!(symbols.to_string(), text);
+    }
+}
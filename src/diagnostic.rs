@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt::{self, Write as _};
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Error => "\x1b[1;31m",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const GUTTER: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+// a problem with a particular line of a program. We keep a copy of the
+// offending source line around so that we can point at it when reporting,
+// long after the rest of the source has been thrown away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // 1-based; 0 if we don't know which line this is about (yet)
+    pub line: usize,
+    // byte range within `text`
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            line: 0,
+            span: 0..0,
+            text: String::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    // points the diagnostic at `token`, which must be a slice of `text`
+    pub fn at(mut self, text: &str, token: &str) -> Self {
+        let start = (token.as_ptr() as usize).saturating_sub(text.as_ptr() as usize);
+        self.span = start..start + token.len();
+        self
+    }
+
+    pub fn on_line(mut self, line: usize, text: &str) -> Self {
+        self.line = line;
+        self.text = text.to_owned();
+        self
+    }
+
+    // just the `error: message` line, for diagnostics that aren't about
+    // any file in particular
+    pub fn header(&self, color: bool) -> String {
+        let paint = |style: &'static str| if color { style } else { "" };
+        format!(
+            "{}{}{}: {}{}{}\n",
+            paint(self.severity.color()),
+            self.severity,
+            paint(RESET),
+            paint(BOLD),
+            self.message,
+            paint(RESET)
+        )
+    }
+
+    // renders the diagnostic rustc-style, with the source line and a caret
+    // underline beneath the offending span
+    pub fn render(&self, path: &Path, color: bool) -> String {
+        let paint = |style: &'static str| if color { style } else { "" };
+        let reset = paint(RESET);
+
+        let mut out = self.header(color);
+        if self.line == 0 {
+            let _ = writeln!(out, " {}-->{} {}", paint(GUTTER), reset, path.display());
+            return out;
+        }
+
+        let column = self
+            .text
+            .get(..self.span.start)
+            .map_or(0, |s| s.chars().count());
+        let width = self
+            .text
+            .get(self.span.clone())
+            .map_or(1, |s| s.chars().count().max(1));
+        let number = self.line.to_string();
+        let pad = " ".repeat(number.len());
+        let _ = writeln!(
+            out,
+            "{}{}-->{} {}:{}:{}",
+            pad,
+            paint(GUTTER),
+            reset,
+            path.display(),
+            self.line,
+            column + 1
+        );
+        let _ = writeln!(out, "{} {}|{}", pad, paint(GUTTER), reset);
+        let _ = writeln!(
+            out,
+            "{}{} |{} {}",
+            paint(GUTTER),
+            number,
+            reset,
+            self.text.trim_end()
+        );
+        let _ = writeln!(
+            out,
+            "{} {}|{} {}{}{}{}",
+            pad,
+            paint(GUTTER),
+            reset,
+            " ".repeat(column),
+            paint(self.severity.color()),
+            "^".repeat(width),
+            reset
+        );
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl Error for Diagnostic {}
+
+// resolves a `--color` setting into whether we should actually emit colors
+pub fn use_color(setting: Option<&str>) -> Result<bool, String> {
+    match setting.unwrap_or("auto") {
+        "always" => Ok(true),
+        "never" => Ok(false),
+        "auto" => Ok(std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal()),
+        other => Err(format!(
+            "invalid --color `{}` (expected auto, always, or never)",
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_plain() {
+        let text = "   D=Q // oops";
+        let diagnostic = Diagnostic::error("Invalid comp: Q")
+            .at(text, &text[5..6])
+            .on_line(12, text);
+        assert_eq!(
+            diagnostic.render(Path::new("Bad.asm"), false),
+            "error: Invalid comp: Q\n  --> Bad.asm:12:6\n   |\n12 |    D=Q // oops\n   |      ^\n"
+        );
+    }
+
+    #[test]
+    fn render_color() {
+        let diagnostic = Diagnostic::error("Invalid dest: Q").on_line(1, "Q=0");
+        let rendered = diagnostic.render(Path::new("X.asm"), true);
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m"));
+    }
+}
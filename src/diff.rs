@@ -0,0 +1,207 @@
+use std::fmt::Write as _;
+
+use crate::disassemble::describe;
+
+// past this many differences we stop looking for the best alignment and
+// call whatever's left one big change, which keeps the search from taking
+// quadratic time and memory on two unrelated programs
+const MAX_EDITS: usize = 2000;
+
+// one of the programs being compared
+pub struct Side {
+    pub words: Vec<u16>,
+    // ROM addresses of labels, if we know them, sorted by address
+    pub labels: Vec<(u16, String)>,
+}
+
+impl Side {
+    pub fn new(words: Vec<u16>, labels: impl IntoIterator<Item = (String, u16)>) -> Self {
+        let mut labels: Vec<(u16, String)> = labels
+            .into_iter()
+            .map(|(name, address)| (address, name))
+            .collect();
+        labels.sort();
+        Self { words, labels }
+    }
+
+    // an address, along with where it is relative to the nearest label
+    // before it
+    fn address(&self, address: usize) -> String {
+        let label = match self
+            .labels
+            .partition_point(|(start, _)| *start as usize <= address)
+        {
+            0 => return address.to_string(),
+            index => &self.labels[index - 1],
+        };
+        match address - label.0 as usize {
+            0 => format!("{} ({})", address, label.1),
+            offset => format!("{} ({}+{})", address, label.1, offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Same,
+    Delete,
+    Insert,
+}
+
+// the shortest run of edits turning `a` into `b` (Myers' algorithm)
+fn align(a: &[u16], b: &[u16]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (middle_a, middle_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut edits = vec![Edit::Same; prefix];
+    edits.extend(shortest_edit(middle_a, middle_b).unwrap_or_else(|| {
+        let mut edits = vec![Edit::Delete; middle_a.len()];
+        edits.extend(vec![Edit::Insert; middle_b.len()]);
+        edits
+    }));
+    edits.extend(vec![Edit::Same; suffix]);
+    edits
+}
+
+fn shortest_edit(a: &[u16], b: &[u16]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // the furthest x reached along each diagonal k = x - y, and a copy of
+    // the diagonals in play before each round, to retrace our steps by
+    let mut v = vec![0; 2 * max + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    for d in 0..=max.min(MAX_EDITS) as isize {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down =
+                k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]);
+            let mut x = if down {
+                v[(offset + k + 1) as usize]
+            } else {
+                v[(offset + k - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m));
+            }
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let previous = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = at(previous);
+        let previous_y = previous_x - previous;
+        while x > previous_x && y > previous_y {
+            edits.push(Edit::Same);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == previous_x {
+                Edit::Insert
+            } else {
+                Edit::Delete
+            });
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    edits.reverse();
+    edits
+}
+
+// the differences between two programs as instructions, a hunk per run of
+// changed words, along with how many words changed
+pub fn diff(a: &Side, b: &Side) -> (String, usize) {
+    let edits = align(&a.words, &b.words);
+    let mut out = String::new();
+    let mut changed = 0;
+    let (mut x, mut y) = (0, 0);
+    let mut edits = edits.into_iter().peekable();
+    while let Some(edit) = edits.next() {
+        if edit == Edit::Same {
+            x += 1;
+            y += 1;
+            continue;
+        }
+        let _ = writeln!(out, "@@ {} | {} @@", a.address(x), b.address(y));
+        let mut hunk = vec![edit];
+        while let Some(edit) = edits.next_if(|edit| *edit != Edit::Same) {
+            hunk.push(edit);
+        }
+        // deletions before insertions, so that replaced words read as
+        // before and after
+        for edit in [Edit::Delete, Edit::Insert] {
+            for _ in hunk.iter().filter(|change| **change == edit) {
+                let (sign, word) = if edit == Edit::Delete {
+                    x += 1;
+                    ('-', a.words[x - 1])
+                } else {
+                    y += 1;
+                    ('+', b.words[y - 1])
+                };
+                let _ = writeln!(out, "{} {:016b}  {}", sign, word, describe(word));
+            }
+        }
+        changed += hunk.len();
+    }
+    (out, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_around_changes() {
+        let a = Side::new(vec![0, 1, 2, 3, 4], [("LOOP".to_owned(), 1)]);
+        let b = Side::new(vec![0, 1, 9, 3, 4, 5], []);
+        assert_eq!(
+            align(&a.words, &b.words),
+            [
+                Edit::Same,
+                Edit::Same,
+                Edit::Delete,
+                Edit::Insert,
+                Edit::Same,
+                Edit::Same,
+                Edit::Insert
+            ]
+        );
+        let (out, changed) = diff(&a, &b);
+        assert_eq!(changed, 3);
+        assert_eq!(
+            out,
+            "@@ 2 (LOOP+1) | 2 @@\n\
+             - 0000000000000010  @2\n\
+             + 0000000000001001  @9\n\
+             @@ 5 (LOOP+4) | 5 @@\n\
+             + 0000000000000101  @5\n"
+        );
+        assert_eq!(align(&[1, 2], &[3, 4, 1, 2]).len(), 4);
+        assert_eq!(diff(&a, &a).1, 0);
+    }
+}
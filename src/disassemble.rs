@@ -0,0 +1,222 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use crate::debug::Symbols;
+use crate::diagnostic::Diagnostic;
+use crate::{Assemble, Computation, Destination, HackLine, Jump, AM};
+
+// past this, RAM belongs to the VM's stack and heap rather than a program's
+// own variables
+const VARIABLES_END: u16 = 256;
+
+const COMP_MASK: u16 = 0b0001_1111_1100_0000;
+
+// the comp bits of every computation, worked out by asking the assembler
+// itself, so that the two directions can't disagree
+fn computations() -> &'static HashMap<u16, Computation> {
+    static TABLE: OnceLock<HashMap<u16, Computation>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        Computation::ALL
+            .iter()
+            .map(|comp| (comp.assemble() << 6, *comp))
+            .collect()
+    })
+}
+
+// turns a machine word back into the instruction it encodes, or `None` if it
+// isn't a valid instruction
+pub fn disassemble(word: u16) -> Option<HackLine<'static>> {
+    if word & 0x8000 == 0 {
+        return Some(HackLine::AImmediate(word));
+    }
+    // the two bits after the C-instruction marker are unused
+    if word & 0x6000 != 0x6000 {
+        return None;
+    }
+    let comp = *computations().get(&(word & COMP_MASK))?;
+    let dest = Destination::ALL[(word >> 3) as usize & 0b111];
+    let jump = Jump::ALL[word as usize & 0b111];
+    Some(HackLine::C(comp, dest, jump))
+}
+
+// the assembly text for a word, for display
+pub fn describe(word: u16) -> String {
+    match disassemble(word) {
+        Some(line) => line.to_string(),
+        None => format!("<invalid {:016b}>", word),
+    }
+}
+
+// a whole program disassembled, with its addresses named where we can tell
+// what they are: names from `symbols` first, then `LOOP_n` for the targets
+// of jumps backwards and `SKIP_n` forwards, and `R0`-`R15`, the devices and
+// `var_n` for low RAM read or written through M
+pub fn recover(words: &[u16], symbols: &Symbols) -> Result<Vec<HackLine<'static>>, Diagnostic> {
+    let mut code = Vec::with_capacity(words.len());
+    for (address, word) in words.iter().enumerate() {
+        let text = format!("{:016b}", word);
+        let line = disassemble(*word).ok_or_else(|| {
+            Diagnostic::error(format!("{} isn't a valid instruction", text))
+                .at(&text, &text)
+                .on_line(address + 1, &text)
+        })?;
+        code.push(line);
+    }
+
+    let mut labels: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    for (name, address) in &symbols.labels {
+        if (*address as usize) <= words.len() {
+            labels.entry(*address).or_default().push(name.clone());
+        }
+    }
+    for names in labels.values_mut() {
+        names.sort();
+    }
+    let mut variables: HashMap<u16, String> = HashMap::new();
+    for (address, pair) in code.windows(2).enumerate() {
+        let (HackLine::AImmediate(value), HackLine::C(comp, dest, jump)) = (&pair[0], &pair[1])
+        else {
+            continue;
+        };
+        if *jump != Jump::Null && (*value as usize) <= words.len() {
+            labels.entry(*value).or_insert_with(|| {
+                let kind = if (*value as usize) <= address {
+                    "LOOP"
+                } else {
+                    "SKIP"
+                };
+                vec![format!("{}_{}", kind, value)]
+            });
+        } else if comp.operand() == Some(&AM::M) || dest.writes(Destination::M) {
+            let name = symbols
+                .ram_name(*value)
+                .map(str::to_owned)
+                .or(match *value {
+                    0..=15 => Some(format!("R{}", value)),
+                    16384 => Some("SCREEN".to_owned()),
+                    24576 => Some("KBD".to_owned()),
+                    value if value < VARIABLES_END => Some(format!("var_{}", value)),
+                    _ => None,
+                });
+            if let Some(name) = name {
+                variables.entry(*value).or_insert(name);
+            }
+        }
+    }
+
+    // an address loaded for a jump is named as a label, and one loaded for
+    // M as a variable; anything else stays a number
+    // the assembler hands out variables' addresses in the order they're
+    // first used, so a name only stays if it would get the same one back
+    let mut allocated: HashMap<u16, bool> = HashMap::new();
+    let mut next = 16;
+    let mut lines = Vec::with_capacity(code.len() + labels.len());
+    for (address, line) in code.iter().enumerate() {
+        if let Some(names) = labels.get(&(address as u16)) {
+            lines.extend(
+                names
+                    .iter()
+                    .map(|name| HackLine::Label(Cow::Owned(name.clone()))),
+            );
+        }
+        let name = match (line, code.get(address + 1)) {
+            (HackLine::AImmediate(value), Some(HackLine::C(_, _, jump))) if *jump != Jump::Null => {
+                labels.get(value).map(|names| names[0].clone())
+            }
+            (HackLine::AImmediate(value), Some(HackLine::C(..))) => {
+                variables.get(value).cloned().filter(|name| {
+                    crate::SymbolSet::standard().get(name).is_some()
+                        || *allocated.entry(*value).or_insert_with(|| {
+                            let fits = *value == next;
+                            next += u16::from(fits);
+                            fits
+                        })
+                })
+            }
+            _ => None,
+        };
+        lines.push(match name {
+            Some(name) => HackLine::ALocation(Cow::Owned(name)),
+            None => line.clone(),
+        });
+    }
+    if let Some(names) = labels.get(&(words.len() as u16)) {
+        lines.extend(
+            names
+                .iter()
+                .map(|name| HackLine::Label(Cow::Owned(name.clone()))),
+        );
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn rect() {
+        let source = std::fs::read_to_string("resources/Rect.asm").unwrap();
+        let binary = std::fs::read_to_string("resources/Rect.hack").unwrap();
+        let instructions = parse(&source)
+            .unwrap()
+            .into_iter()
+            .filter(|line| !matches!(line, HackLine::Label(_)));
+        let words = binary
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap());
+
+        for (expected, word) in instructions.zip(words) {
+            match expected {
+                // symbols are lost in assembly
+                HackLine::ALocation(_) => assert!(word & 0x8000 == 0),
+                expected => assert_eq!(disassemble(word), Some(expected)),
+            }
+        }
+    }
+
+    #[test]
+    fn every_computation() {
+        for comp in Computation::ALL {
+            let line = HackLine::C(comp, Destination::AMD, Jump::JLE);
+            let word = line.word_with(|_| unreachable!()).unwrap();
+            assert_eq!(disassemble(word), Some(line));
+        }
+        assert_eq!(disassemble(0b1000_0000_0000_0000), None);
+        assert_eq!(describe(0b1110_1100_0001_0000), "D=A");
+    }
+
+    #[test]
+    fn recovers_symbols() {
+        let source = "@i\nM=1\n(LOOP)\n@i\nD=M\n@END\nD;JEQ\n@R13\nM=D\n@LOOP\n0;JMP\n(END)\n";
+        let mut binary = Vec::new();
+        crate::assemble_lines(&parse(source).unwrap(), &mut binary).unwrap();
+        let words = crate::emulator::load(&binary[..]).unwrap();
+        let show = |words: &[u16], symbols: &Symbols| {
+            recover(words, symbols)
+                .unwrap()
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(
+            show(&words, &Symbols::default()),
+            "@var_16 M=1 (LOOP_2) @var_16 D=M @SKIP_10 D;JEQ @R13 M=D @LOOP_2 0;JMP (SKIP_10)"
+        );
+        let symbols = Symbols::parse("(LOOP) 2 // the top\n(END) 10\ni 16\n").unwrap();
+        assert_eq!(
+            show(&words, &symbols),
+            "@i M=1 (LOOP) @i D=M @END D;JEQ @R13 M=D @LOOP 0;JMP (END)"
+        );
+        assert_eq!(recover(&[0xffff], &symbols).unwrap_err().line, 1);
+
+        // 17 is used first, so it can't be named without moving it to 16
+        assert_eq!(
+            show(&[17, 0xfc10, 16, 0xe308, 17, 0xe308], &Symbols::default()),
+            "@17 D=M @var_16 M=D @17 M=D"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{self, BufWriter, Write};
+
+// where the second pass of the assembler sends each instruction once it's
+// encoded. Encoding stays in `HackLine`; an emitter only decides how words
+// are laid out, so that other output formats don't have to touch it
+pub trait Emitter {
+    // `address` is the instruction's place in ROM, counting up from 0
+    fn emit_word(&mut self, address: u16, word: u16) -> io::Result<()>;
+
+    // called once after the last word
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a `.hack` file: one word per line, in binary
+pub struct Text<W: Write> {
+    output: BufWriter<W>,
+}
+
+impl<W: Write> Text<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output: BufWriter::new(output),
+        }
+    }
+}
+
+impl<W: Write> Emitter for Text<W> {
+    // writes the line in one go rather than a bit at a time
+    fn emit_word(&mut self, _: u16, word: u16) -> io::Result<()> {
+        let mut line = [b'0'; 17];
+        for (bit, digit) in line[..16].iter_mut().rev().enumerate() {
+            if word & 1 << bit != 0 {
+                *digit = b'1';
+            }
+        }
+        line[16] = b'\n';
+        self.output.write_all(&line)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // keeps whatever it's given
+    impl Emitter for Vec<(u16, u16)> {
+        fn emit_word(&mut self, address: u16, word: u16) -> io::Result<()> {
+            self.push((address, word));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn emits_each_word_at_its_address() {
+        let lines = crate::parse("(LOOP)\n@LOOP\n0;JMP\n").unwrap();
+        let mut words = Vec::new();
+        crate::assemble_lines_with(
+            &lines,
+            crate::SymbolSet::standard(),
+            &Default::default(),
+            &mut words,
+        )
+        .unwrap();
+        assert_eq!(words, [(0, 0), (1, 0b1110101010000111)]);
+
+        let mut binary = Vec::new();
+        let mut text = Text::new(&mut binary);
+        text.emit_word(0, 5).unwrap();
+        text.finish().unwrap();
+        drop(text);
+        assert_eq!(binary, b"0000000000000101\n");
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use crate::disassemble::describe;
+use crate::os::Traps;
+
+pub const ROM_SIZE: usize = 32768;
+// A is 16 bits wide but only 15 of them address memory
+pub const RAM_SIZE: usize = 32768;
+
+// reads a `.hack` file: one 16-character binary word per line
+pub fn load(reader: impl BufRead) -> Result<Vec<u16>, Box<dyn Error + Send + Sync>> {
+    let mut rom = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.len() != 16 {
+            Err(format!(
+                "line {}: expected a 16-bit binary word",
+                number + 1
+            ))?;
+        }
+        let word = u16::from_str_radix(line, 2)
+            .map_err(|_| format!("line {}: expected a 16-bit binary word", number + 1))?;
+        rom.push(word);
+    }
+    if rom.len() > ROM_SIZE {
+        Err(format!(
+            "program has {} words, but ROM only holds {}",
+            rom.len(),
+            ROM_SIZE
+        ))?;
+    }
+    Ok(rom)
+}
+
+// the Hack ALU: `bits` are the six control bits zx, nx, zy, ny, f, no
+pub fn alu(x: u16, y: u16, bits: u16) -> u16 {
+    let bit = |n: u16| bits & (1 << (5 - n)) != 0;
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+    if bit(5) {
+        !out
+    } else {
+        out
+    }
+}
+
+// the data memory traffic of a single instruction: Hack instructions read
+// and write at most one word each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Access {
+    pub read: Option<u16>,
+    // the address, and the value written there
+    pub write: Option<(u16, u16)>,
+}
+
+// what it takes to undo one executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undo {
+    // the cycle the instruction ran in, counting from 1
+    pub cycle: u64,
+    // the registers before the instruction ran
+    pub pc: u16,
+    pub a: u16,
+    pub d: u16,
+    // the address written, and the value it held before
+    pub write: Option<(u16, u16)>,
+}
+
+// a bounded history of executed instructions, oldest first, so that
+// execution can be run backwards. Once full, the oldest entries are dropped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    pub entries: VecDeque<Undo>,
+    // zero means we aren't recording at all
+    pub capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn record(&mut self, undo: Undo) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(undo);
+    }
+
+    // the most recent instruction we remember writing to `address`
+    pub fn last_write(&self, address: u16) -> Option<&Undo> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|undo| undo.write.is_some_and(|(written, _)| written == address))
+    }
+}
+
+// the Hack CPU along with its instruction and data memories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cpu {
+    pub rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+    // how much of ROM the loaded program occupies
+    pub program_length: usize,
+    // off unless someone asks for it, since it costs on every step
+    pub journal: Journal,
+    // native OS functions to run in place of the program's
+    pub traps: Option<Traps>,
+}
+
+impl Cpu {
+    pub fn new(program: &[u16]) -> Self {
+        let mut rom = vec![0; ROM_SIZE];
+        rom[..program.len()].copy_from_slice(program);
+        Self {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            program_length: program.len(),
+            journal: Journal::default(),
+            traps: None,
+        }
+    }
+
+    fn address(value: u16) -> usize {
+        value as usize % RAM_SIZE
+    }
+
+    pub fn read(&self, address: u16) -> u16 {
+        self.ram[Self::address(address)]
+    }
+
+    pub fn write(&mut self, address: u16, value: u16) {
+        self.ram[Self::address(address)] = value;
+    }
+
+    // whether the PC has run past the end of the loaded program
+    pub fn finished(&self) -> bool {
+        self.pc as usize >= self.program_length
+    }
+
+    // whether the program is stuck in the `(END) @END 0;JMP` idiom for
+    // stopping: an unconditional jump to itself that changes nothing, from
+    // which it can never escape. Or it's called the OS's `Sys.halt`
+    pub fn halted(&self) -> bool {
+        // a C-instruction with no destination that always jumps
+        let spins = |word: u16| word & 0xE03F == 0xE007;
+        let word = |address: u16| self.rom[address as usize % ROM_SIZE];
+        // `@address` followed by such a jump
+        let stuck = |address: u16| word(address) == address && spins(word(address.wrapping_add(1)));
+        self.traps.as_ref().is_some_and(|traps| traps.os.halted)
+            || stuck(self.pc)
+            || (spins(self.instruction()) && (self.a == self.pc || stuck(self.a)))
+    }
+
+    pub fn instruction(&self) -> u16 {
+        self.rom[self.pc as usize % ROM_SIZE]
+    }
+
+    // a line describing the instruction about to run, for tracing
+    pub fn trace(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:>10} {:>5} A={:<6} D={:<6} {}",
+            self.cycles + 1,
+            self.pc,
+            self.a as i16,
+            self.d as i16,
+            describe(self.instruction())
+        )
+    }
+
+    // executes a single instruction, reporting which memory it touched
+    pub fn step(&mut self) -> Access {
+        if let Some(traps) = &mut self.traps {
+            if let Some(name) = traps.at(self.pc) {
+                let (return_to, access) = traps.run(name, &mut self.ram);
+                self.cycles += 1;
+                // RAM can't be put back the way it was before a native call,
+                // so nothing before one can be undone either
+                self.journal.entries.clear();
+                if let Some(return_to) = return_to {
+                    self.pc = return_to;
+                }
+                return access;
+            }
+        }
+        let instruction = self.instruction();
+        let mut access = Access::default();
+        let mut undo = Undo {
+            cycle: self.cycles + 1,
+            pc: self.pc,
+            a: self.a,
+            d: self.d,
+            write: None,
+        };
+        self.cycles += 1;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1);
+            self.journal.record(undo);
+            return access;
+        }
+
+        let y = if instruction & 0x1000 != 0 {
+            access.read = Some(self.a);
+            self.read(self.a)
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, (instruction >> 6) & 0b11_1111);
+
+        // M is written through the old value of A
+        if instruction & 0b001_000 != 0 {
+            access.write = Some((self.a, out));
+            undo.write = Some((self.a, self.read(self.a)));
+            self.write(self.a, out);
+        }
+        if instruction & 0b100_000 != 0 {
+            self.a = out;
+        }
+        if instruction & 0b010_000 != 0 {
+            self.d = out;
+        }
+
+        let negative = (out as i16) < 0;
+        let zero = out == 0;
+        let jump = (instruction & 0b100 != 0 && negative)
+            || (instruction & 0b010 != 0 && zero)
+            || (instruction & 0b001 != 0 && !negative && !zero);
+        self.pc = if jump {
+            self.a
+        } else {
+            self.pc.wrapping_add(1)
+        };
+        self.journal.record(undo);
+        access
+    }
+
+    // undoes the most recently executed instruction, if the journal still
+    // remembers it
+    pub fn back(&mut self) -> Option<Undo> {
+        let undo = self.journal.entries.pop_back()?;
+        if let Some((address, old)) = undo.write {
+            self.write(address, old);
+        }
+        self.pc = undo.pc;
+        self.a = undo.a;
+        self.d = undo.d;
+        self.cycles = undo.cycle - 1;
+        Some(undo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::BufReader};
+
+    fn rect() -> Cpu {
+        let rect = File::open("resources/Rect.hack").unwrap();
+        Cpu::new(&load(BufReader::new(rect)).unwrap())
+    }
+
+    #[test]
+    fn runs_rect() {
+        let mut cpu = rect();
+        cpu.ram[0] = 3;
+        for _ in 0..1000 {
+            cpu.step();
+        }
+        // three rows of the rectangle, 32 words apart
+        assert_eq!(cpu.ram[16384], 0xFFFF);
+        assert_eq!(cpu.ram[16416], 0xFFFF);
+        assert_eq!(cpu.ram[16448], 0xFFFF);
+        assert_eq!(cpu.ram[16480], 0);
+        // and then it spins in the infinite loop at the end
+        assert!((23..=24).contains(&cpu.pc));
+        assert!(cpu.halted());
+        cpu.step();
+        assert!(cpu.halted());
+    }
+
+    #[test]
+    fn only_tight_loops_halt() {
+        // @0, D;JMP is stuck, but @0, M=M+1;JMP keeps writing
+        assert!(Cpu::new(&[0, 0xE307]).halted());
+        assert!(!Cpu::new(&[0, 0xFDCF]).halted());
+        assert!(!Cpu::new(&[1, 0xE307]).halted());
+    }
+
+    #[test]
+    fn reports_accesses() {
+        let mut cpu = rect();
+        cpu.ram[0] = 7;
+        assert_eq!(cpu.step(), Access::default());
+        // D=M
+        assert_eq!(
+            cpu.step(),
+            Access {
+                read: Some(0),
+                write: None
+            }
+        );
+        for _ in 0..3 {
+            cpu.step();
+        }
+        // @counter, M=D
+        assert_eq!(
+            cpu.step(),
+            Access {
+                read: None,
+                write: Some((16, 7))
+            }
+        );
+    }
+
+    #[test]
+    fn runs_backwards() {
+        let mut cpu = rect();
+        cpu.journal = Journal::new(64);
+        cpu.ram[0] = 3;
+        let start = cpu.clone();
+        let mut history = vec![];
+        for _ in 0..100 {
+            history.push(cpu.clone());
+            cpu.step();
+        }
+        // the loop counts `counter` down
+        assert_eq!(cpu.journal.last_write(16).map(|undo| undo.pc), Some(20));
+
+        // the journal only goes back 64 instructions
+        for _ in 0..64 {
+            cpu.back().unwrap();
+            let mut expected = history.pop().unwrap();
+            expected.journal = cpu.journal.clone();
+            assert_eq!(cpu, expected);
+        }
+        assert_eq!(cpu.back(), None);
+        assert_ne!(cpu.ram, start.ram);
+    }
+
+    #[test]
+    fn traces() {
+        let mut cpu = rect();
+        let mut out = Vec::new();
+        for _ in 0..3 {
+            cpu.trace(&mut out).unwrap();
+            cpu.step();
+        }
+        let lines: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::trim)
+            .map(str::to_owned)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "1     0 A=0      D=0      @0",
+                "2     1 A=0      D=0      D=M",
+                "3     2 A=0      D=0      @23",
+            ]
+        );
+    }
+
+    #[test]
+    fn alu_table() {
+        // D=5, A=3 through a few of the standard computations
+        let (x, y) = (5, 3);
+        assert_eq!(alu(x, y, 0b101010), 0);
+        assert_eq!(alu(x, y, 0b111111), 1);
+        assert_eq!(alu(x, y, 0b111010), 0xFFFF);
+        assert_eq!(alu(x, y, 0b000010), 8);
+        assert_eq!(alu(x, y, 0b010011), 2);
+        assert_eq!(alu(x, y, 0b000111), 0xFFFE);
+        assert_eq!(alu(x, y, 0b000000), 1);
+        assert_eq!(alu(x, y, 0b010101), 7);
+    }
+}
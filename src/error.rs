@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::diagnostic::Diagnostic;
+
+// exit codes, so that scripts can tell a broken program apart from a broken
+// environment
+pub const EXIT_ASSEMBLY: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_IO: u8 = 3;
+
+#[derive(Debug)]
+pub enum HackError {
+    Usage(String),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Assembly {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    // some files of a batch failed; each of them has already been reported
+    Batch {
+        failed: usize,
+        total: usize,
+        code: u8,
+    },
+}
+
+impl HackError {
+    // sorts an error coming out of one of the assembler's passes into either
+    // an I/O problem or a problem with the program itself
+    pub fn new(path: &Path, source: Box<dyn Error + Send + Sync>) -> Self {
+        match source.downcast::<io::Error>() {
+            Ok(source) => Self::Io {
+                path: path.to_owned(),
+                source: *source,
+            },
+            Err(source) => Self::Assembly {
+                path: path.to_owned(),
+                source,
+            },
+        }
+    }
+
+    pub fn io(path: &Path) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    // formats the error for the terminal, with source context if we have any
+    pub fn render(&self, color: bool) -> String {
+        if let HackError::Assembly { path, source } = self {
+            if let Some(diagnostic) = source.downcast_ref::<Diagnostic>() {
+                return diagnostic.render(path, color);
+            }
+        }
+        Diagnostic::error(self.to_string()).header(color)
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            HackError::Usage(_) => EXIT_USAGE,
+            HackError::Io { .. } => EXIT_IO,
+            HackError::Assembly { .. } => EXIT_ASSEMBLY,
+            HackError::Batch { code, .. } => *code,
+        }
+    }
+
+    // combines the errors from a batch run into a single one, exiting with
+    // the most severe of the individual codes
+    pub fn batch(errors: &[HackError], total: usize) -> Option<Self> {
+        let code = errors.iter().map(HackError::code).max()?;
+        Some(Self::Batch {
+            failed: errors.len(),
+            total,
+            code,
+        })
+    }
+}
+
+impl fmt::Display for HackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HackError::Usage(message) => write!(f, "{}", message),
+            HackError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            HackError::Assembly { path, source } => write!(f, "{}: {}", path.display(), source),
+            HackError::Batch { failed, total, .. } => {
+                write!(f, "{} of {} files failed", failed, total)
+            }
+        }
+    }
+}
+
+impl Error for HackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HackError::Usage(_) | HackError::Batch { .. } => None,
+            HackError::Io { source, .. } => Some(source),
+            HackError::Assembly { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<HackError> for ExitCode {
+    fn from(err: HackError) -> Self {
+        ExitCode::from(err.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let path = Path::new("Prog.asm");
+        let io = HackError::new(path, io::Error::other("disk on fire").into());
+        assert_eq!(io.code(), EXIT_IO);
+        let asm = HackError::new(path, "Invalid comp: Q".into());
+        assert_eq!(asm.code(), EXIT_ASSEMBLY);
+        assert_eq!(asm.to_string(), "Prog.asm: Invalid comp: Q");
+
+        let batch = HackError::batch(&[asm, io], 3).unwrap();
+        assert_eq!(batch.code(), EXIT_IO);
+        assert_eq!(batch.to_string(), "2 of 3 files failed");
+        assert!(HackError::batch(&[], 3).is_none());
+    }
+}
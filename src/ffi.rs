@@ -0,0 +1,245 @@
+// what every function needs of its pointers is the same, and is described
+// once below rather than on each of them
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, CString};
+use std::ptr;
+use std::slice;
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::emulator::{self, Cpu};
+
+// `extern "C"` entry points, so that the assembler and emulator can be
+// embedded in C, C++ or Swift teaching tools: build with `--features ffi`,
+// link against `libhack.a` or `libhack.so`, and include `include/hack.h`,
+// which is kept in step with this file by hand. Whatever we hand out belongs
+// to us until it's given back to the matching `_free` function. The structs
+// are `repr(C)`, and any fields they gain will go on the end.
+//
+// Any pointer may be null, which is ignored or treated as empty. Otherwise
+// buffers must be valid for the length given alongside them, and everything
+// else must have come from us and not yet been freed
+
+#[repr(C)]
+pub struct HackDiagnostic {
+    // 0 for a warning, 1 for an error
+    pub severity: u32,
+    // 1-based, or 0 if the problem isn't with any one line
+    pub line: u32,
+    // the byte range of the line at fault
+    pub start: u32,
+    pub end: u32,
+    // NUL-terminated UTF-8
+    pub message: *mut c_char,
+}
+
+#[repr(C)]
+pub struct HackAssembly {
+    // one word per instruction; null if there are none, which is also the
+    // case for a program with no instructions, so check `ok`
+    pub words: *mut u16,
+    pub length: usize,
+    pub diagnostics: *mut HackDiagnostic,
+    pub diagnostic_count: usize,
+    // whether the program assembled
+    pub ok: bool,
+}
+
+// an emulator, which C only ever sees through a pointer
+pub struct HackEmulator {
+    cpu: Cpu,
+}
+
+fn leak<T>(items: Vec<T>) -> (*mut T, usize) {
+    if items.is_empty() {
+        return (ptr::null_mut(), 0);
+    }
+    let length = items.len();
+    (Box::into_raw(items.into_boxed_slice()).cast(), length)
+}
+
+unsafe fn unleak<T>(items: *mut T, length: usize) -> Vec<T> {
+    if items.is_null() {
+        return Vec::new();
+    }
+    Box::from_raw(ptr::slice_from_raw_parts_mut(items, length)).into_vec()
+}
+
+fn diagnostic(diagnostic: &Diagnostic) -> HackDiagnostic {
+    // messages are ours, and never have NULs in them
+    let message = CString::new(diagnostic.message.replace('\0', "")).unwrap_or_default();
+    HackDiagnostic {
+        severity: match diagnostic.severity {
+            Severity::Warning => 0,
+            Severity::Error => 1,
+        },
+        line: diagnostic.line as u32,
+        start: diagnostic.span.start as u32,
+        end: diagnostic.span.end as u32,
+        message: message.into_raw(),
+    }
+}
+
+fn assemble(source: &[u8]) -> Result<Vec<u16>, Vec<Diagnostic>> {
+    let error = |err: &dyn std::error::Error| vec![Diagnostic::error(err.to_string())];
+    let source = std::str::from_utf8(source).map_err(|err| error(&err))?;
+    let program = crate::parse_program(source)?;
+    let lines: Vec<_> = program
+        .lines
+        .into_iter()
+        .map(|source| source.line)
+        .collect();
+    let mut binary = Vec::new();
+    crate::assemble_lines(&lines, &mut binary)
+        .and_then(|()| emulator::load(&binary[..]))
+        .map_err(|err| error(&*err))
+}
+
+// assembles `length` bytes of Hack assembly at `source`, never returning
+// null. Free the result with `hack_assembly_free`
+#[no_mangle]
+pub unsafe extern "C" fn hack_assemble_buffer(
+    source: *const u8,
+    length: usize,
+) -> *mut HackAssembly {
+    let source = if source.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(source, length)
+    };
+    let (ok, words, diagnostics) = match assemble(source) {
+        Ok(words) => (true, words, Vec::new()),
+        Err(errors) => (false, Vec::new(), errors.iter().map(diagnostic).collect()),
+    };
+    let (words, length) = leak(words);
+    let (diagnostics, diagnostic_count) = leak(diagnostics);
+    Box::into_raw(Box::new(HackAssembly {
+        words,
+        length,
+        diagnostics,
+        diagnostic_count,
+        ok,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hack_assembly_free(assembly: *mut HackAssembly) {
+    if assembly.is_null() {
+        return;
+    }
+    let assembly = Box::from_raw(assembly);
+    drop(unleak(assembly.words, assembly.length));
+    for diagnostic in unleak(assembly.diagnostics, assembly.diagnostic_count) {
+        drop(CString::from_raw(diagnostic.message));
+    }
+}
+
+// a machine with `length` words of program at `words` in ROM, or null if
+// they won't fit. Free it with `hack_emulator_free`
+#[no_mangle]
+pub unsafe extern "C" fn hack_emulator_new(words: *const u16, length: usize) -> *mut HackEmulator {
+    if length > emulator::ROM_SIZE || (words.is_null() && length > 0) {
+        return ptr::null_mut();
+    }
+    let program = if words.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(words, length)
+    };
+    Box::into_raw(Box::new(HackEmulator {
+        cpu: Cpu::new(program),
+    }))
+}
+
+// runs at most `count` instructions, stopping early if the program finishes
+// or halts, and returns how many ran
+#[no_mangle]
+pub unsafe extern "C" fn hack_emulator_step(emulator: *mut HackEmulator, count: u64) -> u64 {
+    let Some(emulator) = emulator.as_mut() else {
+        return 0;
+    };
+    let cpu = &mut emulator.cpu;
+    let mut ran = 0;
+    while ran < count && !cpu.finished() && !cpu.halted() {
+        cpu.step();
+        ran += 1;
+    }
+    ran
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hack_emulator_read_ram(
+    emulator: *const HackEmulator,
+    address: u16,
+) -> u16 {
+    emulator
+        .as_ref()
+        .map_or(0, |emulator| emulator.cpu.read(address))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hack_emulator_write_ram(
+    emulator: *mut HackEmulator,
+    address: u16,
+    value: u16,
+) {
+    if let Some(emulator) = emulator.as_mut() {
+        emulator.cpu.write(address, value);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hack_emulator_pc(emulator: *const HackEmulator) -> u16 {
+    emulator.as_ref().map_or(0, |emulator| emulator.cpu.pc)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hack_emulator_free(emulator: *mut HackEmulator) {
+    if !emulator.is_null() {
+        drop(Box::from_raw(emulator));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    #[test]
+    fn assembles_and_runs() {
+        let source = b"@2\nD=A\n@3\nD=D+A\n@0\nM=D\n(END)\n@END\n0;JMP\n";
+        unsafe {
+            let assembly = hack_assemble_buffer(source.as_ptr(), source.len());
+            assert_eq!((*assembly).length, 8);
+            assert_eq!((*assembly).diagnostic_count, 0);
+            assert!((*assembly).ok);
+            let emulator = hack_emulator_new((*assembly).words, (*assembly).length);
+            hack_assembly_free(assembly);
+            assert_eq!(hack_emulator_step(emulator, 100), 6);
+            assert_eq!(hack_emulator_read_ram(emulator, 0), 5);
+            assert_eq!(hack_emulator_pc(emulator), 6);
+            hack_emulator_free(emulator);
+        }
+    }
+
+    #[test]
+    fn reports_diagnostics() {
+        let source = b"@1\nD=Q\n(\n";
+        unsafe {
+            let assembly = hack_assemble_buffer(source.as_ptr(), source.len());
+            assert!((*assembly).words.is_null() && !(*assembly).ok);
+            assert_eq!((*assembly).diagnostic_count, 2);
+            let error = &*(*assembly).diagnostics;
+            assert_eq!((error.severity, error.line), (1, 2));
+            assert!(!CStr::from_ptr(error.message).to_bytes().is_empty());
+            hack_assembly_free(assembly);
+            assert!(hack_emulator_new(ptr::null(), emulator::ROM_SIZE + 1).is_null());
+
+            // nothing to assemble is fine, and still gives no words
+            let assembly = hack_assemble_buffer(b"// empty\n".as_ptr(), 9);
+            assert!((*assembly).words.is_null() && (*assembly).ok);
+            hack_assembly_free(assembly);
+        }
+    }
+}
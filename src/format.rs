@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::io::BufRead;
+
+use crate::diagnostic::Diagnostic;
+use crate::{split_comment, HackLine};
+
+const INDENT: &str = "    ";
+
+// a lossless view of a line of source: unlike `parse`, which throws away
+// everything the assembler doesn't need, this keeps comments and blank lines
+// around so that we can write them back out
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Blank,
+    Comment(String),
+    Label(String, Option<String>),
+    Instruction(String, Option<String>),
+}
+
+fn read(input: impl BufRead) -> Result<Vec<Item>, Box<dyn Error + Send + Sync>> {
+    let mut items = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = if number == 0 {
+            crate::strip_bom(&line)
+        } else {
+            &line
+        };
+        let (code, comment) = split_comment(line);
+        let comment = comment.map(|comment| comment.trim_end().to_owned());
+
+        // whitespace is never significant inside an instruction
+        let code: String = code.split_whitespace().collect();
+        items.push(match (code.is_empty(), comment) {
+            (true, None) => Item::Blank,
+            (true, Some(comment)) => Item::Comment(comment),
+            (false, comment) => {
+                // make sure we only ever reformat valid code
+                let parsed = code
+                    .parse::<HackLine>()
+                    .map_err(|err: Diagnostic| err.on_line(number + 1, line))?;
+                if let HackLine::Label(_) = parsed {
+                    Item::Label(code, comment)
+                } else {
+                    Item::Instruction(code, comment)
+                }
+            }
+        });
+    }
+    Ok(items)
+}
+
+fn indent(item: &Item) -> &'static str {
+    match item {
+        Item::Instruction(..) => INDENT,
+        _ => "",
+    }
+}
+
+// formats a program: labels sit flush left with instructions indented beneath
+// them, inline comments are aligned within each run of consecutive code lines,
+// runs of blank lines collapse into one, and standalone comments are indented
+// to match the code that follows them
+pub fn format(input: impl BufRead) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut items = read(input)?;
+
+    // collapse repeated blank lines and trim them from both ends
+    items.dedup_by(|a, b| *a == Item::Blank && *b == Item::Blank);
+    while items.first() == Some(&Item::Blank) {
+        items.remove(0);
+    }
+    while items.last() == Some(&Item::Blank) {
+        items.pop();
+    }
+
+    let mut out = String::new();
+    let mut index = 0;
+    while index < items.len() {
+        match &items[index] {
+            Item::Blank => {
+                out.push('\n');
+                index += 1;
+            }
+            Item::Comment(comment) => {
+                let next = items[index..]
+                    .iter()
+                    .find(|item| !matches!(item, Item::Comment(_)));
+                out.push_str(next.map_or("", indent));
+                out.push_str(comment);
+                out.push('\n');
+                index += 1;
+            }
+            Item::Label(..) | Item::Instruction(..) => {
+                // a run of consecutive code lines shares a comment column
+                let run: Vec<_> = items[index..]
+                    .iter()
+                    .map_while(|item| match item {
+                        Item::Label(code, comment) | Item::Instruction(code, comment) => {
+                            Some((indent(item), code, comment))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let width = run
+                    .iter()
+                    .filter(|(_, _, comment)| comment.is_some())
+                    .map(|(indent, code, _)| indent.len() + code.len())
+                    .max()
+                    .unwrap_or(0);
+
+                for (indent, code, comment) in &run {
+                    out.push_str(indent);
+                    out.push_str(code);
+                    if let Some(comment) = comment {
+                        let padding = width - indent.len() - code.len() + 1;
+                        out.push_str(&" ".repeat(padding));
+                        out.push_str(comment);
+                    }
+                    out.push('\n');
+                }
+                index += run.len();
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_program() {
+        let input = "\
+// header comment
+
+
+@i   // counter
+  M = 1
+(LOOP)
+// loop body
+@i
+MD=M+1 // increment
+@LOOP
+D ; JLT // and again
+
+
+";
+        let expected = "\
+// header comment
+
+    @i // counter
+    M=1
+(LOOP)
+    // loop body
+    @i
+    MD=M+1 // increment
+    @LOOP
+    D;JLT  // and again
+";
+        assert_eq!(format(input.as_bytes()).unwrap(), expected);
+        // formatting is idempotent
+        assert_eq!(format(expected.as_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_code() {
+        assert!(format("D=Q\n".as_bytes()).is_err());
+    }
+}
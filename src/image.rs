@@ -0,0 +1,217 @@
+use crate::screen::{pixel, HEIGHT, WIDTH};
+
+// screen snapshots as image files, written by hand since they're simple
+// enough not to need an image library: both formats let us skip compression
+// entirely
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// a zlib stream of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(u16::MAX as usize).peekable();
+    if chunks.peek().is_none() {
+        out.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(chunks.peek().is_none() as u8);
+        let length = chunk.len() as u16;
+        out.extend(length.to_le_bytes());
+        out.extend((!length).to_le_bytes());
+        out.extend(chunk);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+// the screen as a 1-bit grayscale PNG, with Hack's set pixels black
+pub fn png(ram: &[u16]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(HEIGHT * (1 + WIDTH / 8));
+    for y in 0..HEIGHT {
+        // no filtering
+        raw.push(0);
+        for x in (0..WIDTH).step_by(8) {
+            let mut byte = 0;
+            for bit in 0..8 {
+                // grayscale 0 is black, and the leftmost pixel is the high bit
+                if !pixel(ram, x + bit, y) {
+                    byte |= 0x80 >> bit;
+                }
+            }
+            raw.push(byte);
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend((WIDTH as u32).to_be_bytes());
+    header.extend((HEIGHT as u32).to_be_bytes());
+    // bit depth 1, grayscale, deflate, no filter method, no interlacing
+    header.extend([1, 0, 0, 0, 0]);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+// LZW codes packed least significant bit first, as GIF wants them
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn push(&mut self, code: u16, width: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += width;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+// an animated GIF of the screen, one frame at a time
+pub struct Gif {
+    out: Vec<u8>,
+    // hundredths of a second to show each frame for
+    delay: u16,
+}
+
+impl Gif {
+    // a two-colour image needs the minimum code size of 2, making the clear
+    // and end codes 4 and 5
+    const CLEAR: u16 = 4;
+    const END: u16 = 5;
+
+    pub fn new(delay: u16) -> Self {
+        let mut out = b"GIF89a".to_vec();
+        out.extend((WIDTH as u16).to_le_bytes());
+        out.extend((HEIGHT as u16).to_le_bytes());
+        // a global colour table of four entries, no background, square pixels
+        out.extend([0x81, 0, 0]);
+        // white for clear pixels and black for set ones, then padding
+        out.extend([
+            0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ]);
+        // loop forever
+        out.extend(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+        Self { out, delay }
+    }
+
+    pub fn frame(&mut self, ram: &[u16]) {
+        self.out.extend([0x21, 0xF9, 0x04, 0]);
+        self.out.extend(self.delay.to_le_bytes());
+        self.out.extend([0, 0]);
+        self.out.push(0x2C);
+        self.out.extend([0, 0, 0, 0]);
+        self.out.extend((WIDTH as u16).to_le_bytes());
+        self.out.extend((HEIGHT as u16).to_le_bytes());
+        self.out.push(0);
+
+        // a clear code before every pair of pixels stops the decoder's table
+        // growing, so every code stays 3 bits wide and we never need to
+        // actually compress anything
+        let mut bits = Bits::default();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if x % 2 == 0 {
+                    bits.push(Self::CLEAR, 3);
+                }
+                bits.push(pixel(ram, x, y) as u16, 3);
+            }
+        }
+        bits.push(Self::END, 3);
+
+        self.out.push(2);
+        for block in bits.finish().chunks(255) {
+            self.out.push(block.len() as u8);
+            self.out.extend(block);
+        }
+        self.out.push(0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out.push(0x3B);
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::RAM_SIZE;
+    use crate::screen::SCREEN;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        let stored = zlib_stored(&[7; 70000]);
+        // two blocks, of which only the second is final
+        assert_eq!(&stored[2..5], [0, 0xFF, 0xFF]);
+        assert_eq!(stored[2 + 5 + 65535], 1);
+    }
+
+    #[test]
+    fn writes_images() {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[SCREEN as usize] = 0b1;
+
+        let png = png(&ram);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\x02\0\0\0\x01\0\x01\0"));
+        assert!(png.ends_with(b"IEND\xAE\x42\x60\x82"));
+        // the first row's filter byte, then the top-left pixel set
+        let pixels = png.windows(4).position(|w| w == b"IDAT").unwrap() + 4 + 2 + 5;
+        assert_eq!(png[pixels..pixels + 3], [0, 0x7F, 0xFF]);
+
+        let mut gif = Gif::new(10);
+        gif.frame(&ram);
+        gif.frame(&ram);
+        let gif = gif.finish();
+        assert!(gif.starts_with(b"GIF89a\0\x02\0\x01"));
+        assert_eq!(gif.last(), Some(&0x3B));
+    }
+}
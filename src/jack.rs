@@ -0,0 +1,852 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::diagnostic::Diagnostic;
+
+// the Jack language: tokens, the syntax tree, and a parser from one to the
+// other. Jack has no operator precedence, so binary expressions simply
+// associate to the left
+
+const KEYWORDS: [&str; 21] = [
+    "class",
+    "constructor",
+    "function",
+    "method",
+    "field",
+    "static",
+    "var",
+    "int",
+    "char",
+    "boolean",
+    "void",
+    "true",
+    "false",
+    "null",
+    "this",
+    "let",
+    "do",
+    "if",
+    "else",
+    "while",
+    "return",
+];
+
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Keyword(&'static str),
+    Symbol(char),
+    Int(u16),
+    Str(String),
+    Ident(String),
+    // the instructions of an `asm { ... }` block, by line
+    Asm(Vec<(usize, String)>),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Keyword(keyword) => write!(f, "`{}`", keyword),
+            Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+            Token::Int(value) => write!(f, "`{}`", value),
+            Token::Str(_) => write!(f, "a string"),
+            Token::Asm(_) => write!(f, "an asm block"),
+            Token::Ident(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+// a token along with where it is: a 1-based line, and a byte range within
+// that line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexed {
+    pub token: Token,
+    pub line: usize,
+    pub span: Range<usize>,
+}
+
+// an error pointing at part of a line of `source`
+pub fn error(source: &str, line: usize, span: Range<usize>, message: String) -> Diagnostic {
+    let text = source.lines().nth(line - 1).unwrap_or_default();
+    let mut diagnostic = Diagnostic::error(message).on_line(line, text);
+    diagnostic.span = span;
+    diagnostic
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Lexed>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let bytes = source.as_bytes();
+    let (mut at, mut line, mut line_start) = (0, 1, 0);
+    let lexed = |token, line, start: usize, end: usize, line_start: usize| Lexed {
+        token,
+        line,
+        span: start - line_start..end - line_start,
+    };
+
+    while at < bytes.len() {
+        let c = bytes[at];
+        let rest = &source[at..];
+        if c == b'\n' {
+            at += 1;
+            line += 1;
+            line_start = at;
+        } else if c.is_ascii_whitespace() {
+            at += 1;
+        } else if rest.starts_with("//") {
+            at += rest.find('\n').unwrap_or(rest.len());
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/").ok_or_else(|| {
+                error(
+                    source,
+                    line,
+                    at - line_start..at - line_start + 2,
+                    "unterminated comment".to_owned(),
+                )
+            })?;
+            // comments can span lines
+            for (offset, byte) in rest[..end + 4].bytes().enumerate() {
+                if byte == b'\n' {
+                    line += 1;
+                    line_start = at + offset + 1;
+                }
+            }
+            at += end + 4;
+        } else if c == b'"' {
+            let end = rest[1..]
+                .find(['"', '\n'])
+                .filter(|&end| rest.as_bytes()[end + 1] == b'"')
+                .ok_or_else(|| {
+                    error(
+                        source,
+                        line,
+                        at - line_start..at - line_start + 1,
+                        "unterminated string".to_owned(),
+                    )
+                })?;
+            let text = rest[1..end + 1].to_owned();
+            tokens.push(lexed(Token::Str(text), line, at, at + end + 2, line_start));
+            at += end + 2;
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let value = rest[..end]
+                .parse::<u16>()
+                .ok()
+                .filter(|&value| value <= 32767)
+                .ok_or_else(|| {
+                    error(
+                        source,
+                        line,
+                        at - line_start..at - line_start + end,
+                        format!("integer constant {} is bigger than 32767", &rest[..end]),
+                    )
+                })?;
+            tokens.push(lexed(Token::Int(value), line, at, at + end, line_start));
+            at += end;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let after = rest[end..].trim_start();
+            if word == "asm" && after.starts_with('{') {
+                // the block's Hack assembly isn't made of Jack tokens, so it
+                // gets taken as it is, up to the first `}`
+                let span = at - line_start..at - line_start + end;
+                let open = rest.len() - after.len();
+                let close = open
+                    + after.find('}').ok_or_else(|| {
+                        error(
+                            source,
+                            line,
+                            span.clone(),
+                            "unterminated asm block".to_owned(),
+                        )
+                    })?;
+                let start_line = line;
+                for (offset, byte) in rest[..open].bytes().enumerate() {
+                    if byte == b'\n' {
+                        line += 1;
+                        line_start = at + offset + 1;
+                    }
+                }
+                let mut code = Vec::new();
+                for (index, text) in rest[open + 1..close].split('\n').enumerate() {
+                    if index > 0 {
+                        line += 1;
+                        line_start = text.as_ptr() as usize - source.as_ptr() as usize;
+                    }
+                    let instruction = crate::split_comment(text).0.trim();
+                    if instruction.is_empty() {
+                        continue;
+                    }
+                    let column =
+                        instruction.as_ptr() as usize - source.as_ptr() as usize - line_start;
+                    instruction.parse::<crate::HackLine>().map_err(|err| {
+                        let span = err.span.start + column..err.span.end + column;
+                        error(source, line, span, err.message)
+                    })?;
+                    code.push((line, instruction.to_owned()));
+                }
+                tokens.push(Lexed {
+                    token: Token::Asm(code),
+                    line: start_line,
+                    span,
+                });
+                at += close + 1;
+                continue;
+            }
+            let token = match KEYWORDS.iter().find(|keyword| **keyword == word) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Ident(word.to_owned()),
+            };
+            tokens.push(lexed(token, line, at, at + end, line_start));
+            at += end;
+        } else if SYMBOLS.contains(c as char) {
+            tokens.push(lexed(
+                Token::Symbol(c as char),
+                line,
+                at,
+                at + 1,
+                line_start,
+            ));
+            at += 1;
+        } else {
+            let width = rest.chars().next().map_or(1, char::len_utf8);
+            Err(error(
+                source,
+                line,
+                at - line_start..at - line_start + width,
+                format!("unexpected character `{}`", &rest[..width]),
+            ))?;
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Class(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarKind {
+    Static,
+    Field,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Var {
+    pub name: String,
+    pub ty: Type,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    pub kind: SubroutineKind,
+    // `None` for void
+    pub returns: Option<Type>,
+    pub name: String,
+    pub params: Vec<Var>,
+    pub locals: Vec<Var>,
+    pub body: Vec<Statement>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Class {
+    pub name: String,
+    pub vars: Vec<(VarKind, Var)>,
+    pub subroutines: Vec<Subroutine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Lt,
+    Gt,
+    Eq,
+}
+
+impl BinaryOp {
+    fn from_symbol(symbol: char) -> Option<Self> {
+        Some(match symbol {
+            '+' => BinaryOp::Add,
+            '-' => BinaryOp::Sub,
+            '*' => BinaryOp::Mul,
+            '/' => BinaryOp::Div,
+            '&' => BinaryOp::And,
+            '|' => BinaryOp::Or,
+            '<' => BinaryOp::Lt,
+            '>' => BinaryOp::Gt,
+            '=' => BinaryOp::Eq,
+            _ => return None,
+        })
+    }
+
+    pub fn symbol(self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+            BinaryOp::And => '&',
+            BinaryOp::Or => '|',
+            BinaryOp::Lt => '<',
+            BinaryOp::Gt => '>',
+            BinaryOp::Eq => '=',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    // a class or variable name, for `Thing.name(...)`
+    pub receiver: Option<String>,
+    pub name: String,
+    pub args: Vec<Expr>,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(receiver) = &self.receiver {
+            write!(f, "{}.", receiver)?;
+        }
+        write!(f, "{}(", self.name)?;
+        for (index, arg) in self.args.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Int(u16),
+    Str(String),
+    True,
+    False,
+    Null,
+    This,
+    Var(String),
+    Index(String, Box<Expr>),
+    Call(Call),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Int(value) => write!(f, "{}", value),
+            Expr::Str(text) => write!(f, "{:?}", text),
+            Expr::True => write!(f, "true"),
+            Expr::False => write!(f, "false"),
+            Expr::Null => write!(f, "null"),
+            Expr::This => write!(f, "this"),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::Index(name, index) => write!(f, "{}[{}]", name, index),
+            Expr::Call(call) => write!(f, "{}", call),
+            Expr::Unary(UnaryOp::Neg, term) => write!(f, "-{}", term),
+            Expr::Unary(UnaryOp::Not, term) => write!(f, "~{}", term),
+            Expr::Binary(left, op, right) => {
+                write!(f, "{} {} ", left, op.symbol())?;
+                // without precedence, a compound right-hand side needs
+                // brackets to mean the same thing
+                match **right {
+                    Expr::Binary(..) => write!(f, "({})", right),
+                    _ => write!(f, "{}", right),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementKind {
+    Let {
+        name: String,
+        index: Option<Expr>,
+        value: Expr,
+    },
+    If {
+        condition: Expr,
+        then: Vec<Statement>,
+        otherwise: Vec<Statement>,
+    },
+    While {
+        condition: Expr,
+        body: Vec<Statement>,
+    },
+    Do(Call),
+    Return(Option<Expr>),
+    // Hack assembly to pass straight through, by line
+    Asm(Vec<(usize, String)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub line: usize,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Lexed>,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at).map(|lexed| &lexed.token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.at)
+            .or(self.tokens.last())
+            .map_or(1, |lexed| lexed.line)
+    }
+
+    // an error at the next token, or the end of the file
+    fn error(&self, message: String) -> Diagnostic {
+        match self.tokens.get(self.at) {
+            Some(lexed) => error(self.source, lexed.line, lexed.span.clone(), message),
+            None => {
+                let line = self.source.lines().count().max(1);
+                let end = self.source.lines().last().map_or(0, str::len);
+                error(self.source, line, end..end, message)
+            }
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> Diagnostic {
+        match self.peek() {
+            Some(token) => self.error(format!("expected {}, found {}", expected, token)),
+            None => self.error(format!("expected {}, found the end of the file", expected)),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.at += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn symbol(&mut self, symbol: char) -> Result<(), Diagnostic> {
+        if self.eat(&Token::Symbol(symbol)) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", symbol)))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &'static str) -> Result<(), Diagnostic> {
+        if self.eat(&Token::Keyword(keyword)) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", keyword)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, Diagnostic> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let name = name.clone();
+                self.at += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn ty(&mut self) -> Result<Type, Diagnostic> {
+        let ty = match self.peek() {
+            Some(Token::Keyword("int")) => Type::Int,
+            Some(Token::Keyword("char")) => Type::Char,
+            Some(Token::Keyword("boolean")) => Type::Boolean,
+            Some(Token::Ident(name)) => Type::Class(name.clone()),
+            _ => return Err(self.unexpected("a type")),
+        };
+        self.at += 1;
+        Ok(ty)
+    }
+
+    // `type name, name, ...;`
+    fn var_names(&mut self) -> Result<Vec<Var>, Diagnostic> {
+        let ty = self.ty()?;
+        let mut vars = Vec::new();
+        loop {
+            let line = self.line();
+            let name = self.ident()?;
+            vars.push(Var {
+                name,
+                ty: ty.clone(),
+                line,
+            });
+            if !self.eat(&Token::Symbol(',')) {
+                break;
+            }
+        }
+        self.symbol(';')?;
+        Ok(vars)
+    }
+
+    fn class(&mut self) -> Result<Class, Diagnostic> {
+        self.keyword("class")?;
+        let name = self.ident()?;
+        self.symbol('{')?;
+        let mut vars = Vec::new();
+        loop {
+            let kind = match self.peek() {
+                Some(Token::Keyword("static")) => VarKind::Static,
+                Some(Token::Keyword("field")) => VarKind::Field,
+                _ => break,
+            };
+            self.at += 1;
+            vars.extend(self.var_names()?.into_iter().map(|var| (kind, var)));
+        }
+        let mut subroutines = Vec::new();
+        while !self.eat(&Token::Symbol('}')) {
+            subroutines.push(self.subroutine()?);
+        }
+        if self.peek().is_some() {
+            Err(self.unexpected("the end of the file"))?;
+        }
+        Ok(Class {
+            name,
+            vars,
+            subroutines,
+        })
+    }
+
+    fn subroutine(&mut self) -> Result<Subroutine, Diagnostic> {
+        let line = self.line();
+        let kind = match self.next() {
+            Some(Token::Keyword("constructor")) => SubroutineKind::Constructor,
+            Some(Token::Keyword("function")) => SubroutineKind::Function,
+            Some(Token::Keyword("method")) => SubroutineKind::Method,
+            _ => {
+                self.at -= 1;
+                return Err(self.unexpected("a subroutine or `}`"));
+            }
+        };
+        let returns = if self.eat(&Token::Keyword("void")) {
+            None
+        } else {
+            Some(self.ty()?)
+        };
+        let name = self.ident()?;
+
+        self.symbol('(')?;
+        let mut params = Vec::new();
+        if !self.eat(&Token::Symbol(')')) {
+            loop {
+                let ty = self.ty()?;
+                let line = self.line();
+                let name = self.ident()?;
+                params.push(Var { name, ty, line });
+                if self.eat(&Token::Symbol(')')) {
+                    break;
+                }
+                self.symbol(',')?;
+            }
+        }
+
+        self.symbol('{')?;
+        let mut locals = Vec::new();
+        while self.eat(&Token::Keyword("var")) {
+            locals.extend(self.var_names()?);
+        }
+        let body = self.statements()?;
+        Ok(Subroutine {
+            kind,
+            returns,
+            name,
+            params,
+            locals,
+            body,
+            line,
+        })
+    }
+
+    // statements up to and including the closing `}`
+    fn statements(&mut self) -> Result<Vec<Statement>, Diagnostic> {
+        let mut statements = Vec::new();
+        while !self.eat(&Token::Symbol('}')) {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, Diagnostic> {
+        self.symbol('{')?;
+        self.statements()
+    }
+
+    fn condition(&mut self) -> Result<Expr, Diagnostic> {
+        self.symbol('(')?;
+        let condition = self.expr()?;
+        self.symbol(')')?;
+        Ok(condition)
+    }
+
+    fn statement(&mut self) -> Result<Statement, Diagnostic> {
+        let line = self.line();
+        let kind = match self.next() {
+            Some(Token::Keyword("let")) => {
+                let name = self.ident()?;
+                let index = if self.eat(&Token::Symbol('[')) {
+                    let index = self.expr()?;
+                    self.symbol(']')?;
+                    Some(index)
+                } else {
+                    None
+                };
+                self.symbol('=')?;
+                let value = self.expr()?;
+                self.symbol(';')?;
+                StatementKind::Let { name, index, value }
+            }
+            Some(Token::Keyword("if")) => {
+                let condition = self.condition()?;
+                let then = self.block()?;
+                let otherwise = if self.eat(&Token::Keyword("else")) {
+                    self.block()?
+                } else {
+                    Vec::new()
+                };
+                StatementKind::If {
+                    condition,
+                    then,
+                    otherwise,
+                }
+            }
+            Some(Token::Keyword("while")) => {
+                let condition = self.condition()?;
+                let body = self.block()?;
+                StatementKind::While { condition, body }
+            }
+            Some(Token::Keyword("do")) => {
+                let name = self.ident()?;
+                let call = self.call(name)?;
+                self.symbol(';')?;
+                StatementKind::Do(call)
+            }
+            Some(Token::Asm(code)) => StatementKind::Asm(code),
+            Some(Token::Keyword("return")) => {
+                let value = if self.eat(&Token::Symbol(';')) {
+                    None
+                } else {
+                    let value = self.expr()?;
+                    self.symbol(';')?;
+                    Some(value)
+                };
+                StatementKind::Return(value)
+            }
+            _ => {
+                self.at -= 1;
+                return Err(self.unexpected("a statement"));
+            }
+        };
+        Ok(Statement { kind, line })
+    }
+
+    // the rest of a subroutine call, after its first name
+    fn call(&mut self, first: String) -> Result<Call, Diagnostic> {
+        let (receiver, name) = if self.eat(&Token::Symbol('.')) {
+            (Some(first), self.ident()?)
+        } else {
+            (None, first)
+        };
+        self.symbol('(')?;
+        let mut args = Vec::new();
+        if !self.eat(&Token::Symbol(')')) {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(&Token::Symbol(')')) {
+                    break;
+                }
+                self.symbol(',')?;
+            }
+        }
+        Ok(Call {
+            receiver,
+            name,
+            args,
+        })
+    }
+
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.term()?;
+        while let Some(op) = match self.peek() {
+            Some(Token::Symbol(symbol)) => BinaryOp::from_symbol(*symbol),
+            _ => None,
+        } {
+            self.at += 1;
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
+        Ok(match self.next() {
+            Some(Token::Int(value)) => Expr::Int(value),
+            Some(Token::Str(text)) => Expr::Str(text),
+            Some(Token::Keyword("true")) => Expr::True,
+            Some(Token::Keyword("false")) => Expr::False,
+            Some(Token::Keyword("null")) => Expr::Null,
+            Some(Token::Keyword("this")) => Expr::This,
+            Some(Token::Symbol('(')) => {
+                let expr = self.expr()?;
+                self.symbol(')')?;
+                expr
+            }
+            Some(Token::Symbol('-')) => Expr::Unary(UnaryOp::Neg, Box::new(self.term()?)),
+            Some(Token::Symbol('~')) => Expr::Unary(UnaryOp::Not, Box::new(self.term()?)),
+            Some(Token::Ident(name)) => match self.peek() {
+                Some(Token::Symbol('[')) => {
+                    self.at += 1;
+                    let index = self.expr()?;
+                    self.symbol(']')?;
+                    Expr::Index(name, Box::new(index))
+                }
+                Some(Token::Symbol('(' | '.')) => Expr::Call(self.call(name)?),
+                _ => Expr::Var(name),
+            },
+            _ => {
+                self.at -= 1;
+                return Err(self.unexpected("an expression"));
+            }
+        })
+    }
+}
+
+pub fn parse(source: &str) -> Result<Class, Diagnostic> {
+    let tokens = tokenize(source)?;
+    Parser {
+        source,
+        tokens,
+        at: 0,
+    }
+    .class()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes() {
+        let tokens = tokenize("let x = \"hi there\"; /* a\ncomment */ do Foo.bar(12);\n").unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|lexed| lexed.token.clone()).collect();
+        assert_eq!(kinds[0], Token::Keyword("let"));
+        assert_eq!(kinds[3], Token::Str("hi there".to_owned()));
+        assert_eq!(kinds[10], Token::Int(12));
+        // `do` comes after the comment's line break
+        assert_eq!((tokens[5].line, tokens[5].span.clone()), (2, 11..13));
+        assert!(tokenize("let x = 40000;").is_err());
+        assert!(tokenize("let s = \"unterminated;\n").is_err());
+    }
+
+    #[test]
+    fn takes_asm_as_it_is() {
+        let tokens =
+            tokenize("do f(); asm {\n  @SCREEN // first word\n\n  M=-1\n} return;").unwrap();
+        assert_eq!(
+            tokens[5].token,
+            Token::Asm(vec![(2, "@SCREEN".to_owned()), (4, "M=-1".to_owned())])
+        );
+        assert_eq!(
+            tokens[6],
+            Lexed {
+                token: Token::Keyword("return"),
+                line: 5,
+                span: 2..8
+            }
+        );
+        // `asm` on its own is still a name
+        assert_eq!(
+            tokenize("asm").unwrap()[0].token,
+            Token::Ident("asm".to_owned())
+        );
+
+        let err = tokenize("asm {\n  D=Q\n}").unwrap_err();
+        assert_eq!((err.line, err.span.clone()), (2, 4..5));
+        assert!(tokenize("asm { @0").is_err());
+    }
+
+    #[test]
+    fn parses_without_precedence() {
+        let class = parse(
+            "class Main {
+                field int x, y;
+                method int f(int a) { var Array b; let b[a] = x + y * -2; return b[a]; }
+            }",
+        )
+        .unwrap();
+        assert_eq!(class.vars.len(), 2);
+        let f = &class.subroutines[0];
+        assert_eq!(
+            (f.kind, f.params.len(), f.locals.len()),
+            (SubroutineKind::Method, 1, 1)
+        );
+        let StatementKind::Let { value, .. } = &f.body[0].kind else {
+            panic!("expected a let");
+        };
+        // (x + y) * -2
+        assert_eq!(value.to_string(), "x + y * -2");
+        assert!(matches!(value, Expr::Binary(_, BinaryOp::Mul, _)));
+
+        let err = parse("class Main { function void f() { let = 1; } }").unwrap_err();
+        assert_eq!(err.message, "expected a name, found `=`");
+        assert_eq!(err.span, 37..38);
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::{self, Read};
+use std::ops::Range;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::peripheral::Peripheral;
+
+// the memory-mapped keyboard: the code of the key currently held, or 0
+pub const KBD: u16 = 24576;
+
+// Hack's codes for keys outside printable ASCII
+pub const NEWLINE: u16 = 128;
+pub const BACKSPACE: u16 = 129;
+pub const LEFT: u16 = 130;
+pub const UP: u16 = 131;
+pub const RIGHT: u16 = 132;
+pub const DOWN: u16 = 133;
+pub const HOME: u16 = 134;
+pub const END: u16 = 135;
+pub const PAGE_UP: u16 = 136;
+pub const PAGE_DOWN: u16 = 137;
+pub const INSERT: u16 = 138;
+pub const DELETE: u16 = 139;
+pub const ESCAPE: u16 = 140;
+pub const F1: u16 = 141;
+
+// the escape sequences terminals send for special keys, after the `ESC`
+const SEQUENCES: &[(&[u8], u16)] = &[
+    (b"[A", UP),
+    (b"[B", DOWN),
+    (b"[C", RIGHT),
+    (b"[D", LEFT),
+    (b"[H", HOME),
+    (b"[F", END),
+    (b"[1~", HOME),
+    (b"[4~", END),
+    (b"[2~", INSERT),
+    (b"[3~", DELETE),
+    (b"[5~", PAGE_UP),
+    (b"[6~", PAGE_DOWN),
+    (b"OP", F1),
+    (b"OQ", F1 + 1),
+    (b"OR", F1 + 2),
+    (b"OS", F1 + 3),
+    (b"[15~", F1 + 4),
+    (b"[17~", F1 + 5),
+    (b"[18~", F1 + 6),
+    (b"[19~", F1 + 7),
+    (b"[20~", F1 + 8),
+    (b"[21~", F1 + 9),
+    (b"[23~", F1 + 10),
+    (b"[24~", F1 + 11),
+];
+
+// names for keys that can't be written as themselves
+const NAMES: &[(&str, u16)] = &[
+    ("none", 0),
+    ("space", b' ' as u16),
+    ("newline", NEWLINE),
+    ("enter", NEWLINE),
+    ("backspace", BACKSPACE),
+    ("left", LEFT),
+    ("up", UP),
+    ("right", RIGHT),
+    ("down", DOWN),
+    ("home", HOME),
+    ("end", END),
+    ("pageup", PAGE_UP),
+    ("pagedown", PAGE_DOWN),
+    ("insert", INSERT),
+    ("delete", DELETE),
+    ("escape", ESCAPE),
+];
+
+// the code for a key given by name: a single printable character stands
+// for itself, and otherwise it's one of `NAMES` or `f1` to `f12`, in any case
+pub fn code(name: &str) -> Option<u16> {
+    let mut chars = name.chars();
+    if let (Some(c @ ' '..='~'), None) = (chars.next(), chars.next()) {
+        return Some(c as u16);
+    }
+    let name = name.to_ascii_lowercase();
+    if let Some(&(_, code)) = NAMES.iter().find(|(key, _)| *key == name) {
+        return Some(code);
+    }
+    match name.strip_prefix('f')?.parse::<u16>().ok()? {
+        n @ 1..=12 => Some(F1 + n - 1),
+        _ => None,
+    }
+}
+
+// checking the keyboard costs more than an instruction, and nobody types
+// that fast
+const POLL_CYCLES: u64 = 1024;
+
+// what the terminal sends for ctrl-c once we've taken it out of its usual
+// mode
+const INTERRUPT: u8 = 0x03;
+
+// terminals only tell us when a key is pressed (and again as it repeats),
+// never when it's released, so we count a key as held for this long after
+// we last heard about it
+const HOLD: Duration = Duration::from_millis(200);
+
+// the Hack key codes for a chunk of terminal input. Terminals send each
+// escape sequence in a single write, so an `ESC` ending a chunk is the
+// escape key itself
+pub fn decode(mut bytes: &[u8]) -> Vec<u16> {
+    let mut keys = Vec::new();
+    while let Some((&byte, rest)) = bytes.split_first() {
+        bytes = rest;
+        let key = match byte {
+            b'\r' | b'\n' => NEWLINE,
+            0x7f | 0x08 => BACKSPACE,
+            0x1b => match SEQUENCES
+                .iter()
+                .find(|(sequence, _)| bytes.starts_with(sequence))
+            {
+                Some((sequence, key)) => {
+                    bytes = &bytes[sequence.len()..];
+                    *key
+                }
+                None => ESCAPE,
+            },
+            b' '..=b'~' => byte as u16,
+            // other control characters have no Hack equivalent
+            _ => continue,
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+// feeds the host's keystrokes to a running program. Creating one puts the
+// terminal into a mode where we see keys as they're typed, and dropping it
+// puts the terminal back
+pub struct Keyboard {
+    input: Receiver<Vec<u8>>,
+    held: Option<(u16, Instant)>,
+    interrupted: bool,
+    // the terminal settings to restore
+    saved: String,
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("`stty` failed: is stdin a terminal?"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+impl Keyboard {
+    pub fn open() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        // no line buffering, echo, or signals: ctrl-c comes to us, so that
+        // we get to restore the terminal
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+
+        let (sender, input) = mpsc::channel();
+        // blocking reads are all std offers, so they get a thread of their
+        // own; it's left blocked when we're done, and dies with the process
+        thread::spawn(move || {
+            let mut stdin = io::stdin();
+            let mut buffer = [0; 64];
+            while let Ok(read @ 1..) = stdin.read(&mut buffer) {
+                if sender.send(buffer[..read].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            input,
+            held: None,
+            interrupted: false,
+            saved,
+        })
+    }
+
+    // the code to put in RAM[KBD] right now
+    pub fn poll(&mut self) -> u16 {
+        while let Ok(bytes) = self.input.try_recv() {
+            self.interrupted |= bytes.contains(&INTERRUPT);
+            if let Some(&key) = decode(&bytes).last() {
+                self.held = Some((key, Instant::now()));
+            }
+        }
+        match self.held {
+            Some((key, since)) if since.elapsed() < HOLD => key,
+            _ => 0,
+        }
+    }
+
+    // whether the user has pressed ctrl-c
+    pub fn interrupted(&self) -> bool {
+        self.interrupted
+    }
+}
+
+impl Peripheral for Keyboard {
+    fn range(&self) -> Range<u16> {
+        KBD..KBD + 1
+    }
+
+    fn update(&mut self, cycles: u64, words: &mut [u16]) {
+        if cycles.is_multiple_of(POLL_CYCLES) {
+            words[0] = self.poll();
+        }
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_keys() {
+        assert_eq!(decode(b"a Z~"), [97, 32, 90, 126]);
+        assert_eq!(decode(b"\r\x7f"), [NEWLINE, BACKSPACE]);
+        assert_eq!(
+            decode(b"\x1b[A\x1b[D\x1b[3~\x1bOP\x1b[24~"),
+            [UP, LEFT, DELETE, F1, F1 + 11]
+        );
+        assert_eq!(decode(b"\x1b"), [ESCAPE]);
+        assert_eq!(decode(b"\x01x"), [b'x' as u16]);
+    }
+
+    #[test]
+    fn names_keys() {
+        assert_eq!(code("a"), Some(97));
+        assert_eq!(code("A"), Some(65));
+        assert_eq!(code("Enter"), Some(NEWLINE));
+        assert_eq!(code("none"), Some(0));
+        assert_eq!(code("F12"), Some(F1 + 11));
+        assert_eq!(code("f13"), None);
+        assert_eq!(code("frobnicate"), None);
+    }
+}
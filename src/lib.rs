@@ -0,0 +1,1317 @@
+// the Hack assembler, and everything built around it, as a library: the
+// `hack` binary is a command line over this. The usual way in is
+// `parse_program`, which reports every bad line at once, followed by
+// `assemble_lines` to write the `.hack` text; `builder` makes programs
+// without going through text at all, and `emulator` runs them
+
+use core::str::FromStr;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use itertools::Itertools;
+
+use crate::diagnostic::Diagnostic;
+use crate::emit::Emitter;
+use crate::error::HackError;
+use crate::predefined::SymbolSet;
+
+pub mod allocate;
+pub mod backtrace;
+pub mod builder;
+pub mod callgraph;
+pub mod cfg;
+pub mod compat;
+pub mod compile;
+pub mod coverage;
+pub mod dap;
+pub mod debug;
+pub mod diagnostic;
+pub mod diff;
+pub mod disassemble;
+pub mod emit;
+pub mod emulator;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod font;
+pub mod format;
+pub mod image;
+pub mod jack;
+pub mod json;
+pub mod keyboard;
+pub mod link;
+pub mod lint;
+pub mod lsp;
+pub mod optimize;
+pub mod os;
+pub mod out;
+pub mod peripheral;
+pub mod predefined;
+pub mod profile;
+pub mod repl;
+pub mod run;
+pub mod screen;
+pub mod script;
+pub mod selftest;
+pub mod snapshot;
+pub mod sourcemap;
+pub mod stats;
+pub mod stream;
+pub mod translate;
+pub mod vcd;
+pub mod verify;
+pub mod vm;
+pub mod vmdebug;
+pub mod xref;
+
+pub const PREDEFINED_SYMBOLS: [(&str, u16); 23] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+// each part of an instruction encodes to a few bits, right-aligned, which
+// whoever holds it shifts into place
+trait Assemble {
+    fn assemble(&self) -> u16;
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Null,
+    M,
+    D,
+    MD,
+    A,
+    AM,
+    AD,
+    AMD,
+}
+
+impl Destination {
+    const ALL: [Destination; 8] = [
+        Destination::Null,
+        Destination::M,
+        Destination::D,
+        Destination::MD,
+        Destination::A,
+        Destination::AM,
+        Destination::AD,
+        Destination::AMD,
+    ];
+
+    pub fn writes(self, register: Destination) -> bool {
+        self as u8 & register as u8 != 0
+    }
+}
+
+// the registers can come in any order, as in `DM` for `MD`, but only once
+impl FromStr for Destination {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(());
+        }
+        let mut bits = 0;
+        for register in s.chars() {
+            let bit = match register {
+                'A' => Destination::A,
+                'M' => Destination::M,
+                'D' => Destination::D,
+                _ => return Err(()),
+            } as u8;
+            if bits & bit != 0 {
+                return Err(());
+            }
+            bits |= bit;
+        }
+        Ok(Destination::ALL[bits as usize])
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Null => Ok(()),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+// the registers written, in the order the canonical mnemonic names them
+impl From<Destination> for json::Json {
+    fn from(dest: Destination) -> Self {
+        [Destination::A, Destination::M, Destination::D]
+            .into_iter()
+            .filter(|register| dest.writes(*register))
+            .map(|register| format!("{:?}", register))
+            .collect()
+    }
+}
+
+impl Assemble for Destination {
+    fn assemble(&self) -> u16 {
+        *self as u16
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, parse_display::FromStr)]
+pub enum Jump {
+    Null,
+    JGT,
+    JEQ,
+    JGE,
+    JLT,
+    JNE,
+    JLE,
+    JMP,
+}
+
+impl Jump {
+    const ALL: [Jump; 8] = [
+        Jump::Null,
+        Jump::JGT,
+        Jump::JEQ,
+        Jump::JGE,
+        Jump::JLT,
+        Jump::JNE,
+        Jump::JLE,
+        Jump::JMP,
+    ];
+}
+
+impl fmt::Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Jump::Null => Ok(()),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl From<Jump> for json::Json {
+    fn from(jump: Jump) -> Self {
+        match jump {
+            Jump::Null => json::Json::Null,
+            other => format!("{:?}", other).into(),
+        }
+    }
+}
+
+impl Assemble for Jump {
+    fn assemble(&self) -> u16 {
+        *self as u16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, parse_display::FromStr)]
+pub enum AM {
+    A,
+    M,
+}
+
+impl Assemble for AM {
+    fn assemble(&self) -> u16 {
+        *self as u16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Computation {
+    Zero,
+    One,
+    Neg1,
+    D,
+    X(AM),
+    NegD,
+    NegX(AM),
+    DPlusOne,
+    XPlusOne(AM),
+    DMinusOne,
+    XMinusOne(AM),
+    DPlusX(AM),
+    DMinusX(AM),
+    XMinusD(AM),
+    NotD,
+    NotX(AM),
+    DAndX(AM),
+    DOrX(AM),
+    // only on `Target::HackExt`
+    DShiftLeft,
+    XShiftLeft(AM),
+    DShiftRight,
+    XShiftRight(AM),
+    DTimesX(AM),
+}
+
+impl FromStr for Computation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Computation as C;
+        match s {
+            "0" => Ok(C::Zero),
+            "1" => Ok(C::One),
+            "-1" => Ok(C::Neg1),
+            "D" => Ok(C::D),
+            "A" => Ok(C::X(AM::A)),
+            "M" => Ok(C::X(AM::M)),
+            "!D" => Ok(C::NotD),
+            "!A" => Ok(C::NotX(AM::A)),
+            "!M" => Ok(C::NotX(AM::M)),
+            "-D" => Ok(C::NegD),
+            "-A" => Ok(C::NegX(AM::A)),
+            "-M" => Ok(C::NegX(AM::M)),
+            "D+1" => Ok(C::DPlusOne),
+            "A+1" => Ok(C::XPlusOne(AM::A)),
+            "M+1" => Ok(C::XPlusOne(AM::M)),
+            "D-1" => Ok(C::DMinusOne),
+            "A-1" => Ok(C::XMinusOne(AM::A)),
+            "M-1" => Ok(C::XMinusOne(AM::M)),
+            "D+A" => Ok(C::DPlusX(AM::A)),
+            "D+M" => Ok(C::DPlusX(AM::M)),
+            "D-A" => Ok(C::DMinusX(AM::A)),
+            "D-M" => Ok(C::DMinusX(AM::M)),
+            "A-D" => Ok(C::XMinusD(AM::A)),
+            "M-D" => Ok(C::XMinusD(AM::M)),
+            "D&A" => Ok(C::DAndX(AM::A)),
+            "D&M" => Ok(C::DAndX(AM::M)),
+            "D|A" => Ok(C::DOrX(AM::A)),
+            "D|M" => Ok(C::DOrX(AM::M)),
+            "D<<" => Ok(C::DShiftLeft),
+            "A<<" => Ok(C::XShiftLeft(AM::A)),
+            "M<<" => Ok(C::XShiftLeft(AM::M)),
+            "D>>" => Ok(C::DShiftRight),
+            "A>>" => Ok(C::XShiftRight(AM::A)),
+            "M>>" => Ok(C::XShiftRight(AM::M)),
+            "D*A" => Ok(C::DTimesX(AM::A)),
+            "D*M" => Ok(C::DTimesX(AM::M)),
+            other => Err(format!("Invalid comp: {}", other)),
+        }
+    }
+}
+
+impl Computation {
+    const ALL: [Computation; 28] = {
+        use Computation as C;
+        [
+            C::Zero,
+            C::One,
+            C::Neg1,
+            C::D,
+            C::X(AM::A),
+            C::X(AM::M),
+            C::NegD,
+            C::NegX(AM::A),
+            C::NegX(AM::M),
+            C::DPlusOne,
+            C::XPlusOne(AM::A),
+            C::XPlusOne(AM::M),
+            C::DMinusOne,
+            C::XMinusOne(AM::A),
+            C::XMinusOne(AM::M),
+            C::DPlusX(AM::A),
+            C::DPlusX(AM::M),
+            C::DMinusX(AM::A),
+            C::DMinusX(AM::M),
+            C::XMinusD(AM::A),
+            C::XMinusD(AM::M),
+            C::NotD,
+            C::NotX(AM::A),
+            C::NotX(AM::M),
+            C::DAndX(AM::A),
+            C::DAndX(AM::M),
+            C::DOrX(AM::A),
+            C::DOrX(AM::M),
+        ]
+    };
+
+    // what `Target::HackExt` adds to them
+    const EXTENDED: [Computation; 8] = {
+        use Computation as C;
+        [
+            C::DShiftLeft,
+            C::XShiftLeft(AM::A),
+            C::XShiftLeft(AM::M),
+            C::DShiftRight,
+            C::XShiftRight(AM::A),
+            C::XShiftRight(AM::M),
+            C::DTimesX(AM::A),
+            C::DTimesX(AM::M),
+        ]
+    };
+
+    pub fn extended(&self) -> bool {
+        Computation::EXTENDED.contains(self)
+    }
+
+    // the top three bits of a C-instruction computing this. Extensions go in
+    // the two the course leaves unused, so that no Hack CPU mistakes them
+    // for one of its own instructions
+    pub fn marker(&self) -> u16 {
+        if self.extended() {
+            0b101
+        } else {
+            0b111
+        }
+    }
+
+    // which of A or M this computation reads, if either
+    pub fn operand(&self) -> Option<&AM> {
+        use Computation as C;
+        match self {
+            C::X(x)
+            | C::NegX(x)
+            | C::XPlusOne(x)
+            | C::XMinusOne(x)
+            | C::XMinusD(x)
+            | C::DPlusX(x)
+            | C::DMinusX(x)
+            | C::NotX(x)
+            | C::DAndX(x)
+            | C::DOrX(x)
+            | C::XShiftLeft(x)
+            | C::XShiftRight(x)
+            | C::DTimesX(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Computation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Computation as C;
+        match self {
+            C::Zero => write!(f, "0"),
+            C::One => write!(f, "1"),
+            C::Neg1 => write!(f, "-1"),
+            C::D => write!(f, "D"),
+            C::X(x) => write!(f, "{:?}", x),
+            C::NegD => write!(f, "-D"),
+            C::NegX(x) => write!(f, "-{:?}", x),
+            C::DPlusOne => write!(f, "D+1"),
+            C::XPlusOne(x) => write!(f, "{:?}+1", x),
+            C::DMinusOne => write!(f, "D-1"),
+            C::XMinusOne(x) => write!(f, "{:?}-1", x),
+            C::DPlusX(x) => write!(f, "D+{:?}", x),
+            C::DMinusX(x) => write!(f, "D-{:?}", x),
+            C::XMinusD(x) => write!(f, "{:?}-D", x),
+            C::NotD => write!(f, "!D"),
+            C::NotX(x) => write!(f, "!{:?}", x),
+            C::DAndX(x) => write!(f, "D&{:?}", x),
+            C::DOrX(x) => write!(f, "D|{:?}", x),
+            C::DShiftLeft => write!(f, "D<<"),
+            C::XShiftLeft(x) => write!(f, "{:?}<<", x),
+            C::DShiftRight => write!(f, "D>>"),
+            C::XShiftRight(x) => write!(f, "{:?}>>", x),
+            C::DTimesX(x) => write!(f, "D*{:?}", x),
+        }
+    }
+}
+
+impl From<Computation> for json::Json {
+    fn from(comp: Computation) -> Self {
+        json::Json::object([
+            ("expression", comp.to_string().into()),
+            ("operand", comp.operand().map(|x| format!("{:?}", x)).into()),
+        ])
+    }
+}
+
+impl Assemble for Computation {
+    fn assemble(&self) -> u16 {
+        let a = self.operand().map_or(0, |x| x.assemble());
+        let c = match self {
+            Computation::Zero => 0b101010,
+            Computation::One => 0b111111,
+            Computation::Neg1 => 0b111010,
+            Computation::D => 0b001100,
+            Computation::X(_) => 0b110000,
+            Computation::NegD => 0b001111,
+            Computation::NegX(_) => 0b110011,
+            Computation::DPlusOne => 0b011111,
+            Computation::XPlusOne(_) => 0b110111,
+            Computation::DMinusOne => 0b001110,
+            Computation::XMinusOne(_) => 0b110010,
+            Computation::DPlusX(_) => 0b000010,
+            Computation::DMinusX(_) => 0b010011,
+            Computation::XMinusD(_) => 0b000111,
+            Computation::NotD => 0b001101,
+            Computation::NotX(_) => 0b110001,
+            Computation::DAndX(_) => 0b000000,
+            Computation::DOrX(_) => 0b010101,
+            // behind `Computation::marker`'s `0b101`
+            Computation::DShiftLeft => 0b110000,
+            Computation::XShiftLeft(_) => 0b100000,
+            Computation::DShiftRight => 0b010000,
+            Computation::XShiftRight(_) => 0b000000,
+            Computation::DTimesX(_) => 0b000001,
+        };
+        a << 6 | c
+    }
+}
+
+// the machine a program is assembled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    // the course's Hack
+    Hack,
+    // Hack with the shift and multiply instructions some FPGA builds add
+    HackExt,
+}
+
+impl Target {
+    pub fn supports(self, comp: Computation) -> bool {
+        self == Target::HackExt || !comp.extended()
+    }
+}
+
+// symbols are borrowed from the source where we can, since a big program
+// would otherwise make an allocation for every one of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HackLine<'a> {
+    Label(Cow<'a, str>),
+    AImmediate(u16),
+    ALocation(Cow<'a, str>),
+    C(Computation, Destination, Jump),
+}
+
+impl<'a> AsRef<HackLine<'a>> for HackLine<'a> {
+    fn as_ref(&self) -> &HackLine<'a> {
+        self
+    }
+}
+
+impl HackLine<'_> {
+    // a copy that no longer borrows from the source
+    pub fn into_owned(self) -> HackLine<'static> {
+        match self {
+            HackLine::Label(label) => HackLine::Label(Cow::Owned(label.into_owned())),
+            HackLine::AImmediate(imm) => HackLine::AImmediate(imm),
+            HackLine::ALocation(name) => HackLine::ALocation(Cow::Owned(name.into_owned())),
+            HackLine::C(comp, dest, jump) => HackLine::C(comp, dest, jump),
+        }
+    }
+}
+
+impl FromStr for HackLine<'static> {
+    type Err = Diagnostic;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        HackLine::parse(line).map(HackLine::into_owned)
+    }
+}
+
+impl<'a> HackLine<'a> {
+    pub fn parse(line: &'a str) -> Result<Self, Diagnostic> {
+        HackLine::parse_for(line, Target::Hack)
+    }
+
+    pub fn parse_for(line: &'a str, target: Target) -> Result<Self, Diagnostic> {
+        // errors point back into the untrimmed line, so that they line up
+        // with the source when reported
+        let error = |token: &str, message: String| Diagnostic::error(message).at(line, token);
+
+        let s = line.trim();
+        if let Some((index, c)) = s.char_indices().find(|(_, c)| !c.is_ascii()) {
+            // most likely a smart quote or a non-breaking space pasted in
+            // from somewhere, which the rest of the parser would only be
+            // confused by
+            return Err(error(
+                &s[index..index + c.len_utf8()],
+                format!(
+                    "non-ASCII character `{}` (U+{:04X}) in instruction; only comments can have them",
+                    c, c as u32
+                ),
+            ));
+        }
+        if let Some(rest) = s.strip_prefix('(') {
+            // line is a label
+            match rest.strip_suffix(')').map(str::trim) {
+                Some(label) if is_symbol(label) => Ok(Self::Label(Cow::Borrowed(label))),
+                _ => Err(error(s, format!("Invalid label: {}", s))),
+            }
+        } else if let Some(value) = s.strip_prefix('@') {
+            // A-instruction
+            let value = value.trim_start();
+            if value.starts_with(|c: char| c.is_ascii_digit()) {
+                // plain memory address, which has to leave the top bit clear
+                // or it'd be a C-instruction
+                match value.parse() {
+                    Ok(address @ 0..=32767) => Ok(Self::AImmediate(address)),
+                    Ok(_) => Err(error(
+                        value,
+                        format!("Invalid address: {} (the most is 32767)", value),
+                    )),
+                    Err(_) => Err(error(value, format!("Invalid address: {}", value))),
+                }
+            } else if is_symbol(value) {
+                // location
+                Ok(Self::ALocation(Cow::Borrowed(value)))
+            } else {
+                Err(error(s, format!("Invalid symbol: {}", value)))
+            }
+        } else {
+            // split C-instruction into dest, comp, and jump, each of which
+            // may have spaces around its operators
+            let field = |token: &'a str, name: &str| {
+                let token = token.trim();
+                compact(token).ok_or_else(|| error(token, format!("Invalid {}: {}", name, token)))
+            };
+            let (dest, comp, jump) = {
+                let (dest, comp) = match s.split('=').collect_vec()[..] {
+                    [comp] => (Destination::Null, comp),
+                    [dest, comp] => (
+                        field(dest, "dest")?.parse().map_err(|_| {
+                            error(dest.trim(), format!("Invalid dest: {}", dest.trim()))
+                        })?,
+                        comp,
+                    ),
+                    _ => Err(error(
+                        s,
+                        "more than one equal sign in instruction".to_owned(),
+                    ))?,
+                };
+
+                let (comp, jump) = match comp.split(';').collect_vec()[..] {
+                    [comp] => (comp, Jump::Null),
+                    [comp, jump] => (
+                        comp,
+                        field(jump, "jump")?.parse().map_err(|_| {
+                            error(jump.trim(), format!("Invalid jump: {}", jump.trim()))
+                        })?,
+                    ),
+                    _ => Err(error(s, "more than one ; in instruction".to_owned()))?,
+                };
+
+                let parsed = field(comp, "comp")?
+                    .parse()
+                    .map_err(|err| error(comp.trim(), err))?;
+                if !target.supports(parsed) {
+                    Err(error(
+                        comp.trim(),
+                        format!(
+                            "`{}` is only in the extended instruction set; assemble with \
+                             `--target hack-ext` to use it",
+                            parsed
+                        ),
+                    ))?;
+                }
+                (dest, parsed, jump)
+            };
+            Ok(Self::C(comp, dest, jump))
+        }
+    }
+}
+
+// takes the spaces out of a part of a C-instruction, as in `M + 1`. Spaces
+// can't split a word, though, so `A M` and `J MP` are still wrong
+pub fn compact(token: &str) -> Option<Cow<'_, str>> {
+    if !token.contains(char::is_whitespace) {
+        return Some(Cow::Borrowed(token));
+    }
+    let mut out = String::with_capacity(token.len());
+    let mut spaced = false;
+    for c in token.chars() {
+        if c.is_whitespace() {
+            spaced = true;
+            continue;
+        }
+        let word = |c: char| c.is_ascii_alphanumeric();
+        if spaced && word(c) && out.ends_with(word) {
+            return None;
+        }
+        spaced = false;
+        out.push(c);
+    }
+    Some(Cow::Owned(out))
+}
+
+impl fmt::Display for HackLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HackLine::Label(label) => write!(f, "({})", label),
+            HackLine::AImmediate(imm) => write!(f, "@{}", imm),
+            HackLine::ALocation(name) => write!(f, "@{}", name),
+            HackLine::C(comp, dest, jump) => {
+                if *dest != Destination::Null {
+                    write!(f, "{}=", dest)?;
+                }
+                write!(f, "{}", comp)?;
+                if *jump != Jump::Null {
+                    write!(f, ";{}", jump)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<&HackLine<'_>> for json::Json {
+    fn from(line: &HackLine<'_>) -> Self {
+        use json::Json;
+        match line {
+            HackLine::Label(label) => {
+                Json::object([("kind", "label".into()), ("name", label.as_ref().into())])
+            }
+            HackLine::AImmediate(imm) => {
+                Json::object([("kind", "a".into()), ("value", (*imm).into())])
+            }
+            HackLine::ALocation(name) => {
+                Json::object([("kind", "a".into()), ("symbol", name.as_ref().into())])
+            }
+            HackLine::C(comp, dest, jump) => Json::object([
+                ("kind", "c".into()),
+                ("dest", (*dest).into()),
+                ("comp", (*comp).into()),
+                ("jump", (*jump).into()),
+            ]),
+        }
+    }
+}
+
+impl<'a> HackLine<'a> {
+    // the machine word this line assembles to, or `None` for a label, which
+    // doesn't take up one
+    pub fn word<'slf>(&'slf self, table: &mut SymbolTable<'slf>) -> Option<u16> {
+        self.word_with(|name| {
+            if let Some(address) = table.label(name) {
+                // existing label
+                address
+            } else {
+                // variable (allocating a new one if it doesn't already exist)
+                table.variable(name)
+            }
+        })
+    }
+
+    // the same, with `address` deciding where symbols point
+    pub fn word_with<'slf>(&'slf self, address: impl FnOnce(&'slf str) -> u16) -> Option<u16> {
+        match self {
+            HackLine::Label(_) => None,
+            HackLine::AImmediate(imm) => Some(*imm),
+            HackLine::ALocation(name) => Some(address(name)),
+            HackLine::C(c, d, j) => {
+                Some(c.marker() << 13 | c.assemble() << 6 | d.assemble() << 3 | j.assemble())
+            }
+        }
+    }
+}
+
+pub struct SymbolTable<'data> {
+    pub labels: HashMap<&'data str, u16>,
+    pub variables: HashMap<&'data str, u16>,
+    variable_address: u16,
+    // addresses no variable can be allocated to, from `allocate`
+    pinned: &'data [allocate::Pin],
+    // the lines each symbol is used on, for symbols looked up with `refer`
+    references: HashMap<&'data str, Vec<usize>>,
+}
+
+impl<'data> SymbolTable<'data> {
+    // by taking an `Iterator`, we guarantee to our caller that we
+    // iterate at most once
+    pub fn new<'line: 'data, I>(predefined: &'data SymbolSet, iter: I) -> Self
+    where
+        I: IntoIterator<Item = &'data HackLine<'line>>,
+    {
+        let mut labels: HashMap<&str, u16> = predefined.iter().collect();
+        let mut program_length = 0; // where labels point to
+
+        for line in iter.into_iter() {
+            if let HackLine::Label(label) = line {
+                labels.insert(label, program_length);
+            } else {
+                // label lines shouldn't contribute to program length. A
+                // program this long won't fit in ROM anyway, but it
+                // shouldn't bring the assembler down
+                program_length = u16::saturating_add(program_length, 1)
+            }
+        }
+
+        Self {
+            labels,
+            variables: HashMap::new(),
+            variable_address: 16,
+            pinned: &[],
+            references: HashMap::new(),
+        }
+    }
+
+    pub fn label(&mut self, key: &'data str) -> Option<u16> {
+        self.labels.get(key).copied()
+    }
+
+    // this function will always alloc a new variable if one doesn't already exist
+    pub fn variable<'slf>(&'slf mut self, key: &'data str) -> u16 {
+        let (next, pinned) = (&mut self.variable_address, self.pinned);
+        *self.variables.entry(key).or_insert_with(|| {
+            *next = allocate::unpinned(pinned, *next).saturating_add(1);
+            *next - 1
+        })
+    }
+
+    // gives variables their addresses ahead of the second pass, as `layout`
+    // says, given every symbol the program uses. Any left over are
+    // allocated in the order they're first used, as they come
+    pub fn allocate<I>(&mut self, layout: &'data allocate::Layout, names: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = &'data str>,
+    {
+        layout.check(|name| self.labels.contains_key(name))?;
+        self.pinned = &layout.pins;
+        for pin in &layout.pins {
+            self.variables.insert(&pin.name, pin.address);
+        }
+        if layout.order == allocate::Order::Alphabetical {
+            let names: BTreeSet<&str> = names
+                .into_iter()
+                .filter(|name| !self.labels.contains_key(name))
+                .collect();
+            for name in names {
+                self.variable(name);
+            }
+        }
+        Ok(())
+    }
+
+    // where a symbol used on `line` points, as a label if it is one and a
+    // variable otherwise, remembering the line
+    pub fn refer(&mut self, key: &'data str, line: usize) -> u16 {
+        self.references.entry(key).or_default().push(line);
+        match self.label(key) {
+            Some(address) => address,
+            None => self.variable(key),
+        }
+    }
+
+    pub fn references(&self, key: &str) -> &[usize] {
+        self.references.get(key).map_or(&[], Vec::as_slice)
+    }
+}
+
+// symbols are made of letters, digits, `_`, `.`, `$` and `:`, and don't start
+// with a digit
+pub fn is_symbol(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+// `--relaxed-case`: the program with the mnemonics of its C-instructions in
+// uppercase, as in `d=m+1;jmp`. Symbols are left alone, since `loop` and
+// `LOOP` are still different labels
+pub fn relax_case(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    for line in strip_bom(source).lines() {
+        let (code, comment) = split_comment(line);
+        if code.trim_start().starts_with(['(', '@']) {
+            out.push_str(code);
+        } else {
+            out.push_str(&code.to_ascii_uppercase());
+        }
+        out.push_str(comment.unwrap_or(""));
+        out.push('\n');
+    }
+    out
+}
+
+// editors on Windows like to start files with a byte order mark, which is
+// no part of the program
+pub fn strip_bom(source: &str) -> &str {
+    source.strip_prefix('\u{feff}').unwrap_or(source)
+}
+
+// splits a line into its code and its trailing `//` comment, if any
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    match line.find("//") {
+        Some(index) => (&line[..index], Some(&line[index..])),
+        None => (line, None),
+    }
+}
+
+// a parsed line, along with where it came from, for passes that want to
+// point back at the source
+#[derive(Debug, Clone)]
+pub struct SourceLine<'a> {
+    pub number: usize,
+    pub text: &'a str,
+    pub line: HackLine<'a>,
+}
+
+impl<'a> AsRef<HackLine<'a>> for SourceLine<'a> {
+    fn as_ref(&self) -> &HackLine<'a> {
+        &self.line
+    }
+}
+
+impl SourceLine<'_> {
+    // the code part of the line, for diagnostics to point at
+    pub fn code(&self) -> &str {
+        split_comment(self.text).0.trim()
+    }
+}
+
+// parses each line of a program in turn, skipping comments and blank lines
+pub fn parse_lines(
+    source: &str,
+    target: Target,
+) -> impl Iterator<Item = Result<SourceLine<'_>, Diagnostic>> {
+    // `lines` takes care of `\r\n` line endings, and `HackLine::parse` of a
+    // stray `\r` at the very end
+    strip_bom(source)
+        .lines()
+        .enumerate()
+        .filter_map(move |(number, text)| {
+            let (code, _) = split_comment(text);
+            if code.trim().is_empty() {
+                return None;
+            }
+            Some(match HackLine::parse_for(code, target) {
+                Ok(line) => Ok(SourceLine {
+                    number: number + 1,
+                    text,
+                    line,
+                }),
+                Err(err) => Err(err.on_line(number + 1, text)),
+            })
+        })
+}
+
+// parses a whole program, which the lines go on borrowing from
+pub fn parse_source(source: &str) -> Result<Vec<SourceLine<'_>>, Diagnostic> {
+    parse_source_for(source, Target::Hack)
+}
+
+pub fn parse_source_for(source: &str, target: Target) -> Result<Vec<SourceLine<'_>>, Diagnostic> {
+    parse_lines(source, target).collect()
+}
+
+// a parsed program, for tools like editors that would rather hear about
+// every line that's wrong with it than only the first
+pub struct Program<'a> {
+    pub lines: Vec<SourceLine<'a>>,
+}
+
+// never panics, whatever it's given
+pub fn parse_program(source: &str) -> Result<Program<'_>, Vec<Diagnostic>> {
+    let (lines, errors): (Vec<_>, Vec<_>) = parse_lines(source, Target::Hack).partition_result();
+    if errors.is_empty() {
+        Ok(Program { lines })
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn parse(source: &str) -> Result<Vec<HackLine<'_>>, Diagnostic> {
+    Ok(parse_source(source)?
+        .into_iter()
+        .map(|source| source.line)
+        .collect())
+}
+
+pub fn assemble(
+    mut input: impl BufRead,
+    output: &mut impl Write,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut source = String::new();
+    input.read_to_string(&mut source)?;
+    assemble_lines(&parse(&source)?, output)
+}
+
+// writes `lines` as a `.hack` file, one binary word per line, with the
+// standard symbols and variables from 16 in order of first use
+pub fn assemble_lines(
+    lines: &[HackLine],
+    output: &mut impl Write,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    assemble_lines_with(
+        lines,
+        SymbolSet::standard(),
+        &allocate::Layout::default(),
+        &mut emit::Text::new(output),
+    )
+}
+
+pub fn assemble_lines_with(
+    lines: &[HackLine],
+    predefined: &SymbolSet,
+    layout: &allocate::Layout,
+    emitter: &mut impl Emitter,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // first pass: collect labels into a symbol table
+    let mut symbols = SymbolTable::new(predefined, lines);
+    symbols.allocate(layout, used_symbols(lines))?;
+
+    // second pass: generate binary instructions
+    let mut address: u16 = 0;
+    for line in lines {
+        if let Some(word) = line.word(&mut symbols) {
+            emitter.emit_word(address, word)?;
+            address = address.wrapping_add(1);
+        }
+    }
+    emitter.finish()?;
+
+    Ok(())
+}
+
+// every symbol an A-instruction refers to, in order, repeats and all
+pub fn used_symbols<'a>(lines: &'a [HackLine]) -> impl Iterator<Item = &'a str> {
+    lines.iter().filter_map(|line| match line {
+        HackLine::ALocation(name) => Some(name.as_ref()),
+        _ => None,
+    })
+}
+
+// runs every pass of the assembler without producing any output
+pub fn check(input: impl BufRead) -> Result<(), Box<dyn Error + Send + Sync>> {
+    assemble(input, &mut std::io::sink())
+}
+
+// the final addresses of every label and variable in a program
+pub fn symbols(lines: &[HackLine]) -> debug::Symbols {
+    symbols_with(lines, SymbolSet::standard(), &allocate::Layout::default())
+        .expect("there are no pins to clash")
+}
+
+pub fn symbols_with(
+    lines: &[HackLine],
+    predefined: &SymbolSet,
+    layout: &allocate::Layout,
+) -> Result<debug::Symbols, String> {
+    let mut table = SymbolTable::new(predefined, lines);
+    table.allocate(layout, used_symbols(lines))?;
+    for line in lines {
+        if let HackLine::ALocation(name) = line {
+            if table.label(name).is_none() {
+                table.variable(name);
+            }
+        }
+    }
+    let owned = |map: HashMap<&str, u16>| {
+        map.into_iter()
+            .map(|(name, address)| (name.to_owned(), address))
+            .collect()
+    };
+    let mut labels: HashMap<String, u16> = owned(table.labels);
+    labels.retain(|name, _| predefined.get(name).is_none());
+    Ok(debug::Symbols {
+        labels,
+        variables: owned(table.variables),
+    })
+}
+
+// a program loaded for the emulator, ready to run
+pub struct LoadedProgram {
+    pub cpu: emulator::Cpu,
+    pub map: Option<sourcemap::SourceMap>,
+    pub symbols: debug::Symbols,
+}
+
+// loads a program for the emulator, along with a source map and symbols if
+// we can find them: `.asm` files are assembled on the fly, while `.hack`
+// files pick up a `.map` file sitting next to them. `.snap` snapshots pick
+// up where an earlier run left off
+pub fn load_program(path: &Path) -> Result<LoadedProgram, HackError> {
+    if path.extension().is_some_and(|ext| ext == "asm") {
+        let source = fs::read_to_string(path).map_err(HackError::io(path))?;
+        let sources = parse_source(&source).map_err(|err| HackError::new(path, err.into()))?;
+        let layout = allocate::Layout::new(allocate::Order::FirstUse, &source)
+            .map_err(|err| HackError::new(path, err.into()))?;
+        let map = sourcemap::SourceMap::new(&path.to_string_lossy(), &sources);
+        let lines: Vec<HackLine> = sources.into_iter().map(|source| source.line).collect();
+        let mut binary = Vec::new();
+        let predefined = SymbolSet::standard();
+        let assembled = assemble_lines_with(
+            &lines,
+            predefined,
+            &layout,
+            &mut emit::Text::new(&mut binary),
+        );
+        assembled
+            .and_then(|()| {
+                let symbols = symbols_with(&lines, predefined, &layout)?;
+                Ok((emulator::load(&binary[..])?, symbols))
+            })
+            .map(|(rom, symbols)| LoadedProgram {
+                cpu: emulator::Cpu::new(&rom),
+                map: Some(map),
+                symbols,
+            })
+            .map_err(|err| HackError::new(path, err))
+    } else {
+        let file = File::open(path).map_err(HackError::io(path))?;
+        let cpu = if path.extension().is_some_and(|ext| ext == "snap") {
+            snapshot::read(BufReader::new(file))
+        } else {
+            emulator::load(BufReader::new(file)).map(|rom| emulator::Cpu::new(&rom))
+        }
+        .map_err(|err| HackError::new(path, err))?;
+        let map_path = path.with_extension("map");
+        let map = match File::open(&map_path) {
+            Ok(map_file) => Some(
+                sourcemap::SourceMap::read(BufReader::new(map_file))
+                    .map_err(|err| HackError::new(&map_path, err))?,
+            ),
+            Err(_) => None,
+        };
+        Ok(LoadedProgram {
+            cpu,
+            map,
+            symbols: debug::Symbols::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn rect() {
+        let mut result = Vec::new();
+        let mut rect = File::open("resources/Rect.asm").unwrap();
+        assemble(BufReader::new(&mut rect), &mut result).unwrap();
+
+        let expected = std::fs::read("resources/Rect.hack").unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn check_writes_nothing() {
+        assert!(check("@i\nM=1\n".as_bytes()).is_ok());
+        assert!(check("@i\nM=Q\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for line in [
+            "(", "()", "(LOOP", "(a b)", "@", "@70000", "@32768", "@65535", "@-1", "@1x", "=", ";",
+            "=M", "D=;JMP", "M=D;",
+        ] {
+            assert!(HackLine::parse(line).is_err(), "{} parsed", line);
+        }
+        for line in ["A M=1", "D;J MP", "@R 0", "(LO OP)", "D=M 1"] {
+            assert!(HackLine::parse(line).is_err(), "{} parsed", line);
+        }
+        for dest in ["DM", "MA", "DAM", "MDA"] {
+            assert!(
+                HackLine::parse(&format!("{}=1", dest)).is_ok(),
+                "{} rejected",
+                dest
+            );
+        }
+        for dest in ["MM", "ADA", "X", "AMDM"] {
+            assert!(
+                HackLine::parse(&format!("{}=1", dest)).is_err(),
+                "{} parsed",
+                dest
+            );
+        }
+        for (spaced, line) in [
+            ("D = M + 1 ; JGT", "D=M+1;JGT"),
+            ("@ R0", "@R0"),
+            ("( LOOP )", "(LOOP)"),
+            ("AM = - 1", "AM=-1"),
+        ] {
+            assert_eq!(HackLine::parse(spaced).unwrap().to_string(), line);
+        }
+        assert!(HackLine::parse("@32767").is_ok());
+        let errors = parse_program("@i\n(\nD=Q\n@i\n").err().unwrap();
+        let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+        assert_eq!(lines, [2, 3]);
+    }
+
+    #[test]
+    fn never_panics() {
+        // the characters and fragments that matter to the parser, strung
+        // together at random
+        const PIECES: &[&str] = &[
+            "(", ")", "@", "=", ";", "//", "!", "-", "+", "&", "|", "A", "M", "D", "0", "1", "JMP",
+            "JGT", " ", "\t", "\r", "\n", "é", "\u{0}", "65535", "65536", "$", ".", "x",
+        ];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        for _ in 0..5000 {
+            let source: String = (0..next() % 24)
+                .map(|_| PIECES[next() % PIECES.len()])
+                .collect();
+            if let Ok(program) = parse_program(&source) {
+                let lines: Vec<HackLine> = program
+                    .lines
+                    .into_iter()
+                    .map(|source| source.line)
+                    .collect();
+                assemble_lines(&lines, &mut std::io::sink()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn windows_and_unicode() {
+        let lines = parse("\u{feff}(LOOP)\r\n@LOOP // ↺ forever\r\n0;JMP\r").unwrap();
+        assert_eq!(lines.len(), 3);
+        let err = parse("@1\nD=M\u{a0}+1\n").unwrap_err();
+        assert_eq!((err.line, err.span.clone()), (2, 3..5));
+        assert!(err.message.contains("U+00A0"));
+    }
+
+    #[test]
+    fn relaxed_case() {
+        let source = relax_case("(loop)\n@loop\nd=m+1 // Keep\nam=d|a;jmp\n");
+        assert_eq!(source, "(loop)\n@loop\nD=M+1 // Keep\nAM=D|A;JMP\n");
+        assert!(parse(&source).is_ok());
+    }
+
+    #[test]
+    fn extended_target() {
+        let source = "D=D<<\nAM=M>>;JGT\nD=D*A\n";
+        let err = parse_source(source).unwrap_err();
+        assert_eq!(
+            (err.line, err.message.contains("--target hack-ext")),
+            (1, true)
+        );
+
+        let lines = parse_source_for(source, Target::HackExt).unwrap();
+        let words: Vec<u16> = lines
+            .iter()
+            .filter_map(|source| source.line.word_with(|_| 0))
+            .collect();
+        assert_eq!(
+            words,
+            [0b1010110000010000, 0b1011000000101001, 0b1010000001010000]
+        );
+        // the extensions keep clear of everything the course's CPU runs
+        assert!(words
+            .iter()
+            .all(|word| disassemble::disassemble(*word).is_none()));
+    }
+
+    #[test]
+    fn variables_allocated_once() {
+        let lines = parse("@a\n@a\n@b\n").unwrap();
+        let mut table = SymbolTable::new(SymbolSet::standard(), &lines);
+        assert_eq!(table.variable("a"), 16);
+        assert_eq!(table.variable("a"), 16);
+        assert_eq!(table.variable("b"), 17);
+    }
+
+    #[test]
+    fn allocation_policies() {
+        let source = "@zeta\n@alpha // @pin alpha 16\n@mid\n(LOOP)\n@LOOP\n";
+        let lines = parse(source).unwrap();
+        let layout = |order| allocate::Layout::new(order, source).unwrap();
+        let variables = |order| {
+            let symbols = symbols_with(&lines, SymbolSet::standard(), &layout(order)).unwrap();
+            let mut variables: Vec<_> = symbols.variables.into_iter().collect();
+            variables.sort_by_key(|(_, address)| *address);
+            variables
+        };
+        let named = |names: &[(&str, u16)]| -> Vec<(String, u16)> {
+            names.iter().map(|(n, a)| (n.to_string(), *a)).collect()
+        };
+        assert_eq!(
+            variables(allocate::Order::FirstUse),
+            named(&[("alpha", 16), ("zeta", 17), ("mid", 18)])
+        );
+        assert_eq!(
+            variables(allocate::Order::Alphabetical),
+            named(&[("alpha", 16), ("mid", 17), ("zeta", 18)])
+        );
+
+        let mut words = Vec::new();
+        let mut text = emit::Text::new(&mut words);
+        let pinned = allocate::Layout::new(allocate::Order::FirstUse, "// @pin LOOP 20\n");
+        let err = assemble_lines_with(&lines, SymbolSet::standard(), &pinned.unwrap(), &mut text);
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "`LOOP` is pinned, but it isn't a variable"
+        );
+    }
+
+    // a benchmark rather than a test: run it with
+    // `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_output() {
+        use std::time::Instant;
+
+        // about what the VM translator makes of a big program
+        let source = "(LOOP)\n@SP\nAM=M-1\nD=M\n@R13\nM=D\n@LOOP\nD;JGT\n".repeat(50_000);
+        let lines = parse(&source).unwrap();
+        let dir = std::env::temp_dir().join(format!("hack-bench-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Bench.hack");
+
+        // the way we used to do it, a few bits at a time straight to the file
+        let start = Instant::now();
+        let mut file = File::create(&path).unwrap();
+        let mut table = SymbolTable::new(SymbolSet::standard(), &lines);
+        for line in &lines {
+            match line.word(&mut table) {
+                Some(word) if word & 0x8000 != 0 => {
+                    write!(file, "111").unwrap();
+                    write!(file, "{:07b}", word >> 6 & 0x7f).unwrap();
+                    write!(file, "{:03b}", word >> 3 & 0b111).unwrap();
+                    write!(file, "{:03b}", word & 0b111).unwrap();
+                    writeln!(file).unwrap();
+                }
+                Some(word) => writeln!(file, "{:016b}", word).unwrap(),
+                None => {}
+            }
+        }
+        drop(file);
+        let fragments = start.elapsed();
+        let expected = fs::read(&path).unwrap();
+
+        let start = Instant::now();
+        let mut file = File::create(&path).unwrap();
+        assemble_lines(&lines, &mut file).unwrap();
+        drop(file);
+        let words = start.elapsed();
+
+        println!("{} instructions", lines.len() * 7 / 8);
+        println!("  a few bits at a time, unbuffered: {:?}", fragments);
+        println!("  a word at a time, buffered:       {:?}", words);
+        assert_eq!(fs::read(&path).unwrap(), expected);
+        assert!(words < fragments);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
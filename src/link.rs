@@ -0,0 +1,316 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use crate::allocate::{Layout, Order, Pin};
+use crate::debug::Symbols;
+use crate::predefined::SymbolSet;
+use crate::{HackLine, SymbolTable, PREDEFINED_SYMBOLS};
+
+// a single word of a relocatable object's instruction stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Word {
+    // a fully encoded instruction that doesn't depend on where the module ends up
+    Absolute(u16),
+    // a reference to one of this module's own labels, as an offset from the
+    // start of the module
+    Relative(u16),
+    // a symbol this module doesn't define: either some other module's label,
+    // or (failing that) one of this module's variables
+    External(String),
+}
+
+// an assembled module that hasn't been placed in ROM yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub name: String,
+    pub labels: Vec<(String, u16)>,
+    // this module's variables that its source pins, by their own names
+    pub pins: Vec<Pin>,
+    pub code: Vec<Word>,
+}
+
+impl Object {
+    pub fn new(
+        name: &str,
+        lines: &[HackLine],
+        pins: &[Pin],
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut labels = Vec::new();
+        let mut offset = 0;
+        for line in lines {
+            if let HackLine::Label(label) = line {
+                labels.push((label.to_string(), offset));
+            } else {
+                offset += 1;
+            }
+        }
+
+        let layout = Layout {
+            order: Order::FirstUse,
+            pins: pins.to_vec(),
+        };
+        layout.check(|name| labels.iter().any(|(label, _)| label == name))?;
+
+        let mut code = Vec::new();
+        for line in lines {
+            code.push(match line {
+                HackLine::Label(_) => continue,
+                HackLine::AImmediate(imm) => Word::Absolute(*imm),
+                HackLine::ALocation(name) => {
+                    if let Some((_, offset)) = labels.iter().find(|(label, _)| label == name) {
+                        Word::Relative(*offset)
+                    } else if let Some((_, address)) =
+                        PREDEFINED_SYMBOLS.iter().find(|(symbol, _)| symbol == name)
+                    {
+                        Word::Absolute(*address)
+                    } else {
+                        Word::External(name.to_string())
+                    }
+                }
+                // C-instructions don't refer to any symbols
+                HackLine::C(..) => Word::Absolute(
+                    line.word_with(|_| unreachable!())
+                        .expect("C-instructions always take up a word"),
+                ),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            labels,
+            pins: layout.pins,
+            code,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), std::io::Error> {
+        writeln!(writer, "module {}", self.name)?;
+        for (label, offset) in &self.labels {
+            writeln!(writer, "label {} {}", label, offset)?;
+        }
+        for pin in &self.pins {
+            writeln!(writer, "pin {} {}", pin.name, pin.address)?;
+        }
+        for word in &self.code {
+            match word {
+                Word::Absolute(word) => writeln!(writer, "abs {:016b}", word)?,
+                Word::Relative(offset) => writeln!(writer, "rel {}", offset)?,
+                Word::External(name) => writeln!(writer, "ext {}", name)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut object = Self {
+            name: String::new(),
+            labels: Vec::new(),
+            pins: Vec::new(),
+            code: Vec::new(),
+        };
+
+        for line in reader.lines() {
+            let line = line?;
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => {}
+                ["module", name] => object.name = name.to_owned(),
+                ["label", label, offset] => object.labels.push((label.to_owned(), offset.parse()?)),
+                ["pin", name, address] => object.pins.push(Pin {
+                    name: name.to_owned(),
+                    address: address.parse()?,
+                }),
+                ["abs", word] => object
+                    .code
+                    .push(Word::Absolute(u16::from_str_radix(word, 2)?)),
+                ["rel", offset] => object.code.push(Word::Relative(offset.parse()?)),
+                ["ext", name] => object.code.push(Word::External(name.to_owned())),
+                _ => Err(format!("malformed object line: {}", line))?,
+            }
+        }
+
+        if object.name.is_empty() {
+            Err("object is missing its module name")?;
+        }
+        Ok(object)
+    }
+}
+
+// lays the modules out in ROM in the order given, resolves every external
+// reference against the other modules' labels, and gives each module its own
+// namespace for variables so that two modules' `@i` don't collide. Variables
+// are allocated across every module at once, in `order` around any pins, and
+// the final address of everything comes back for a `.sym` file
+pub fn link(
+    objects: &[Object],
+    order: Order,
+    output: &mut impl Write,
+) -> Result<Symbols, Box<dyn Error + Send + Sync>> {
+    let mut labels: HashMap<&str, (u16, &str)> = HashMap::new();
+    let mut bases = Vec::new();
+    let mut base: u16 = 0;
+    for object in objects {
+        for (label, offset) in &object.labels {
+            match labels.entry(label) {
+                Entry::Occupied(entry) => Err(format!(
+                    "label `{}` is defined in both `{}` and `{}`",
+                    label,
+                    entry.get().1,
+                    object.name
+                ))?,
+                Entry::Vacant(entry) => entry.insert((base + offset, &object.name)),
+            };
+        }
+        bases.push(base);
+        base = u16::try_from(object.code.len())
+            .ok()
+            .and_then(|len| base.checked_add(len))
+            .ok_or("linked program doesn't fit in ROM")?;
+    }
+
+    let names: Vec<Vec<String>> = objects
+        .iter()
+        .map(|object| {
+            object
+                .code
+                .iter()
+                .map(|word| match word {
+                    Word::External(name) if !labels.contains_key(name.as_str()) => {
+                        format!("{}.{}", object.name, name)
+                    }
+                    _ => String::new(),
+                })
+                .collect()
+        })
+        .collect();
+    let layout = Layout {
+        order,
+        pins: objects
+            .iter()
+            .flat_map(|object| {
+                object.pins.iter().map(|pin| Pin {
+                    name: format!("{}.{}", object.name, pin.name),
+                    address: pin.address,
+                })
+            })
+            .collect(),
+    };
+    let mut variables = SymbolTable::new(SymbolSet::standard(), std::iter::empty());
+    let used = names.iter().flatten().filter(|name| !name.is_empty());
+    variables.allocate(&layout, used.map(String::as_str))?;
+
+    for ((object, base), names) in objects.iter().zip(bases).zip(&names) {
+        for (word, qualified) in object.code.iter().zip(names) {
+            let word = match word {
+                Word::Absolute(word) => *word,
+                Word::Relative(offset) => base + offset,
+                Word::External(name) => match labels.get(name.as_str()) {
+                    Some((address, _)) => *address,
+                    None => variables.variable(qualified),
+                },
+            };
+            writeln!(output, "{:016b}", word)?;
+        }
+    }
+
+    Ok(Symbols {
+        labels: labels
+            .into_iter()
+            .map(|(label, (address, _))| (label.to_owned(), address))
+            .collect(),
+        variables: variables
+            .variables
+            .into_iter()
+            .map(|(name, address)| (name.to_owned(), address))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(name: &str, source: &str) -> Object {
+        let pins = Layout::new(Order::FirstUse, source).unwrap().pins;
+        Object::new(name, &crate::parse(source).unwrap(), &pins).unwrap()
+    }
+
+    fn words(output: &[u8]) -> Vec<u16> {
+        std::str::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn object_round_trip() {
+        let main = object(
+            "Main",
+            "(START)\n@i // @pin i 30\nM=1\n@START\n0;JMP\n@SCREEN\n",
+        );
+        let mut written = Vec::new();
+        main.write(&mut written).unwrap();
+        assert_eq!(Object::read(&written[..]).unwrap(), main);
+    }
+
+    #[test]
+    fn cross_module_labels_and_local_variables() {
+        let main = object("Main", "@i\nM=1\n@HELPER\n0;JMP\n");
+        let helper = object("Helper", "(HELPER)\n@i\nM=0\n@HELPER\n0;JMP\n");
+
+        let mut output = Vec::new();
+        link(&[main, helper], Order::FirstUse, &mut output).unwrap();
+        let words = words(&output);
+
+        // Main.i and Helper.i are distinct variables
+        assert_eq!(words[0], 16);
+        assert_eq!(words[4], 17);
+        // both modules jump to HELPER, which lives after Main's four instructions
+        assert_eq!(words[2], 4);
+        assert_eq!(words[6], 4);
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let a = object("A", "(LOOP)\n@LOOP\n");
+        let b = object("B", "(LOOP)\n@LOOP\n");
+        assert!(link(&[a, b], Order::FirstUse, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn allocation_across_modules() {
+        let main = object("Main", "@z\nM=1\n@a\nM=1\n@HELPER\n0;JMP\n");
+        let helper = object("Helper", "// @pin flag 16\n(HELPER)\n@flag\nM=0\n");
+
+        let mut output = Vec::new();
+        let symbols = link(&[main, helper], Order::Alphabetical, &mut output).unwrap();
+        // Helper.flag holds 16, so Main.a and Main.z are packed around it
+        assert_eq!(
+            words(&output)[..7],
+            [
+                18,
+                0b1110111111001000,
+                17,
+                0b1110111111001000,
+                6,
+                0b1110101010000111,
+                16
+            ]
+        );
+        assert_eq!(
+            symbols.to_string(),
+            "(HELPER) 6\nHelper.flag 16\nMain.a 17\nMain.z 18\n"
+        );
+
+        let bad = Object::new(
+            "Bad",
+            &crate::parse("(X)\n").unwrap(),
+            &[Pin {
+                name: "X".to_owned(),
+                address: 20,
+            }],
+        );
+        assert!(bad.is_err());
+    }
+}
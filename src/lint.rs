@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+
+use crate::diagnostic::Diagnostic;
+use crate::predefined::SymbolSet;
+use crate::{Destination, HackLine, Jump, SourceLine, SymbolTable, PREDEFINED_SYMBOLS};
+
+pub const LINTS: &[(&str, &str)] = &[
+    ("unused-label", "a label is defined but never referenced"),
+    (
+        "shadowed-predefined",
+        "a label has the same name as a predefined symbol",
+    ),
+    (
+        "clobbered-a",
+        "A is overwritten before the value loaded into it is used",
+    ),
+    (
+        "falls-off-end",
+        "execution can run past the last instruction in ROM",
+    ),
+];
+
+fn warn(source: &SourceLine, lint: &str, message: String) -> Diagnostic {
+    Diagnostic::warning(format!("{} [{}]", message, lint))
+        .at(source.text, source.code())
+        .on_line(source.number, source.text)
+}
+
+pub fn lint(lines: &[SourceLine], allowed: &[&str]) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    let mut table = SymbolTable::new(
+        SymbolSet::standard(),
+        lines.iter().map(|source| &source.line),
+    );
+    let enabled = |lint: &str| !allowed.contains(&lint);
+
+    let referenced: HashSet<&str> = lines
+        .iter()
+        .filter_map(|source| match &source.line {
+            HackLine::ALocation(name) => Some(name.as_ref()),
+            _ => None,
+        })
+        .collect();
+    let program_length = lines
+        .iter()
+        .filter(|source| !matches!(source.line, HackLine::Label(_)))
+        .count();
+
+    for (index, source) in lines.iter().enumerate() {
+        let next = lines.get(index + 1).map(|source| &source.line);
+        match &source.line {
+            HackLine::Label(label) => {
+                if enabled("unused-label") && !referenced.contains(label.as_ref()) {
+                    warnings.push(warn(
+                        source,
+                        "unused-label",
+                        format!("label `{}` is never used", label),
+                    ));
+                }
+                if enabled("shadowed-predefined")
+                    && PREDEFINED_SYMBOLS.iter().any(|(name, _)| name == label)
+                {
+                    warnings.push(warn(
+                        source,
+                        "shadowed-predefined",
+                        format!("label `{}` shadows the predefined symbol", label),
+                    ));
+                }
+            }
+            HackLine::AImmediate(_) | HackLine::ALocation(_) => {
+                let clobbered = match next {
+                    Some(HackLine::AImmediate(_) | HackLine::ALocation(_)) => true,
+                    Some(HackLine::C(comp, dest, jump)) => {
+                        dest.writes(Destination::A)
+                            && comp.operand().is_none()
+                            && matches!(jump, Jump::Null)
+                    }
+                    _ => false,
+                };
+                if enabled("clobbered-a") && clobbered {
+                    let loaded = source.code();
+                    warnings.push(warn(
+                        source,
+                        "clobbered-a",
+                        format!(
+                            "the value loaded by `{}` is overwritten before it's used",
+                            loaded
+                        ),
+                    ));
+                }
+                if let HackLine::ALocation(name) = &source.line {
+                    let past_end = table.label(name) == u16::try_from(program_length).ok()
+                        && !PREDEFINED_SYMBOLS.iter().any(|(symbol, _)| symbol == name);
+                    if enabled("falls-off-end") && past_end {
+                        warnings.push(warn(
+                            source,
+                            "falls-off-end",
+                            format!("`{}` points past the end of the program", name),
+                        ));
+                    }
+                }
+            }
+            HackLine::C(_, dest, jump) => {
+                if enabled("clobbered-a")
+                    && dest.writes(Destination::A)
+                    && !matches!(jump, Jump::Null)
+                {
+                    warnings.push(warn(
+                        source,
+                        "clobbered-a",
+                        "this jumps to the value it just wrote to A".to_owned(),
+                    ));
+                }
+            }
+        }
+    }
+
+    let last = lines
+        .iter()
+        .rev()
+        .find(|source| !matches!(source.line, HackLine::Label(_)));
+    if let Some(last) = last.filter(|last| !matches!(last.line, HackLine::C(_, _, Jump::JMP))) {
+        if enabled("falls-off-end") {
+            warnings.push(warn(
+                last,
+                "falls-off-end",
+                "execution continues past the end of the program".to_owned(),
+            ));
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_source;
+
+    fn lints(source: &str, allowed: &[&str]) -> Vec<String> {
+        let lines = parse_source(source).unwrap();
+        lint(&lines, allowed)
+            .into_iter()
+            .map(|warning| format!("{}: {}", warning.line, warning.message))
+            .collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        let rect = std::fs::read_to_string("resources/Rect.asm").unwrap();
+        assert_eq!(lints(&rect, &[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn finds_pitfalls() {
+        let source = "(R0)\n(UNUSED)\n@x\nA=D\nM=1\n@END\nAM=M-1;JGT\n(END)\n";
+        assert_eq!(
+            lints(source, &[]),
+            [
+                "1: label `R0` is never used [unused-label]",
+                "1: label `R0` shadows the predefined symbol [shadowed-predefined]",
+                "2: label `UNUSED` is never used [unused-label]",
+                "3: the value loaded by `@x` is overwritten before it's used [clobbered-a]",
+                "6: `END` points past the end of the program [falls-off-end]",
+                "7: this jumps to the value it just wrote to A [clobbered-a]",
+                "7: execution continues past the end of the program [falls-off-end]",
+            ]
+        );
+        assert_eq!(
+            lints(source, &["unused-label", "falls-off-end", "clobbered-a"]),
+            ["1: label `R0` shadows the predefined symbol [shadowed-predefined]"]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+use crate::cli::Matches;
+use crate::json::Json;
+
+// how much to say: `-q` for errors only, and `-v` or `-vv` for more detail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Quiet,
+    Info,
+    Verbose,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Quiet => "error",
+            Level::Info => "info",
+            Level::Verbose => "verbose",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    // one object per line, for scripts and CI
+    Json,
+}
+
+// where a command reports on its progress: in text, each event is its
+// message, and in JSON, an object holding the message along with its fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Log {
+    pub level: Level,
+    pub format: Format,
+}
+
+impl Log {
+    pub fn new(matches: &Matches) -> Result<Self, String> {
+        let level = match (matches.flag("quiet"), matches.count("verbose")) {
+            (true, 0) => Level::Quiet,
+            (true, _) => Err("`--quiet` and `--verbose` can't be combined")?,
+            (false, 0) => Level::Info,
+            (false, 1) => Level::Verbose,
+            (false, _) => Level::Debug,
+        };
+        let format = match matches.value("log-format").unwrap_or("text") {
+            "text" => Format::Text,
+            "json" => Format::Json,
+            other => Err(format!(
+                "invalid log format `{}` (expected text or json)",
+                other
+            ))?,
+        };
+        Ok(Self { level, format })
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn event<'a>(
+        &self,
+        level: Level,
+        message: &str,
+        fields: impl IntoIterator<Item = (&'a str, Json)>,
+    ) {
+        if !self.enabled(level) {
+            return;
+        }
+        match self.format {
+            // detail sits under the line it's about
+            Format::Text if level > Level::Info => println!("  {}", message),
+            Format::Text => println!("{}", message),
+            Format::Json => {
+                let mut event = vec![("level", level.name().into()), ("message", message.into())];
+                event.extend(fields);
+                println!("{}", Json::object(event));
+            }
+        }
+    }
+
+    // an error, which in text goes to stderr rendered with its source line
+    pub fn error(&self, rendered: &str, message: &str, fields: Vec<(&str, Json)>) {
+        match self.format {
+            Format::Text => eprint!("{}", rendered),
+            Format::Json => {
+                let mut event = vec![("level", "error".into()), ("message", message.into())];
+                event.extend(fields);
+                println!("{}", Json::object(event));
+            }
+        }
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            format: Format::Text,
+        }
+    }
+}
+
+// times the passes of some piece of work, one after another
+pub struct Timer {
+    lap: Instant,
+    pub passes: Vec<(&'static str, Duration)>,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        Self {
+            lap: Instant::now(),
+            passes: Vec::new(),
+        }
+    }
+
+    // the pass called `name` has just finished
+    pub fn lap(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.passes.push((name, now - self.lap));
+        self.lap = now;
+    }
+}
+
+// a duration in milliseconds, to a microsecond
+pub fn millis(duration: Duration) -> f64 {
+    (duration.as_micros() as f64) / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::parse;
+
+    #[test]
+    fn levels() {
+        let log = |args: &[&str]| Log::new(&parse(args.iter().map(|arg| arg.to_string())).unwrap());
+        assert_eq!(log(&["a.asm"]).unwrap(), Log::default());
+        assert_eq!(log(&["-q", "a.asm"]).unwrap().level, Level::Quiet);
+        assert_eq!(log(&["-v", "a.asm"]).unwrap().level, Level::Verbose);
+        assert_eq!(log(&["-vv", "a.asm"]).unwrap().level, Level::Debug);
+        assert_eq!(log(&["-v", "-v", "-v"]).unwrap().level, Level::Debug);
+        assert!(log(&["-qv"]).is_err());
+        let json = log(&["--log-format", "json"]).unwrap();
+        assert_eq!(json.format, Format::Json);
+        assert!(!json.enabled(Level::Verbose));
+        assert!(log(&["--log-format", "xml"]).is_err());
+    }
+}
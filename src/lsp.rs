@@ -0,0 +1,792 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::jack::{self, Class, SubroutineKind, Token, VarKind};
+use crate::json::Json;
+use crate::{compile, is_symbol, lint, split_comment, HackLine, PREDEFINED_SYMBOLS};
+
+// a language server for Hack assembly and Jack, so that editors can show
+// our diagnostics as you work, and find, describe and rename symbols.
+// Positions are counted in bytes rather than UTF-16 units, which is the
+// same thing for the ASCII both languages are written in
+
+// reads one message in the framing LSP and DAP share: headers, of which we
+// only care about the length, a blank line, then that much JSON. `None` at
+// the end of the input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let length = length.ok_or_else(|| invalid("message has no Content-Length".to_owned()))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let text = String::from_utf8(body).map_err(|err| invalid(err.to_string()))?;
+    Json::parse(&text).map(Some).map_err(invalid)
+}
+
+pub fn write_message(out: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+// a 0-based line and byte offset into it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+impl Position {
+    fn from_json(json: &Json) -> Option<Self> {
+        Some(Self {
+            line: json.get("line")?.as_f64()? as usize,
+            character: json.get("character")?.as_f64()? as usize,
+        })
+    }
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let position =
+        |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    Json::object([("start", position(start)), ("end", position(end))])
+}
+
+// a mention of a symbol, within a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Occurrence {
+    line: usize,
+    start: usize,
+    end: usize,
+    name: String,
+}
+
+impl Occurrence {
+    fn contains(&self, position: Position) -> bool {
+        position.line == self.line && (self.start..=self.end).contains(&position.character)
+    }
+
+    fn range(&self) -> Json {
+        range(self.line, self.start, self.end)
+    }
+}
+
+// what an editor asked about, worked out from a document's text
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    // where it's defined, if anywhere
+    definition: Option<Occurrence>,
+    description: String,
+    // every mention of it, for renaming; empty if it can't be renamed
+    occurrences: Vec<Occurrence>,
+}
+
+// the labels and `@symbols` of each line of an assembly program, read the
+// way the assembler reads them
+fn asm_occurrences(text: &str) -> Vec<(Occurrence, bool)> {
+    let mut found = Vec::new();
+    for (line, source) in text.lines().enumerate() {
+        let code = split_comment(source).0;
+        let (name, definition) = match HackLine::parse(code) {
+            Ok(HackLine::ALocation(Cow::Borrowed(name))) => (name, false),
+            Ok(HackLine::Label(Cow::Borrowed(name))) => (name, true),
+            _ => continue,
+        };
+        let start = name.as_ptr() as usize - code.as_ptr() as usize;
+        let occurrence = Occurrence {
+            line,
+            start,
+            end: start + name.len(),
+            name: name.to_owned(),
+        };
+        found.push((occurrence, definition));
+    }
+    found
+}
+
+fn asm_symbol(text: &str, position: Position) -> Option<Symbol> {
+    let found = asm_occurrences(text);
+    let (at, _) = found
+        .iter()
+        .find(|(occurrence, _)| occurrence.contains(position))?;
+    let name = at.name.as_str();
+    let mentions = || {
+        found
+            .iter()
+            .filter(|(occurrence, _)| occurrence.name == name)
+    };
+
+    // the program's addresses, if it assembles
+    let lines: Option<Vec<HackLine>> = text
+        .lines()
+        .map(|line| split_comment(line).0)
+        .filter(|code| !code.trim().is_empty())
+        .map(|code| code.parse().ok())
+        .collect();
+    let symbols = lines.as_deref().map(crate::symbols);
+    let address = |kind: &str, addresses: Option<&HashMap<String, u16>>| match addresses
+        .and_then(|addresses| addresses.get(name))
+    {
+        Some(address) => format!(
+            "{} `{}`: {} address {}",
+            kind,
+            name,
+            rom_or_ram(kind),
+            address
+        ),
+        None => format!("{} `{}`", kind, name),
+    };
+
+    if let Some((_, address)) = PREDEFINED_SYMBOLS
+        .iter()
+        .find(|(symbol, _)| *symbol == name)
+    {
+        return Some(Symbol {
+            definition: None,
+            description: format!("predefined `{}`: RAM address {}", name, address),
+            occurrences: Vec::new(),
+        });
+    }
+    let occurrences = mentions()
+        .map(|(occurrence, _)| occurrence.clone())
+        .collect();
+    Some(match mentions().find(|(_, definition)| *definition) {
+        Some((label, _)) => Symbol {
+            definition: Some(label.clone()),
+            description: address("label", symbols.as_ref().map(|symbols| &symbols.labels)),
+            occurrences,
+        },
+        // a variable comes into being where it's first used
+        None => Symbol {
+            definition: mentions().next().map(|(first, _)| first.clone()),
+            description: address(
+                "variable",
+                symbols.as_ref().map(|symbols| &symbols.variables),
+            ),
+            occurrences,
+        },
+    })
+}
+
+fn rom_or_ram(kind: &str) -> &'static str {
+    if kind == "label" {
+        "ROM"
+    } else {
+        "RAM"
+    }
+}
+
+// the start of `name` as a whole word in `text`
+fn find_word(text: &str, name: &str) -> Option<usize> {
+    let word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+    text.match_indices(name)
+        .map(|(start, _)| start)
+        .find(|&start| {
+            !word(text[..start].chars().next_back())
+                && !word(text[start + name.len()..].chars().next())
+        })
+}
+
+fn jack_symbol(text: &str, position: Position) -> Option<Symbol> {
+    let class = jack::parse(text).ok()?;
+    let tokens = jack::tokenize(text).ok()?;
+    // (0-based) lines
+    let occurrence = |lexed: &jack::Lexed, name: &str| Occurrence {
+        line: lexed.line - 1,
+        start: lexed.span.start,
+        end: lexed.span.end,
+        name: name.to_owned(),
+    };
+    // names of variables, rather than of classes or subroutines
+    let variables: Vec<Occurrence> = tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(index, lexed)| {
+            let Token::Ident(name) = &lexed.token else {
+                return None;
+            };
+            let before = index.checked_sub(1).map(|index| &tokens[index].token);
+            let after = tokens.get(index + 1).map(|lexed| &lexed.token);
+            let member = before == Some(&Token::Symbol('.'));
+            let call = after == Some(&Token::Symbol('('));
+            (!member && !call).then(|| occurrence(lexed, name))
+        })
+        .collect();
+    let at = variables
+        .iter()
+        .find(|occurrence| occurrence.contains(position))?;
+    let name = at.name.as_str();
+
+    // which subroutine each line is in, by the 0-based line it starts on
+    let starts: Vec<usize> = class
+        .subroutines
+        .iter()
+        .map(|subroutine| subroutine.line - 1)
+        .collect();
+    let subroutine_at = |line: usize| starts.iter().rposition(|&start| start <= line);
+    let declared_in = |index: usize| {
+        let subroutine = &class.subroutines[index];
+        let first = (subroutine.kind == SubroutineKind::Method) as usize;
+        let params = subroutine
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, var)| (var, "argument", i + first));
+        let locals = subroutine
+            .locals
+            .iter()
+            .enumerate()
+            .map(|(i, var)| (var, "local", i));
+        params.chain(locals).find(|(var, _, _)| var.name == name)
+    };
+
+    let here = subroutine_at(at.line);
+    let (var, segment, index, scope): (&jack::Var, &str, usize, Option<usize>) =
+        match here.and_then(|index| Some((declared_in(index)?, index))) {
+            Some(((var, segment, number), subroutine)) => (var, segment, number, Some(subroutine)),
+            None => {
+                let (kind, var, index) = class_var(&class, name)?;
+                let segment = match kind {
+                    VarKind::Static => "static",
+                    VarKind::Field => "this",
+                };
+                (var, segment, index, None)
+            }
+        };
+
+    let line = var.line - 1;
+    let source = text.lines().nth(line).unwrap_or_default();
+    let definition = find_word(source, name).map(|start| Occurrence {
+        line,
+        start,
+        end: start + name.len(),
+        name: name.to_owned(),
+    });
+    // a class's variables are hidden wherever a subroutine declares its own
+    let occurrences = variables
+        .iter()
+        .filter(|occurrence| occurrence.name == name)
+        .filter(|occurrence| {
+            let subroutine = subroutine_at(occurrence.line);
+            match scope {
+                Some(scope) => subroutine == Some(scope),
+                None => subroutine.is_none_or(|index| declared_in(index).is_none()),
+            }
+        })
+        .cloned()
+        .collect();
+    let kind = match segment {
+        "this" => "field",
+        other => other,
+    };
+    Some(Symbol {
+        definition,
+        description: format!("{} {} {} ({} {})", kind, var.ty, name, segment, index),
+        occurrences,
+    })
+}
+
+fn class_var<'a>(class: &'a Class, name: &str) -> Option<(VarKind, &'a jack::Var, usize)> {
+    let mut counts = [0, 0];
+    for (kind, var) in &class.vars {
+        let count = &mut counts[*kind as usize];
+        if var.name == name {
+            return Some((*kind, var, *count));
+        }
+        *count += 1;
+    }
+    None
+}
+
+fn lsp_diagnostic(diagnostic: &Diagnostic) -> Json {
+    let line = diagnostic.line.saturating_sub(1);
+    let severity = match diagnostic.severity {
+        Severity::Error => 1u64,
+        Severity::Warning => 2,
+    };
+    Json::object([
+        (
+            "range",
+            range(line, diagnostic.span.start, diagnostic.span.end),
+        ),
+        ("severity", severity.into()),
+        ("source", "hack".into()),
+        ("message", diagnostic.message.clone().into()),
+    ])
+}
+
+fn diagnostics(uri: &str, text: &str) -> Vec<Diagnostic> {
+    if uri.ends_with(".asm") {
+        match crate::parse_program(text) {
+            Ok(program) => lint::lint(&program.lines, &[]),
+            Err(errors) => errors,
+        }
+    } else if uri.ends_with(".jack") {
+        compile::compile(text).err().into_iter().collect()
+    } else {
+        Vec::new()
+    }
+}
+
+fn symbol(uri: &str, text: &str, position: Position) -> Option<Symbol> {
+    if uri.ends_with(".asm") {
+        asm_symbol(text, position)
+    } else if uri.ends_with(".jack") {
+        jack_symbol(text, position)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Server {
+    // the text of each open document, by URI
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+// an error to send back rather than a result
+struct Failure(i64, String);
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+const REQUEST_FAILED: i64 = -32803;
+
+impl Server {
+    fn capabilities() -> Json {
+        Json::object([(
+            "capabilities",
+            Json::object([
+                (
+                    "textDocumentSync",
+                    Json::object([
+                        ("openClose", true.into()),
+                        // the whole document, every time
+                        ("change", 1u64.into()),
+                        ("save", Json::object([("includeText", true.into())])),
+                    ]),
+                ),
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                ("renameProvider", true.into()),
+            ]),
+        )])
+    }
+
+    fn publish(&self, uri: &str, out: &mut impl Write) -> io::Result<()> {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let diagnostics: Json = diagnostics(uri, text).iter().map(lsp_diagnostic).collect();
+        let params = Json::object([("uri", uri.into()), ("diagnostics", diagnostics)]);
+        write_message(
+            out,
+            &Json::object([
+                ("jsonrpc", "2.0".into()),
+                ("method", "textDocument/publishDiagnostics".into()),
+                ("params", params),
+            ]),
+        )
+    }
+
+    // the symbol a request's position is on, and the document it's in
+    fn lookup(&self, params: &Json) -> Result<(String, Option<Symbol>), Failure> {
+        let invalid = || {
+            Failure(
+                INVALID_PARAMS,
+                "missing textDocument or position".to_owned(),
+            )
+        };
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .ok_or_else(invalid)?;
+        let position = params
+            .get("position")
+            .and_then(Position::from_json)
+            .ok_or_else(invalid)?;
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        Ok((uri.to_owned(), symbol(uri, text, position)))
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, Failure> {
+        match method {
+            "initialize" => Ok(Self::capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, symbol) = self.lookup(params)?;
+                Ok(match symbol.and_then(|symbol| symbol.definition) {
+                    Some(definition) => {
+                        Json::object([("uri", uri.into()), ("range", definition.range())])
+                    }
+                    None => Json::Null,
+                })
+            }
+            "textDocument/hover" => {
+                let (_, symbol) = self.lookup(params)?;
+                Ok(match symbol {
+                    Some(symbol) => Json::object([(
+                        "contents",
+                        Json::object([
+                            ("kind", "plaintext".into()),
+                            ("value", symbol.description.into()),
+                        ]),
+                    )]),
+                    None => Json::Null,
+                })
+            }
+            "textDocument/rename" => {
+                let (uri, symbol) = self.lookup(params)?;
+                let new_name = params
+                    .get("newName")
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| Failure(INVALID_PARAMS, "missing newName".to_owned()))?;
+                if !is_symbol(new_name) {
+                    Err(Failure(
+                        REQUEST_FAILED,
+                        format!("`{}` isn't a valid name", new_name),
+                    ))?;
+                }
+                let symbol = symbol.filter(|symbol| !symbol.occurrences.is_empty());
+                let symbol = symbol.ok_or_else(|| {
+                    Failure(
+                        REQUEST_FAILED,
+                        "there's nothing here that can be renamed".to_owned(),
+                    )
+                })?;
+                let edits: Json = symbol
+                    .occurrences
+                    .iter()
+                    .map(|occurrence| {
+                        Json::object([("range", occurrence.range()), ("newText", new_name.into())])
+                    })
+                    .collect();
+                Ok(Json::object([(
+                    "changes",
+                    Json::Object(vec![(uri, edits)]),
+                )]))
+            }
+            _ => Err(Failure(
+                METHOD_NOT_FOUND,
+                format!("unsupported method {}", method),
+            )),
+        }
+    }
+
+    fn notification(
+        &mut self,
+        method: &str,
+        params: &Json,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let document = params.get("textDocument");
+        let Some(uri) = document
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+        else {
+            return Ok(());
+        };
+        let text = document
+            .and_then(|document| document.get("text"))
+            .or_else(|| params.get("text"))
+            .and_then(Json::as_str);
+        match method {
+            "textDocument/didOpen" | "textDocument/didSave" => {
+                if let Some(text) = text {
+                    self.documents.insert(uri.to_owned(), text.to_owned());
+                }
+                self.publish(uri, out)?;
+            }
+            // we only report problems on save, so as not to nag mid-edit
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").map_or(&[][..], Json::as_array);
+                if let Some(text) = changes.last().and_then(|change| change.get("text")) {
+                    let text = text.as_str().unwrap_or_default();
+                    self.documents.insert(uri.to_owned(), text.to_owned());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // handles one message, returning false once the client's done with us
+    pub fn handle(&mut self, message: &Json, out: &mut impl Write) -> io::Result<bool> {
+        let method = message
+            .get("method")
+            .and_then(Json::as_str)
+            .unwrap_or_default();
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        if method == "exit" {
+            return Ok(false);
+        }
+        let Some(id) = message.get("id") else {
+            self.notification(method, &params, out)?;
+            return Ok(true);
+        };
+        let outcome = match self.request(method, &params) {
+            Ok(result) => ("result", result),
+            Err(Failure(code, message)) => (
+                "error",
+                Json::object([
+                    ("code", Json::Number(code as f64)),
+                    ("message", message.into()),
+                ]),
+            ),
+        };
+        write_message(
+            out,
+            &Json::object([("jsonrpc", "2.0".into()), ("id", id.clone()), outcome]),
+        )?;
+        Ok(true)
+    }
+}
+
+pub fn serve(mut input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            // a message we couldn't make sense of, which has been read all
+            // the same, so the client hears about it and we go on to the next
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                let error = Json::object([
+                    ("code", Json::Number(PARSE_ERROR as f64)),
+                    ("message", err.to_string().into()),
+                ]);
+                write_message(
+                    &mut out,
+                    &Json::object([
+                        ("jsonrpc", "2.0".into()),
+                        ("id", Json::Null),
+                        ("error", error),
+                    ]),
+                )?;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if !server.handle(&message, &mut out)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs a session, returning the server's messages
+    fn session(messages: &[Json]) -> Vec<Json> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).unwrap();
+        let mut replies = Vec::new();
+        let mut output = &output[..];
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        replies
+    }
+
+    fn open(uri: &str, text: &str) -> Json {
+        Json::parse(&format!(
+            r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","text":{}}}}}}}"#,
+            uri,
+            Json::from(text)
+        ))
+        .unwrap()
+    }
+
+    fn request(id: u64, method: &str, uri: &str, line: u64, character: u64, extra: &str) -> Json {
+        Json::parse(&format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}{}}}}}"#,
+            id, method, uri, line, character, extra
+        ))
+        .unwrap()
+    }
+
+    const ASM: &str = "@i\nM=1\n(LOOP)\n  @i\n  M=M+1\n  @LOOP\n  0;JMP\n@SCREEN\n";
+
+    #[test]
+    fn navigates_assembly() {
+        let replies = session(&[
+            open("file:///a.asm", ASM),
+            request(1, "textDocument/definition", "file:///a.asm", 5, 4, ""),
+            request(2, "textDocument/hover", "file:///a.asm", 3, 3, ""),
+            request(3, "textDocument/hover", "file:///a.asm", 7, 2, ""),
+            request(
+                4,
+                "textDocument/rename",
+                "file:///a.asm",
+                0,
+                1,
+                r#","newName":"count""#,
+            ),
+            request(
+                5,
+                "textDocument/rename",
+                "file:///a.asm",
+                7,
+                2,
+                r#","newName":"x""#,
+            ),
+        ]);
+        // lint warnings come with the document
+        assert_eq!(
+            replies[0].get("method").and_then(Json::as_str),
+            Some("textDocument/publishDiagnostics")
+        );
+        assert_eq!(
+            replies[1].get("result").unwrap().to_string(),
+            r#"{"uri":"file:///a.asm","range":{"start":{"line":2,"character":1},"end":{"line":2,"character":5}}}"#
+        );
+        let hover = |reply: &Json| {
+            let contents = reply.get("result")?.get("contents")?;
+            contents.get("value")?.as_str().map(str::to_owned)
+        };
+        assert_eq!(
+            hover(&replies[2]).as_deref(),
+            Some("variable `i`: RAM address 16")
+        );
+        assert_eq!(
+            hover(&replies[3]).as_deref(),
+            Some("predefined `SCREEN`: RAM address 16384")
+        );
+        let edits = replies[4]
+            .get("result")
+            .unwrap()
+            .get("changes")
+            .unwrap()
+            .get("file:///a.asm")
+            .unwrap();
+        assert_eq!(edits.as_array().len(), 2);
+        assert!(replies[5].get("error").is_some());
+    }
+
+    #[test]
+    fn navigates_spaced_assembly() {
+        let replies = session(&[
+            open("file:///b.asm", "( LOOP )\n@ LOOP // back\n0;JMP\n"),
+            request(1, "textDocument/definition", "file:///b.asm", 1, 3, ""),
+        ]);
+        assert_eq!(
+            replies[1].get("result").unwrap().to_string(),
+            r#"{"uri":"file:///b.asm","range":{"start":{"line":0,"character":2},"end":{"line":0,"character":6}}}"#
+        );
+    }
+
+    #[test]
+    fn answers_bad_messages() {
+        let mut input = b"Content-Length: 5\r\n\r\n{oops".to_vec();
+        write_message(
+            &mut input,
+            &Json::parse(r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#).unwrap(),
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).unwrap();
+        let mut output = &output[..];
+        let error = read_message(&mut output).unwrap().unwrap();
+        let code = error.get("error").and_then(|error| error.get("code"));
+        assert_eq!(code.map(Json::to_string).as_deref(), Some("-32700"));
+        let reply = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(reply.get("id").map(Json::to_string).as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn navigates_jack() {
+        let jack = "class Main {\n  static int n;\n  function void f(int n) {\n    let n = n + 1;\n    return;\n  }\n  function void g() {\n    var int m;\n    let m = n;\n    return;\n  }\n}\n";
+        let replies = session(&[
+            open("file:///Main.jack", jack),
+            request(1, "textDocument/hover", "file:///Main.jack", 3, 8, ""),
+            request(2, "textDocument/definition", "file:///Main.jack", 8, 12, ""),
+            request(
+                3,
+                "textDocument/rename",
+                "file:///Main.jack",
+                8,
+                12,
+                r#","newName":"total""#,
+            ),
+            open(
+                "file:///Bad.jack",
+                "class Bad {\n  function void f() { let x = 1; }\n}\n",
+            ),
+        ]);
+        let diagnostics = replies[0]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert!(diagnostics.as_array().is_empty());
+        let hover = replies[1].get("result").unwrap().get("contents").unwrap();
+        assert_eq!(
+            hover.get("value").and_then(Json::as_str),
+            Some("argument int n (argument 0)")
+        );
+        let range = replies[2].get("result").unwrap().get("range").unwrap();
+        assert_eq!(
+            range.to_string(),
+            r#"{"start":{"line":1,"character":13},"end":{"line":1,"character":14}}"#
+        );
+        // the static, but not the argument that hides it in `f`
+        let edits = replies[3]
+            .get("result")
+            .unwrap()
+            .get("changes")
+            .unwrap()
+            .get("file:///Main.jack")
+            .unwrap();
+        let lines: Vec<_> = edits
+            .as_array()
+            .iter()
+            .map(|edit| {
+                edit.get("range")
+                    .unwrap()
+                    .get("start")
+                    .unwrap()
+                    .get("line")
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(lines, ["1", "8"]);
+        let bad = replies[4]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert_eq!(
+            bad.as_array()[0].to_string(),
+            r#"{"range":{"start":{"line":1,"character":26},"end":{"line":1,"character":27}},"severity":1,"source":"hack","message":"no variable called `x`"}"#
+        );
+    }
+}
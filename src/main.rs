@@ -1,396 +1,1416 @@
-use core::str::FromStr;
-use std::error::Error;
-use std::fs::File;
-use std::io::Write;
-use std::{collections::HashMap, io::BufRead};
-use std::{env, io::BufReader};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
 
-const PREDEFINED_SYMBOLS: [(&str, u16); 23] = [
-    ("SP", 0),
-    ("LCL", 1),
-    ("ARG", 2),
-    ("THIS", 3),
-    ("THAT", 4),
-    ("R0", 0),
-    ("R1", 1),
-    ("R2", 2),
-    ("R3", 3),
-    ("R4", 4),
-    ("R5", 5),
-    ("R6", 6),
-    ("R7", 7),
-    ("R8", 8),
-    ("R9", 9),
-    ("R10", 10),
-    ("R11", 11),
-    ("R12", 12),
-    ("R13", 13),
-    ("R14", 14),
-    ("R15", 15),
-    ("SCREEN", 16384),
-    ("KBD", 24576),
-];
-
-trait Assemble {
-    fn assemble<'slf>(
-        &'slf self,
-        table: &mut SymbolTable<'slf>,
-        writer: &mut impl Write,
-    ) -> Result<(), std::io::Error>;
-}
-
-#[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, Clone, Copy, parse_display::FromStr)]
-enum Destination {
-    Null,
-    M,
-    D,
-    MD,
-    A,
-    AM,
-    AD,
-    AMD,
-}
-
-impl Assemble for Destination {
-    fn assemble(
-        &self,
-        _table: &mut SymbolTable,
-        writer: &mut impl Write,
-    ) -> Result<(), std::io::Error> {
-        write!(writer, "{:03b}", *self as u8)
-    }
-}
-
-#[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, Clone, Copy, parse_display::FromStr)]
-enum Jump {
-    Null,
-    JGT,
-    JEQ,
-    JGE,
-    JLT,
-    JNE,
-    JLE,
-    JMP,
-}
-
-impl Assemble for Jump {
-    fn assemble(
-        &self,
-        _table: &mut SymbolTable,
-        writer: &mut impl Write,
-    ) -> Result<(), std::io::Error> {
-        write!(writer, "{:03b}", *self as u8)
-    }
-}
-
-#[derive(Debug, Clone, Copy, parse_display::FromStr)]
-enum AM {
-    A,
-    M,
-}
-
-impl Assemble for AM {
-    fn assemble(
-        &self,
-        _table: &mut SymbolTable,
-        writer: &mut impl Write,
-    ) -> Result<(), std::io::Error> {
-        write!(writer, "{:b}", *self as u8)
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-enum Computation {
-    Zero,
-    One,
-    Neg1,
-    D,
-    X(AM),
-    NegD,
-    NegX(AM),
-    DPlusOne,
-    XPlusOne(AM),
-    DMinusOne,
-    XMinusOne(AM),
-    DPlusX(AM),
-    DMinusX(AM),
-    XMinusD(AM),
-    NotD,
-    NotX(AM),
-    DAndX(AM),
-    DOrX(AM),
-}
-
-impl FromStr for Computation {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use Computation as C;
-        match s {
-            "0" => Ok(C::Zero),
-            "1" => Ok(C::One),
-            "-1" => Ok(C::Neg1),
-            "D" => Ok(C::D),
-            "A" => Ok(C::X(AM::A)),
-            "M" => Ok(C::X(AM::M)),
-            "!D" => Ok(C::NotD),
-            "!A" => Ok(C::NotX(AM::A)),
-            "!M" => Ok(C::NotX(AM::M)),
-            "-D" => Ok(C::NegD),
-            "-A" => Ok(C::NegX(AM::A)),
-            "-M" => Ok(C::NegX(AM::M)),
-            "D+1" => Ok(C::DPlusOne),
-            "A+1" => Ok(C::XPlusOne(AM::A)),
-            "M+1" => Ok(C::XPlusOne(AM::M)),
-            "D-1" => Ok(C::DMinusOne),
-            "A-1" => Ok(C::XMinusOne(AM::A)),
-            "M-1" => Ok(C::XMinusOne(AM::M)),
-            "D+A" => Ok(C::DPlusX(AM::A)),
-            "D+M" => Ok(C::DPlusX(AM::M)),
-            "D-A" => Ok(C::DMinusX(AM::A)),
-            "D-M" => Ok(C::DMinusX(AM::M)),
-            "A-D" => Ok(C::XMinusD(AM::A)),
-            "M-D" => Ok(C::XMinusD(AM::M)),
-            "D&A" => Ok(C::DAndX(AM::A)),
-            "D&M" => Ok(C::DAndX(AM::M)),
-            "D|A" => Ok(C::DOrX(AM::A)),
-            "D|M" => Ok(C::DOrX(AM::M)),
-            other => Err(format!("Invalid comp: {}", other)),
-        }
-    }
-}
-
-impl Assemble for Computation {
-    fn assemble<'slf>(
-        &'slf self,
-        table: &mut SymbolTable<'slf>,
-        writer: &mut impl Write,
-    ) -> Result<(), std::io::Error> {
-        use Computation as C;
-        if let C::X(x)
-        | C::NegX(x)
-        | C::XPlusOne(x)
-        | C::XMinusOne(x)
-        | C::XMinusD(x)
-        | C::DPlusX(x)
-        | C::DMinusX(x)
-        | C::NotX(x)
-        | C::DAndX(x)
-        | C::DOrX(x) = self
+use hack::diagnostic::{self, Diagnostic};
+use hack::emit;
+use hack::error::{self, HackError};
+use hack::predefined::SymbolSet;
+use hack::{
+    allocate, backtrace, callgraph, cfg, compat, compile, coverage, dap, debug, diff, disassemble,
+    emulator, format, image, json, link, lint, lsp, optimize, os, peripheral, profile, repl, run,
+    script, selftest, snapshot, sourcemap, stats, stream, translate, vcd, verify, vm, vmdebug,
+    xref,
+};
+use hack::{
+    assemble_lines_with, check, load_program, parse, parse_source, parse_source_for, relax_case,
+    symbols, symbols_with, HackLine, LoadedProgram, SourceLine, SymbolTable, Target,
+};
+
+mod cli;
+mod completions;
+mod log;
+mod watch;
+
+// expands the command-line arguments into the list of files to assemble:
+// plain paths are taken as-is, directories contribute every `.asm` file
+// directly inside them (sorted, so batch output is deterministic)
+fn collect_inputs(args: &[String], extension: &str) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut inputs = Vec::new();
+    for arg in args {
+        let path = Path::new(arg);
+        if path.is_dir() {
+            let mut entries = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.is_file() && entry.extension().is_some_and(|ext| ext == extension) {
+                    entries.push(entry);
+                }
+            }
+            entries.sort();
+            inputs.extend(entries);
+        } else {
+            inputs.push(path.to_owned());
+        }
+    }
+    Ok(inputs)
+}
+
+// how `asm` should process each file
+#[derive(Clone)]
+struct AsmOptions {
+    object: bool,
+    optimize: bool,
+    source_map: bool,
+    // write the parsed program as JSON rather than assembling it
+    ast_json: bool,
+    // read the program twice instead of holding it in memory
+    stream: bool,
+    // behave exactly like the course's assembler
+    compat: bool,
+    // accept mnemonics in lowercase
+    relaxed_case: bool,
+    // what programs can use without defining, from `--symbols`
+    symbols: SymbolSet,
+    target: Target,
+    // the order variables are allocated in, around whatever each file pins
+    order: allocate::Order,
+    // write a .sym file alongside each .hack file
+    sym: bool,
+}
+
+impl AsmOptions {
+    fn new(matches: &cli::Matches) -> Result<Self, HackError> {
+        let ast_json = match matches.value("emit").unwrap_or("hack") {
+            "hack" => false,
+            "ast-json" => true,
+            other => Err(HackError::Usage(format!(
+                "invalid output `{}` (expected hack or ast-json)",
+                other
+            )))?,
+        };
+        if ast_json
+            && ["object", "source-map", "sym"]
+                .iter()
+                .any(|flag| matches.flag(flag))
+        {
+            Err(HackError::Usage(
+                "`--emit ast-json` can't be combined with `--object`, `--source-map` or `--sym`"
+                    .to_owned(),
+            ))?;
+        }
+        let order = matches
+            .value("allocate")
+            .map_or(Ok(allocate::Order::FirstUse), allocate::Order::parse)
+            .map_err(HackError::Usage)?;
+        if matches.flag("object") && (order != allocate::Order::FirstUse || matches.flag("sym")) {
+            Err(HackError::Usage(
+                "objects get their variables from `hack link`, which takes `--allocate` and \
+                 `--sym` itself"
+                    .to_owned(),
+            ))?;
+        }
+        let target = match matches.value("target").unwrap_or("hack") {
+            "hack" => Target::Hack,
+            "hack-ext" => Target::HackExt,
+            other => Err(HackError::Usage(format!(
+                "invalid target `{}` (expected hack or hack-ext)",
+                other
+            )))?,
+        };
+        let stream = matches.flag("stream");
+        if stream
+            && (ast_json
+                || target != Target::Hack
+                || order != allocate::Order::FirstUse
+                || ["object", "optimize", "source-map", "relaxed-case", "sym"]
+                    .iter()
+                    .any(|flag| matches.flag(flag)))
+        {
+            Err(HackError::Usage(
+                "`--stream` can only write .hack files, without any other options".to_owned(),
+            ))?;
+        }
+        let compat = match matches.value("compat") {
+            None => false,
+            Some("nand2tetris") => true,
+            Some(other) => Err(HackError::Usage(format!(
+                "invalid compatibility mode `{}` (expected nand2tetris)",
+                other
+            )))?,
+        };
+        let relaxed_case = matches.flag("relaxed-case");
+        if compat
+            && (ast_json
+                || stream
+                || relaxed_case
+                || target != Target::Hack
+                || order != allocate::Order::FirstUse
+                || matches.flag("object")
+                || matches.flag("optimize"))
         {
-            x.assemble(table, writer)?;
+            Err(HackError::Usage(
+                "`--compat` can only write .hack files for the standard target, without \
+                 optimizing, streaming, relaxing case or reordering variables"
+                    .to_owned(),
+            ))?;
+        }
+        let symbols = match matches.value("symbols") {
+            None => SymbolSet::standard().clone(),
+            // objects are linked against the standard symbols, and the course's
+            // assembler knows no others
+            Some(_) if compat || matches.flag("object") => Err(HackError::Usage(
+                "`--symbols` can't be combined with `--object` or `--compat`".to_owned(),
+            ))?,
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(HackError::io(Path::new(path)))?;
+                SymbolSet::parse(&text)
+                    .map_err(|err| HackError::new(Path::new(path), err.into()))?
+            }
+        };
+        Ok(Self {
+            object: matches.flag("object"),
+            optimize: matches.flag("optimize"),
+            source_map: matches.flag("source-map"),
+            ast_json,
+            stream,
+            compat,
+            relaxed_case,
+            symbols,
+            target,
+            order,
+            sym: matches.flag("sym"),
+        })
+    }
+}
+
+// the parsed program, for tools that want our front end without our output
+fn ast_json(path: &Path, sources: &[SourceLine]) -> json::Json {
+    let lines = sources.iter().map(|source| {
+        json::Json::object([
+            ("line", source.number.into()),
+            ("instruction", (&source.line).into()),
+        ])
+    });
+    json::Json::object([
+        ("file", path.to_string_lossy().into_owned().into()),
+        ("lines", lines.collect()),
+    ])
+}
+
+// what `assemble_file` made of a file, for the log
+struct Assembled {
+    output: PathBuf,
+    report: optimize::Report,
+    // instructions written, and labels defined and variables used, unless
+    // the file was streamed rather than held in memory
+    instructions: Option<usize>,
+    symbols: Option<(usize, usize)>,
+    // how long each pass took, in order
+    passes: Vec<(&'static str, Duration)>,
+}
+
+fn assemble_file(input_file_path: &Path, options: &AsmOptions) -> Result<Assembled, HackError> {
+    let mut timer = log::Timer::start();
+    if options.stream {
+        let output_file_path = input_file_path.with_extension("hack");
+        let mut output_file =
+            File::create(&output_file_path).map_err(HackError::io(&output_file_path))?;
+        stream::assemble(
+            || File::open(input_file_path).map(BufReader::new),
+            &options.symbols,
+            &mut emit::Text::new(&mut output_file),
+        )
+        .map_err(|err| HackError::new(input_file_path, err))?;
+        timer.lap("stream");
+        return Ok(Assembled {
+            output: output_file_path,
+            report: optimize::Report::default(),
+            instructions: None,
+            symbols: None,
+            passes: timer.passes,
+        });
+    }
+
+    let mut source = fs::read_to_string(input_file_path).map_err(HackError::io(input_file_path))?;
+    timer.lap("read");
+    if options.compat {
+        source = compat::normalize(&source);
+    }
+    if options.relaxed_case {
+        source = relax_case(&source);
+    }
+    let mut sources = parse_source_for(&source, options.target).map_err(|err| {
+        let err = if options.compat {
+            compat::reword(err)
         } else {
-            write!(writer, "0")?;
+            err
         };
+        HackError::new(input_file_path, err.into())
+    })?;
+    if options.compat {
+        compat::check(&sources).map_err(|err| HackError::new(input_file_path, err.into()))?;
+    }
+    let layout = allocate::Layout::new(options.order, &source)
+        .map_err(|err| HackError::new(input_file_path, err.into()))?;
+    timer.lap("parse");
+
+    let report = if options.optimize {
+        let report = optimize::optimize(&mut sources);
+        timer.lap("optimize");
+        report
+    } else {
+        optimize::Report::default()
+    };
+    let lines: Vec<&HackLine> = sources.iter().map(|source| &source.line).collect();
+    let instructions = lines
+        .iter()
+        .filter(|line| !matches!(line, HackLine::Label(_)))
+        .count();
+    let symbols = symbol_counts(&lines, &options.symbols);
+    let done = |output: PathBuf, timer: log::Timer| Assembled {
+        output,
+        report,
+        instructions: Some(instructions),
+        symbols: Some(symbols),
+        passes: timer.passes,
+    };
+
+    if options.ast_json {
+        let output_file_path = input_file_path.with_extension("json");
+        let text = format!("{}\n", ast_json(input_file_path, &sources));
+        fs::write(&output_file_path, text).map_err(HackError::io(&output_file_path))?;
+        timer.lap("write");
+        return Ok(done(output_file_path, timer));
+    }
+
+    if options.source_map && !options.object {
+        let map = sourcemap::SourceMap::new(&input_file_path.to_string_lossy(), &sources);
+        let map_file_path = input_file_path.with_extension("map");
+        File::create(&map_file_path)
+            .and_then(|mut map_file| map.write(&mut map_file))
+            .map_err(HackError::io(&map_file_path))?;
+        timer.lap("source map");
+    }
+    let lines: Vec<HackLine> = sources.into_iter().map(|source| source.line).collect();
+
+    if options.object {
+        let name = input_file_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let object = link::Object::new(&name, &lines, &layout.pins)
+            .map_err(|err| HackError::new(input_file_path, err))?;
+
+        let output_file_path = input_file_path.with_extension("hobj");
+        File::create(&output_file_path)
+            .and_then(|mut output_file| object.write(&mut output_file))
+            .map_err(HackError::io(&output_file_path))?;
+        timer.lap("write");
+        return Ok(done(output_file_path, timer));
+    }
 
-        write!(
-            writer,
-            "{}",
-            match self {
-                Computation::Zero => "101010",
-                Computation::One => "111111",
-                Computation::Neg1 => "111010",
-                Computation::D => "001100",
-                Computation::X(_) => "110000",
-                Computation::NegD => "001111",
-                Computation::NegX(_) => "110011",
-                Computation::DPlusOne => "011111",
-                Computation::XPlusOne(_) => "110111",
-                Computation::DMinusOne => "001110",
-                Computation::XMinusOne(_) => "110010",
-                Computation::DPlusX(_) => "000010",
-                Computation::DMinusX(_) => "010011",
-                Computation::XMinusD(_) => "000111",
-                Computation::NotD => "001101",
-                Computation::NotX(_) => "110001",
-                Computation::DAndX(_) => "000000",
-                Computation::DOrX(_) => "010101",
+    let output_file_path = input_file_path.with_extension("hack");
+    let mut output_file =
+        File::create(&output_file_path).map_err(HackError::io(&output_file_path))?;
+
+    assemble_lines_with(
+        &lines,
+        &options.symbols,
+        &layout,
+        &mut emit::Text::new(&mut output_file),
+    )
+    .map_err(|err| HackError::new(input_file_path, err))?;
+    timer.lap("assemble");
+
+    if options.sym {
+        let symbols = symbols_with(&lines, &options.symbols, &layout)
+            .map_err(|err| HackError::new(input_file_path, err.into()))?;
+        let sym_file_path = input_file_path.with_extension("sym");
+        fs::write(&sym_file_path, symbols.to_string()).map_err(HackError::io(&sym_file_path))?;
+        timer.lap("symbols");
+    }
+
+    Ok(done(output_file_path, timer))
+}
+
+// how many labels a program defines and variables it uses
+fn symbol_counts<'a>(lines: &[&'a HackLine<'a>], predefined: &SymbolSet) -> (usize, usize) {
+    let mut table = SymbolTable::new(predefined, lines.iter().copied());
+    let mut labels = 0;
+    for line in lines {
+        match line {
+            HackLine::Label(_) => labels += 1,
+            HackLine::ALocation(name) if table.label(name).is_none() => {
+                table.variable(name);
             }
-        )
+            _ => {}
+        }
     }
+    (labels, table.variables.len())
 }
 
-#[derive(Debug, Clone)]
-enum HackLine {
-    Label(String),
-    AImmediate(u16),
-    ALocation(String),
-    C(Computation, Destination, Jump),
+fn collect(matches: &cli::Matches) -> Result<Vec<PathBuf>, HackError> {
+    let inputs = collect_inputs(&matches.positionals, "asm")
+        .map_err(|err| HackError::Usage(format!("error reading inputs: {}", err)))?;
+    if inputs.is_empty() {
+        Err(HackError::Usage("no .asm files found".to_owned()))?;
+    }
+    Ok(inputs)
 }
 
-impl FromStr for HackLine {
-    type Err = Box<dyn Error>;
+// runs `f` on every input, reporting on each one, and returns the failures
+fn for_each_input(
+    inputs: &[PathBuf],
+    color: bool,
+    mut f: impl FnMut(&Path) -> Result<String, HackError>,
+) -> Vec<HackError> {
+    let mut errors = Vec::new();
+    for input in inputs {
+        match f(input) {
+            Ok(status) => println!("{}: {}", input.display(), status),
+            Err(err) => {
+                eprint!("{}", err.render(color));
+                errors.push(err);
+            }
+        }
+    }
+    errors
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.trim();
-        if s.starts_with('(') {
-            // line is a label
-            let label = s.trim_start_matches('(').trim_end_matches(')');
-            Ok(Self::Label(label.to_owned()))
-        } else if s.starts_with('@') {
-            // A-instruction
-            let value = s.trim_start_matches('@');
-            Ok(if let Ok(imm) = value.parse::<u16>() {
-                // plain memory address
-                Self::AImmediate(imm)
-            } else {
-                // location
-                Self::ALocation(value.to_owned())
-            })
-        } else {
-            // split C-instruction into dest, comp, and jump
-            let (dest, comp, jump) = {
-                let (dest, comp) = match s.split('=').collect_vec()[..] {
-                    [comp] => (Destination::Null, comp),
-                    [dest, comp] => (dest.parse()?, comp),
-                    _ => Err("more than one equal sign in instruction")?,
-                };
+// like `for_each_input`, but working on up to `jobs` files at once. Files
+// are still reported in the order they were given, so that a batch reads
+// the same however many threads it ran on
+fn for_each_input_parallel(
+    inputs: &[PathBuf],
+    color: bool,
+    jobs: usize,
+    f: impl Fn(&Path) -> Result<String, HackError> + Sync,
+) -> Vec<HackError> {
+    for_each_input_parallel_with(inputs, jobs, f, |input, result| match result {
+        Ok(status) => println!("{}: {}", input.display(), status),
+        Err(err) => eprint!("{}", err.render(color)),
+    })
+}
 
-                let (comp, jump) = match comp.split(';').collect_vec()[..] {
-                    [comp] => (comp, Jump::Null),
-                    [comp, jump] => (comp, jump.parse()?),
-                    _ => Err("more than one ; in instruction")?,
+// the same, handing each result to `report` rather than printing it
+fn for_each_input_parallel_with<T: Send>(
+    inputs: &[PathBuf],
+    jobs: usize,
+    f: impl Fn(&Path) -> Result<T, HackError> + Sync,
+    mut report: impl FnMut(&Path, Result<T, &HackError>),
+) -> Vec<HackError> {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, inputs.len().max(1)) {
+            let sender = sender.clone();
+            let (next, f) = (&next, &f);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(input) = inputs.get(index) else {
+                    break;
                 };
+                if sender.send((index, f(input))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // hold on to anything that finishes early until its turn comes
+        let mut errors = Vec::new();
+        let mut finished = BTreeMap::new();
+        let mut reported = 0;
+        for (index, result) in receiver {
+            finished.insert(index, result);
+            while let Some(result) = finished.remove(&reported) {
+                match result {
+                    Ok(value) => report(&inputs[reported], Ok(value)),
+                    Err(err) => {
+                        report(&inputs[reported], Err(&err));
+                        errors.push(err);
+                    }
+                }
+                reported += 1;
+            }
+        }
+        errors
+    })
+}
+
+// how many files to work on at once, from `--jobs`
+fn jobs(matches: &cli::Matches) -> Result<usize, HackError> {
+    match matches.value("jobs") {
+        Some(jobs) => jobs
+            .parse()
+            .ok()
+            .filter(|jobs| *jobs > 0)
+            .ok_or_else(|| HackError::Usage(format!("invalid job count `{}`", jobs))),
+        None => Ok(thread::available_parallelism().map_or(1, usize::from)),
+    }
+}
 
-                (dest, comp.parse()?, jump)
+fn assemble_all(
+    inputs: &[PathBuf],
+    options: &AsmOptions,
+    jobs: usize,
+    color: bool,
+    log: &log::Log,
+) -> Vec<HackError> {
+    for_each_input_parallel_with(
+        inputs,
+        jobs,
+        |input| assemble_file(input, options),
+        |input, result| {
+            let file = || ("file", input.to_string_lossy().into_owned().into());
+            let assembled = match result {
+                Ok(assembled) => assembled,
+                Err(err) => return log.error(&err.render(color), &err.to_string(), vec![file()]),
             };
-            Ok(Self::C(comp, dest, jump))
-        }
-    }
-}
-
-impl Assemble for HackLine {
-    fn assemble<'slf>(
-        &'slf self,
-        table: &mut SymbolTable<'slf>,
-        writer: &mut impl Write,
-    ) -> Result<(), std::io::Error> {
-        match self {
-            HackLine::Label(_) => {}
-            HackLine::AImmediate(imm) => writeln!(writer, "{:016b}", imm)?,
-            HackLine::ALocation(name) => {
-                let address = if let Some(address) = table.label(name) {
-                    // existing label
-                    address
-                } else {
-                    // variable (allocating a new one if it doesn't already exist)
-                    table.variable(name)
+            let mut status = format!("{}: ok ({}", input.display(), assembled.output.display());
+            if assembled.report.removed() > 0 {
+                status += &format!(", {}", assembled.report);
+            }
+            status.push(')');
+            log.event(
+                log::Level::Info,
+                &status,
+                [
+                    file(),
+                    (
+                        "output",
+                        assembled.output.to_string_lossy().into_owned().into(),
+                    ),
+                    ("removed", assembled.report.removed().into()),
+                ],
+            );
+
+            if log.enabled(log::Level::Verbose) {
+                let bytes = fs::metadata(&assembled.output).map_or(0, |metadata| metadata.len());
+                let count = |n: usize, what: &str| {
+                    format!("{} {}{}", n, what, if n == 1 { "" } else { "s" })
                 };
-                writeln!(writer, "{:016b}", address)?
+                let mut sizes = vec![count(bytes as usize, "byte")];
+                let mut fields = vec![file(), ("bytes", bytes.into())];
+                if let Some(instructions) = assembled.instructions {
+                    sizes.insert(0, count(instructions, "instruction"));
+                    fields.push(("instructions", instructions.into()));
+                }
+                if let Some((labels, variables)) = assembled.symbols {
+                    sizes.push(count(labels, "label"));
+                    sizes.push(count(variables, "variable"));
+                    fields.push(("labels", labels.into()));
+                    fields.push(("variables", variables.into()));
+                }
+                log.event(log::Level::Verbose, &sizes.join(", "), fields);
             }
-            HackLine::C(c, d, j) => {
-                write!(writer, "111")?;
-                c.assemble(table, writer)?;
-                d.assemble(table, writer)?;
-                j.assemble(table, writer)?;
-                writeln!(writer)?;
+            let passes = assembled.passes.iter().map(|(pass, time)| {
+                (
+                    format!("{} {:.3}ms", pass, log::millis(*time)),
+                    (*pass, log::millis(*time).into()),
+                )
+            });
+            let (text, fields): (Vec<_>, Vec<_>) = passes.unzip();
+            log.event(
+                log::Level::Debug,
+                &text.join(", "),
+                [file(), ("passes", json::Json::object(fields))],
+            );
+        },
+    )
+}
+
+fn asm_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let options = AsmOptions::new(matches)?;
+    let jobs = jobs(matches)?;
+    let log = log::Log::new(matches).map_err(HackError::Usage)?;
+    if matches.flag("watch") {
+        log.event(
+            log::Level::Info,
+            "Watching for changes (press Ctrl-C to stop)",
+            [],
+        );
+        watch::watch(
+            || collect_inputs(&matches.positionals, "asm").unwrap_or_default(),
+            |changed| {
+                assemble_all(changed, &options, jobs, color, &log);
+            },
+        );
+    }
+
+    let started = Instant::now();
+    let inputs = collect(matches)?;
+    let errors = assemble_all(&inputs, &options, jobs, color, &log);
+    if let Some(err) = HackError::batch(&errors, inputs.len()) {
+        return Err(err);
+    }
+
+    let elapsed = started.elapsed();
+    let message = if log.enabled(log::Level::Verbose) {
+        format!(
+            "Done! ({} files in {:.1}ms)",
+            inputs.len(),
+            log::millis(elapsed)
+        )
+    } else {
+        "Done!".to_owned()
+    };
+    log.event(
+        log::Level::Info,
+        &message,
+        [
+            ("files", inputs.len().into()),
+            ("elapsed_ms", log::millis(elapsed).into()),
+        ],
+    );
+    Ok(())
+}
+
+fn check_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let inputs = collect(matches)?;
+    let errors = for_each_input_parallel(&inputs, color, jobs(matches)?, |input| {
+        let file = File::open(input).map_err(HackError::io(input))?;
+        check(BufReader::new(file)).map_err(|err| HackError::new(input, err))?;
+        Ok("ok".to_owned())
+    });
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// where `translate` writes a program: `Prog.vm` becomes `Prog.asm`, and a
+// directory `Prog` becomes `Prog/Prog.asm`, as the course's tools expect
+fn translation_path(input: &Path) -> PathBuf {
+    if input.is_dir() {
+        let name = input
+            .canonicalize()
+            .ok()
+            .and_then(|path| path.file_name().map(|name| name.to_owned()))
+            .unwrap_or_else(|| "out".into());
+        input.join(name).with_extension("asm")
+    } else {
+        input.with_extension("asm")
+    }
+}
+
+fn translate_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let options = translate::Options {
+        inline: matches.flag("inline"),
+        annotate: matches.flag("annotate"),
+    };
+    let inputs: Vec<PathBuf> = matches.positionals.iter().map(PathBuf::from).collect();
+    let errors = for_each_input(&inputs, color, |input| {
+        let program = vm::load(input)?;
+        // translated code has nowhere to go for these
+        if let Some(line) = program.builtins().next() {
+            let text = line.text.trim();
+            Err(HackError::new(
+                &program.files[line.file].path,
+                Diagnostic::error(format!(
+                    "{} needs the OS's own code to be translated, not the emulator's",
+                    line.command
+                ))
+                .at(&line.text, text)
+                .on_line(line.number, &line.text)
+                .into(),
+            ))?;
+        }
+        let asm = translate::translate(&program, options);
+        let output = translation_path(input);
+        fs::write(&output, asm.to_asm()).map_err(HackError::io(&output))?;
+        Ok(format!(
+            "ok ({}, {} instructions)",
+            output.display(),
+            asm.instructions()
+        ))
+    });
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn compile_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let inputs = collect_inputs(&matches.positionals, "jack")
+        .map_err(|err| HackError::Usage(format!("error reading inputs: {}", err)))?;
+    if inputs.is_empty() {
+        Err(HackError::Usage("no .jack files found".to_owned()))?;
+    }
+    let verbose = matches.flag("verbose");
+    let errors = for_each_input(&inputs, color, |input| {
+        let source = fs::read_to_string(input).map_err(HackError::io(input))?;
+        let compiled =
+            compile::compile(&source).map_err(|err| HackError::new(input, err.into()))?;
+        let output = input.with_extension("vm");
+        let mut text = compiled.code.join("\n");
+        text.push('\n');
+        fs::write(&output, text).map_err(HackError::io(&output))?;
+        let mut status = format!("ok ({}, {} commands", output.display(), compiled.code.len());
+        if !compiled.notes.is_empty() {
+            status += &format!(", {} simplified", compiled.notes.len());
+        }
+        status.push(')');
+        if verbose {
+            for (line, note) in &compiled.notes {
+                status += &format!("\n  line {}: {}", line, note);
             }
         }
-        Ok(())
+        Ok(status)
+    });
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None => Ok(()),
     }
 }
 
-struct SymbolTable<'data> {
-    labels: HashMap<&'data str, u16>,
-    variables: HashMap<&'data str, u16>,
-    variable_address: u16,
+fn fmt_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let inputs = collect(matches)?;
+    let errors = for_each_input(&inputs, color, |input| {
+        let file = File::open(input).map_err(HackError::io(input))?;
+        let formatted =
+            format::format(BufReader::new(file)).map_err(|err| HackError::new(input, err))?;
+        let original = fs::read_to_string(input).map_err(HackError::io(input))?;
+        if formatted == original {
+            Ok("already formatted".to_owned())
+        } else if matches.flag("check") {
+            Err(HackError::new(input, "not formatted".into()))
+        } else {
+            fs::write(input, formatted).map_err(HackError::io(input))?;
+            Ok("formatted".to_owned())
+        }
+    });
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
 
-impl<'data> SymbolTable<'data> {
-    // by taking an `Iterator`, we guarantee to our caller that we
-    // iterate at most once
-    fn new<I>(iter: I) -> Self
-    where
-        I: IntoIterator<Item = &'data HackLine>,
+fn lint_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    if matches.flag("list") {
+        for (name, description) in lint::LINTS {
+            println!("{:<20} {}", name, description);
+        }
+        return Ok(());
+    }
+
+    let allowed = matches.values("allow");
+    if let Some(unknown) = allowed
+        .iter()
+        .find(|allow| !lint::LINTS.iter().any(|(name, _)| name == *allow))
     {
-        let mut labels = HashMap::from(PREDEFINED_SYMBOLS);
-        let mut program_length = 0; // where labels point to
+        Err(HackError::Usage(format!("unknown lint `{}`", unknown)))?;
+    }
 
-        for line in iter.into_iter() {
-            if let HackLine::Label(label) = line {
-                labels.insert(label, program_length);
-            } else {
-                // label lines shouldn't contribute to program length
-                program_length += 1
+    let inputs = collect(matches)?;
+    let mut warned = 0;
+    let errors = for_each_input(&inputs, color, |input| {
+        let source = fs::read_to_string(input).map_err(HackError::io(input))?;
+        let lines = parse_source(&source).map_err(|err| HackError::new(input, err.into()))?;
+        let warnings = lint::lint(&lines, &allowed);
+        for warning in &warnings {
+            eprint!("{}", warning.render(input, color));
+        }
+        if !warnings.is_empty() {
+            warned += 1;
+        }
+        Ok(format!("{} warnings", warnings.len()))
+    });
+
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None if warned > 0 => Err(HackError::Batch {
+            failed: warned,
+            total: inputs.len(),
+            code: error::EXIT_ASSEMBLY,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn stats_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let inputs = collect(matches)?;
+    let errors = for_each_input(&inputs, color, |input| {
+        let source = fs::read_to_string(input).map_err(HackError::io(input))?;
+        let lines = parse(&source).map_err(|err| HackError::new(input, err.into()))?;
+        Ok(format!("\n{}", stats::Stats::new(&lines))
+            .trim_end()
+            .to_owned())
+    });
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn xref_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let inputs = collect(matches)?;
+    let errors = for_each_input(&inputs, color, |input| {
+        let source = fs::read_to_string(input).map_err(HackError::io(input))?;
+        let lines = parse_source(&source).map_err(|err| HackError::new(input, err.into()))?;
+        Ok(format!("\n{}", xref::Xref::new(&lines))
+            .trim_end()
+            .to_owned())
+    });
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn selftest_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let results = selftest::run(&collect(matches)?);
+    print!("{}", selftest::table(&results));
+    let failed = results
+        .iter()
+        .filter(|(_, outcome)| !outcome.passed())
+        .count();
+    if failed > 0 {
+        Err(HackError::Batch {
+            failed,
+            total: results.len(),
+            code: error::EXIT_ASSEMBLY,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn verify_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let inputs = collect(matches)?;
+    let mut mismatched = 0;
+    let errors = for_each_input(&inputs, color, |input| {
+        let source = fs::read_to_string(input).map_err(HackError::io(input))?;
+        let lines = parse_source(&source).map_err(|err| HackError::new(input, err.into()))?;
+        let report = verify::verify(&lines);
+        for mismatch in &report.mismatches {
+            eprint!("{}", mismatch.render(input, color));
+        }
+        if !report.mismatches.is_empty() {
+            mismatched += 1;
+        }
+        Ok(format!(
+            "{} of {} instructions round-trip",
+            report.instructions - report.mismatches.len(),
+            report.instructions
+        ))
+    });
+
+    match HackError::batch(&errors, inputs.len()) {
+        Some(err) => Err(err),
+        None if mismatched > 0 => Err(HackError::Batch {
+            failed: mismatched,
+            total: inputs.len(),
+            code: error::EXIT_ASSEMBLY,
+        }),
+        None => Ok(()),
+    }
+}
+
+// a program to compare, with whatever labels we can find for it: those of
+// an `.asm` file, or of the source a `.hack` file's source map points to
+fn diff_side(path: &Path) -> Result<diff::Side, HackError> {
+    let program = load_program(path)?;
+    let words = program.cpu.rom[..program.cpu.program_length].to_vec();
+    let mut labels = program.symbols.labels;
+    if let Some(location) = labels
+        .is_empty()
+        .then_some(program.map)
+        .flatten()
+        .and_then(|map| map.locations.into_iter().next())
+    {
+        if let Ok(source) = fs::read_to_string(&location.file) {
+            if let Ok(lines) = parse(&source) {
+                labels = symbols(&lines).labels;
             }
         }
+    }
+    Ok(diff::Side::new(words, labels))
+}
+
+fn diff_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let [a, b] = &matches.positionals[..] else {
+        return Err(HackError::Usage(
+            "`diff` takes exactly two programs to compare".to_owned(),
+        ));
+    };
+    let (a, b) = (Path::new(a), Path::new(b));
+    let (out, changed) = diff::diff(&diff_side(a)?, &diff_side(b)?);
+    print!("{}", out);
+    if changed > 0 {
+        Err(HackError::new(
+            b,
+            format!("{} instructions differ from {}", changed, a.display()).into(),
+        ))
+    } else {
+        println!("{} and {} are identical", a.display(), b.display());
+        Ok(())
+    }
+}
+
+fn disasm_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let input = single_input(matches)?;
+    let file = File::open(input).map_err(HackError::io(input))?;
+    let words = emulator::load(BufReader::new(file)).map_err(|err| HackError::new(input, err))?;
+    let symbols = match matches.value("sym") {
+        Some(path) => {
+            let path = Path::new(path);
+            let text = fs::read_to_string(path).map_err(HackError::io(path))?;
+            debug::Symbols::parse(&text).map_err(|err| HackError::new(path, err.into()))?
+        }
+        None => debug::Symbols::default(),
+    };
+    let lines =
+        disassemble::recover(&words, &symbols).map_err(|err| HackError::new(input, err.into()))?;
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    // laid out the way `fmt` would
+    let asm = format::format(text.as_bytes()).map_err(|err| HackError::new(input, err))?;
+    match matches.value("output") {
+        Some(output) => fs::write(output, asm).map_err(HackError::io(Path::new(output))),
+        None => {
+            print!("{}", asm);
+            Ok(())
+        }
+    }
+}
 
-        Self {
-            labels,
-            variables: HashMap::new(),
-            variable_address: 16,
+fn completions_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let shell = match &matches.positionals[..] {
+        [shell] => shell,
+        _ => Err(HackError::Usage(format!(
+            "`completions` takes the shell to complete for: {}",
+            completions::SHELLS.join(", ")
+        )))?,
+    };
+    let script = completions::completions(shell).ok_or_else(|| {
+        HackError::Usage(format!(
+            "unsupported shell `{}` (expected {})",
+            shell,
+            completions::SHELLS.join(", ")
+        ))
+    })?;
+    print!("{}", script);
+    Ok(())
+}
+
+fn cfg_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let input = single_input(matches)?;
+    let source = fs::read_to_string(input).map_err(HackError::io(input))?;
+    let lines = parse(&source).map_err(|err| HackError::new(input, err.into()))?;
+    let dot = cfg::Cfg::new(&lines).dot();
+    match matches.value("dot") {
+        Some(output) => fs::write(output, dot).map_err(HackError::io(Path::new(output))),
+        None => {
+            print!("{}", dot);
+            Ok(())
         }
     }
+}
+
+// a VM program, or the one a Jack program compiles to
+fn load_calls(input: &Path) -> Result<vm::Program, HackError> {
+    let jack = if input.is_dir() {
+        collect_inputs(&[input.to_string_lossy().into_owned()], "jack")
+            .map_err(HackError::io(input))?
+    } else if input.extension().is_some_and(|ext| ext == "jack") {
+        vec![input.to_owned()]
+    } else {
+        Vec::new()
+    };
+    if jack.is_empty() {
+        return vm::load(input);
+    }
+    let mut sources = Vec::new();
+    for path in jack {
+        let source = fs::read_to_string(&path).map_err(HackError::io(&path))?;
+        let compiled =
+            compile::compile(&source).map_err(|err| HackError::new(&path, err.into()))?;
+        sources.push((path.with_extension("vm"), compiled.code.join("\n")));
+    }
+    vm::Program::new(&sources)
+}
+
+fn callgraph_command(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    let input = single_input(matches)?;
+    let graph = callgraph::CallGraph::new(&load_calls(input)?);
+    for function in graph.unreachable() {
+        let warning = Diagnostic::warning(format!("{} is never called", function.name));
+        eprint!("{}", warning.header(color));
+    }
+    for cycle in &graph.cycles {
+        let names = cycle.iter().map(|index| &graph.functions[*index].name);
+        let warning = Diagnostic::warning(format!("recursion through {}", names.format(", ")));
+        eprint!("{}", warning.header(color));
+    }
+
+    let mut written = false;
+    for (flag, text) in [
+        ("dot", graph.dot()),
+        ("json", format!("{}\n", graph.json())),
+    ] {
+        if let Some(output) = matches.value(flag) {
+            fs::write(output, text).map_err(HackError::io(Path::new(output)))?;
+            written = true;
+        }
+    }
+    if !written {
+        print!("{}", graph.dot());
+    }
+    Ok(())
+}
+
+fn single_input(matches: &cli::Matches) -> Result<&Path, HackError> {
+    match &matches.positionals[..] {
+        [input] => Ok(Path::new(input)),
+        _ => Err(HackError::Usage(format!(
+            "`{}` takes exactly one input file",
+            matches.command.name
+        ))),
+    }
+}
+
+// a VM program to emulate, which can't have any Hack assembly in it
+fn load_vm(input: &Path) -> Result<vm::Program, HackError> {
+    let program = vm::load(input)?;
+    if program.has_asm() {
+        Err(HackError::Usage(format!(
+            "{} has asm in it, so it can only run once it's translated",
+            input.display()
+        )))?;
+    }
+    Ok(program)
+}
+
+fn debug_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let input = single_input(matches)?;
+    let stdin = std::io::stdin();
+    if vm::is_vm(input) {
+        let mut vm = vm::Vm::new(load_vm(input)?);
+        vm.os.replace = matches.flag("builtins");
+        let mut debugger = vmdebug::VmDebugger::new(vm);
+        return debugger
+            .repl(stdin.lock(), &mut std::io::stdout())
+            .map_err(HackError::io(Path::new("<stdin>")));
+    }
+    let mut program = load_program(input)?;
+    if matches.flag("builtins") {
+        program.cpu.traps = Some(os::Traps::new(&program.symbols));
+    }
+    let mut debugger = debug::Debugger::new(program.cpu, program.map, program.symbols);
+    debugger
+        .repl(stdin.lock(), &mut std::io::stdout())
+        .map_err(HackError::io(Path::new("<stdin>")))
+}
+
+fn repl_command() -> Result<(), HackError> {
+    let stdin = std::io::stdin();
+    repl::Repl::default()
+        .run(stdin.lock(), &mut std::io::stdout())
+        .map_err(HackError::io(Path::new("<stdin>")))
+}
+
+const GIF_FRAMES: usize = 100;
+
+fn run_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let input = single_input(matches)?;
+    if vm::is_vm(input) {
+        return vm_run_command(matches, input);
+    }
+    let mut program = load_program(input)?;
+    if matches.flag("builtins") {
+        program.cpu.traps = Some(os::Traps::new(&program.symbols));
+    }
+    let format = matches.value("format").unwrap_or("text");
+    if !["text", "json"].contains(&format) {
+        return Err(HackError::Usage(format!(
+            "invalid profile format `{}` (expected text or json)",
+            format
+        )));
+    }
 
-    fn label(&mut self, key: &'data str) -> Option<u16> {
-        self.labels.get(key).copied()
+    let mut runner = run::Runner::new(program.cpu.clone());
+    // coverage is worked out from the same counts as the profile
+    if matches.flag("profile") || matches.flag("coverage") {
+        runner.profile = Some(profile::Profile::new(program.cpu.program_length));
+    }
+    let outcome = execute(matches, input, &mut runner, &program.symbols)?;
+    if let Some(path) = matches.value("save") {
+        let path = Path::new(path);
+        let mut out = Vec::new();
+        snapshot::write(&runner.machine, &mut out)
+            .and_then(|()| fs::write(path, out))
+            .map_err(HackError::io(path))?;
+    }
+    if let Some(profile) = &runner.profile {
+        report_profile(matches, &program, &runner.machine, profile)?;
     }
+    outcome
+}
 
-    // this function will always alloc a new variable if one doesn't already exist
-    fn variable<'slf>(&'slf mut self, key: &'data str) -> u16 {
-        let ret = self.variables.entry(key).or_insert(self.variable_address);
-        self.variable_address += 1;
-        *ret
+// runs a directory of `.vm` files, or a single one, without translating it
+fn vm_run_command(matches: &cli::Matches, input: &Path) -> Result<(), HackError> {
+    if let Some(flag) = ["profile", "coverage", "save"]
+        .into_iter()
+        .find(|flag| matches.flag(flag))
+    {
+        return Err(HackError::Usage(format!(
+            "`--{}` isn't supported for VM programs",
+            flag
+        )));
     }
+    let mut vm = vm::Vm::new(load_vm(input)?);
+    vm.os.replace = matches.flag("builtins");
+    let mut runner = run::Runner::new(vm);
+    execute(matches, input, &mut runner, &debug::Symbols::default())?
 }
 
-fn assemble(input: impl BufRead, output: &mut impl Write) -> Result<(), Box<dyn Error>> {
-    // read file into memory
-    let lines: Result<Vec<_>, _> = input
-        .lines()
-        // filter out comments and empty lines
-        .filter_ok(|line| !line.trim().starts_with("//") && !line.is_empty())
-        .map_ok(|line| line.parse::<HackLine>())
-        .map(|res| res?)
-        .collect();
-    let lines = lines?;
+// the part of `run` shared by Hack and VM programs: sets up RAM and
+// everything watching, runs the program to the end (or as the script
+// says), and reports how it went. The outer error is for the run going
+// wrong, the inner one for the program failing
+fn execute<M: run::Machine>(
+    matches: &cli::Matches,
+    input: &Path,
+    runner: &mut run::Runner<M>,
+    symbols: &debug::Symbols,
+) -> Result<Result<(), HackError>, HackError> {
+    let frames = match matches.value("frames") {
+        Some(frames) => frames
+            .parse()
+            .map_err(|_| HackError::Usage(format!("invalid frame count `{}`", frames)))?,
+        None => GIF_FRAMES,
+    };
+    let max_cycles = match matches.value("max-cycles") {
+        Some(cycles) => cycles
+            .parse()
+            .map_err(|_| HackError::Usage(format!("invalid cycle count `{}`", cycles)))?,
+        None => u64::MAX,
+    };
+    let script = match matches.value("script") {
+        Some(path) => {
+            let path = Path::new(path);
+            let text = fs::read_to_string(path).map_err(HackError::io(path))?;
+            let script =
+                script::Script::parse(&text, symbols).map_err(|err| HackError::new(path, err))?;
+            Some((script, path))
+        }
+        None => None,
+    };
+
+    if let Some(path) = matches.value("ram") {
+        let path = Path::new(path);
+        let text = fs::read_to_string(path).map_err(HackError::io(path))?;
+        snapshot::load_ram(runner.machine.ram_mut(), &text, symbols)
+            .map_err(|err| HackError::new(path, err))?;
+    }
+    if let Some(hz) = matches.value("hz") {
+        let hz = hz
+            .parse()
+            .ok()
+            .filter(|hz| *hz > 0)
+            .ok_or_else(|| HackError::Usage(format!("invalid speed `{}`", hz)))?;
+        runner.throttle = Some(run::Throttle::new(hz, runner.machine.cycles()));
+    }
+    if let Some(path) = matches.value("trace") {
+        runner.trace_to(Path::new(path))?;
+    }
+    match (matches.value("vcd"), matches.value("signals")) {
+        (Some(path), signals) => {
+            let names: Vec<&str> = match signals {
+                Some(list) => list.split(',').map(str::trim).collect(),
+                None => vcd::REGISTERS
+                    .into_iter()
+                    .filter(|name| runner.machine.register(name).is_some())
+                    .collect(),
+            };
+            let signals = names
+                .iter()
+                .map(|name| vcd::Signal::parse(name, &runner.machine, symbols))
+                .collect::<Result<_, _>>()
+                .map_err(HackError::Usage)?;
+            let mut vcd = vcd::Vcd::create(Path::new(path), signals)?;
+            vcd.sample(&runner.machine)?;
+            runner.vcd = Some(vcd);
+        }
+        (None, Some(_)) => Err(HackError::Usage(
+            "`--signals` only means something with `--vcd`".to_owned(),
+        ))?,
+        (None, None) => {}
+    }
+    for spec in matches.values("device") {
+        let device = peripheral::parse(spec, &runner.devices).map_err(HackError::Usage)?;
+        runner.devices.push(device);
+    }
+    if let Some(path) = matches.value("gif") {
+        runner.recording = Some(run::Recording::new(PathBuf::from(path), frames));
+    }
+    if matches.flag("screen") {
+        runner.show_screen()?;
+        // the script is doing the typing
+        if script.is_some() {
+            runner.keyboard = None;
+        }
+    }
 
-    // first pass: collect labels into a symbol table
-    let mut symbols = SymbolTable::new(&lines);
+    let failures = match &script {
+        Some((script, path)) => {
+            let outcome = script.run(runner)?;
+            if script.writes_output() {
+                let out_path = path.with_extension("out");
+                let text: String = outcome
+                    .output
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect();
+                fs::write(&out_path, text).map_err(HackError::io(&out_path))?;
+            }
+            outcome.failures
+        }
+        None => {
+            runner.run(max_cycles)?;
+            Vec::new()
+        }
+    };
+    runner.finish()?;
+    if let Some(path) = matches.value("png") {
+        fs::write(path, image::png(runner.machine.ram()))
+            .map_err(HackError::io(Path::new(path)))?;
+    }
 
-    // second pass: generate binary instructions
-    for line in &lines {
-        line.assemble(&mut symbols, output)?;
+    let machine = &runner.machine;
+    let ending = if runner.interrupted {
+        "interrupted"
+    } else if machine.halted() {
+        "halted"
+    } else if machine.finished() {
+        "ran off the end of the program"
+    } else {
+        "stopped"
+    };
+    println!(
+        "{} after {} cycles: {}",
+        ending,
+        machine.cycles(),
+        machine.registers()
+    );
+    // a program that's stopped somewhere unexpected is worth locating
+    let backtrace = machine.backtrace(symbols);
+    if !machine.halted() && !machine.finished() && !backtrace.is_empty() {
+        println!("backtrace: {}", backtrace::render(&backtrace));
     }
+    // scripts decide for themselves how long to run for
+    let out_of_cycles =
+        script.is_none() && !runner.interrupted && !machine.halted() && !machine.finished();
 
+    Ok(match script {
+        Some((script, path)) if !failures.is_empty() => {
+            for failure in &failures {
+                println!("{}: {}", path.display(), failure);
+            }
+            let expectations = script
+                .commands
+                .iter()
+                .filter(|(_, command)| {
+                    matches!(
+                        command,
+                        script::Command::Expect(..) | script::Command::Pixel(..)
+                    )
+                })
+                .count();
+            Err(HackError::new(
+                path,
+                format!("{} of {} expectations failed", failures.len(), expectations).into(),
+            ))
+        }
+        _ if out_of_cycles => Err(HackError::new(
+            input,
+            format!("didn't halt within {} cycles", max_cycles).into(),
+        )),
+        _ => Ok(()),
+    })
+}
+
+fn report_profile(
+    matches: &cli::Matches,
+    program: &LoadedProgram,
+    cpu: &emulator::Cpu,
+    profile: &profile::Profile,
+) -> Result<(), HackError> {
+    if matches.flag("profile") {
+        let format = matches.value("format").unwrap_or("text");
+        match format {
+            "json" => println!("{}", profile.json(&cpu.rom, &program.symbols)),
+            _ => print!("\n{}", profile.text(&cpu.rom, &program.symbols)),
+        }
+    }
+    if let Some(path) = matches.value("coverage") {
+        let path = PathBuf::from(path);
+        println!("{}", coverage::summary(&profile.counts, cpu.program_length));
+        let listing = coverage::listing(
+            &profile.counts,
+            &cpu.rom,
+            cpu.program_length,
+            program.map.as_ref(),
+            |file| fs::read_to_string(file),
+        )
+        .map_err(|err| HackError::new(&path, err.into()))?;
+        fs::write(&path, listing).map_err(HackError::io(&path))?;
+    }
     Ok(())
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Please provide a file path as a command-line argument");
-        return;
+fn link_command(matches: &cli::Matches) -> Result<(), HackError> {
+    let mut objects = Vec::new();
+    for input in &matches.positionals {
+        let path = Path::new(input);
+        let object = if path.extension().is_some_and(|ext| ext == "hobj") {
+            let reader = BufReader::new(File::open(path).map_err(HackError::io(path))?);
+            link::Object::read(reader)
+        } else {
+            let source = fs::read_to_string(path).map_err(HackError::io(path))?;
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            allocate::Layout::new(allocate::Order::FirstUse, &source)
+                .and_then(|layout| Ok((layout, parse(&source)?)))
+                .map_err(Into::into)
+                .and_then(|(layout, lines)| link::Object::new(&name, &lines, &layout.pins))
+        };
+        objects.push(object.map_err(|err| HackError::new(path, err))?);
     }
 
-    let input_file_path = &args[1];
-    let mut input_file = File::open(input_file_path).expect("Error opening file");
+    let order = matches
+        .value("allocate")
+        .map_or(Ok(allocate::Order::FirstUse), allocate::Order::parse)
+        .map_err(HackError::Usage)?;
+    let output_file_path = PathBuf::from(matches.value("output").unwrap_or("a.hack"));
+    let mut output_file =
+        File::create(&output_file_path).map_err(HackError::io(&output_file_path))?;
+    let symbols = link::link(&objects, order, &mut output_file)
+        .map_err(|err| HackError::new(&output_file_path, err))?;
+    if matches.flag("sym") {
+        let sym_file_path = output_file_path.with_extension("sym");
+        fs::write(&sym_file_path, symbols.to_string()).map_err(HackError::io(&sym_file_path))?;
+    }
+
+    println!("Linked into {}", output_file_path.display());
+    Ok(())
+}
 
-    let output_file_path = format!("{}.hack", input_file_path.trim_end_matches(".asm"));
-    let mut output_file = File::create(output_file_path).expect("Error creating output file");
+fn lsp_command() -> Result<(), HackError> {
+    let stdin = std::io::stdin();
+    lsp::serve(stdin.lock(), std::io::stdout().lock()).map_err(HackError::io(Path::new("-")))
+}
 
-    assemble(BufReader::new(&mut input_file), &mut output_file)
-        .expect("Error writing to output file");
+fn dap_command() -> Result<(), HackError> {
+    dap::serve(BufReader::new(std::io::stdin()), std::io::stdout().lock())
+        .map_err(HackError::io(Path::new("-")))
+}
 
-    println!("Done!");
+fn run(matches: &cli::Matches, color: bool) -> Result<(), HackError> {
+    match matches.command.name {
+        "check" => check_command(matches, color),
+        "debug" => debug_command(matches),
+        "repl" => repl_command(),
+        "fmt" => fmt_command(matches, color),
+        "lint" => lint_command(matches, color),
+        "run" => run_command(matches),
+        "stats" => stats_command(matches, color),
+        "translate" => translate_command(matches, color),
+        "compile" => compile_command(matches, color),
+        "link" => link_command(matches),
+        "verify" => verify_command(matches, color),
+        "diff" => diff_command(matches),
+        "cfg" => cfg_command(matches),
+        "disasm" => disasm_command(matches),
+        "xref" => xref_command(matches, color),
+        "callgraph" => callgraph_command(matches, color),
+        "selftest" => selftest_command(matches),
+        "completions" => completions_command(matches),
+        "man" => {
+            print!("{}", completions::man());
+            Ok(())
+        }
+        "lsp" => lsp_command(),
+        "dap" => dap_command(),
+        _ => asm_command(matches, color),
+    }
+}
+
+fn main() -> ExitCode {
+    let (matches, color) = match cli::parse(env::args().skip(1)).and_then(|matches| {
+        let color = diagnostic::use_color(matches.value("color"))?;
+        Ok((matches, color))
+    }) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let err = HackError::Usage(err);
+            eprint!(
+                "{}",
+                err.render(diagnostic::use_color(None).unwrap_or(false))
+            );
+            return err.into();
+        }
+    };
+
+    // commands that take no arguments, like `lsp`, have nothing to be missing
+    let missing = matches.positionals.is_empty() && !matches.command.args.is_empty();
+    if matches.flag("help") || (missing && !matches.flag("list")) {
+        print!("{}", cli::help(matches.command));
+        return ExitCode::SUCCESS;
+    }
+
+    match run(&matches, color) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprint!("{}", err.render(color));
+            err.into()
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{fs::File, io::BufReader};
 
     #[test]
-    fn rect() {
-        let mut result = Vec::new();
-        let mut rect = File::open("resources/Rect.asm").unwrap();
-        assemble(BufReader::new(&mut rect), &mut result).unwrap();
+    fn directory_inputs() {
+        let inputs = collect_inputs(&["resources".to_owned()], "asm").unwrap();
+        assert_eq!(inputs, vec![PathBuf::from("resources/Rect.asm")]);
+    }
 
-        let expected = std::fs::read("resources/Rect.hack").unwrap();
-        assert_eq!(result, expected);
+    #[test]
+    fn parallel_batches_report_in_order() {
+        let inputs: Vec<PathBuf> = (0..8)
+            .map(|i| PathBuf::from(format!("{}.asm", i)))
+            .collect();
+        let errors = for_each_input_parallel(&inputs, false, 4, |input| {
+            let i: u64 = input
+                .file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            // later files finish first
+            thread::sleep(std::time::Duration::from_millis(8 - i));
+            if i.is_multiple_of(3) {
+                Err(HackError::Usage(input.display().to_string()))
+            } else {
+                Ok("ok".to_owned())
+            }
+        });
+        let failed: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(failed, ["0.asm", "3.asm", "6.asm"]);
+    }
+
+    #[test]
+    fn ast_as_json() {
+        let sources = parse_source("(LOOP)\n@i\n@5 // five\nAM=M-1;JGT\n").unwrap();
+        assert_eq!(
+            ast_json(Path::new("Loop.asm"), &sources).to_string(),
+            r#"{"file":"Loop.asm","lines":[{"line":1,"instruction":{"kind":"label","name":"LOOP"}},{"line":2,"instruction":{"kind":"a","symbol":"i"}},{"line":3,"instruction":{"kind":"a","value":5}},{"line":4,"instruction":{"kind":"c","dest":["A","M"],"comp":{"expression":"M-1","operand":"M"},"jump":"JGT"}}]}"#
+        );
     }
 }
@@ -0,0 +1,228 @@
+use std::fmt;
+
+use crate::cfg::Cfg;
+use crate::{Computation, Destination, HackLine, Jump, AM};
+
+// what the optimizer managed to remove
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub redundant_loads: usize,
+    pub useless_jumps: usize,
+    pub no_ops: usize,
+    // a description of each unreachable block that was removed
+    pub unreachable: Vec<String>,
+    pub unreachable_instructions: usize,
+}
+
+impl Report {
+    // instructions, rather than things found: each useless jump is an
+    // `@L` and a `0;JMP`
+    pub fn removed(&self) -> usize {
+        self.redundant_loads + 2 * self.useless_jumps + self.no_ops + self.unreachable_instructions
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "removed {} instructions ({} redundant loads, {} jumps to the next instruction, {} no-ops, {} unreachable)",
+            self.removed(),
+            self.redundant_loads,
+            self.useless_jumps,
+            self.no_ops,
+            self.unreachable_instructions
+        )?;
+        for block in &self.unreachable {
+            write!(f, "\n  unreachable: {}", block)?;
+        }
+        Ok(())
+    }
+}
+
+// instructions that have no effect at all: `D=D`, `M=M`, `A=A`, or a
+// computation whose result goes nowhere
+fn is_no_op(line: &HackLine) -> bool {
+    use Computation as C;
+    match line {
+        HackLine::C(comp, dest, Jump::Null) => matches!(
+            (comp, dest),
+            (_, Destination::Null)
+                | (C::D, Destination::D)
+                | (C::X(AM::A), Destination::A)
+                | (C::X(AM::M), Destination::M)
+        ),
+        _ => false,
+    }
+}
+
+// runs a single sweep of the peephole rules over the program, returning
+// whether anything changed
+fn sweep<'a>(lines: &mut Vec<impl AsRef<HackLine<'a>>>, report: &mut Report) -> bool {
+    let before = report.removed();
+
+    // `@L` / `0;JMP` / `(L)`: an unconditional jump to the very next
+    // instruction, with nothing else going on, is just a slower fallthrough
+    let mut index = 0;
+    while index + 2 < lines.len() {
+        if let (
+            HackLine::ALocation(target),
+            HackLine::C(_, Destination::Null, Jump::JMP),
+            HackLine::Label(label),
+        ) = (
+            lines[index].as_ref(),
+            lines[index + 1].as_ref(),
+            lines[index + 2].as_ref(),
+        ) {
+            if target == label {
+                lines.drain(index..index + 2);
+                report.useless_jumps += 1;
+                continue;
+            }
+        }
+        index += 1;
+    }
+
+    // drop no-ops, and A-instructions loading the value A already holds. We
+    // only know what's in A between labels, since a label can be reached
+    // from anywhere
+    let mut a: Option<HackLine> = None;
+    lines.retain(|line| match line.as_ref() {
+        HackLine::Label(_) => {
+            a = None;
+            true
+        }
+        HackLine::AImmediate(_) | HackLine::ALocation(_) => {
+            if a.as_ref() == Some(line.as_ref()) {
+                report.redundant_loads += 1;
+                false
+            } else {
+                a = Some(line.as_ref().clone());
+                true
+            }
+        }
+        HackLine::C(..) if is_no_op(line.as_ref()) => {
+            report.no_ops += 1;
+            false
+        }
+        HackLine::C(_, dest, _) => {
+            if dest.writes(Destination::A) {
+                a = None;
+            }
+            true
+        }
+    });
+
+    report.removed() != before
+}
+
+// removes every block of code that control can never reach, e.g. code
+// following an unconditional jump that no label leads back into
+fn eliminate_unreachable<'a>(
+    lines: &mut Vec<impl AsRef<HackLine<'a>>>,
+    report: &mut Report,
+) -> bool {
+    let cfg = Cfg::new(lines);
+    let reachable = cfg.reachable();
+
+    let mut dead = vec![false; lines.len()];
+    let mut label = None;
+    for (block, reachable) in cfg.blocks.iter().zip(reachable) {
+        if let Some(first) = block.labels.first() {
+            label = Some(first);
+        }
+        if reachable || block.end == block.start {
+            continue;
+        }
+
+        let count = block.end - block.start;
+        report.unreachable_instructions += count;
+        report
+            .unreachable
+            .push(match (block.labels.first(), label) {
+                (Some(first), _) => format!("{} instructions at ({})", count, first),
+                (None, Some(label)) => format!("{} instructions after ({})", count, label),
+                (None, None) => format!("{} instructions at address {}", count, block.start),
+            });
+        dead[block.lines.clone()].fill(true);
+    }
+
+    let mut dead = dead.into_iter();
+    let before = lines.len();
+    lines.retain(|_| !dead.next().unwrap_or(false));
+    lines.len() != before
+}
+
+// shrinks a program by repeatedly applying a handful of peephole rewrites,
+// until none of them apply. Labels are kept as lines of their own, so their
+// addresses are recomputed correctly when the symbol table is built afterwards.
+// This works on anything wrapping a `HackLine`, so that callers can keep track
+// of where each surviving instruction came from
+pub fn optimize<'a>(lines: &mut Vec<impl AsRef<HackLine<'a>>>) -> Report {
+    let mut report = Report::default();
+    while eliminate_unreachable(lines, &mut report) | sweep(lines, &mut report) {}
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn optimized(source: &str) -> (Vec<HackLine<'_>>, Report) {
+        let mut lines = parse(source).unwrap();
+        let report = optimize(&mut lines);
+        (lines, report)
+    }
+
+    #[test]
+    fn peephole() {
+        let source = "@SP\nD=M\n@SP\nM=D\nD=D\n@NEXT\n0;JMP\n(NEXT)\n@SP\nAM=M-1\n@SP\nD;JGT\n";
+        let (lines, report) = optimized(source);
+        let instructions = |lines: &[HackLine]| {
+            let labels = lines
+                .iter()
+                .filter(|line| matches!(line, HackLine::Label(_)));
+            lines.len() - labels.count()
+        };
+        let before = instructions(&parse(source).unwrap());
+        assert_eq!(report.removed(), before - instructions(&lines));
+        assert_eq!(report.removed(), 4);
+        assert_eq!(
+            lines,
+            parse("@SP\nD=M\nM=D\n(NEXT)\n@SP\nAM=M-1\n@SP\nD;JGT\n").unwrap()
+        );
+        assert_eq!(
+            report,
+            Report {
+                redundant_loads: 1,
+                useless_jumps: 1,
+                no_ops: 1,
+                ..Report::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unreachable() {
+        let (lines, report) =
+            optimized("(LOOP)\n@LOOP\n0;JMP\nD=M\nM=D\n(DEAD)\n@DEAD\nD;JGT\n(END)\n@END\n0;JMP\n");
+        assert_eq!(lines, parse("(LOOP)\n@LOOP\n0;JMP\n").unwrap());
+        assert_eq!(report.unreachable_instructions, 6);
+        assert_eq!(
+            report.unreachable,
+            [
+                "2 instructions after (LOOP)",
+                "2 instructions at (DEAD)",
+                "2 instructions at (END)",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_reset_a() {
+        let (lines, report) = optimized("@i\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n");
+        assert_eq!(lines.len(), 6);
+        assert_eq!(report.removed(), 0);
+    }
+}
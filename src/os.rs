@@ -0,0 +1,746 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::debug::Symbols;
+use crate::emulator::Access;
+use crate::font;
+use crate::keyboard::{BACKSPACE, KBD, NEWLINE};
+use crate::screen::{HEIGHT, SCREEN, WIDTH};
+use crate::vm::{ARG, LCL, SP, THAT, THIS};
+
+// native versions of the Jack OS, which the emulators can run in place of
+// the program's own (or missing) ones, as the course's VM emulator does
+// with its built-in classes. Strings are laid out our own way, as their
+// capacity, their length and then their characters, so the functions that
+// take or make one only work alongside our String class
+
+// what we provide, and how many arguments each takes
+const FUNCTIONS: [(&str, u16); 40] = [
+    ("Math.multiply", 2),
+    ("Math.divide", 2),
+    ("Math.min", 2),
+    ("Math.max", 2),
+    ("Math.abs", 1),
+    ("Math.sqrt", 1),
+    ("Memory.peek", 1),
+    ("Memory.poke", 2),
+    ("Memory.alloc", 1),
+    ("Memory.deAlloc", 1),
+    ("Screen.clearScreen", 0),
+    ("Screen.setColor", 1),
+    ("Screen.drawPixel", 2),
+    ("Screen.drawLine", 4),
+    ("Screen.drawRectangle", 4),
+    ("Screen.drawCircle", 3),
+    ("String.new", 1),
+    ("String.dispose", 1),
+    ("String.length", 1),
+    ("String.charAt", 2),
+    ("String.setCharAt", 3),
+    ("String.appendChar", 2),
+    ("String.eraseLastChar", 1),
+    ("String.intValue", 1),
+    ("String.setInt", 2),
+    ("String.backSpace", 0),
+    ("String.doubleQuote", 0),
+    ("String.newLine", 0),
+    ("Output.moveCursor", 2),
+    ("Output.printChar", 1),
+    ("Output.printString", 1),
+    ("Output.printInt", 1),
+    ("Output.println", 0),
+    ("Output.backSpace", 0),
+    ("Keyboard.keyPressed", 0),
+    ("Keyboard.readChar", 0),
+    ("Keyboard.readLine", 1),
+    ("Keyboard.readInt", 1),
+    ("Sys.wait", 1),
+    ("Sys.halt", 0),
+];
+
+// the functions outside String that take or make strings, which can't be
+// mixed with a String class of the program's own
+const STRINGS: [&str; 3] = [
+    "Output.printString",
+    "Keyboard.readLine",
+    "Keyboard.readInt",
+];
+
+// where the OS's Memory class keeps its heap
+const HEAP: u16 = 2048;
+const HEAP_END: u16 = SCREEN;
+
+// the number of arguments a function we provide takes
+pub fn arity(name: &str) -> Option<u16> {
+    FUNCTIONS
+        .iter()
+        .find(|(function, _)| *function == name)
+        .map(|(_, args)| *args)
+}
+
+// whether one of our functions needs our String class to go with it
+pub fn needs_strings(name: &str) -> bool {
+    STRINGS.contains(&name)
+}
+
+// the characters on a line of text, and the lines on the screen
+const COLUMNS: u16 = (WIDTH / font::WIDTH) as u16;
+const ROWS: u16 = (HEIGHT / font::HEIGHT) as u16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Return(u16),
+    // the call is waiting on the keyboard, and has to be made again, with
+    // the same arguments, until it returns
+    Wait,
+    // the program's over, because it asked to be or because it made an
+    // error the OS would have stopped it for: the call never returns
+    Halt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Os {
+    // whether to use our versions even of functions the program has its own
+    // versions of
+    pub replace: bool,
+    black: bool,
+    // the blocks `Memory.alloc` has handed out, by address, with their sizes
+    blocks: BTreeMap<u16, u16>,
+    // set once the program's stopped, after which it's stuck making the
+    // same call over and over
+    pub halted: bool,
+    // the error the program stopped with, the way the OS reports them
+    pub error: Option<String>,
+    // where Output prints next, as a row and column of characters
+    cursor: (u16, u16),
+    // what's being typed, while Keyboard is reading it
+    typing: Option<Typing>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Typing {
+    line: Vec<u16>,
+    // the key that was down when we last looked, if any
+    key: u16,
+}
+
+impl Default for Os {
+    fn default() -> Self {
+        Self {
+            replace: false,
+            black: true,
+            blocks: BTreeMap::new(),
+            halted: false,
+            error: None,
+            cursor: (0, 0),
+            typing: None,
+        }
+    }
+}
+
+// the RAM a call runs against, remembering what it did for anyone watching
+struct Memory<'a> {
+    ram: &'a mut [u16],
+    access: Access,
+}
+
+impl Memory<'_> {
+    fn read(&mut self, address: u16) -> u16 {
+        self.access.read = Some(address);
+        self.ram[address as usize % self.ram.len()]
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.access.write = Some((address, value));
+        self.ram[address as usize % self.ram.len()] = value;
+    }
+
+    fn pixel(&mut self, x: i32, y: i32, black: bool) {
+        let address = SCREEN + (y * WIDTH as i32 / 16 + x / 16) as u16;
+        let bit = 1 << (x % 16);
+        let word = self.ram[address as usize];
+        self.write(address, if black { word | bit } else { word & !bit });
+    }
+
+    fn row(&mut self, y: i32, from: i32, to: i32, black: bool) {
+        for x in from.min(to)..=from.max(to) {
+            self.pixel(x, y, black);
+        }
+    }
+}
+
+// the characters of one of our strings
+fn text(memory: &mut Memory, string: u16) -> Vec<u16> {
+    let length = memory.read(string.wrapping_add(1));
+    (0..length)
+        .map(|index| memory.read(string.wrapping_add(2 + index)))
+        .collect()
+}
+
+fn chars(text: &str) -> Vec<u16> {
+    text.bytes().map(u16::from).collect()
+}
+
+// the number at the start of some text, as String.intValue reads it: an
+// optional minus sign and then digits, up to the first thing that isn't one
+fn int_value(text: &[u16]) -> u16 {
+    let (negative, digits) = match text {
+        [45, rest @ ..] => (true, rest),
+        _ => (false, text),
+    };
+    let value = digits
+        .iter()
+        .map_while(|c| (48..=57).contains(c).then(|| c - 48))
+        .fold(0u16, |value, digit| {
+            value.wrapping_mul(10).wrapping_add(digit)
+        });
+    if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    }
+}
+
+fn on_screen(x: i32, y: i32) -> bool {
+    (0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y)
+}
+
+impl Os {
+    // runs one of our functions against RAM, reporting what it touched
+    pub fn call(&mut self, name: &str, args: &[u16], ram: &mut [u16]) -> (Outcome, Access) {
+        let mut memory = Memory {
+            ram,
+            access: Access::default(),
+        };
+        let signed = |index: usize| args.get(index).copied().unwrap_or(0) as i16;
+        let int = |index: usize| signed(index) as i32;
+        let error = |code: u16, message: &str| Err((code, message.to_owned()));
+
+        let result: Result<u16, (u16, String)> = match name {
+            "Math.multiply" => Ok(signed(0).wrapping_mul(signed(1)) as u16),
+            "Math.divide" if signed(1) == 0 => error(3, "division by zero"),
+            "Math.divide" => Ok(signed(0).wrapping_div(signed(1)) as u16),
+            "Math.min" => Ok(signed(0).min(signed(1)) as u16),
+            "Math.max" => Ok(signed(0).max(signed(1)) as u16),
+            "Math.abs" => Ok(signed(0).wrapping_abs() as u16),
+            "Math.sqrt" if signed(0) < 0 => {
+                error(4, "cannot compute the square root of a negative number")
+            }
+            "Math.sqrt" => Ok((int(0) as f64).sqrt() as u16),
+            "Memory.peek" => Ok(memory.read(args[0])),
+            "Memory.poke" => {
+                memory.write(args[0], args[1]);
+                Ok(0)
+            }
+            "Memory.alloc" if signed(0) <= 0 => error(5, "allocated memory size must be positive"),
+            "Memory.alloc" => self.alloc(args[0]).ok_or((6, "heap overflow".to_owned())),
+            "Memory.deAlloc" => {
+                self.blocks.remove(&args[0]);
+                Ok(0)
+            }
+            "Screen.clearScreen" => {
+                for address in SCREEN..SCREEN + (WIDTH * HEIGHT / 16) as u16 {
+                    memory.write(address, 0);
+                }
+                Ok(0)
+            }
+            "Screen.setColor" => {
+                self.black = args[0] != 0;
+                Ok(0)
+            }
+            "Screen.drawPixel" if !on_screen(int(0), int(1)) => {
+                error(7, "illegal pixel coordinates")
+            }
+            "Screen.drawPixel" => {
+                memory.pixel(int(0), int(1), self.black);
+                Ok(0)
+            }
+            "Screen.drawLine" if !on_screen(int(0), int(1)) || !on_screen(int(2), int(3)) => {
+                error(8, "illegal line coordinates")
+            }
+            "Screen.drawLine" => {
+                self.line(&mut memory, (int(0), int(1)), (int(2), int(3)));
+                Ok(0)
+            }
+            "Screen.drawRectangle"
+                if !on_screen(int(0), int(1))
+                    || !on_screen(int(2), int(3))
+                    || int(0) > int(2)
+                    || int(1) > int(3) =>
+            {
+                error(9, "illegal rectangle coordinates")
+            }
+            "Screen.drawRectangle" => {
+                for y in int(1)..=int(3) {
+                    memory.row(y, int(0), int(2), self.black);
+                }
+                Ok(0)
+            }
+            "Screen.drawCircle" if !on_screen(int(0), int(1)) => {
+                error(12, "illegal center coordinates")
+            }
+            "Screen.drawCircle"
+                if int(2) < 0
+                    || !on_screen(int(0) - int(2), int(1) - int(2))
+                    || !on_screen(int(0) + int(2), int(1) + int(2)) =>
+            {
+                error(13, "illegal radius")
+            }
+            "Screen.drawCircle" => {
+                let (x, y, r) = (int(0), int(1), int(2));
+                for dy in -r..=r {
+                    let half = ((r * r - dy * dy) as f64).sqrt() as i32;
+                    memory.row(y + dy, x - half, x + half, self.black);
+                }
+                Ok(0)
+            }
+            "String.new" if signed(0) < 0 => error(14, "maximum length must be non-negative"),
+            "String.new" => match self.alloc(args[0] + 2) {
+                Some(string) => {
+                    memory.write(string, args[0]);
+                    memory.write(string + 1, 0);
+                    Ok(string)
+                }
+                None => error(6, "heap overflow"),
+            },
+            "String.dispose" => {
+                self.blocks.remove(&args[0]);
+                Ok(0)
+            }
+            "String.length" => Ok(memory.read(args[0].wrapping_add(1))),
+            "String.charAt" | "String.setCharAt"
+                if args[1] >= memory.read(args[0].wrapping_add(1)) =>
+            {
+                error(15, "string index out of bounds")
+            }
+            "String.charAt" => Ok(memory.read(args[0].wrapping_add(2 + args[1]))),
+            "String.setCharAt" => {
+                memory.write(args[0].wrapping_add(2 + args[1]), args[2]);
+                Ok(0)
+            }
+            "String.appendChar" => {
+                let length = memory.read(args[0].wrapping_add(1));
+                if length >= memory.read(args[0]) {
+                    error(17, "string is full")
+                } else {
+                    memory.write(args[0].wrapping_add(2 + length), args[1]);
+                    memory.write(args[0].wrapping_add(1), length + 1);
+                    Ok(args[0])
+                }
+            }
+            "String.eraseLastChar" => match memory.read(args[0].wrapping_add(1)) {
+                0 => error(18, "string is empty"),
+                length => {
+                    memory.write(args[0].wrapping_add(1), length - 1);
+                    Ok(0)
+                }
+            },
+            "String.intValue" => Ok(int_value(&text(&mut memory, args[0]))),
+            "String.setInt" => {
+                let digits = signed(1).to_string();
+                if digits.len() > memory.read(args[0]) as usize {
+                    error(19, "insufficient string capacity")
+                } else {
+                    for (index, digit) in (2..).zip(digits.bytes()) {
+                        memory.write(args[0].wrapping_add(index), digit as u16);
+                    }
+                    memory.write(args[0].wrapping_add(1), digits.len() as u16);
+                    Ok(0)
+                }
+            }
+            "String.backSpace" => Ok(BACKSPACE),
+            "String.doubleQuote" => Ok(b'"' as u16),
+            "String.newLine" => Ok(NEWLINE),
+            "Output.moveCursor" if args[0] >= ROWS || args[1] >= COLUMNS => {
+                error(20, "illegal cursor location")
+            }
+            "Output.moveCursor" => {
+                self.cursor = (args[0], args[1]);
+                Ok(0)
+            }
+            "Output.printChar" => {
+                self.print(&mut memory, &[args[0]]);
+                Ok(0)
+            }
+            "Output.printString" => {
+                let text = text(&mut memory, args[0]);
+                self.print(&mut memory, &text);
+                Ok(0)
+            }
+            "Output.printInt" => {
+                self.print(&mut memory, &chars(&signed(0).to_string()));
+                Ok(0)
+            }
+            "Output.println" => {
+                self.print(&mut memory, &[NEWLINE]);
+                Ok(0)
+            }
+            "Output.backSpace" => {
+                self.print(&mut memory, &[BACKSPACE]);
+                Ok(0)
+            }
+            "Keyboard.keyPressed" => Ok(memory.read(KBD)),
+            "Keyboard.readChar" => match self.typed(&mut memory) {
+                Some(key) => {
+                    self.typing = None;
+                    self.print(&mut memory, &[key]);
+                    Ok(key)
+                }
+                None => return (Outcome::Wait, memory.access),
+            },
+            "Keyboard.readLine" | "Keyboard.readInt" => {
+                if self.typing.is_none() {
+                    let message = text(&mut memory, args[0]);
+                    self.print(&mut memory, &message);
+                }
+                let Some(line) = self.read_line(&mut memory) else {
+                    return (Outcome::Wait, memory.access);
+                };
+                if name == "Keyboard.readInt" {
+                    Ok(int_value(&line))
+                } else {
+                    self.string(&mut memory, &line)
+                        .ok_or((6, "heap overflow".to_owned()))
+                }
+            }
+            "Sys.wait" if signed(0) < 0 => error(1, "duration must be positive"),
+            "Sys.wait" => Ok(0),
+            "Sys.halt" => {
+                self.halted = true;
+                return (Outcome::Halt, memory.access);
+            }
+            _ => unreachable!("{} isn't built in", name),
+        };
+
+        match result {
+            Ok(value) => (Outcome::Return(value), memory.access),
+            Err((code, message)) => {
+                self.error = Some(format!("{}: {} (error {})", name, message, code));
+                self.halted = true;
+                (Outcome::Halt, memory.access)
+            }
+        }
+    }
+
+    // the first gap in the heap that's big enough
+    fn alloc(&mut self, size: u16) -> Option<u16> {
+        let mut start = HEAP;
+        for (&address, &length) in &self.blocks {
+            if address - start >= size {
+                break;
+            }
+            start = address + length;
+        }
+        if HEAP_END - start < size {
+            return None;
+        }
+        self.blocks.insert(start, size);
+        Some(start)
+    }
+
+    // a new string holding `text`
+    fn string(&mut self, memory: &mut Memory, text: &[u16]) -> Option<u16> {
+        let length = text.len() as u16;
+        let string = self.alloc(length + 2)?;
+        memory.write(string, length);
+        memory.write(string + 1, length);
+        for (address, c) in (string + 2..).zip(text) {
+            memory.write(address, *c);
+        }
+        Some(string)
+    }
+
+    // prints at the cursor the way Output does, moving on a character at a
+    // time and back to the top once the screen's full
+    fn print(&mut self, memory: &mut Memory, text: &[u16]) {
+        for &c in text {
+            let (row, column) = self.cursor;
+            match c {
+                NEWLINE => self.cursor = ((row + 1) % ROWS, 0),
+                BACKSPACE => {
+                    self.cursor = match (row, column) {
+                        (0, 0) => (0, 0),
+                        (row, 0) => (row - 1, COLUMNS - 1),
+                        (row, column) => (row, column - 1),
+                    };
+                    self.draw(memory, b' ' as u16);
+                }
+                c => {
+                    self.draw(memory, c);
+                    self.cursor = if column + 1 == COLUMNS {
+                        ((row + 1) % ROWS, 0)
+                    } else {
+                        (row, column + 1)
+                    };
+                }
+            }
+        }
+    }
+
+    // draws a character over whatever's at the cursor. Each word of the
+    // screen holds two, the one on the left in its low byte
+    fn draw(&self, memory: &mut Memory, c: u16) {
+        let (row, column) = self.cursor;
+        let shift = column % 2 * 8;
+        for (y, bits) in (row * font::HEIGHT as u16..).zip(font::glyph(c)) {
+            let address = SCREEN + y * (WIDTH / 16) as u16 + column / 2;
+            let word = memory.ram[address as usize] & !(0xFF << shift);
+            memory.write(address, word | (*bits as u16) << shift);
+        }
+    }
+
+    // the key just typed, once it's been let go of
+    fn typed(&mut self, memory: &mut Memory) -> Option<u16> {
+        let typing = self.typing.get_or_insert_with(Typing::default);
+        let key = memory.read(KBD);
+        let typed = (typing.key != 0 && key != typing.key).then_some(typing.key);
+        typing.key = key;
+        typed
+    }
+
+    // echoes what's typed until it's a whole line, which it then returns
+    fn read_line(&mut self, memory: &mut Memory) -> Option<Vec<u16>> {
+        let key = self.typed(memory)?;
+        let typing = self.typing.as_mut()?;
+        match key {
+            NEWLINE => {
+                let line = self.typing.take()?.line;
+                self.print(memory, &[NEWLINE]);
+                return Some(line);
+            }
+            BACKSPACE => {
+                if typing.line.pop().is_some() {
+                    self.print(memory, &[BACKSPACE]);
+                }
+            }
+            key => {
+                typing.line.push(key);
+                self.print(memory, &[key]);
+            }
+        }
+        None
+    }
+
+    fn line(&self, memory: &mut Memory, from: (i32, i32), to: (i32, i32)) {
+        let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+        let (sx, sy) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+        let (mut x, mut y, mut error) = (from.0, from.1, dx + dy);
+        loop {
+            memory.pixel(x, y, self.black);
+            if (x, y) == to {
+                break;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+// the built-in functions of a translated program, by the ROM addresses of
+// their labels, for the CPU emulator to catch calls to before they run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Traps {
+    pub os: Os,
+    entries: HashMap<u16, &'static str>,
+}
+
+impl Traps {
+    pub fn new(symbols: &Symbols) -> Self {
+        let entries = FUNCTIONS
+            .iter()
+            .filter_map(|(name, _)| Some((*symbols.labels.get(*name)?, *name)))
+            .collect();
+        Self {
+            os: Os::default(),
+            entries,
+        }
+    }
+
+    pub fn at(&self, pc: u16) -> Option<&'static str> {
+        self.entries.get(&pc).copied()
+    }
+
+    // runs the function whose code starts at the PC, just after its call:
+    // the arguments are at ARG and the caller's frame is below LCL, just as
+    // the standard calling convention leaves them. Returns like the VM's
+    // `return` would, or `None` if it never returns
+    pub fn run(&mut self, name: &str, ram: &mut [u16]) -> (Option<u16>, Access) {
+        let arg = ram[ARG as usize];
+        let args: Vec<u16> = (0..arity(name).unwrap_or(0))
+            .map(|index| ram[arg.wrapping_add(index) as usize % ram.len()])
+            .collect();
+        let (outcome, access) = self.os.call(name, &args, ram);
+        let Outcome::Return(value) = outcome else {
+            return (None, access);
+        };
+        let (frame, size) = (ram[LCL as usize], ram.len());
+        let at = |offset: u16| frame.wrapping_sub(offset) as usize % size;
+        let return_to = ram[at(5)];
+        ram[arg as usize % size] = value;
+        ram[SP as usize] = arg.wrapping_add(1);
+        for (offset, pointer) in (1..).zip([THAT, THIS, ARG, LCL]) {
+            ram[pointer as usize] = ram[at(offset)];
+        }
+        (Some(return_to), access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::RAM_SIZE;
+
+    #[test]
+    fn computes_and_draws() {
+        let mut os = Os::default();
+        let mut ram = vec![0; RAM_SIZE];
+        let mut call = |name: &str, args: &[u16]| os.call(name, args, &mut ram).0;
+        assert_eq!(
+            call("Math.multiply", &[(-7i16) as u16, 6]),
+            Outcome::Return((-42i16) as u16)
+        );
+        assert_eq!(call("Math.divide", &[100, 7]), Outcome::Return(14));
+        assert_eq!(call("Math.sqrt", &[30000]), Outcome::Return(173));
+
+        // a 3x2 rectangle in the top left corner, then its middle rubbed out
+        call("Screen.drawRectangle", &[0, 0, 2, 1]);
+        call("Screen.setColor", &[0]);
+        call("Screen.drawLine", &[1, 0, 1, 1]);
+        assert_eq!(&ram[16384..16386], [0b101, 0]);
+        assert_eq!(ram[16384 + 32], 0b101);
+
+        assert_eq!(os.call("Math.divide", &[1, 0], &mut ram).0, Outcome::Halt);
+        assert_eq!(
+            os.error.as_deref(),
+            Some("Math.divide: division by zero (error 3)")
+        );
+    }
+
+    #[test]
+    fn prints_and_reads_text() {
+        let mut os = Os::default();
+        let mut ram = vec![0; RAM_SIZE];
+        let mut call = |name: &str, args: &[u16]| match os.call(name, args, &mut ram).0 {
+            Outcome::Return(value) => value,
+            outcome => panic!("{} didn't return: {:?}", name, outcome),
+        };
+        let hi = call("String.new", &[3]);
+        for c in "Hi".bytes() {
+            call("String.appendChar", &[hi, c as u16]);
+        }
+        call("Output.printString", &[hi]);
+        call("Output.println", &[]);
+        call("Output.printInt", &[(-7i16) as u16]);
+        call("String.setInt", &[hi, (-12i16) as u16]);
+        let value = call("String.intValue", &[hi]);
+        let length = call("String.length", &[hi]);
+        // `H` on the left of the first word and `i` on the right, then the
+        // middle of `-7` on the next line of text
+        let screen = SCREEN as usize;
+        assert_eq!(ram[screen], 51 | 12 << 8);
+        assert_eq!(ram[screen + 32 * 11 + 5 * 32], 63 | 12 << 8);
+        assert_eq!((value as i16, length), (-12, 3));
+
+        // typing `ok`, with a mistake rubbed out, then return
+        ram[KBD as usize] = 0;
+        let prompt = os.string(
+            &mut Memory {
+                ram: &mut ram,
+                access: Access::default(),
+            },
+            &[],
+        );
+        let mut line = None;
+        for key in [
+            b'o' as u16,
+            0,
+            b'x' as u16,
+            BACKSPACE,
+            0,
+            b'k' as u16,
+            0,
+            NEWLINE,
+            0,
+        ] {
+            ram[KBD as usize] = key;
+            match os.call("Keyboard.readLine", &[prompt.unwrap()], &mut ram).0 {
+                Outcome::Wait => {}
+                Outcome::Return(string) => line = Some(string),
+                Outcome::Halt => panic!("{:?}", os.error),
+            }
+        }
+        let mut memory = Memory {
+            ram: &mut ram,
+            access: Access::default(),
+        };
+        assert_eq!(text(&mut memory, line.unwrap()), chars("ok"));
+
+        let full = os.string(&mut memory, &chars("full")).unwrap();
+        assert_eq!(
+            os.call("String.appendChar", &[full, 33], &mut ram).0,
+            Outcome::Halt
+        );
+        assert_eq!(
+            os.error.as_deref(),
+            Some("String.appendChar: string is full (error 17)")
+        );
+    }
+
+    #[test]
+    fn traps_translated_calls() {
+        // our own Math.multiply is wrong, but the trap never lets it run
+        let vm = "\
+function Sys.init 0
+push constant 300
+push constant 5
+call Math.multiply 2
+pop static 0
+label END
+goto END
+function Math.multiply 0
+push constant 0
+return
+";
+        let program = crate::vm::Program::new(&[("Sys.vm".into(), vm.to_owned())]).unwrap();
+        let asm = crate::translate::translate(&program, Default::default());
+        let binary = asm.to_hack();
+        let mut cpu = crate::emulator::Cpu::new(&crate::emulator::load(binary.as_bytes()).unwrap());
+        let lines: Vec<_> = asm.code().cloned().collect();
+        cpu.traps = Some(Traps::new(&crate::symbols(&lines)));
+        while !cpu.halted() {
+            assert!(cpu.cycles < 10_000, "didn't halt");
+            cpu.step();
+        }
+        assert_eq!(cpu.ram[16], 1500);
+        // back in Sys.init, with nothing left on the stack but its frame
+        assert_eq!(cpu.ram[SP as usize], 256 + 5);
+        assert_eq!(cpu.ram[LCL as usize], 256 + 5);
+    }
+
+    #[test]
+    fn allocates_first_fit() {
+        let mut os = Os::default();
+        let mut ram = vec![0; RAM_SIZE];
+        let mut alloc = |size: u16| match os.call("Memory.alloc", &[size], &mut ram).0 {
+            Outcome::Return(address) => address,
+            _ => 0,
+        };
+        assert_eq!((alloc(10), alloc(5), alloc(20)), (2048, 2058, 2063));
+        os.call("Memory.deAlloc", &[2048], &mut ram);
+        let mut alloc = |size: u16| match os.call("Memory.alloc", &[size], &mut ram).0 {
+            Outcome::Return(address) => address,
+            _ => 0,
+        };
+        assert_eq!((alloc(12), alloc(8)), (2083, 2048));
+        assert_eq!(alloc(20000), 0);
+        assert_eq!(
+            os.error.as_deref(),
+            Some("Memory.alloc: heap overflow (error 6)")
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// the columns of an `.out` file, in the official tools' format, so that the
+// `.cmp` files the course hands out can be compared against ours. Each
+// column is a variable and how to show it:
+//
+//     RAM[0]%D2.6.2
+//
+// is RAM[0] in decimal, six characters wide with two spaces either side.
+// The formats are B (binary), D (decimal), X (hex) and S (as a string), and
+// a column without one is `%B1.16.1`
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Binary,
+    Decimal,
+    Hex,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub radix: Radix,
+    pub left: usize,
+    pub width: usize,
+    pub right: usize,
+}
+
+impl Column {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let error = || format!("invalid output column `{}`", spec);
+        let (name, format) = match spec.split_once('%') {
+            Some((name, format)) => (name, format),
+            None => (spec, "B1.16.1"),
+        };
+        let mut chars = format.chars();
+        let radix = match chars.next() {
+            Some('B') => Radix::Binary,
+            Some('D') => Radix::Decimal,
+            Some('X') => Radix::Hex,
+            Some('S') => Radix::String,
+            _ => return Err(error()),
+        };
+        let sizes: Vec<usize> = chars
+            .as_str()
+            .split('.')
+            .map(|size| size.parse().map_err(|_| error()))
+            .collect::<Result<_, _>>()?;
+        let [left, width, right] = sizes[..] else {
+            return Err(error());
+        };
+        if name.is_empty() || width == 0 {
+            return Err(error());
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            radix,
+            left,
+            width,
+            right,
+        })
+    }
+
+    // the column's name, centred over it, and cut short if it doesn't fit
+    fn heading(&self) -> String {
+        let total = self.left + self.width + self.right;
+        let name: String = self.name.chars().take(total).collect();
+        let before = (total - name.chars().count()) / 2;
+        format!("{:before$}{:<rest$}", "", name, rest = total - before)
+    }
+
+    // `value` as the column shows it: binary and hex are the word's last
+    // `width` digits, decimal is signed and right-aligned, and strings are
+    // left-aligned
+    pub fn cell(&self, value: i64) -> String {
+        let width = self.width;
+        let text = match self.radix {
+            Radix::Binary => last(&format!("{:016b}", value as u16), width),
+            Radix::Hex => last(&format!("{:04X}", value as u16), width),
+            Radix::Decimal => format!("{:>width$}", value),
+            Radix::String => format!("{:<width$.width$}", value.to_string()),
+        };
+        format!("{:l$}{}{:r$}", "", text, "", l = self.left, r = self.right)
+    }
+}
+
+// the last `width` characters of `digits`, padded with zeros if need be
+fn last(digits: &str, width: usize) -> String {
+    if digits.len() >= width {
+        digits[digits.len() - width..].to_owned()
+    } else {
+        format!("{:0>width$}", digits)
+    }
+}
+
+// the line `output-list` writes, naming each column
+pub fn header<'a>(columns: impl IntoIterator<Item = &'a Column>) -> String {
+    let mut line = String::from("|");
+    for column in columns {
+        line.push_str(&column.heading());
+        line.push('|');
+    }
+    line
+}
+
+// the line `output` writes, given each column's value
+pub fn row<'a>(cells: impl IntoIterator<Item = (&'a Column, i64)>) -> String {
+    let mut line = String::from("|");
+    for (column, value) in cells {
+        line.push_str(&column.cell(value));
+        line.push('|');
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn official_formats() {
+        // as in the course's Mult.cmp and CPU.cmp
+        let columns: Vec<Column> = ["RAM[0]%D2.6.2", "RAM[1]%D2.6.2", "instruction%B0.16.0"]
+            .iter()
+            .map(|spec| Column::parse(spec).unwrap())
+            .collect();
+        assert_eq!(header(&columns), "|  RAM[0]  |  RAM[1]  |  instruction   |");
+        assert_eq!(
+            row(columns.iter().zip([6, -1, 0x3039])),
+            "|       6  |      -1  |0011000000111001|"
+        );
+
+        let time = Column::parse("time%S1.4.1").unwrap();
+        assert_eq!(time.cell(12), " 12   ");
+        assert_eq!(Column::parse("A%X1.4.1").unwrap().cell(-1), " FFFF ");
+        assert_eq!(Column::parse("A%B1.4.1").unwrap().cell(5), " 0101 ");
+        assert_eq!(
+            Column::parse("A").unwrap(),
+            Column::parse("A%B1.16.1").unwrap()
+        );
+        for bad in ["A%Q1.2.3", "A%D1.2", "%D1.6.1", "A%D1.0.1", "A%Dx.6.1"] {
+            assert!(Column::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{self, Stdout, Write};
+use std::ops::Range;
+
+use crate::emulator::RAM_SIZE;
+use crate::keyboard::{KBD, NEWLINE};
+use crate::screen;
+
+// a device mapped into a range of RAM, like the screen and keyboard. It
+// doesn't sit between the program and memory: RAM holds the device's
+// words, which the device sets before each instruction, and everything
+// reads and writes them there. Once an instruction has run, the device is
+// told what it read and wrote in its range, so it reacts after the fact.
+// A native OS call like `Memory.poke` counts as one instruction, and
+// reports the last word it read and the last it wrote
+pub trait Peripheral {
+    // the addresses it's mapped at
+    fn range(&self) -> Range<u16>;
+
+    // called before every instruction with the device's own words of RAM,
+    // for it to put there whatever the program should find
+    fn update(&mut self, _cycles: u64, _words: &mut [u16]) {}
+
+    // the program has just read `address`, and got what was in RAM
+    fn read(&mut self, _address: u16) {}
+
+    // the program has just written `value` to `address`
+    fn write(&mut self, _address: u16, _value: u16) {}
+}
+
+// a clock the program can read: it counts thousands of cycles since it was
+// last written to
+pub struct Timer {
+    address: u16,
+    start: u64,
+    now: u64,
+}
+
+impl Timer {
+    const CYCLES: u64 = 1000;
+
+    pub fn new(address: u16) -> Self {
+        Self {
+            address,
+            start: 0,
+            now: 0,
+        }
+    }
+}
+
+impl Peripheral for Timer {
+    fn range(&self) -> Range<u16> {
+        self.address..self.address + 1
+    }
+
+    fn update(&mut self, cycles: u64, words: &mut [u16]) {
+        self.now = cycles;
+        words[0] = (cycles.saturating_sub(self.start) / Self::CYCLES) as u16;
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {
+        self.start = self.now;
+    }
+}
+
+// a serial console: each character the program writes to it is printed,
+// using the keyboard's codes, so that 128 is a newline
+pub struct Console<W: Write = Stdout> {
+    address: u16,
+    out: W,
+}
+
+impl Console {
+    pub fn new(address: u16) -> Self {
+        Self {
+            address,
+            out: io::stdout(),
+        }
+    }
+}
+
+impl<W: Write> Peripheral for Console<W> {
+    fn range(&self) -> Range<u16> {
+        self.address..self.address + 1
+    }
+
+    fn write(&mut self, _address: u16, value: u16) {
+        let text = match value {
+            NEWLINE => "\n".to_owned(),
+            32..=126 => (value as u8 as char).to_string(),
+            _ => return,
+        };
+        // there's nobody to tell if the console's gone away
+        let _ = self.out.write_all(text.as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+// a device given on the command line as `NAME@ADDRESS`, as in
+// `timer@24577`. It has to fit in RAM and stay clear of the screen and
+// keyboard, and of the `others` given before it
+pub fn parse(spec: &str, others: &[Box<dyn Peripheral>]) -> Result<Box<dyn Peripheral>, String> {
+    let (name, address) = spec
+        .split_once('@')
+        .ok_or_else(|| format!("expected `NAME@ADDRESS` for a device, not `{}`", spec))?;
+    let address: u16 = address
+        .parse()
+        .ok()
+        .filter(|address| (*address as usize) < RAM_SIZE)
+        .ok_or_else(|| format!("invalid address `{}` for `{}`", address, name))?;
+    let device: Box<dyn Peripheral> = match name {
+        "timer" => Box::new(Timer::new(address)),
+        "console" => Box::new(Console::new(address)),
+        _ => {
+            return Err(format!(
+                "unknown device `{}` (expected timer or console)",
+                name
+            ))
+        }
+    };
+    let range = device.range();
+    let overlaps = |other: Range<u16>| range.start < other.end && other.start < range.end;
+    if overlaps(screen::RANGE) || overlaps(KBD..KBD + 1) {
+        return Err(format!(
+            "`{}` would be on top of the screen or keyboard",
+            spec
+        ));
+    }
+    if others.iter().any(|other| overlaps(other.range())) {
+        return Err(format!("`{}` would be on top of another device", spec));
+    }
+    Ok(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices() {
+        let mut console = Console {
+            address: 24578,
+            out: Vec::new(),
+        };
+        for value in [b'h' as u16, b'i' as u16, NEWLINE, 7] {
+            console.write(24578, value);
+        }
+        assert_eq!(console.out, b"hi\n");
+
+        let mut timer = Timer::new(24577);
+        let mut words = [0];
+        timer.update(2500, &mut words);
+        assert_eq!(words, [2]);
+        timer.write(24577, 0);
+        timer.update(3400, &mut words);
+        assert_eq!(words, [0]);
+
+        let others = [parse("timer@24577", &[]).unwrap()];
+        assert_eq!(others[0].range(), 24577..24578);
+        for bad in [
+            "timer",
+            "timer@32768",
+            "clock@24577",
+            "console@16400",
+            "console@24576",
+            "console@24577",
+        ] {
+            assert!(parse(bad, &others).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use crate::json::Json;
+use crate::PREDEFINED_SYMBOLS;
+
+// the symbols a program can use without defining them. Hack variants with
+// their devices somewhere else, or with more of them, can describe theirs in
+// a JSON file for `--symbols`:
+//
+//     { "standard": true, "symbols": { "LED": 24577, "R15": null } }
+//
+// which starts from the standard set (the default), then adds or moves
+// `LED` and takes `R15` away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolSet {
+    symbols: Vec<(Cow<'static, str>, u16)>,
+}
+
+impl SymbolSet {
+    pub fn standard() -> &'static SymbolSet {
+        static STANDARD: OnceLock<SymbolSet> = OnceLock::new();
+        STANDARD.get_or_init(|| SymbolSet {
+            symbols: PREDEFINED_SYMBOLS
+                .iter()
+                .map(|(name, address)| (Cow::Borrowed(*name), *address))
+                .collect(),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.symbols
+            .iter()
+            .map(|(name, address)| (name.as_ref(), *address))
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.iter()
+            .find(|(symbol, _)| *symbol == name)
+            .map(|(_, address)| address)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config = Json::parse(text)?;
+        let mut set = match config.get("standard").map(|standard| standard.as_bool()) {
+            None | Some(Some(true)) => SymbolSet::standard().clone(),
+            Some(Some(false)) => SymbolSet {
+                symbols: Vec::new(),
+            },
+            Some(None) => return Err("`standard` should be true or false".to_owned()),
+        };
+        let symbols = match config.get("symbols") {
+            None => &[][..],
+            Some(Json::Object(symbols)) => symbols,
+            Some(_) => return Err("`symbols` should be an object".to_owned()),
+        };
+        for (name, address) in symbols {
+            if !crate::is_symbol(name) {
+                return Err(format!("`{}` isn't a valid symbol", name));
+            }
+            set.symbols.retain(|(symbol, _)| symbol != name);
+            match address {
+                Json::Null => {}
+                Json::Number(address)
+                    if address.fract() == 0.0 && (0.0..=u16::MAX as f64).contains(address) =>
+                {
+                    set.symbols
+                        .push((Cow::Owned(name.clone()), *address as u16));
+                }
+                _ => return Err(format!("`{}` should be an address, or null", name)),
+            }
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configures() {
+        let set =
+            SymbolSet::parse(r#"{"symbols": {"LED": 24577, "KBD": 24580, "R15": null}}"#).unwrap();
+        assert_eq!(set.get("LED"), Some(24577));
+        assert_eq!(set.get("KBD"), Some(24580));
+        assert_eq!(set.get("R15"), None);
+        assert_eq!(set.get("SCREEN"), Some(16384));
+
+        // without its predefined address, `R15` is just another variable
+        let lines = crate::parse("@LED\n@R15\n").unwrap();
+        let mut binary = Vec::new();
+        crate::assemble_lines_with(
+            &lines,
+            &set,
+            &Default::default(),
+            &mut crate::emit::Text::new(&mut binary),
+        )
+        .unwrap();
+        assert_eq!(binary, b"0110000000000001\n0000000000010000\n");
+
+        let bare = SymbolSet::parse(r#"{"standard": false, "symbols": {"IO": 8}}"#).unwrap();
+        assert_eq!(bare.iter().collect::<Vec<_>>(), [("IO", 8)]);
+        assert!(SymbolSet::parse(r#"{"symbols": {"LED": 70000}}"#).is_err());
+        assert!(SymbolSet::parse(r#"{"symbols": {"1X": 1}}"#).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::Write as _;
+
+use crate::debug::Symbols;
+use crate::disassemble::describe;
+use crate::json::Json;
+
+// what a PC gets attributed to before the program's first label
+const ENTRY: &str = "<entry>";
+
+// how many times each instruction ran. Every Hack instruction takes a single
+// cycle, so these are cycle counts too
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    // indexed by PC
+    pub counts: Vec<u64>,
+}
+
+impl Profile {
+    pub fn new(program_length: usize) -> Self {
+        Self {
+            counts: vec![0; program_length],
+        }
+    }
+
+    pub fn record(&mut self, pc: u16) {
+        let pc = pc as usize;
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => count as f64 / total as f64 * 100.0,
+        }
+    }
+
+    // every instruction that ran, hottest first
+    pub fn instructions(&self) -> Vec<(u16, u64)> {
+        let mut instructions: Vec<_> = (0..)
+            .zip(self.counts.iter().copied())
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        instructions.sort_by_key(|&(pc, count)| (std::cmp::Reverse(count), pc));
+        instructions
+    }
+
+    // counts summed over the code following each label up to the next one,
+    // hottest first
+    pub fn labels(&self, symbols: &Symbols) -> Vec<(String, u64)> {
+        let mut starts: Vec<(u16, &str)> = symbols
+            .labels
+            .iter()
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        starts.sort();
+
+        let mut labels: Vec<(String, u64)> = Vec::new();
+        for (pc, count) in (0..).zip(self.counts.iter().copied()) {
+            if count == 0 {
+                continue;
+            }
+            // the nearest label at or before the instruction
+            let label = match starts.partition_point(|(address, _)| *address <= pc) {
+                0 => ENTRY,
+                index => starts[index - 1].1,
+            };
+            match labels.iter_mut().find(|(name, _)| name == label) {
+                Some((_, total)) => *total += count,
+                None => labels.push((label.to_owned(), count)),
+            }
+        }
+        labels.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        labels
+    }
+
+    pub fn text(&self, rom: &[u16], symbols: &Symbols) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{:>12}  {:>6}  label", "cycles", "share");
+        for (label, count) in self.labels(symbols) {
+            let _ = writeln!(out, "{:>12}  {:>5.1}%  {}", count, self.share(count), label);
+        }
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "{:>12}  {:>6}  {:>5}  instruction",
+            "cycles", "share", "pc"
+        );
+        for (pc, count) in self.instructions() {
+            let _ = writeln!(
+                out,
+                "{:>12}  {:>5.1}%  {:>5}  {}",
+                count,
+                self.share(count),
+                pc,
+                describe(rom[pc as usize])
+            );
+        }
+        out
+    }
+
+    pub fn json(&self, rom: &[u16], symbols: &Symbols) -> Json {
+        let labels = self.labels(symbols).into_iter().map(|(label, count)| {
+            Json::object([
+                ("label", label.into()),
+                ("cycles", count.into()),
+                ("share", self.share(count).into()),
+            ])
+        });
+        let instructions = self.instructions().into_iter().map(|(pc, count)| {
+            Json::object([
+                ("pc", pc.into()),
+                ("instruction", describe(rom[pc as usize]).into()),
+                ("cycles", count.into()),
+                ("share", self.share(count).into()),
+            ])
+        });
+        Json::object([
+            ("cycles", self.total().into()),
+            ("labels", labels.collect()),
+            ("instructions", instructions.collect()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn attributes_counts_to_labels() {
+        let mut profile = Profile::new(4);
+        for pc in [0, 1, 2, 3, 2, 3, 2, 3] {
+            profile.record(pc);
+        }
+        let symbols = Symbols {
+            labels: HashMap::from([("LOOP".to_owned(), 2)]),
+            variables: HashMap::new(),
+        };
+        assert_eq!(profile.instructions(), [(2, 3), (3, 3), (0, 1), (1, 1)]);
+        assert_eq!(
+            profile.labels(&symbols),
+            [("LOOP".to_owned(), 6), (ENTRY.to_owned(), 2)]
+        );
+
+        let json = profile.json(&[0, 1, 0xEA87, 2], &symbols).to_string();
+        assert!(json.starts_with(r#"{"cycles":8,"labels":[{"label":"LOOP","cycles":6,"share":75}"#));
+        assert!(json.contains(r#"{"pc":2,"instruction":"0;JMP","cycles":3,"share":37.5}"#));
+    }
+}
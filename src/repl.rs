@@ -0,0 +1,277 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use crate::debug::{parse_value, Symbols};
+use crate::emulator::{Cpu, ROM_SIZE};
+use crate::predefined::SymbolSet;
+use crate::{screen, split_comment, HackLine};
+
+// how long a jump back into the program may run for before we give up on it
+const MAX_CYCLES: u64 = 1_000_000;
+
+const HELP: &str = "\
+type a line of assembly to run it there and then, or one of these commands:
+  :r, :regs              show A, D, PC and the cycle count
+  :x, :ram ADDR [COUNT]  show COUNT words of RAM starting at ADDR
+  :poke ADDR VALUE       change a word of RAM
+  :screen                draw the screen
+  :l, :list              show the program so far, with addresses
+  :reset                 start again with an empty machine
+  :h, :help              show this message
+  :q, :quit              leave
+each instruction goes on the end of the program and runs straight away. A label
+names the next instruction, and jumping back to one runs the program from there
+until it gets back to the end";
+
+// an emulator that's given its program a line at a time, for trying out
+// instructions and idioms to see what they do
+pub struct Repl {
+    cpu: Cpu,
+    symbols: Symbols,
+    // where the next new variable goes
+    next_variable: u16,
+    // what was typed for each instruction and label, for `:list`
+    listing: Vec<(Option<u16>, String)>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self {
+            cpu: Cpu::new(&[]),
+            symbols: Symbols::default(),
+            next_variable: 16,
+            listing: Vec::new(),
+        }
+    }
+}
+
+// an instruction's word split into its fields, so that it's easy to see how
+// each part is encoded
+fn fields(word: u16) -> String {
+    if word & 0x8000 == 0 {
+        format!("0 {:015b}", word)
+    } else {
+        format!(
+            "111 {:b} {:06b} {:03b} {:03b}",
+            word >> 12 & 1,
+            word >> 6 & 0x3f,
+            word >> 3 & 7,
+            word & 7
+        )
+    }
+}
+
+impl Repl {
+    fn registers(&self) -> String {
+        format!(
+            "A={} D={} PC={} cycles={}",
+            self.cpu.a as i16, self.cpu.d as i16, self.cpu.pc, self.cpu.cycles
+        )
+    }
+
+    // where a symbol points, making it a new variable if it's nothing else
+    fn resolve(&mut self, name: &str, out: &mut impl Write) -> io::Result<u16> {
+        if let Some(address) = self.symbols.labels.get(name) {
+            return Ok(*address);
+        }
+        if let Some(address) = SymbolSet::standard().get(name) {
+            return Ok(address);
+        }
+        if let Some(address) = self.symbols.variables.get(name) {
+            return Ok(*address);
+        }
+        let address = self.next_variable;
+        self.next_variable = self.next_variable.saturating_add(1);
+        self.symbols.variables.insert(name.to_owned(), address);
+        writeln!(out, "{} is a new variable, at RAM[{}]", name, address)?;
+        Ok(address)
+    }
+
+    // adds a line of assembly to the program and runs it
+    fn enter(
+        &mut self,
+        code: &str,
+        out: &mut impl Write,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let line = HackLine::parse(code)?;
+        let address = self.cpu.program_length as u16;
+        let word = match &line {
+            HackLine::Label(label) => {
+                if self.symbols.variables.contains_key(label.as_ref()) {
+                    Err(format!("`{}` is already a variable", label))?;
+                }
+                self.symbols.labels.insert(label.to_string(), address);
+                self.listing.push((None, code.trim().to_owned()));
+                return Ok(());
+            }
+            HackLine::ALocation(name) => {
+                let target = self.resolve(name, out)?;
+                line.word_with(|_| target)
+            }
+            _ => line.word_with(|_| unreachable!()),
+        }
+        .expect("only labels have no word");
+        if self.cpu.program_length >= ROM_SIZE {
+            Err("ROM is full (try `:reset`)")?;
+        }
+        self.cpu.rom[address as usize] = word;
+        self.cpu.program_length += 1;
+        self.listing.push((Some(address), code.trim().to_owned()));
+
+        // the new instruction runs whatever it is, then anything it jumps
+        // back to
+        let start = self.cpu.cycles;
+        let mut written = self.cpu.step().write;
+        while !self.cpu.finished() && !self.cpu.halted() && self.cpu.cycles - start < MAX_CYCLES {
+            written = self.cpu.step().write;
+        }
+        let cycles = self.cpu.cycles - start;
+        writeln!(out, "{}  {}", fields(word), self.registers())?;
+        match written {
+            Some((address, value)) if cycles == 1 => {
+                writeln!(out, "RAM[{}] = {}", address, value as i16)?
+            }
+            _ if cycles > 1 => writeln!(out, "ran {} instructions", cycles)?,
+            _ => {}
+        }
+        // come back to the end, ready for the next line
+        let end = self.cpu.program_length as u16;
+        if self.cpu.pc != end {
+            if self.cpu.halted() {
+                write!(out, "halted at PC {}", self.cpu.pc)?;
+            } else if self.cpu.finished() {
+                write!(out, "jumped past the end, to PC {}", self.cpu.pc)?;
+            } else {
+                write!(out, "still running at PC {}", self.cpu.pc)?;
+            }
+            writeln!(out, "; carrying on from {}", end)?;
+            self.cpu.pc = end;
+        }
+        Ok(())
+    }
+
+    // runs a single line, returning whether to keep going
+    pub fn line(
+        &mut self,
+        text: &str,
+        out: &mut impl Write,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let (code, _) = split_comment(text);
+        let Some(command) = code.trim().strip_prefix(':') else {
+            if !code.trim().is_empty() {
+                self.enter(code, out)?;
+            }
+            return Ok(true);
+        };
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words[..] {
+            ["r" | "regs"] => writeln!(out, "{}", self.registers())?,
+            ["x" | "ram", address, ..] => {
+                let address = self.symbols.ram(address)?;
+                let count = match words.get(2) {
+                    Some(count) => count
+                        .parse()
+                        .map_err(|_| format!("invalid count `{}`", count))?,
+                    None => 1,
+                };
+                for offset in 0..count {
+                    let address = address.wrapping_add(offset);
+                    let value = self.cpu.read(address);
+                    writeln!(out, "RAM[{}] = {} ({:#06x})", address, value as i16, value)?;
+                }
+            }
+            ["poke", address, value] => {
+                let (address, value) = (self.symbols.ram(address)?, parse_value(value)?);
+                self.cpu.write(address, value);
+            }
+            ["screen"] => write!(out, "{}", screen::render(&self.cpu.ram))?,
+            ["l" | "list"] => {
+                for (address, code) in &self.listing {
+                    match address {
+                        Some(address) => writeln!(out, "{:>5}  {}", address, code)?,
+                        None => writeln!(out, "       {}", code)?,
+                    }
+                }
+            }
+            ["reset"] => *self = Self::default(),
+            ["h" | "help"] => writeln!(out, "{}", HELP)?,
+            ["q" | "quit"] => return Ok(false),
+            _ => Err(format!(
+                "unknown command `:{}` (try `:help`)",
+                command.trim()
+            ))?,
+        }
+        Ok(true)
+    }
+
+    // the interactive loop: reads lines from `input` until it runs dry or
+    // the user quits
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "(asm) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            match self.line(&line?, out) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(err) => writeln!(out, "error: {}", err)?,
+            }
+            write!(out, "(asm) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(lines: &str) -> String {
+        let mut out = Vec::new();
+        Repl::default().run(lines.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn runs_each_line() {
+        let transcript =
+            session("@5\nD=A\n@sum\nM=D // keep it\nD=D+1;JEQ\n:x sum\nD=Q\n:nope\n:q\n");
+        let expected = "\
+(asm) 0 000000000000101  A=5 D=0 PC=1 cycles=1
+(asm) 111 0 110000 010 000  A=5 D=5 PC=2 cycles=2
+(asm) sum is a new variable, at RAM[16]
+0 000000000010000  A=16 D=5 PC=3 cycles=3
+(asm) 111 0 001100 001 000  A=16 D=5 PC=4 cycles=4
+RAM[16] = 5
+(asm) 111 0 011111 010 010  A=16 D=6 PC=5 cycles=5
+(asm) RAM[16] = 5 (0x0005)
+(asm) error: Invalid comp: Q
+(asm) error: unknown command `:nope` (try `:help`)
+(asm) ";
+        assert_eq!(transcript, expected);
+    }
+
+    #[test]
+    fn loops_run_back_to_the_end() {
+        let transcript =
+            session("@3\nD=A\n(LOOP)\nD=D-1\n@LOOP\nD;JGT\n:list\n(END)\n@END\n0;JMP\n");
+        let expected = "\
+(asm) 0 000000000000011  A=3 D=0 PC=1 cycles=1
+(asm) 111 0 110000 010 000  A=3 D=3 PC=2 cycles=2
+(asm) (asm) 111 0 001110 010 000  A=3 D=2 PC=3 cycles=3
+(asm) 0 000000000000010  A=2 D=2 PC=4 cycles=4
+(asm) 111 0 001100 000 001  A=2 D=0 PC=5 cycles=11
+ran 7 instructions
+(asm)     0  @3
+    1  D=A
+       (LOOP)
+    2  D=D-1
+    3  @LOOP
+    4  D;JGT
+(asm) (asm) 0 000000000000101  A=5 D=0 PC=6 cycles=12
+(asm) 111 0 101010 000 111  A=5 D=0 PC=5 cycles=13
+halted at PC 5; carrying on from 7
+(asm) \n";
+        assert_eq!(transcript, expected);
+    }
+}
@@ -0,0 +1,509 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backtrace::Functions;
+use crate::debug::Symbols;
+use crate::emulator::{Access, Cpu};
+use crate::error::HackError;
+use crate::image::Gif;
+use crate::keyboard::Keyboard;
+use crate::peripheral::Peripheral;
+use crate::profile::Profile;
+use crate::screen::{self, Terminal};
+use crate::vcd::Vcd;
+use crate::vm::{self, Vm};
+
+// how often a GIF recording checks whether the screen needs a new frame, and
+// how long each frame is shown for in hundredths of a second
+const GIF_FRAME_CYCLES: u64 = 100_000;
+const GIF_FRAME_DELAY: u16 = 10;
+
+const STDOUT: &str = "<stdout>";
+
+// holds the emulator to a given clock speed. Rather than timing every
+// instruction, it checks every so often how far ahead of the wall clock the
+// cycle count has got and sleeps off the difference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Throttle {
+    pub hz: u64,
+    start: Instant,
+    // the cycle count at `start`
+    cycles: u64,
+}
+
+impl Throttle {
+    // how many times a second we check the time
+    const CHECKS: u64 = 1000;
+
+    pub fn new(hz: u64, cycles: u64) -> Self {
+        Self {
+            hz: hz.max(1),
+            start: Instant::now(),
+            cycles,
+        }
+    }
+
+    // starts the schedule afresh, e.g. after the program's been paused
+    pub fn reset(&mut self, cycles: u64) {
+        self.start = Instant::now();
+        self.cycles = cycles;
+    }
+
+    pub fn pace(&mut self, cycles: u64) {
+        if !cycles.is_multiple_of((self.hz / Self::CHECKS).max(1)) {
+            return;
+        }
+        let ran = cycles.saturating_sub(self.cycles);
+        let due = Duration::from_secs_f64(ran as f64 / self.hz as f64);
+        if let Some(ahead) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
+
+// what the runner needs from whatever it's running, so that the CPU
+// emulator and the VM emulator share the screen, keyboard and the rest
+pub trait Machine {
+    fn step(&mut self) -> Access;
+    fn read(&self, address: u16) -> u16;
+    fn write(&mut self, address: u16, value: u16);
+    fn ram(&self) -> &[u16];
+    fn ram_mut(&mut self) -> &mut [u16];
+    fn cycles(&self) -> u64;
+    // the instruction, or VM command, about to run
+    fn pc(&self) -> u16;
+    fn finished(&self) -> bool;
+    fn halted(&self) -> bool;
+    fn trace(&self, out: &mut impl Write) -> io::Result<()>;
+    // the registers, for reporting once the program's stopped
+    fn registers(&self) -> String;
+    // a single register by name, if there's one called that
+    fn register(&self, name: &str) -> Option<u16>;
+    // the functions being called, outermost first, if we can tell
+    fn backtrace(&self, symbols: &Symbols) -> Vec<String>;
+}
+
+impl Machine for Cpu {
+    fn step(&mut self) -> Access {
+        Cpu::step(self)
+    }
+
+    fn read(&self, address: u16) -> u16 {
+        Cpu::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        Cpu::write(self, address, value)
+    }
+
+    fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn finished(&self) -> bool {
+        Cpu::finished(self)
+    }
+
+    fn halted(&self) -> bool {
+        Cpu::halted(self)
+    }
+
+    fn trace(&self, out: &mut impl Write) -> io::Result<()> {
+        Cpu::trace(self, out)
+    }
+
+    fn registers(&self) -> String {
+        let registers = format!("A={} D={} PC={}", self.a as i16, self.d as i16, self.pc);
+        match self
+            .traps
+            .as_ref()
+            .and_then(|traps| traps.os.error.as_ref())
+        {
+            Some(error) => format!("{} ({})", registers, error),
+            None => registers,
+        }
+    }
+
+    fn register(&self, name: &str) -> Option<u16> {
+        match name {
+            "A" => Some(self.a),
+            "D" => Some(self.d),
+            "PC" => Some(self.pc),
+            _ => None,
+        }
+    }
+
+    fn backtrace(&self, symbols: &Symbols) -> Vec<String> {
+        Functions::new(symbols).backtrace(|address| self.read(address), self.pc)
+    }
+}
+
+impl Machine for Vm {
+    fn step(&mut self) -> Access {
+        Vm::step(self)
+    }
+
+    fn read(&self, address: u16) -> u16 {
+        Vm::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        Vm::write(self, address, value)
+    }
+
+    fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn pc(&self) -> u16 {
+        self.pc as u16
+    }
+
+    fn finished(&self) -> bool {
+        Vm::finished(self)
+    }
+
+    fn halted(&self) -> bool {
+        Vm::halted(self)
+    }
+
+    fn trace(&self, out: &mut impl Write) -> io::Result<()> {
+        Vm::trace(self, out)
+    }
+
+    fn registers(&self) -> String {
+        let command = self
+            .line()
+            .map_or_else(|| "(end)".to_owned(), |line| line.command.to_string());
+        let registers = format!("SP={} PC={} ({})", self.read(vm::SP), self.pc, command);
+        match &self.os.error {
+            Some(error) => format!("{} ({})", registers, error),
+            None => registers,
+        }
+    }
+
+    // the VM keeps everything else in RAM
+    fn register(&self, name: &str) -> Option<u16> {
+        (name == "PC").then_some(self.pc as u16)
+    }
+
+    fn backtrace(&self, _symbols: &Symbols) -> Vec<String> {
+        Vm::backtrace(self)
+    }
+}
+
+pub struct Recording {
+    gif: Gif,
+    path: PathBuf,
+    frames: usize,
+    limit: usize,
+    // whether the screen has changed since the last frame
+    dirty: bool,
+}
+
+impl Recording {
+    pub fn new(path: PathBuf, limit: usize) -> Self {
+        Self {
+            gif: Gif::new(GIF_FRAME_DELAY),
+            path,
+            frames: 0,
+            limit,
+            dirty: true,
+        }
+    }
+}
+
+impl Peripheral for Recording {
+    fn range(&self) -> Range<u16> {
+        screen::RANGE
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {
+        self.dirty = true;
+    }
+}
+
+// the devices mapped into memory: whichever of the built-in ones are
+// switched on, then any others
+fn peripherals<'a>(
+    keyboard: &'a mut Option<Keyboard>,
+    terminal: &'a mut Option<Terminal>,
+    recording: &'a mut Option<Recording>,
+    devices: &'a mut [Box<dyn Peripheral>],
+) -> impl Iterator<Item = &'a mut (dyn Peripheral + 'static)> {
+    let keyboard = keyboard
+        .iter_mut()
+        .map(|device| device as &mut dyn Peripheral);
+    let terminal = terminal
+        .iter_mut()
+        .map(|device| device as &mut dyn Peripheral);
+    let recording = recording
+        .iter_mut()
+        .map(|device| device as &mut dyn Peripheral);
+    let devices = devices.iter_mut().map(|device| device.as_mut());
+    keyboard.chain(terminal).chain(recording).chain(devices)
+}
+
+// the emulator along with everything that might be watching it run: each is
+// optional, and only costs anything when it's there
+pub struct Runner<M = Cpu> {
+    pub machine: M,
+    pub trace: Option<(BufWriter<File>, PathBuf)>,
+    pub profile: Option<Profile>,
+    pub recording: Option<Recording>,
+    pub vcd: Option<Vcd>,
+    pub terminal: Option<Terminal>,
+    pub keyboard: Option<Keyboard>,
+    // memory-mapped devices besides the screen and keyboard
+    pub devices: Vec<Box<dyn Peripheral>>,
+    pub throttle: Option<Throttle>,
+    // set once the user asks us to stop
+    pub interrupted: bool,
+}
+
+impl<M: Machine> Runner<M> {
+    pub fn new(machine: M) -> Self {
+        Self {
+            machine,
+            trace: None,
+            profile: None,
+            recording: None,
+            vcd: None,
+            terminal: None,
+            keyboard: None,
+            devices: Vec::new(),
+            throttle: None,
+            interrupted: false,
+        }
+    }
+
+    pub fn trace_to(&mut self, path: &Path) -> Result<(), HackError> {
+        let file = File::create(path).map_err(HackError::io(path))?;
+        self.trace = Some((BufWriter::new(file), path.to_owned()));
+        Ok(())
+    }
+
+    // shows the screen in the terminal, and wires up the keyboard if there's
+    // someone to type on it
+    pub fn show_screen(&mut self) -> Result<(), HackError> {
+        print!("\x1b[2J");
+        self.terminal = Some(Terminal::new());
+        if io::IsTerminal::is_terminal(&io::stdin()) {
+            let keyboard = Keyboard::open().map_err(HackError::io(Path::new("<stdin>")))?;
+            self.keyboard = Some(keyboard);
+        }
+        Ok(())
+    }
+
+    // whether there's any point stepping again
+    pub fn running(&self) -> bool {
+        !self.machine.finished() && !self.machine.halted() && !self.interrupted
+    }
+
+    // executes one instruction, keeping everything watching up to date
+    pub fn step(&mut self) -> Result<(), HackError> {
+        let machine = &mut self.machine;
+        let (keyboard, terminal, recording) =
+            (&mut self.keyboard, &mut self.terminal, &mut self.recording);
+        let cycles = machine.cycles();
+        for device in peripherals(keyboard, terminal, recording, &mut self.devices) {
+            let Range { start, end } = device.range();
+            device.update(cycles, &mut machine.ram_mut()[start as usize..end as usize]);
+        }
+        if let Some(keyboard) = keyboard {
+            self.interrupted |= keyboard.interrupted();
+        }
+        if let Some((out, path)) = &mut self.trace {
+            machine.trace(out).map_err(HackError::io(path))?;
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(machine.pc());
+        }
+
+        let access = machine.step();
+
+        for device in peripherals(keyboard, terminal, recording, &mut self.devices) {
+            let range = device.range();
+            if let Some(address) = access.read.filter(|address| range.contains(address)) {
+                device.read(address);
+            }
+            if let Some((address, value)) = access.write {
+                if range.contains(&address) {
+                    device.write(address, value);
+                }
+            }
+        }
+        if let Some(recording) = recording {
+            if recording.dirty
+                && recording.frames < recording.limit
+                && machine.cycles().is_multiple_of(GIF_FRAME_CYCLES)
+            {
+                recording.gif.frame(machine.ram());
+                recording.frames += 1;
+                recording.dirty = false;
+            }
+        }
+        if let Some(vcd) = &mut self.vcd {
+            vcd.sample(machine)?;
+        }
+        if let Some(terminal) = terminal {
+            terminal
+                .refresh(machine.ram(), &mut io::stdout())
+                .map_err(HackError::io(Path::new(STDOUT)))?;
+        }
+        if let Some(throttle) = &mut self.throttle {
+            throttle.pace(machine.cycles());
+        }
+        Ok(())
+    }
+
+    // steps until the program ends, or for at most `cycles`
+    pub fn run(&mut self, cycles: u64) -> Result<(), HackError> {
+        let end = self.machine.cycles().saturating_add(cycles);
+        while self.running() && self.machine.cycles() < end {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // writes out everything that's been collecting output as we ran
+    pub fn finish(&mut self) -> Result<(), HackError> {
+        if let Some((out, path)) = &mut self.trace {
+            out.flush().map_err(HackError::io(path))?;
+        }
+        if let Some(vcd) = &mut self.vcd {
+            vcd.finish()?;
+        }
+        if let Some(mut recording) = self.recording.take() {
+            // always end on the final screen
+            if recording.dirty && recording.frames < recording.limit || recording.frames == 0 {
+                recording.gif.frame(self.machine.ram());
+            }
+            fs::write(&recording.path, recording.gif.finish())
+                .map_err(HackError::io(&recording.path))?;
+        }
+        if let Some(terminal) = &mut self.terminal {
+            terminal
+                .draw(self.machine.ram(), &mut io::stdout())
+                .map_err(HackError::io(Path::new(STDOUT)))?;
+        }
+        // put the terminal back before we print anything else
+        self.keyboard = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttles() {
+        // a program that spins forever
+        let mut runner = Runner::new(Cpu::new(&[0, 0xFDCF]));
+        runner.throttle = Some(Throttle::new(1000, 0));
+        let start = Instant::now();
+        runner.run(50).unwrap();
+        assert_eq!(runner.machine.cycles, 50);
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    // a device at 100 that reads as the cycle count, and remembers what the
+    // program did with it
+    #[derive(Default)]
+    struct Probe(std::rc::Rc<std::cell::RefCell<Vec<String>>>);
+
+    impl Peripheral for Probe {
+        fn range(&self) -> Range<u16> {
+            100..101
+        }
+
+        fn update(&mut self, cycles: u64, words: &mut [u16]) {
+            words[0] = cycles as u16;
+        }
+
+        fn read(&mut self, address: u16) {
+            self.0.borrow_mut().push(format!("read {}", address));
+        }
+
+        fn write(&mut self, address: u16, value: u16) {
+            self.0
+                .borrow_mut()
+                .push(format!("write {} {}", address, value));
+        }
+    }
+
+    #[test]
+    fn routes_to_devices() {
+        // @100, D=M, M=D+1, @101, M=D
+        let mut runner = Runner::new(Cpu::new(&[100, 0xFC10, 0xE7C8, 101, 0xE308]));
+        let probe = Probe::default();
+        let log = std::rc::Rc::clone(&probe.0);
+        runner.devices.push(Box::new(probe));
+        runner.run(5).unwrap();
+        assert_eq!(*log.borrow(), ["read 100", "write 100 2"]);
+        assert_eq!(runner.machine.ram[101], 1);
+    }
+
+    #[test]
+    fn routes_native_calls_to_devices() {
+        // the OS's own Memory.poke does nothing, so only the native one,
+        // run by the VM or trapped on the CPU, can write to the device
+        let source = "\
+function Sys.init 0
+push constant 100
+push constant 65
+call Memory.poke 2
+label END
+goto END
+function Memory.poke 0
+push constant 0
+return
+";
+        let program = vm::Program::new(&[(PathBuf::from("Sys.vm"), source.to_owned())]).unwrap();
+        let asm = crate::translate::translate(&program, Default::default());
+        let mut cpu = Cpu::new(&crate::emulator::load(asm.to_hack().as_bytes()).unwrap());
+        let lines: Vec<_> = asm.code().cloned().collect();
+        cpu.traps = Some(crate::os::Traps::new(&crate::symbols(&lines)));
+        let mut vm = Vm::new(program);
+        vm.os.replace = true;
+
+        let probe = Probe::default();
+        let log = std::rc::Rc::clone(&probe.0);
+        let mut runner = Runner::new(cpu);
+        runner.devices.push(Box::new(probe));
+        runner.run(1000).unwrap();
+        assert_eq!(*log.borrow(), ["write 100 65"]);
+
+        let probe = Probe::default();
+        let log = std::rc::Rc::clone(&probe.0);
+        let mut runner = Runner::new(vm);
+        runner.devices.push(Box::new(probe));
+        runner.run(10).unwrap();
+        assert_eq!(*log.borrow(), ["write 100 65"]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::peripheral::Peripheral;
+
+// the memory-mapped screen: 256 rows of 32 words, each word holding 16
+// pixels with the least significant bit leftmost
+pub const SCREEN: u16 = 16384;
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+const WORDS_PER_ROW: usize = WIDTH / 16;
+const SCREEN_WORDS: u16 = (HEIGHT * WORDS_PER_ROW) as u16;
+pub const RANGE: Range<u16> = SCREEN..SCREEN + SCREEN_WORDS;
+
+// how often a running program's screen is redrawn at most
+const FRAME: Duration = Duration::from_millis(33);
+
+pub fn pixel(ram: &[u16], x: usize, y: usize) -> bool {
+    let word = ram[SCREEN as usize + y * WORDS_PER_ROW + x / 16];
+    word & (1 << (x % 16)) != 0
+}
+
+// the screen drawn with braille characters, each of which holds a 2x4 block
+// of pixels, so that the whole thing fits in a (wide) terminal
+pub fn render(ram: &[u16]) -> String {
+    // the bit for each dot of a braille cell, by row then column
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut out = String::with_capacity((WIDTH / 2 + 1) * (HEIGHT / 4) * 3);
+    for row in (0..HEIGHT).step_by(4) {
+        for column in (0..WIDTH).step_by(2) {
+            let mut cell = 0;
+            for (dy, dots) in DOTS.iter().enumerate() {
+                for (dx, dot) in dots.iter().enumerate() {
+                    if pixel(ram, column + dx, row + dy) {
+                        cell |= dot;
+                    }
+                }
+            }
+            out.push(char::from_u32(0x2800 + cell).expect("braille is contiguous"));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// keeps a terminal showing the screen of a running program, redrawing it
+// in place whenever the program has drawn something since the last frame
+pub struct Terminal {
+    dirty: bool,
+    last_frame: Option<Instant>,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        Self {
+            // draw the blank screen straight away
+            dirty: true,
+            last_frame: None,
+        }
+    }
+
+    // redraws if anything has changed and a frame's worth of time has passed
+    pub fn refresh(&mut self, ram: &[u16], out: &mut impl Write) -> io::Result<()> {
+        if self.dirty && self.last_frame.is_none_or(|last| last.elapsed() >= FRAME) {
+            self.draw(ram, out)?;
+        }
+        Ok(())
+    }
+
+    pub fn draw(&mut self, ram: &[u16], out: &mut impl Write) -> io::Result<()> {
+        // home the cursor and draw over the previous frame, rather than
+        // clearing, which flickers
+        write!(out, "\x1b[H{}", render(ram))?;
+        out.flush()?;
+        self.dirty = false;
+        self.last_frame = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Peripheral for Terminal {
+    fn range(&self) -> Range<u16> {
+        RANGE
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::RAM_SIZE;
+
+    #[test]
+    fn renders_braille() {
+        let mut ram = vec![0; RAM_SIZE];
+        // the top-left pixel, and the one to its right a row down
+        ram[SCREEN as usize] = 0b01;
+        ram[SCREEN as usize + WORDS_PER_ROW] = 0b10;
+        // the bottom-right pixel
+        ram[SCREEN as usize + SCREEN_WORDS as usize - 1] = 0x8000;
+        assert!(pixel(&ram, 0, 0));
+        assert!(pixel(&ram, 511, 255));
+
+        let screen = render(&ram);
+        let rows: Vec<_> = screen.lines().collect();
+        assert_eq!(rows.len(), HEIGHT / 4);
+        assert_eq!(rows[0].chars().count(), WIDTH / 2);
+        assert_eq!(rows[0].chars().next(), Some('\u{2811}'));
+        assert_eq!(rows[63].chars().last(), Some('\u{2880}'));
+        assert!(RANGE.contains(&SCREEN) && !RANGE.contains(&(SCREEN + SCREEN_WORDS)));
+    }
+}
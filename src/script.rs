@@ -0,0 +1,294 @@
+use std::error::Error;
+
+use crate::debug::{parse_value, Symbols};
+use crate::error::HackError;
+use crate::keyboard::{self, KBD};
+use crate::out::{self, Column};
+use crate::run::{Machine, Runner};
+use crate::screen::{self, HEIGHT, WIDTH};
+
+// scripts drive a program headlessly: they hold down keys, let the program
+// run for a while, and check what it did to memory and the screen. One
+// command per line, with `#` starting a comment:
+//
+//     run 100000          # let the program run for this many cycles
+//     key a               # hold down a key (see `keyboard::code`)
+//     key none            # let go of it
+//     set R0 5            # write to RAM
+//     expect SCREEN -1    # check a RAM address holds a value
+//     expect pixel 0 0 on # check a pixel is set (or `off`)
+//     output-list RAM[0]%D2.6.2 PC%D1.5.1
+//                         # start an `.out` file with these columns (see
+//                         # `out`), which can be RAM, PC or time (the cycle)
+//     output              # add a line to it, with the values as they are now
+//
+// addresses may be numbers or symbols, and values decimal, hex or binary,
+// just like in the debugger
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run(u64),
+    Key(u16),
+    Set(u16, u16),
+    Expect(u16, u16),
+    Pixel(usize, usize, bool),
+    OutputList(Vec<(Variable, Column)>),
+    Output,
+}
+
+// what an `.out` column shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    Ram(u16),
+    Pc,
+    Time,
+}
+
+impl Variable {
+    fn parse(name: &str, symbols: &Symbols) -> Result<Self, String> {
+        Ok(match name {
+            "PC" => Variable::Pc,
+            "time" => Variable::Time,
+            _ => match name.strip_prefix("RAM[").and_then(|n| n.strip_suffix(']')) {
+                Some(address) => Variable::Ram(parse_value(address)?),
+                None => Variable::Ram(symbols.ram(name)?),
+            },
+        })
+    }
+
+    fn value<M: Machine>(self, machine: &M) -> i64 {
+        match self {
+            Variable::Ram(address) => machine.read(address) as i16 as i64,
+            Variable::Pc => machine.pc() as i64,
+            Variable::Time => machine.cycles() as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    // along with the line they're on
+    pub commands: Vec<(usize, Command)>,
+}
+
+// how a script went
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    // a message for every expectation that didn't hold
+    pub failures: Vec<String>,
+    // the lines of the `.out` file, if the script has an `output-list`
+    pub output: Vec<String>,
+}
+
+fn parse_command(words: &[&str], symbols: &Symbols) -> Result<Command, String> {
+    let coordinate = |word: &str, limit: usize| {
+        word.parse::<usize>()
+            .ok()
+            .filter(|n| *n < limit)
+            .ok_or_else(|| format!("invalid coordinate `{}`", word))
+    };
+    Ok(match words {
+        ["run", cycles] => Command::Run(
+            cycles
+                .parse()
+                .map_err(|_| format!("invalid cycle count `{}`", cycles))?,
+        ),
+        ["key", key] => {
+            Command::Key(keyboard::code(key).ok_or_else(|| format!("unknown key `{}`", key))?)
+        }
+        ["set", address, value] => Command::Set(symbols.ram(address)?, parse_value(value)?),
+        ["expect", "pixel", x, y, state] => Command::Pixel(
+            coordinate(x, WIDTH)?,
+            coordinate(y, HEIGHT)?,
+            match *state {
+                "on" => true,
+                "off" => false,
+                _ => Err(format!("expected `on` or `off`, found `{}`", state))?,
+            },
+        ),
+        ["expect", address, value] => Command::Expect(symbols.ram(address)?, parse_value(value)?),
+        ["output-list", columns @ ..] if !columns.is_empty() => Command::OutputList(
+            columns
+                .iter()
+                .map(|spec| {
+                    let column = Column::parse(spec)?;
+                    Ok((Variable::parse(&column.name, symbols)?, column))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        ["output"] => Command::Output,
+        _ => Err(format!("invalid command `{}`", words.join(" ")))?,
+    })
+}
+
+impl Script {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut commands = Vec::new();
+        for (number, line) in (1..).zip(text.lines()) {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let command = parse_command(&words, symbols)
+                .map_err(|err| format!("line {}: {}", number, err))?;
+            if command == Command::Output
+                && !commands
+                    .iter()
+                    .any(|(_, c)| matches!(c, Command::OutputList(_)))
+            {
+                Err(format!(
+                    "line {}: `output` needs an `output-list` before it",
+                    number
+                ))?;
+            }
+            commands.push((number, command));
+        }
+        Ok(Self { commands })
+    }
+
+    // whether running the script makes an `.out` file
+    pub fn writes_output(&self) -> bool {
+        self.commands
+            .iter()
+            .any(|(_, command)| matches!(command, Command::OutputList(_)))
+    }
+
+    // runs the script against the program. The program is stopped when the
+    // script ends
+    pub fn run<M: Machine>(&self, runner: &mut Runner<M>) -> Result<Outcome, HackError> {
+        let mut failures = Vec::new();
+        let mut output = Vec::new();
+        let mut columns: &[(Variable, Column)] = &[];
+        for (number, command) in &self.commands {
+            let machine = &mut runner.machine;
+            match *command {
+                Command::Run(cycles) => runner.run(cycles)?,
+                Command::Key(key) => machine.write(KBD, key),
+                Command::Set(address, value) => machine.write(address, value),
+                Command::Expect(address, expected) => {
+                    let actual = machine.read(address);
+                    if actual != expected {
+                        failures.push(format!(
+                            "line {}: expected RAM[{}] to be {}, but it was {}",
+                            number, address, expected as i16, actual as i16
+                        ));
+                    }
+                }
+                Command::Pixel(x, y, expected) => {
+                    if screen::pixel(machine.ram(), x, y) != expected {
+                        let state = |on| if on { "on" } else { "off" };
+                        failures.push(format!(
+                            "line {}: expected pixel ({}, {}) to be {}, but it was {}",
+                            number,
+                            x,
+                            y,
+                            state(expected),
+                            state(!expected)
+                        ));
+                    }
+                }
+                Command::OutputList(ref list) => {
+                    columns = list;
+                    output.push(out::header(columns.iter().map(|(_, column)| column)));
+                }
+                Command::Output => output.push(out::row(
+                    columns
+                        .iter()
+                        .map(|(variable, column)| (column, variable.value(&*machine))),
+                )),
+            }
+        }
+        Ok(Outcome { failures, output })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Cpu;
+    use crate::{parse, symbols};
+
+    // fills the screen's first word while any key is held, and clears it
+    // otherwise
+    const FILL: &str = "\
+(LOOP)
+@KBD
+D=M
+@CLEAR
+D;JEQ
+@SCREEN
+M=-1
+@LOOP
+0;JMP
+(CLEAR)
+@SCREEN
+M=0
+@LOOP
+0;JMP
+";
+
+    fn run(script: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let lines = parse(FILL)?;
+        let symbols = symbols(&lines);
+        let mut binary = Vec::new();
+        crate::assemble_lines(&lines, &mut binary)?;
+        let cpu = Cpu::new(&crate::emulator::load(&binary[..])?);
+        let script = Script::parse(script, &symbols)?;
+        Ok(script.run(&mut Runner::new(cpu))?.failures)
+    }
+
+    #[test]
+    fn drives_the_keyboard() {
+        let script = "\
+# nothing pressed yet
+run 100
+expect SCREEN 0
+key a
+run 100
+expect SCREEN -1
+expect pixel 15 0 on
+key none
+run 100
+expect pixel 0 0 on
+expect 16385 1
+";
+        assert_eq!(
+            run(script).unwrap(),
+            [
+                "line 10: expected pixel (0, 0) to be on, but it was off",
+                "line 11: expected RAM[16385] to be 1, but it was 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_out_files() {
+        let lines = parse(FILL).unwrap();
+        let mut binary = Vec::new();
+        crate::assemble_lines(&lines, &mut binary).unwrap();
+        let cpu = Cpu::new(&crate::emulator::load(&binary[..]).unwrap());
+        let script = "\
+output-list KBD%D1.3.1 RAM[16384]%D2.6.2 PC%D0.2.0
+key a
+run 6
+output
+";
+        let script = Script::parse(script, &symbols(&lines)).unwrap();
+        assert!(script.writes_output());
+        let outcome = script.run(&mut Runner::new(cpu)).unwrap();
+        assert_eq!(
+            outcome.output,
+            ["| KBD |RAM[16384]|PC|", "|  97 |      -1  | 6|"]
+        );
+        assert!(Script::parse("output\n", &Symbols::default()).is_err());
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let err = run("run 10\nkey hyper\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: unknown key `hyper`");
+        assert!(run("expect pixel 512 0 on").is_err());
+        assert!(run("frobnicate").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use itertools::{EitherOrBoth, Itertools};
+
+// how one fixture fared against its reference binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    // the first line of the binary that doesn't match, 1-based
+    Mismatch(usize),
+    // there's no `.hack` file to compare against
+    NoReference,
+    // the fixture didn't assemble
+    Error(String),
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        *self == Outcome::Pass
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "pass"),
+            Outcome::Mismatch(line) => write!(f, "FAIL (differs at line {})", line),
+            Outcome::NoReference => write!(f, "FAIL (no .hack reference)"),
+            Outcome::Error(err) => write!(f, "FAIL ({})", err),
+        }
+    }
+}
+
+// assembles a fixture in memory and compares it with the `.hack` file
+// beside it, which is never overwritten
+pub fn check(fixture: &Path) -> Outcome {
+    let reference = match fs::read_to_string(fixture.with_extension("hack")) {
+        Ok(reference) => reference,
+        Err(_) => return Outcome::NoReference,
+    };
+    let mut binary = Vec::new();
+    let assembled = File::open(fixture)
+        .map_err(|err| err.into())
+        .and_then(|file| crate::assemble(BufReader::new(file), &mut binary));
+    if let Err(err) = assembled {
+        return Outcome::Error(err.to_string());
+    }
+    let binary = String::from_utf8_lossy(&binary);
+
+    // the course's files are often written on Windows
+    let expected = reference.lines().map(str::trim_end);
+    match expected.zip_longest(binary.lines()).position(
+        |pair| !matches!(pair, EitherOrBoth::Both(expected, actual) if expected == actual),
+    ) {
+        Some(index) => Outcome::Mismatch(index + 1),
+        None => Outcome::Pass,
+    }
+}
+
+// checks every fixture, in the order given
+pub fn run(fixtures: &[PathBuf]) -> Vec<(PathBuf, Outcome)> {
+    fixtures
+        .iter()
+        .map(|fixture| (fixture.clone(), check(fixture)))
+        .collect()
+}
+
+// the results as a table, with a tally at the bottom
+pub fn table(results: &[(PathBuf, Outcome)]) -> String {
+    let width = results
+        .iter()
+        .map(|(fixture, _)| fixture.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("fixture".len());
+    let mut out = format!("{:<width$}  result\n", "fixture", width = width);
+    for (fixture, outcome) in results {
+        out.push_str(&format!(
+            "{:<width$}  {}\n",
+            fixture.display().to_string(),
+            outcome,
+            width = width
+        ));
+    }
+    let passed = results
+        .iter()
+        .filter(|(_, outcome)| outcome.passed())
+        .count();
+    out.push_str(&format!(
+        "\n{} passed, {} failed\n",
+        passed,
+        results.len() - passed
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures() {
+        let dir = std::env::temp_dir().join(format!("hack-selftest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Add.asm"), "@2\nD=A\n").unwrap();
+        fs::write(
+            dir.join("Add.hack"),
+            "0000000000000010\r\n1110110000010000\r\n",
+        )
+        .unwrap();
+        fs::write(dir.join("Wrong.asm"), "@2\nD=M\n").unwrap();
+        fs::write(
+            dir.join("Wrong.hack"),
+            "0000000000000010\n1110110000010000\n",
+        )
+        .unwrap();
+        fs::write(dir.join("Alone.asm"), "@2\n").unwrap();
+
+        let results = run(&[
+            PathBuf::from("resources/Rect.asm"),
+            dir.join("Add.asm"),
+            dir.join("Wrong.asm"),
+            dir.join("Alone.asm"),
+        ]);
+        let outcomes: Vec<&Outcome> = results.iter().map(|(_, outcome)| outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                &Outcome::Pass,
+                &Outcome::Pass,
+                &Outcome::Mismatch(2),
+                &Outcome::NoReference
+            ]
+        );
+        assert!(table(&results).ends_with("\n2 passed, 2 failed\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use crate::debug::{parse_value, Symbols};
+use crate::emulator::{Cpu, RAM_SIZE, ROM_SIZE};
+
+// the complete state of a machine, so that a run can be picked up later. As
+// with our other file formats it's line-based text: the registers, then the
+// loaded program and any RAM that isn't zero, one word per line:
+//
+//     hack-snapshot
+//     a 23
+//     d 0
+//     pc 23
+//     cycles 4
+//     rom 0 0000000000000000
+//     ram 16384 -1
+const MAGIC: &str = "hack-snapshot";
+
+pub fn write(cpu: &Cpu, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", MAGIC)?;
+    writeln!(out, "a {}", cpu.a)?;
+    writeln!(out, "d {}", cpu.d)?;
+    writeln!(out, "pc {}", cpu.pc)?;
+    writeln!(out, "cycles {}", cpu.cycles)?;
+    for (address, word) in cpu.rom.iter().enumerate().take(cpu.program_length) {
+        writeln!(out, "rom {} {:016b}", address, word)?;
+    }
+    for (address, word) in cpu.ram.iter().enumerate() {
+        if *word != 0 {
+            writeln!(out, "ram {} {}", address, *word as i16)?;
+        }
+    }
+    Ok(())
+}
+
+fn address(word: &str, size: usize) -> Result<usize, String> {
+    word.parse()
+        .ok()
+        .filter(|address| *address < size)
+        .ok_or_else(|| format!("invalid address `{}`", word))
+}
+
+pub fn read(reader: impl BufRead) -> Result<Cpu, Box<dyn Error + Send + Sync>> {
+    let mut lines = reader.lines();
+    if lines.next().transpose()?.as_deref() != Some(MAGIC) {
+        Err("not a snapshot: expected it to start with `hack-snapshot`")?;
+    }
+
+    let mut cpu = Cpu::new(&[]);
+    for (number, line) in (2..).zip(lines) {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let malformed = || format!("line {}: malformed snapshot entry: {}", number, line);
+        match words[..] {
+            [] => {}
+            ["a", value] => cpu.a = parse_value(value)?,
+            ["d", value] => cpu.d = parse_value(value)?,
+            ["pc", value] => cpu.pc = parse_value(value)?,
+            ["cycles", value] => cpu.cycles = value.parse().map_err(|_| malformed())?,
+            ["rom", at, bits] => {
+                let at = address(at, ROM_SIZE)?;
+                cpu.rom[at] = u16::from_str_radix(bits, 2).map_err(|_| malformed())?;
+                cpu.program_length = cpu.program_length.max(at + 1);
+            }
+            ["ram", at, value] => cpu.ram[address(at, RAM_SIZE)?] = parse_value(value)?,
+            _ => Err(malformed())?,
+        }
+    }
+    Ok(cpu)
+}
+
+// sets up RAM before a run from lines of `ADDRESS VALUE`, where addresses
+// may be symbols, e.g. `R0 3`. `#` starts a comment
+pub fn load_ram(
+    ram: &mut [u16],
+    text: &str,
+    symbols: &Symbols,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for (number, line) in (1..).zip(text.lines()) {
+        let line = line.split('#').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (address, value) = match words[..] {
+            [] => continue,
+            [address, value] => (symbols.ram(address), parse_value(value)),
+            _ => Err(format!("line {}: expected an address and a value", number))?,
+        };
+        let (address, value) = address
+            .and_then(|address| Ok((address, value?)))
+            .map_err(|err| format!("line {}: {}", number, err))?;
+        let len = ram.len();
+        ram[address as usize % len] = value;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::load;
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn round_trips() {
+        let rect = File::open("resources/Rect.hack").unwrap();
+        let mut cpu = Cpu::new(&load(BufReader::new(rect)).unwrap());
+        load_ram(
+            &mut cpu.ram,
+            "R0 2 # rows\n\n0x4001 -1\n",
+            &Symbols::default(),
+        )
+        .unwrap();
+        assert_eq!((cpu.ram[0], cpu.ram[16385]), (2, 0xFFFF));
+        for _ in 0..30 {
+            cpu.step();
+        }
+
+        let mut saved = Vec::new();
+        write(&cpu, &mut saved).unwrap();
+        assert!(saved.starts_with(b"hack-snapshot\na "));
+        assert_eq!(read(&saved[..]).unwrap(), cpu);
+
+        assert!(read(&b"hack-snapshot\nram 40000 1\n"[..]).is_err());
+        assert!(read(&b"a 1\n"[..]).is_err());
+        assert!(load_ram(&mut cpu.ram, "R0", &Symbols::default()).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+use crate::{HackLine, SourceLine};
+
+// where an instruction came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+}
+
+// relates every ROM address of an assembled program back to its source. It's
+// written alongside the `.hack` file as one `address file:line` entry per
+// line, so that debuggers and emulators can show the source for the current
+// PC. Entries may point at different files, e.g. once translated `.vm` or
+// `.jack` sources are involved
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    // indexed by ROM address
+    pub locations: Vec<Location>,
+}
+
+impl SourceMap {
+    pub fn new(file: &str, lines: &[SourceLine]) -> Self {
+        let locations = lines
+            .iter()
+            .filter(|source| !matches!(source.line, HackLine::Label(_)))
+            .map(|source| Location {
+                file: file.to_owned(),
+                line: source.number,
+            })
+            .collect();
+        Self { locations }
+    }
+
+    pub fn get(&self, address: u16) -> Option<&Location> {
+        self.locations.get(address as usize)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), std::io::Error> {
+        for (address, location) in self.locations.iter().enumerate() {
+            writeln!(writer, "{} {}:{}", address, location.file, location.line)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut map = Self::default();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let malformed = || format!("malformed source map line: {}", line);
+            let (address, location) = line.split_once(' ').ok_or_else(malformed)?;
+            let (file, number) = location.rsplit_once(':').ok_or_else(malformed)?;
+            if address.parse::<usize>()? != map.locations.len() {
+                Err(format!("source map entries out of order at: {}", line))?;
+            }
+            map.locations.push(Location {
+                file: file.to_owned(),
+                line: number.parse()?,
+            });
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_source;
+
+    #[test]
+    fn maps_addresses_to_lines() {
+        let lines = parse_source("// comment\n@i\n\n(LOOP)\nM=M+1 // bump\n").unwrap();
+        let map = SourceMap::new("Prog.asm", &lines);
+        let numbers: Vec<_> = map.locations.iter().map(|location| location.line).collect();
+        assert_eq!(numbers, [2, 5]);
+
+        let mut written = Vec::new();
+        map.write(&mut written).unwrap();
+        assert_eq!(written, b"0 Prog.asm:2\n1 Prog.asm:5\n");
+        assert_eq!(SourceMap::read(&written[..]).unwrap(), map);
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+use crate::predefined::SymbolSet;
+use crate::{HackLine, SymbolTable};
+
+// Hack ROM is 32K words
+pub const ROM_SIZE: usize = 32768;
+// R0-R15 are always reserved, whether or not a program uses them
+const REGISTERS: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub a_instructions: usize,
+    pub c_instructions: usize,
+    pub labels: usize,
+    // in allocation order
+    pub variables: Vec<(String, u16)>,
+}
+
+impl Stats {
+    pub fn new(lines: &[HackLine]) -> Self {
+        let mut table = SymbolTable::new(SymbolSet::standard(), lines);
+        let mut stats = Stats {
+            a_instructions: 0,
+            c_instructions: 0,
+            labels: 0,
+            variables: Vec::new(),
+        };
+
+        for line in lines {
+            match line {
+                HackLine::Label(_) => stats.labels += 1,
+                HackLine::AImmediate(_) => stats.a_instructions += 1,
+                HackLine::ALocation(name) => {
+                    stats.a_instructions += 1;
+                    if table.label(name).is_none() {
+                        let address = table.variable(name);
+                        if !stats.variables.iter().any(|(variable, _)| variable == name) {
+                            stats.variables.push((name.to_string(), address));
+                        }
+                    }
+                }
+                HackLine::C(..) => stats.c_instructions += 1,
+            }
+        }
+
+        stats
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.a_instructions + self.c_instructions
+    }
+
+    // words of RAM the program's own data needs: the registers, plus
+    // everything up to the highest variable
+    pub fn ram_footprint(&self) -> usize {
+        self.variables
+            .iter()
+            .map(|(_, address)| *address as usize + 1)
+            .max()
+            .unwrap_or(0)
+            .max(REGISTERS)
+    }
+
+    pub fn rom_utilization(&self) -> f64 {
+        self.instructions() as f64 / ROM_SIZE as f64 * 100.0
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |part: usize| {
+            if self.instructions() == 0 {
+                0.0
+            } else {
+                part as f64 / self.instructions() as f64 * 100.0
+            }
+        };
+        writeln!(f, "instructions:   {}", self.instructions())?;
+        writeln!(
+            f,
+            "  A:            {} ({:.1}%)",
+            self.a_instructions,
+            percent(self.a_instructions)
+        )?;
+        writeln!(
+            f,
+            "  C:            {} ({:.1}%)",
+            self.c_instructions,
+            percent(self.c_instructions)
+        )?;
+        writeln!(f, "labels:         {}", self.labels)?;
+        writeln!(f, "variables:      {}", self.variables.len())?;
+        for (name, address) in &self.variables {
+            writeln!(f, "  {:<13} {}", name, address)?;
+        }
+        writeln!(f, "RAM footprint:  {} words", self.ram_footprint())?;
+        writeln!(
+            f,
+            "ROM used:       {} of {} words ({:.2}%)",
+            self.instructions(),
+            ROM_SIZE,
+            self.rom_utilization()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use std::fs;
+
+    #[test]
+    fn rect() {
+        let rect = fs::read_to_string("resources/Rect.asm").unwrap();
+        let stats = Stats::new(&parse(&rect).unwrap());
+        assert_eq!(
+            stats,
+            Stats {
+                a_instructions: 12,
+                c_instructions: 13,
+                labels: 2,
+                variables: vec![("counter".to_owned(), 16), ("address".to_owned(), 17)],
+            }
+        );
+        assert_eq!(stats.ram_footprint(), 18);
+    }
+}
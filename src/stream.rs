@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead};
+
+use crate::allocate::{self, Layout, Pin};
+use crate::diagnostic::Diagnostic;
+use crate::emit::Emitter;
+use crate::predefined::SymbolSet;
+use crate::{split_comment, HackLine};
+
+// assembles a program by reading it twice rather than holding onto it: the
+// first read only collects labels, and the second writes out each line as it
+// comes. Memory goes on the symbol table, not the program, which matters for
+// what the VM translator makes of a big one. `open` is called once per read.
+// Variables are allocated in the order they're first used, around any pins
+pub fn assemble<R: BufRead>(
+    mut open: impl FnMut() -> io::Result<R>,
+    predefined: &SymbolSet,
+    emitter: &mut impl Emitter,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // first pass: where each label points
+    let mut labels: HashMap<String, u16> = predefined
+        .iter()
+        .map(|(symbol, address)| (symbol.to_owned(), address))
+        .collect();
+    let mut program_length = 0;
+    let mut layout = Layout::default();
+    let pins = |pin| layout.pins.push(pin);
+    for_each_line(open()?, pins, |line| {
+        match line {
+            HackLine::Label(label) => {
+                labels.insert(label.into_owned(), program_length);
+            }
+            _ => program_length = u16::saturating_add(program_length, 1),
+        }
+        Ok(())
+    })?;
+
+    layout.check(|name| labels.contains_key(name))?;
+
+    // second pass: generate binary instructions
+    let mut variables: HashMap<String, u16> = layout
+        .pins
+        .iter()
+        .map(|pin| (pin.name.clone(), pin.address))
+        .collect();
+    let mut next = 16;
+    let mut address: u16 = 0;
+    for_each_line(
+        open()?,
+        |_| {},
+        |line| {
+            let word = line.word_with(|name| match labels.get(name) {
+                Some(address) => *address,
+                None => *variables.entry(name.to_owned()).or_insert_with(|| {
+                    next = allocate::unpinned(&layout.pins, next).saturating_add(1);
+                    next - 1
+                }),
+            });
+            if let Some(word) = word {
+                emitter.emit_word(address, word)?;
+                address = address.wrapping_add(1);
+            }
+            Ok(())
+        },
+    )?;
+    emitter.finish()?;
+    Ok(())
+}
+
+// parses each line of the program in turn, reusing the one buffer for all
+// of them, and passes on any pins
+fn for_each_line(
+    mut input: impl BufRead,
+    mut pin: impl FnMut(Pin),
+    mut f: impl FnMut(HackLine) -> io::Result<()>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut text = String::new();
+    let mut number = 0;
+    loop {
+        text.clear();
+        if input.read_line(&mut text)? == 0 {
+            return Ok(());
+        }
+        number += 1;
+        let mut text = text.trim_end_matches(['\n', '\r']);
+        if number == 1 {
+            text = crate::strip_bom(text);
+        }
+        if let Some(found) = Pin::scan(number, text)? {
+            pin(found);
+        }
+        let (code, _) = split_comment(text);
+        if code.trim().is_empty() {
+            continue;
+        }
+        let line = HackLine::parse(code).map_err(|err: Diagnostic| err.on_line(number, text))?;
+        f(line)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::Text;
+
+    #[test]
+    fn matches_the_assembler() {
+        let source = std::fs::read_to_string("resources/Rect.asm").unwrap();
+        let mut streamed = Vec::new();
+        assemble(
+            || Ok(source.as_bytes()),
+            SymbolSet::standard(),
+            &mut Text::new(&mut streamed),
+        )
+        .unwrap();
+        assert_eq!(streamed, std::fs::read("resources/Rect.hack").unwrap());
+
+        let pinned = "@a\nM=1\n@b // @pin b 16\nM=1\n";
+        let mut streamed = Vec::new();
+        assemble(
+            || Ok(pinned.as_bytes()),
+            SymbolSet::standard(),
+            &mut Text::new(&mut streamed),
+        )
+        .unwrap();
+        assert!(streamed.starts_with(b"0000000000010001\n"));
+
+        let mut output = Vec::new();
+        let err = assemble(
+            || Ok("@i\n\n(END)\nD=Q\n".as_bytes()),
+            SymbolSet::standard(),
+            &mut Text::new(&mut output),
+        )
+        .unwrap_err();
+        let err = err.downcast::<Diagnostic>().unwrap();
+        assert_eq!(err.line, 4);
+    }
+}
@@ -0,0 +1,708 @@
+use crate::builder::Builder;
+use crate::vm::{self, Command, Op, Program, Segment, VmLine};
+use crate::AM::{A, M};
+use crate::{Computation as C, Destination as D, HackLine, Jump as J};
+
+// where the shared subroutines live. Each is entered with the address to
+// come back to in D, and the translator's scratch registers R13-R15 hold
+// anything else they need
+const CALL: &str = "__call";
+const RETURN: &str = "__return";
+// where the program ends up if it runs off the end, rather than falling
+// into the subroutines
+const END: &str = "__end";
+
+// every number the translator loads is a VM index or argument count, which
+// the parser keeps small enough for an A-instruction, or one of our own
+const LOADABLE: &str = "VM numbers fit in an A-instruction";
+
+fn comparison(op: Op) -> &'static str {
+    match op {
+        Op::Eq => "__eq",
+        Op::Gt => "__gt",
+        Op::Lt => "__lt",
+        _ => unreachable!("{} isn't a comparison", op.name()),
+    }
+}
+
+fn jump(op: Op) -> J {
+    match op {
+        Op::Gt => J::JGT,
+        Op::Lt => J::JLT,
+        _ => unreachable!("{} isn't a comparison", op.name()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    // expand every call, return and comparison in place instead of jumping
+    // into shared subroutines: bigger, but a little faster
+    pub inline: bool,
+    // comment each expansion with the VM command it came from, and mark
+    // where each function starts
+    pub annotate: bool,
+}
+
+// the comment above a banner
+const RULE: &str = "------------------------------------------------------------";
+
+struct Translator<'a> {
+    program: &'a Program,
+    options: Options,
+    out: Builder,
+    // the function we're in, which labels are qualified with
+    function: String,
+    // for making return and comparison labels unique
+    labels: usize,
+}
+
+impl Translator<'_> {
+    // a comment, if we're annotating
+    fn note(&mut self, text: &str) {
+        if self.options.annotate {
+            self.out.comment(text);
+        }
+    }
+
+    fn banner(&mut self, text: &str) {
+        if self.options.annotate {
+            self.out.comment(RULE);
+            self.note(text);
+        }
+    }
+
+    fn label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!("{}${}.{}", self.function, kind, self.labels)
+    }
+
+    // pushes D
+    fn push(&mut self) {
+        self.out
+            .a_sym("SP")
+            .assign(D::AM, C::XPlusOne(M))
+            .assign(D::A, C::XMinusOne(A))
+            .assign(D::M, C::D);
+    }
+
+    // pops into D, leaving A pointing at the word popped
+    fn pop(&mut self) {
+        self.out
+            .a_sym("SP")
+            .assign(D::AM, C::XMinusOne(M))
+            .assign(D::D, C::X(M));
+    }
+
+    // the symbol for a segment held at a fixed address, if it's one of those
+    fn fixed(&self, segment: Segment, index: u16, line: &VmLine) -> Option<String> {
+        match segment {
+            Segment::Static => Some(format!("{}.{}", self.program.file_name(line.file), index)),
+            Segment::Pointer => Some(if index == 0 { "THIS" } else { "THAT" }.to_owned()),
+            Segment::Temp => Some(format!("R{}", vm::TEMP + index)),
+            _ => None,
+        }
+    }
+
+    fn pointer(segment: Segment) -> &'static str {
+        match segment {
+            Segment::Local => "LCL",
+            Segment::Argument => "ARG",
+            Segment::This => "THIS",
+            _ => "THAT",
+        }
+    }
+
+    fn call(&mut self, function: &str, args: u16) {
+        let back = self.label("ret");
+        if self.options.inline {
+            self.out.a_sym(&back).assign(D::D, C::X(A));
+            self.push();
+            for pointer in ["LCL", "ARG", "THIS", "THAT"] {
+                self.out.a_sym(pointer).assign(D::D, C::X(M));
+                self.push();
+            }
+            self.out
+                .a_sym("SP")
+                .assign(D::D, C::X(M))
+                .a(args + 5)
+                .expect(LOADABLE)
+                .assign(D::D, C::DMinusX(A))
+                .a_sym("ARG")
+                .assign(D::M, C::D)
+                .a_sym("SP")
+                .assign(D::D, C::X(M))
+                .a_sym("LCL")
+                .assign(D::M, C::D)
+                .a_sym(function)
+                .jump(C::Zero, J::JMP);
+        } else {
+            self.out
+                .a(args)
+                .expect(LOADABLE)
+                .assign(D::D, C::X(A))
+                .a_sym("R14")
+                .assign(D::M, C::D)
+                .a_sym(function)
+                .assign(D::D, C::X(A))
+                .a_sym("R13")
+                .assign(D::M, C::D)
+                .a_sym(&back)
+                .assign(D::D, C::X(A))
+                .a_sym(CALL)
+                .jump(C::Zero, J::JMP);
+        }
+        self.out.label(back);
+    }
+
+    // the body of `call`, with the return address in D, the function in R13
+    // and the number of arguments in R14
+    fn call_stub(&mut self) {
+        self.push();
+        for pointer in ["LCL", "ARG", "THIS", "THAT"] {
+            self.out.a_sym(pointer).assign(D::D, C::X(M));
+            self.push();
+        }
+        self.out
+            .a_sym("R14")
+            .assign(D::D, C::X(M))
+            .a(5)
+            .expect(LOADABLE)
+            .assign(D::D, C::DPlusX(A))
+            .a_sym("SP")
+            .assign(D::D, C::XMinusD(M))
+            .a_sym("ARG")
+            .assign(D::M, C::D)
+            .a_sym("SP")
+            .assign(D::D, C::X(M))
+            .a_sym("LCL")
+            .assign(D::M, C::D)
+            .a_sym("R13")
+            .assign(D::A, C::X(M))
+            .jump(C::Zero, J::JMP);
+    }
+
+    fn return_body(&mut self) {
+        // the frame, and the return address below it
+        self.out
+            .a_sym("LCL")
+            .assign(D::D, C::X(M))
+            .a_sym("R13")
+            .assign(D::M, C::D)
+            .a(5)
+            .expect(LOADABLE)
+            .assign(D::A, C::DMinusX(A))
+            .assign(D::D, C::X(M))
+            .a_sym("R14")
+            .assign(D::M, C::D);
+        // the return value goes where the arguments were
+        self.pop();
+        self.out
+            .a_sym("ARG")
+            .assign(D::A, C::X(M))
+            .assign(D::M, C::D)
+            .a_sym("ARG")
+            .assign(D::D, C::XPlusOne(M))
+            .a_sym("SP")
+            .assign(D::M, C::D);
+        for pointer in ["THAT", "THIS", "ARG", "LCL"] {
+            self.out
+                .a_sym("R13")
+                .assign(D::AM, C::XMinusOne(M))
+                .assign(D::D, C::X(M))
+                .a_sym(pointer)
+                .assign(D::M, C::D);
+        }
+        self.out
+            .a_sym("R14")
+            .assign(D::A, C::X(M))
+            .jump(C::Zero, J::JMP);
+    }
+
+    // compares the top two words of the stack, replacing them with the
+    // result. The shared subroutines are entered with their return address
+    // in D
+    fn compare_body(&mut self, op: Op, done: &str, shared: bool) {
+        if shared {
+            self.out.a_sym("R15").assign(D::M, C::D);
+        }
+        self.pop();
+        if op == Op::Eq {
+            self.out
+                .assign(D::A, C::XMinusOne(A))
+                .assign(D::D, C::XMinusD(M))
+                .assign(D::M, C::Neg1)
+                .a_sym(done)
+                .jump(C::D, J::JEQ)
+                .a_sym("SP")
+                .assign(D::A, C::XMinusOne(M))
+                .assign(D::M, C::Zero)
+                .label(done);
+        } else {
+            self.order(op, done);
+        }
+        if shared {
+            self.out
+                .a_sym("R15")
+                .assign(D::A, C::X(M))
+                .jump(C::Zero, J::JMP);
+        }
+    }
+
+    // `gt` or `lt` of x, below the top of the stack, and y, already popped
+    // into D. x - y overflows when they have different signs, so then the
+    // signs alone decide, and only numbers of the same sign are subtracted
+    fn order(&mut self, op: Op, done: &str) {
+        let label = |kind: &str| format!("{}.{}", done, kind);
+        let (negative, same, yes, no) = (label("neg"), label("same"), label("yes"), label("no"));
+        // x < 0 <= y or y < 0 <= x
+        let (x_negative, x_positive) = if op == Op::Lt {
+            (&yes, &no)
+        } else {
+            (&no, &yes)
+        };
+        self.out
+            .a_sym("R13")
+            .assign(D::M, C::D)
+            .a_sym(&negative)
+            .jump(C::D, J::JLT)
+            .a_sym("SP")
+            .assign(D::A, C::XMinusOne(M))
+            .assign(D::D, C::X(M))
+            .a_sym(x_negative)
+            .jump(C::D, J::JLT)
+            .a_sym(&same)
+            .jump(C::Zero, J::JMP)
+            .label(&negative)
+            .a_sym("SP")
+            .assign(D::A, C::XMinusOne(M))
+            .assign(D::D, C::X(M))
+            .a_sym(x_positive)
+            .jump(C::D, J::JGE)
+            .label(&same)
+            .a_sym("R13")
+            .assign(D::D, C::X(M))
+            .a_sym("SP")
+            .assign(D::A, C::XMinusOne(M))
+            .assign(D::D, C::XMinusD(M))
+            .a_sym(&yes)
+            .jump(C::D, jump(op))
+            .label(&no)
+            .a_sym("SP")
+            .assign(D::A, C::XMinusOne(M))
+            .assign(D::M, C::Zero)
+            .a_sym(done)
+            .jump(C::Zero, J::JMP)
+            .label(&yes)
+            .a_sym("SP")
+            .assign(D::A, C::XMinusOne(M))
+            .assign(D::M, C::Neg1)
+            .label(done);
+    }
+
+    fn arithmetic(&mut self, op: Op) {
+        match op {
+            Op::Neg | Op::Not => {
+                let comp = if op == Op::Neg {
+                    C::NegX(M)
+                } else {
+                    C::NotX(M)
+                };
+                self.out
+                    .a_sym("SP")
+                    .assign(D::A, C::XMinusOne(M))
+                    .assign(D::M, comp);
+            }
+            Op::Add | Op::Sub | Op::And | Op::Or => {
+                let comp = match op {
+                    Op::Add => C::DPlusX(M),
+                    Op::Sub => C::XMinusD(M),
+                    Op::And => C::DAndX(M),
+                    _ => C::DOrX(M),
+                };
+                self.pop();
+                self.out.assign(D::A, C::XMinusOne(A)).assign(D::M, comp);
+            }
+            Op::Eq | Op::Gt | Op::Lt => {
+                if self.options.inline {
+                    let done = self.label("cmp");
+                    self.compare_body(op, &done, false);
+                } else {
+                    let back = self.label("cmp");
+                    self.out
+                        .a_sym(&back)
+                        .assign(D::D, C::X(A))
+                        .a_sym(comparison(op))
+                        .jump(C::Zero, J::JMP)
+                        .label(back);
+                }
+            }
+        }
+    }
+
+    fn command(&mut self, line: &VmLine) {
+        if let Command::Function(..) = line.command {
+            let banner = format!(
+                "{}    ({}:{})",
+                line.command,
+                self.program.files[line.file].path.display(),
+                line.number
+            );
+            self.banner(&banner);
+        } else {
+            self.note(&line.command.to_string());
+        }
+        match &line.command {
+            Command::Arithmetic(op) => self.arithmetic(*op),
+            Command::Push(Segment::Constant, value) => {
+                self.out.a(*value).expect(LOADABLE).assign(D::D, C::X(A));
+                self.push();
+            }
+            Command::Push(segment, index) => {
+                match self.fixed(*segment, *index, line) {
+                    Some(symbol) => {
+                        self.out.a_sym(symbol).assign(D::D, C::X(M));
+                    }
+                    None => {
+                        self.out
+                            .a(*index)
+                            .expect(LOADABLE)
+                            .assign(D::D, C::X(A))
+                            .a_sym(Self::pointer(*segment))
+                            .assign(D::A, C::DPlusX(M))
+                            .assign(D::D, C::X(M));
+                    }
+                }
+                self.push();
+            }
+            Command::Pop(segment, index) => match self.fixed(*segment, *index, line) {
+                Some(symbol) => {
+                    self.pop();
+                    self.out.a_sym(symbol).assign(D::M, C::D);
+                }
+                None => {
+                    self.out
+                        .a(*index)
+                        .expect(LOADABLE)
+                        .assign(D::D, C::X(A))
+                        .a_sym(Self::pointer(*segment))
+                        .assign(D::D, C::DPlusX(M))
+                        .a_sym("R13")
+                        .assign(D::M, C::D);
+                    self.pop();
+                    self.out
+                        .a_sym("R13")
+                        .assign(D::A, C::X(M))
+                        .assign(D::M, C::D);
+                }
+            },
+            Command::Label(label) => {
+                self.out.label(format!("{}${}", self.function, label));
+            }
+            Command::Goto(label) => {
+                self.out
+                    .a_sym(format!("{}${}", self.function, label))
+                    .jump(C::Zero, J::JMP);
+            }
+            Command::IfGoto(label) => {
+                self.pop();
+                self.out
+                    .a_sym(format!("{}${}", self.function, label))
+                    .jump(C::D, J::JNE);
+            }
+            Command::Function(name, locals) => {
+                self.function = name.clone();
+                self.labels = 0;
+                self.out.label(name);
+                for _ in 0..*locals {
+                    self.out
+                        .a_sym("SP")
+                        .assign(D::AM, C::XPlusOne(M))
+                        .assign(D::A, C::XMinusOne(A))
+                        .assign(D::M, C::Zero);
+                }
+            }
+            Command::Call(function, args) => self.call(function, *args),
+            Command::Asm(code) => {
+                let line = code.parse().expect("asm is checked when it's loaded");
+                match line {
+                    HackLine::Label(label) => {
+                        self.out.label(format!("{}$asm.{}", self.function, label));
+                    }
+                    // the program's loaded, so any other symbol is one of
+                    // this function's labels
+                    HackLine::ALocation(symbol) if !vm::asm_builtin(&symbol) => {
+                        self.out.a_sym(format!("{}$asm.{}", self.function, symbol));
+                    }
+                    line => {
+                        self.out.line(line);
+                    }
+                }
+            }
+            Command::Return => {
+                if self.options.inline {
+                    self.return_body();
+                } else {
+                    self.out.a_sym(RETURN).jump(C::Zero, J::JMP);
+                }
+            }
+        }
+    }
+
+    fn stubs(&mut self) {
+        self.banner("the end of the program");
+        self.out.label(END).a_sym(END).jump(C::Zero, J::JMP);
+        self.banner("call: return address in D, function in R13, argument count in R14");
+        self.out.label(CALL);
+        self.call_stub();
+        self.banner("return");
+        self.out.label(RETURN);
+        self.return_body();
+        for op in [Op::Eq, Op::Gt, Op::Lt] {
+            let name = comparison(op);
+            self.banner(&format!("{}: return address in D", op.name()));
+            self.out.label(name);
+            self.compare_body(op, &format!("{}$true", name), true);
+        }
+    }
+}
+
+// translates a VM program into Hack assembly. Programs with a `Sys.init` get
+// the standard bootstrap code, which sets up the stack and calls it
+pub fn translate(program: &Program, options: Options) -> Builder {
+    let mut translator = Translator {
+        program,
+        options,
+        out: Builder::new(),
+        function: String::new(),
+        labels: 0,
+    };
+    if program.functions.contains_key(vm::ENTRY) {
+        translator.banner("bootstrap: set up the stack and call Sys.init");
+        translator
+            .out
+            .a(vm::STACK)
+            .expect(LOADABLE)
+            .assign(D::D, C::X(A))
+            .a_sym("SP")
+            .assign(D::M, C::D);
+        translator.call(vm::ENTRY, 0);
+    }
+    for line in &program.lines {
+        translator.command(line);
+    }
+    if !options.inline {
+        translator.stubs();
+    }
+    translator.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Cpu;
+    use crate::vm::Vm;
+    use std::path::PathBuf;
+
+    const SYS: &str = "\
+function Sys.init 0
+push constant 6
+call Main.factorial 1
+pop static 0
+push constant 3
+push constant 5
+lt
+push constant 3
+push constant 5
+gt
+push constant 4
+push constant 4
+eq
+pop temp 2
+pop temp 1
+pop temp 0
+push constant 3000
+pop pointer 1
+push constant 9
+pop that 2
+label END
+goto END
+";
+    const MAIN: &str = "\
+function Main.factorial 2
+push argument 0
+push constant 2
+lt
+if-goto BASE
+push argument 0
+push constant 1
+sub
+call Main.factorial 1
+pop local 0
+label MULTIPLY
+push local 1
+push local 0
+add
+pop local 1
+push argument 0
+push constant 1
+sub
+pop argument 0
+push argument 0
+if-goto MULTIPLY
+push local 1
+return
+label BASE
+push constant 1
+return
+";
+
+    fn program() -> Program {
+        Program::new(&[
+            (PathBuf::from("Main.vm"), MAIN.to_owned()),
+            (PathBuf::from("Sys.vm"), SYS.to_owned()),
+        ])
+        .unwrap()
+    }
+
+    // assembles and runs translated code until it halts
+    fn run(asm: &Builder) -> Cpu {
+        let mut cpu = Cpu::new(&crate::emulator::load(asm.to_hack().as_bytes()).unwrap());
+        while !cpu.halted() {
+            assert!(cpu.cycles < 1_000_000, "didn't halt");
+            cpu.step();
+        }
+        cpu
+    }
+
+    #[test]
+    fn agrees_with_the_vm() {
+        let mut vm = Vm::new(program());
+        while !vm.halted() {
+            vm.step();
+        }
+        for inline in [false, true] {
+            let cpu = run(&translate(
+                &program(),
+                Options {
+                    inline,
+                    ..Options::default()
+                },
+            ));
+            // static 0 of Sys is the assembler's first variable
+            assert_eq!(cpu.ram[16], 720);
+            assert_eq!(vm.segment_address(Segment::Static, 0, 1), Some(16));
+            assert_eq!(cpu.ram[5..8], vm.ram[5..8]);
+            assert_eq!(cpu.ram[5..8], [0xFFFF, 0, 0xFFFF]);
+            assert_eq!(cpu.ram[0], vm.ram[0]);
+            assert_eq!((cpu.ram[3002], vm.ram[3002]), (9, 9));
+        }
+    }
+
+    #[test]
+    fn compares_without_overflow() {
+        // each of these is wrong if x - y is taken at face value
+        let source = "\
+function Sys.init 0
+push constant 32767
+push constant 1
+neg
+lt
+pop static 0
+push constant 32767
+push constant 1
+neg
+gt
+pop static 1
+push constant 2
+neg
+push constant 32767
+gt
+pop static 2
+push constant 32767
+neg
+push constant 5
+lt
+pop static 3
+push constant 3
+push constant 7
+lt
+pop static 4
+push constant 3
+neg
+push constant 7
+neg
+gt
+pop static 5
+label END
+goto END
+";
+        let program = Program::new(&[(PathBuf::from("Sys.vm"), source.to_owned())]).unwrap();
+        let mut vm = Vm::new(program.clone());
+        while !vm.halted() {
+            vm.step();
+        }
+        let expected = [0, 0xFFFF, 0, 0xFFFF, 0xFFFF, 0xFFFF];
+        assert_eq!(vm.ram[16..22], expected);
+        for inline in [false, true] {
+            let cpu = run(&translate(
+                &program,
+                Options {
+                    inline,
+                    ..Options::default()
+                },
+            ));
+            assert_eq!(cpu.ram[16..22], expected);
+        }
+    }
+
+    #[test]
+    fn stubs_are_smaller() {
+        let size = |options| translate(&program(), options).instructions();
+        let inline = Options {
+            inline: true,
+            ..Options::default()
+        };
+        assert!(size(Options::default()) < size(inline));
+    }
+
+    #[test]
+    fn statics_match_the_vm() {
+        let program = Program::new(&[
+            (
+                PathBuf::from("A.vm"),
+                "push static 3\npop static 0\n".to_owned(),
+            ),
+            (
+                PathBuf::from("B.vm"),
+                "push static 0\npop static 3\npush static 1\n".to_owned(),
+            ),
+        ])
+        .unwrap();
+        let vm = Vm::new(program.clone());
+        let asm = translate(&program, Options::default());
+        let code: Vec<_> = asm.code().cloned().collect();
+        let symbols = crate::symbols(&code);
+        for (file, name) in [(0, "A"), (1, "B")] {
+            for (index, address) in program.files[file].statics.iter().enumerate() {
+                let symbol = format!("{}.{}", name, index);
+                assert_eq!(symbols.variables.get(&symbol).copied(), *address);
+                assert_eq!(vm.static_address(&symbol), *address);
+            }
+        }
+        assert_eq!(symbols.variables.len(), 5);
+    }
+
+    #[test]
+    fn annotations_are_only_comments() {
+        let annotated = Options {
+            annotate: true,
+            ..Options::default()
+        };
+        let asm = translate(&program(), annotated);
+        let text = asm.to_asm();
+        assert!(text.contains("\n// function Main.factorial 2    (Main.vm:1)\n"));
+        assert!(text.contains("\n// push argument 0\n"));
+        let plain = translate(&program(), Options::default());
+        assert!(asm.code().eq(plain.code()));
+    }
+}
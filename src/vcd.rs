@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::debug::{parse_value, Symbols};
+use crate::error::HackError;
+use crate::run::Machine;
+
+// the registers recorded when `--signals` doesn't say otherwise, as far as
+// the machine has them
+pub const REGISTERS: [&str; 3] = ["A", "D", "PC"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    Register(String),
+    Ram(u16),
+}
+
+// something to record the value of on every cycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signal {
+    // as it's shown in the waveform viewer
+    pub name: String,
+    source: Source,
+}
+
+impl Signal {
+    // a register of `machine`, `RAM[N]`, or a symbol for a RAM address
+    pub fn parse<M: Machine>(name: &str, machine: &M, symbols: &Symbols) -> Result<Self, String> {
+        if machine.register(name).is_some() {
+            return Ok(Self {
+                name: name.to_owned(),
+                source: Source::Register(name.to_owned()),
+            });
+        }
+        let address = match name.strip_prefix("RAM[").and_then(|n| n.strip_suffix(']')) {
+            Some(address) => parse_value(address)?,
+            None => symbols
+                .ram(name)
+                .map_err(|_| format!("unknown signal `{}`", name))?,
+        };
+        // brackets would read as a bit range
+        Ok(Self {
+            name: name.replace('[', "_").replace(']', ""),
+            source: Source::Ram(address),
+        })
+    }
+
+    fn value<M: Machine>(&self, machine: &M) -> u16 {
+        match &self.source {
+            Source::Register(name) => machine.register(name).unwrap_or_default(),
+            Source::Ram(address) => machine.read(*address),
+        }
+    }
+}
+
+// the short name VCD refers to the `index`th signal by, in printable ASCII
+fn identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+// a Value Change Dump of the machine as it runs, for GTKWave and the like:
+// each signal is a 16-bit bus, and time counts cycles. Only changes are
+// written, so a long run of a quiet program stays small
+pub struct Vcd<W: Write = BufWriter<File>> {
+    signals: Vec<Signal>,
+    values: Vec<Option<u16>>,
+    out: W,
+    path: PathBuf,
+}
+
+impl Vcd {
+    pub fn create(path: &Path, signals: Vec<Signal>) -> Result<Self, HackError> {
+        let file = File::create(path).map_err(HackError::io(path))?;
+        Vcd::new(BufWriter::new(file), path, signals).map_err(HackError::io(path))
+    }
+}
+
+impl<W: Write> Vcd<W> {
+    pub fn new(mut out: W, path: &Path, signals: Vec<Signal>) -> io::Result<Self> {
+        writeln!(out, "$version hack $end")?;
+        writeln!(out, "$timescale 1 ns $end")?;
+        writeln!(out, "$scope module hack $end")?;
+        for (index, signal) in signals.iter().enumerate() {
+            writeln!(
+                out,
+                "$var wire 16 {} {} $end",
+                identifier(index),
+                signal.name
+            )?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        Ok(Self {
+            values: vec![None; signals.len()],
+            signals,
+            out,
+            path: path.to_owned(),
+        })
+    }
+
+    // writes whatever's changed since last time
+    pub fn sample<M: Machine>(&mut self, machine: &M) -> Result<(), HackError> {
+        let mut stamped = false;
+        for (index, signal) in self.signals.iter().enumerate() {
+            let value = signal.value(machine);
+            if self.values[index] == Some(value) {
+                continue;
+            }
+            if !stamped {
+                writeln!(self.out, "#{}", machine.cycles()).map_err(HackError::io(&self.path))?;
+                stamped = true;
+            }
+            self.values[index] = Some(value);
+            writeln!(self.out, "b{:b} {}", value, identifier(index))
+                .map_err(HackError::io(&self.path))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), HackError> {
+        self.out.flush().map_err(HackError::io(&self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Cpu;
+
+    #[test]
+    fn records_changes() {
+        // @2, D=A, @16, M=D
+        let mut cpu = Cpu::new(&[2, 0xEC10, 16, 0xE308]);
+        let symbols = Symbols::default();
+        let signals = ["D", "RAM[16]", "R0"]
+            .iter()
+            .map(|name| Signal::parse(name, &cpu, &symbols).unwrap())
+            .collect();
+        assert!(Signal::parse("nope", &cpu, &symbols).is_err());
+
+        let mut vcd = Vcd::new(Vec::new(), Path::new("test.vcd"), signals).unwrap();
+        vcd.sample(&cpu).unwrap();
+        for _ in 0..4 {
+            cpu.step();
+            vcd.sample(&cpu).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(vcd.out).unwrap(),
+            "$version hack $end\n\
+             $timescale 1 ns $end\n\
+             $scope module hack $end\n\
+             $var wire 16 ! D $end\n\
+             $var wire 16 \" RAM_16 $end\n\
+             $var wire 16 # R0 $end\n\
+             $upscope $end\n\
+             $enddefinitions $end\n\
+             #0\nb0 !\nb0 \"\nb0 #\n\
+             #2\nb10 !\n\
+             #4\nb10 \"\n"
+        );
+        assert_eq!(identifier(93), "~");
+        assert_eq!(identifier(94), "!!");
+    }
+}
@@ -0,0 +1,78 @@
+use crate::diagnostic::Diagnostic;
+use crate::disassemble::disassemble;
+use crate::predefined::SymbolSet;
+use crate::{SourceLine, SymbolTable};
+
+// how a program fared when assembled, disassembled and assembled again
+pub struct Report {
+    pub instructions: usize,
+    pub mismatches: Vec<Diagnostic>,
+}
+
+// checks the assembler and disassembler against each other: every word of
+// the program should disassemble to an instruction that assembles back to
+// the very same word
+pub fn verify(lines: &[SourceLine]) -> Report {
+    let mut table = SymbolTable::new(
+        SymbolSet::standard(),
+        lines.iter().map(|source| &source.line),
+    );
+    let mut report = Report {
+        instructions: 0,
+        mismatches: Vec::new(),
+    };
+    for source in lines {
+        let Some(word) = source.line.word(&mut table) else {
+            continue;
+        };
+        report.instructions += 1;
+        let message = match disassemble(word) {
+            // what comes out of the disassembler never refers to a symbol
+            Some(line) => match line.word_with(|_| 0) {
+                Some(again) if again == word => continue,
+                again => format!(
+                    "`{}` assembled to {:016b}, which disassembles to `{}` but reassembles \
+                     to {}",
+                    source.code(),
+                    word,
+                    line,
+                    again.map_or("nothing".to_owned(), |again| format!("{:016b}", again))
+                ),
+            },
+            None => format!(
+                "`{}` assembled to {:016b}, which doesn't disassemble",
+                source.code(),
+                word
+            ),
+        };
+        report.mismatches.push(
+            Diagnostic::error(message)
+                .at(source.text, source.code())
+                .on_line(source.number, source.text),
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HackLine;
+
+    #[test]
+    fn rect_round_trips() {
+        let source = std::fs::read_to_string("resources/Rect.asm").unwrap();
+        let lines = crate::parse_source(&source).unwrap();
+        let report = verify(&lines);
+        assert_eq!(report.instructions, 25);
+        assert!(report.mismatches.is_empty());
+
+        // nothing assembles to a label, so there's nothing to check
+        let label = [SourceLine {
+            number: 1,
+            text: "(END)",
+            line: HackLine::parse("(END)").unwrap(),
+        }];
+        assert_eq!(verify(&label).instructions, 0);
+    }
+}
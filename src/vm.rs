@@ -0,0 +1,1043 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::diagnostic::Diagnostic;
+use crate::emulator::{Access, RAM_SIZE};
+use crate::error::HackError;
+use crate::os::{self, Os, Outcome};
+use crate::split_comment;
+use crate::HackLine;
+
+// the pointers the VM keeps in the bottom of RAM, and where the fixed
+// segments live
+pub const SP: u16 = 0;
+pub const LCL: u16 = 1;
+pub const ARG: u16 = 2;
+pub const THIS: u16 = 3;
+pub const THAT: u16 = 4;
+pub const TEMP: u16 = 5;
+pub const STATIC: u16 = 16;
+pub const STACK: u16 = 256;
+
+// the function the standard bootstrap calls, if the program has one
+pub const ENTRY: &str = "Sys.init";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Argument,
+    Local,
+    Static,
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+impl Segment {
+    const ALL: [Segment; 8] = [
+        Segment::Argument,
+        Segment::Local,
+        Segment::Static,
+        Segment::Constant,
+        Segment::This,
+        Segment::That,
+        Segment::Pointer,
+        Segment::Temp,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Segment> {
+        Segment::ALL
+            .into_iter()
+            .find(|segment| segment.name() == name)
+    }
+
+    // the largest index the segment has: for the ones that aren't a fixed
+    // size, what translated code can load with an A-instruction
+    fn limit(self) -> u16 {
+        match self {
+            Segment::Pointer => 1,
+            Segment::Temp => 7,
+            _ => 32767,
+        }
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+impl Op {
+    const ALL: [Op; 9] = [
+        Op::Add,
+        Op::Sub,
+        Op::Neg,
+        Op::Eq,
+        Op::Gt,
+        Op::Lt,
+        Op::And,
+        Op::Or,
+        Op::Not,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Sub => "sub",
+            Op::Neg => "neg",
+            Op::Eq => "eq",
+            Op::Gt => "gt",
+            Op::Lt => "lt",
+            Op::And => "and",
+            Op::Or => "or",
+            Op::Not => "not",
+        }
+    }
+
+    fn unary(self) -> bool {
+        matches!(self, Op::Neg | Op::Not)
+    }
+
+    // true is all ones, false all zeroes
+    fn apply(self, x: u16, y: u16) -> u16 {
+        let truth = |condition: bool| if condition { 0xFFFF } else { 0 };
+        match self {
+            Op::Add => x.wrapping_add(y),
+            Op::Sub => x.wrapping_sub(y),
+            Op::Neg => x.wrapping_neg(),
+            Op::Eq => truth(x == y),
+            Op::Gt => truth((x as i16) > (y as i16)),
+            Op::Lt => truth((x as i16) < (y as i16)),
+            Op::And => x & y,
+            Op::Or => x | y,
+            Op::Not => !x,
+        }
+    }
+}
+
+// the most arguments a call can pass
+pub const MAX_ARGS: u16 = 32767 - 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Arithmetic(Op),
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Function(String, u16),
+    Call(String, u16),
+    Return,
+    // a Hack instruction or label, passed straight through to translated
+    // code. Its labels belong to the function, like `label`'s
+    Asm(String),
+}
+
+impl FromStr for Command {
+    type Err = Diagnostic;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let error = |token: &str, message: String| Diagnostic::error(message).at(line, token);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |word: &str| {
+            word.parse::<u16>()
+                .map_err(|_| error(word, format!("invalid number: {}", word)))
+        };
+
+        if let [word] = words[..] {
+            if let Some(op) = Op::ALL.iter().find(|op| op.name() == word) {
+                return Ok(Self::Arithmetic(*op));
+            }
+        }
+        match words[..] {
+            ["return"] => Ok(Self::Return),
+            ["asm", _, ..] => {
+                let code = line.trim().strip_prefix("asm").unwrap_or_default().trim();
+                // the instruction's errors point into it, rather than the line
+                let offset = code.as_ptr() as usize - line.as_ptr() as usize;
+                code.parse::<crate::HackLine>().map_err(|mut err| {
+                    err.span = err.span.start + offset..err.span.end + offset;
+                    err
+                })?;
+                Ok(Self::Asm(code.to_owned()))
+            }
+            [kind @ ("push" | "pop"), segment_word, index] => {
+                let segment = Segment::parse(segment_word).ok_or_else(|| {
+                    error(segment_word, format!("unknown segment: {}", segment_word))
+                })?;
+                let index_word = index;
+                let index = number(index)?;
+                if index > segment.limit() {
+                    Err(error(
+                        index_word,
+                        format!("{} only goes up to {}", segment, segment.limit()),
+                    ))?;
+                }
+                if kind == "push" {
+                    Ok(Self::Push(segment, index))
+                } else if segment == Segment::Constant {
+                    Err(error(segment_word, "can't pop to constant".to_owned()))
+                } else {
+                    Ok(Self::Pop(segment, index))
+                }
+            }
+            ["label", label] => Ok(Self::Label(label.to_owned())),
+            ["goto", label] => Ok(Self::Goto(label.to_owned())),
+            ["if-goto", label] => Ok(Self::IfGoto(label.to_owned())),
+            ["function", name, locals] => Ok(Self::Function(name.to_owned(), number(locals)?)),
+            ["call", name, args_word] => {
+                // translated calls load the argument count plus the five
+                // words of the frame with an A-instruction
+                let args = number(args_word)?;
+                if args > MAX_ARGS {
+                    Err(error(
+                        args_word,
+                        format!("calls can pass at most {} arguments", MAX_ARGS),
+                    ))?;
+                }
+                Ok(Self::Call(name.to_owned(), args))
+            }
+            _ => Err(error(
+                line.trim(),
+                format!("invalid VM command: {}", line.trim()),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Arithmetic(op) => f.write_str(op.name()),
+            Command::Push(segment, index) => write!(f, "push {} {}", segment, index),
+            Command::Pop(segment, index) => write!(f, "pop {} {}", segment, index),
+            Command::Label(label) => write!(f, "label {}", label),
+            Command::Goto(label) => write!(f, "goto {}", label),
+            Command::IfGoto(label) => write!(f, "if-goto {}", label),
+            Command::Function(name, locals) => write!(f, "function {} {}", name, locals),
+            Command::Call(name, args) => write!(f, "call {} {}", name, args),
+            Command::Return => f.write_str("return"),
+            Command::Asm(code) => write!(f, "asm {}", code),
+        }
+    }
+}
+
+// a command along with where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmLine {
+    pub command: Command,
+    // index into `Program::files`
+    pub file: usize,
+    pub number: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmFile {
+    pub path: PathBuf,
+    // the address of each of the file's statics, by index, or `None` for
+    // any the file never mentions
+    pub statics: Vec<Option<u16>>,
+}
+
+// where a call to a function the program doesn't define, but that the
+// emulator has built in, goes
+const BUILTIN: usize = usize::MAX;
+
+// every command of a (possibly multi-file) VM program, with its jumps and
+// calls worked out ahead of time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub files: Vec<VmFile>,
+    pub lines: Vec<VmLine>,
+    // where each `goto`, `if-goto` and `call` goes
+    targets: Vec<usize>,
+    pub functions: HashMap<String, usize>,
+}
+
+impl Program {
+    // parses and links the text of each file
+    pub fn new(sources: &[(PathBuf, String)]) -> Result<Self, HackError> {
+        let mut files = Vec::new();
+        let mut lines = Vec::new();
+        for (file, (path, text)) in sources.iter().enumerate() {
+            for (number, text) in (1..).zip(text.lines()) {
+                let (code, _) = split_comment(text);
+                if code.trim().is_empty() {
+                    continue;
+                }
+                let command = code.parse().map_err(|err: Diagnostic| {
+                    HackError::new(path, err.on_line(number, text).into())
+                })?;
+                lines.push(VmLine {
+                    command,
+                    file,
+                    number,
+                    text: text.to_owned(),
+                });
+            }
+            files.push(VmFile {
+                path: path.clone(),
+                statics: Vec::new(),
+            });
+        }
+
+        // statics are given addresses in the order they're first mentioned,
+        // just as the assembler allocates the `File.i` variables translated
+        // code uses for them, so that both put every static in the same place
+        let mut next = STATIC;
+        for line in &lines {
+            let (Command::Push(Segment::Static, index) | Command::Pop(Segment::Static, index)) =
+                line.command
+            else {
+                continue;
+            };
+            let VmFile { path, statics } = &mut files[line.file];
+            if statics.len() <= index as usize {
+                statics.resize(index as usize + 1, None);
+            }
+            if statics[index as usize].is_none() {
+                if next == STACK {
+                    Err(HackError::new(
+                        path,
+                        Diagnostic::error(format!(
+                            "too many statics: there's only room for {}",
+                            STACK - STATIC
+                        ))
+                        .on_line(line.number, &line.text)
+                        .into(),
+                    ))?;
+                }
+                statics[index as usize] = Some(next);
+                next += 1;
+            }
+        }
+
+        // labels belong to the function they're in, so the same name can be
+        // used in different functions
+        let mut functions = HashMap::new();
+        let mut labels = HashMap::new();
+        let mut asm_labels = HashSet::new();
+        let mut function = "";
+        let error = |line: &VmLine, message: String| {
+            let token = line.text.trim();
+            HackError::new(
+                &files[line.file].path,
+                Diagnostic::error(message)
+                    .at(&line.text, token)
+                    .on_line(line.number, &line.text)
+                    .into(),
+            )
+        };
+        for (index, line) in lines.iter().enumerate() {
+            match &line.command {
+                Command::Function(name, _) => {
+                    if functions.insert(name.clone(), index).is_some() {
+                        Err(error(line, format!("function {} defined twice", name)))?;
+                    }
+                    function = name;
+                }
+                Command::Label(label)
+                    if labels.insert((function, label.as_str()), index).is_some() =>
+                {
+                    Err(error(line, format!("label {} defined twice", label)))?;
+                }
+                Command::Asm(code) => {
+                    if let Some(label) = asm_label(code) {
+                        if !asm_labels.insert((function, label)) {
+                            Err(error(line, format!("label {} defined twice", label)))?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // asm can't name variables, which would take RAM statics are using,
+        // or labels outside its own function, which translated code calls
+        // something else
+        let mut function = "";
+        for line in &lines {
+            match &line.command {
+                Command::Function(name, _) => function = name,
+                Command::Asm(code) => {
+                    let Some(symbol) = asm_symbol(code) else {
+                        continue;
+                    };
+                    if !asm_builtin(symbol) && !asm_labels.contains(&(function, symbol)) {
+                        Err(error(
+                            line,
+                            format!(
+                                "asm can only use built-in symbols and its function's \
+                                 own labels, not `{}`",
+                                symbol
+                            ),
+                        ))?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // whether the program has its own strings, which ours can't read
+        let strings = functions.keys().any(|name| name.starts_with("String."));
+        let mut targets = vec![0; lines.len()];
+        let mut function = "";
+        for (index, line) in lines.iter().enumerate() {
+            targets[index] = match &line.command {
+                Command::Function(name, _) => {
+                    function = name;
+                    continue;
+                }
+                Command::Goto(label) | Command::IfGoto(label) => *labels
+                    .get(&(function, label.as_str()))
+                    .ok_or_else(|| error(line, format!("undefined label: {}", label)))?,
+                Command::Call(name, args) => match functions.get(name) {
+                    Some(&target) => target,
+                    // the OS functions we have built in can go missing
+                    None if os::needs_strings(name) && strings => Err(error(
+                        line,
+                        format!(
+                            "undefined function: {} (the built-in one only works with the \
+                             built-in String class)",
+                            name
+                        ),
+                    ))?,
+                    None if os::arity(name) == Some(*args) => BUILTIN,
+                    None => Err(error(line, format!("undefined function: {}", name)))?,
+                },
+                _ => continue,
+            };
+        }
+
+        Ok(Self {
+            files,
+            lines,
+            targets,
+            functions,
+        })
+    }
+
+    // the name and number of locals of the function whose `function`
+    // command is at `index`
+    pub fn function(&self, index: usize) -> (&str, u16) {
+        match &self.lines[index].command {
+            Command::Function(name, locals) => (name, *locals),
+            _ => ("", 0),
+        }
+    }
+
+    // the functions the program calls but leaves to the emulator
+    pub fn builtins(&self) -> impl Iterator<Item = &VmLine> {
+        self.lines
+            .iter()
+            .zip(&self.targets)
+            .filter(|(_, target)| **target == BUILTIN)
+            .map(|(line, _)| line)
+    }
+
+    // whether any of the program is Hack assembly, which only runs once
+    // it's been translated
+    pub fn has_asm(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|line| matches!(line.command, Command::Asm(_)))
+    }
+
+    // what a file's statics are called, e.g. `Main` for `Main.vm`
+    pub fn file_name(&self, file: usize) -> String {
+        self.files[file]
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+// the label an `asm` instruction defines, if it's a label, read the way the
+// assembler reads it, so `( LOOP )` is `LOOP` too
+pub fn asm_label(code: &str) -> Option<&str> {
+    match HackLine::parse(code) {
+        Ok(HackLine::Label(Cow::Borrowed(label))) => Some(label),
+        _ => None,
+    }
+}
+
+// the symbol an `asm` A-instruction uses, if it uses one
+pub fn asm_symbol(code: &str) -> Option<&str> {
+    match HackLine::parse(code) {
+        Ok(HackLine::ALocation(Cow::Borrowed(symbol))) => Some(symbol),
+        _ => None,
+    }
+}
+
+// whether an `asm` instruction's `@symbol` means the same thing wherever
+// it is: a number, or one of the assembler's predefined symbols
+pub fn asm_builtin(symbol: &str) -> bool {
+    symbol.parse::<u16>().is_ok()
+        || crate::PREDEFINED_SYMBOLS
+            .iter()
+            .any(|(name, _)| *name == symbol)
+}
+
+// what we know about a call that RAM doesn't tell us: which function was
+// called and with how many arguments, and so how big its segments are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    // the index of the function's `function` command
+    pub function: usize,
+    pub args: u16,
+    // the command to carry on from once it returns
+    pub return_to: usize,
+}
+
+// a `.vm` file, or a directory holding the files of a program
+pub fn is_vm(path: &Path) -> bool {
+    path.is_dir() || path.extension().is_some_and(|ext| ext == "vm")
+}
+
+// reads a `.vm` file, or every `.vm` file in a directory (sorted, so static
+// addresses don't depend on the order the filesystem lists them in)
+pub fn load(path: &Path) -> Result<Program, HackError> {
+    let mut paths = Vec::new();
+    if path.is_dir() {
+        for entry in fs::read_dir(path).map_err(HackError::io(path))? {
+            let entry = entry.map_err(HackError::io(path))?.path();
+            if entry.is_file() && entry.extension().is_some_and(|ext| ext == "vm") {
+                paths.push(entry);
+            }
+        }
+        paths.sort();
+        if paths.is_empty() {
+            Err(HackError::Usage(format!(
+                "no .vm files found in {}",
+                path.display()
+            )))?;
+        }
+    } else {
+        paths.push(path.to_owned());
+    }
+
+    let mut sources = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path).map_err(HackError::io(&path))?;
+        sources.push((path, text));
+    }
+    Program::new(&sources)
+}
+
+// runs VM commands directly, the way the course's VM emulator does, against
+// RAM laid out just as translated code would lay it out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vm {
+    pub program: Rc<Program>,
+    pub ram: Vec<u16>,
+    // the index of the next command to run
+    pub pc: usize,
+    pub cycles: u64,
+    // the calls in progress, innermost last
+    pub frames: Vec<Frame>,
+    pub os: Os,
+}
+
+impl Vm {
+    // sets up the stack and, if there's a `Sys.init`, calls it as the
+    // standard bootstrap code would. Returning from it ends the program
+    pub fn new(program: Program) -> Self {
+        let entry = program.functions.get(ENTRY).copied();
+        let mut vm = Self {
+            program: Rc::new(program),
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            cycles: 0,
+            frames: Vec::new(),
+            os: Os::default(),
+        };
+        vm.write(SP, STACK);
+        if let Some(entry) = entry {
+            let end = vm.program.lines.len();
+            vm.call(end, entry, 0);
+        }
+        vm
+    }
+
+    fn address(value: u16) -> usize {
+        value as usize % RAM_SIZE
+    }
+
+    pub fn read(&self, address: u16) -> u16 {
+        self.ram[Self::address(address)]
+    }
+
+    pub fn write(&mut self, address: u16, value: u16) {
+        self.ram[Self::address(address)] = value;
+    }
+
+    fn push(&mut self, value: u16) {
+        let sp = self.read(SP);
+        self.write(sp, value);
+        self.write(SP, sp.wrapping_add(1));
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.read(SP).wrapping_sub(1);
+        self.write(SP, sp);
+        self.read(sp)
+    }
+
+    // the RAM address of a segment entry: `constant` doesn't have any, and
+    // nor do statics the program never uses
+    pub fn segment_address(&self, segment: Segment, index: u16, file: usize) -> Option<u16> {
+        let base = match segment {
+            Segment::Argument => self.read(ARG),
+            Segment::Local => self.read(LCL),
+            Segment::This => self.read(THIS),
+            Segment::That => self.read(THAT),
+            Segment::Pointer => THIS,
+            Segment::Temp => TEMP,
+            Segment::Static => {
+                return self.program.files[file]
+                    .statics
+                    .get(index as usize)
+                    .copied()
+                    .flatten()
+            }
+            Segment::Constant => return None,
+        };
+        Some(base.wrapping_add(index))
+    }
+
+    // the names of the functions being called, outermost first
+    pub fn backtrace(&self) -> Vec<String> {
+        self.frames
+            .iter()
+            .map(|frame| self.program.function(frame.function).0.to_owned())
+            .collect()
+    }
+
+    // the call in progress, if we're in a function we know how we got to
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    // how many entries a segment has right now, if we know
+    pub fn segment_len(&self, segment: Segment, file: usize) -> Option<u16> {
+        match segment {
+            Segment::Argument => self.frame().map(|frame| frame.args),
+            Segment::Local => self
+                .frame()
+                .map(|frame| self.program.function(frame.function).1),
+            Segment::Static => Some(self.program.files[file].statics.len() as u16),
+            Segment::Pointer => Some(2),
+            Segment::Temp => Some(8),
+            Segment::This | Segment::That | Segment::Constant => None,
+        }
+    }
+
+    // where the current function's working stack starts: just past its
+    // locals, or at the bottom of the stack outside of any function
+    pub fn stack_base(&self) -> u16 {
+        match self.frame() {
+            Some(frame) => self
+                .read(LCL)
+                .wrapping_add(self.program.function(frame.function).1),
+            None => STACK,
+        }
+    }
+
+    // the name of a static variable, e.g. `Main.3`
+    pub fn static_name(&self, address: u16) -> Option<String> {
+        self.program
+            .files
+            .iter()
+            .enumerate()
+            .find_map(|(file, entry)| {
+                let index = entry.statics.iter().position(|&a| a == Some(address))?;
+                Some(format!("{}.{}", self.program.file_name(file), index))
+            })
+    }
+
+    // the address of a static variable named as by `static_name`
+    pub fn static_address(&self, name: &str) -> Option<u16> {
+        let (stem, index) = name.rsplit_once('.')?;
+        let index: u16 = index.parse().ok()?;
+        let file =
+            (0..self.program.files.len()).find(|&file| self.program.file_name(file) == stem)?;
+        self.program.files[file]
+            .statics
+            .get(index as usize)
+            .copied()
+            .flatten()
+    }
+
+    fn call(&mut self, return_to: usize, function: usize, args: u16) {
+        self.push(return_to as u16);
+        for pointer in [LCL, ARG, THIS, THAT] {
+            self.push(self.read(pointer));
+        }
+        let sp = self.read(SP);
+        self.write(ARG, sp.wrapping_sub(5).wrapping_sub(args));
+        self.write(LCL, sp);
+        self.frames.push(Frame {
+            function,
+            args,
+            return_to,
+        });
+        self.pc = function;
+    }
+
+    pub fn line(&self) -> Option<&VmLine> {
+        self.program.lines.get(self.pc)
+    }
+
+    // whether we've returned from `Sys.init`, or run past the last command
+    pub fn finished(&self) -> bool {
+        self.pc >= self.program.lines.len()
+    }
+
+    // whether the program is stuck in a `label END, goto END` loop, or has
+    // called `Sys.halt`
+    pub fn halted(&self) -> bool {
+        if self.os.halted {
+            return true;
+        }
+        let lines = &self.program.lines;
+        let Some(goto) = (self.pc..lines.len())
+            .find(|&index| !matches!(lines[index].command, Command::Label(_)))
+        else {
+            return false;
+        };
+        let target = self.program.targets[goto];
+        matches!(lines[goto].command, Command::Goto(_))
+            && target <= self.pc.min(goto)
+            && lines[target..goto]
+                .iter()
+                .all(|line| matches!(line.command, Command::Label(_)))
+    }
+
+    // a line describing the command about to run, for tracing
+    pub fn trace(&self, out: &mut impl Write) -> io::Result<()> {
+        let command = self
+            .line()
+            .map_or_else(String::new, |line| line.command.to_string());
+        writeln!(
+            out,
+            "{:>10} {:>5} SP={:<6} {}",
+            self.cycles + 1,
+            self.pc,
+            self.read(SP),
+            command
+        )
+    }
+
+    // runs a single command, reporting which segment entry it touched: the
+    // stack traffic every command makes is left out
+    pub fn step(&mut self) -> Access {
+        let mut access = Access::default();
+        let program = Rc::clone(&self.program);
+        let Some(line) = program.lines.get(self.pc) else {
+            return access;
+        };
+        self.cycles += 1;
+        let mut next = self.pc + 1;
+
+        match line.command {
+            Command::Arithmetic(op) => {
+                let y = self.pop();
+                let x = if op.unary() { 0 } else { self.pop() };
+                let out = if op.unary() {
+                    op.apply(y, 0)
+                } else {
+                    op.apply(x, y)
+                };
+                self.push(out);
+            }
+            Command::Push(Segment::Constant, value) => self.push(value),
+            Command::Push(segment, index) => {
+                let address = self
+                    .segment_address(segment, index, line.file)
+                    .expect("statics are allocated when the program is loaded");
+                access.read = Some(address);
+                self.push(self.read(address));
+            }
+            Command::Pop(segment, index) => {
+                let address = self
+                    .segment_address(segment, index, line.file)
+                    .expect("statics are allocated when the program is loaded");
+                let value = self.pop();
+                access.write = Some((address, value));
+                self.write(address, value);
+            }
+            // only translated code can run these, so we don't get asked to
+            Command::Label(_) | Command::Asm(_) => {}
+            Command::Goto(_) => next = program.targets[self.pc],
+            Command::IfGoto(_) => {
+                if self.pop() != 0 {
+                    next = program.targets[self.pc];
+                }
+            }
+            Command::Function(_, locals) => {
+                for _ in 0..locals {
+                    self.push(0);
+                }
+            }
+            Command::Call(ref name, args) => {
+                let target = program.targets[self.pc];
+                let builtin = os::arity(name) == Some(args);
+                if target != BUILTIN && !(self.os.replace && builtin) {
+                    self.call(next, target, args);
+                    return access;
+                }
+                let sp = self.read(SP).wrapping_sub(args);
+                let values: Vec<u16> = (0..args).map(|i| self.read(sp.wrapping_add(i))).collect();
+                self.write(SP, sp);
+                let (outcome, native) = self.os.call(name, &values, &mut self.ram);
+                access = native;
+                match outcome {
+                    Outcome::Return(value) => self.push(value),
+                    // leave the arguments for the next try
+                    Outcome::Wait => {
+                        self.write(SP, sp.wrapping_add(args));
+                        next = self.pc;
+                    }
+                    Outcome::Halt => next = self.pc,
+                }
+            }
+            Command::Return => {
+                let frame = self.read(LCL);
+                let return_to = self.read(frame.wrapping_sub(5));
+                let value = self.pop();
+                let arg = self.read(ARG);
+                self.write(arg, value);
+                self.write(SP, arg.wrapping_add(1));
+                for (offset, pointer) in (1..).zip([THAT, THIS, ARG, LCL]) {
+                    self.write(pointer, self.read(frame.wrapping_sub(offset)));
+                }
+                self.frames.pop();
+                next = return_to as usize;
+            }
+        }
+        self.pc = next;
+        access
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vm(files: &[(&str, &str)]) -> Vm {
+        let sources: Vec<_> = files
+            .iter()
+            .map(|(path, text)| (PathBuf::from(path), text.to_string()))
+            .collect();
+        Vm::new(Program::new(&sources).unwrap())
+    }
+
+    fn run(vm: &mut Vm) {
+        for _ in 0..10_000 {
+            if vm.finished() || vm.halted() {
+                return;
+            }
+            vm.step();
+        }
+        panic!("didn't stop");
+    }
+
+    #[test]
+    fn stack_arithmetic() {
+        let mut vm = vm(&[(
+            "StackTest.vm",
+            "push constant 7\npush constant 8\nadd // 15\n\
+             push constant 3\nlt\npush constant 2\nneg\n\
+             pop temp 1\npush constant 1\npop pointer 1\npush constant 9\npop that 4\n",
+        )]);
+        run(&mut vm);
+        assert!(vm.finished());
+        assert_eq!(vm.read(SP), 257);
+        assert_eq!(vm.read(STACK), 0);
+        assert_eq!(vm.read(TEMP + 1), (-2i16) as u16);
+        assert_eq!(vm.read(THAT), 1);
+        assert_eq!(vm.read(5), 9);
+    }
+
+    #[test]
+    fn calls_and_statics() {
+        let main = "\
+function Sys.init 0
+push constant 4
+call Main.double 1
+pop static 2
+call Other.get 0
+pop static 0
+label END
+goto END
+";
+        let other = "\
+function Main.double 1
+push argument 0
+push argument 0
+add
+pop local 0
+push local 0
+return
+function Other.get 0
+push constant 3
+pop static 0
+push static 0
+return
+";
+        let mut vm = vm(&[("Main.vm", main), ("Other.vm", other)]);
+        run(&mut vm);
+        assert!(vm.halted() && !vm.finished());
+        // statics get addresses in the order they're mentioned
+        assert_eq!(&vm.ram[16..19], [8, 3, 3]);
+        // the bootstrap frame is all that's left on the stack
+        assert_eq!(vm.read(SP), STACK + 5);
+        assert_eq!(vm.backtrace(), ["Sys.init"]);
+        assert_eq!(vm.static_name(16).as_deref(), Some("Main.2"));
+        assert_eq!(vm.static_address("Other.0"), Some(18));
+        assert_eq!(vm.static_address("Main.1"), None);
+        assert_eq!(vm.static_address("Other.1"), None);
+    }
+
+    #[test]
+    fn runs_builtins() {
+        let main = "\
+function Sys.init 0
+push constant 7
+push constant 6
+call Math.multiply 2
+pop static 0
+push constant 2
+push constant 3
+call Main.max 2
+pop static 1
+push constant 0
+call Math.divide 2
+label END
+goto END
+function Main.max 0
+push constant 0
+return
+";
+        let program = |text: &str| vm(&[("Main.vm", text)]).program;
+        assert_eq!(program(main).builtins().count(), 2);
+        // our own functions win, unless we're told otherwise
+        let mut vm = vm(&[("Main.vm", &main.replace("Main.max", "Math.max"))]);
+        run(&mut vm);
+        assert_eq!(&vm.ram[16..18], [42, 0]);
+        let mut vm = Vm::new((*vm.program).clone());
+        vm.os.replace = true;
+        run(&mut vm);
+        assert_eq!(&vm.ram[16..18], [42, 3]);
+        // dividing by zero stops the program, as the OS would
+        assert!(vm.halted() && vm.line().unwrap().text == "call Math.divide 2");
+        assert_eq!(
+            vm.os.error.as_deref(),
+            Some("Math.divide: division by zero (error 3)")
+        );
+    }
+
+    #[test]
+    fn waits_for_the_keyboard() {
+        let main = "\
+function Sys.init 0
+push constant 1
+call String.new 1
+push constant 63
+call String.appendChar 2
+call Keyboard.readInt 1
+pop static 0
+label END
+goto END
+";
+        let mut vm = vm(&[("Main.vm", main)]);
+        let kbd = crate::keyboard::KBD as usize;
+        for key in [0, b'4' as u16, 0, b'2' as u16, b'7' as u16, 129, 0, 128, 0] {
+            vm.ram[kbd] = key;
+            for _ in 0..10 {
+                vm.step();
+            }
+        }
+        assert_eq!(vm.ram[16], 42);
+        assert!(vm.halted());
+
+        // our strings aren't the program's
+        let own = "function String.new 0\npush constant 0\nreturn\n";
+        let sources = [(
+            PathBuf::from("Main.vm"),
+            format!(
+                "{}function Main.main 0\npush constant 0\ncall Output.printString 1\nreturn\n",
+                own
+            ),
+        )];
+        assert!(Program::new(&sources).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_programs() {
+        let load = |text: &str| Program::new(&[(PathBuf::from("Bad.vm"), text.to_owned())]);
+        assert!(load("pop constant 1").is_err());
+        assert!(load("push temp 8").is_err());
+        assert!(load("push local 32768").is_err());
+        assert!(load("function F 0\ncall F 32763").is_err());
+        assert!(load("function F 0\ncall F 32762").is_ok());
+        assert!(load("push local").is_err());
+        assert!(load("function F 0\ngoto L\nfunction G 0\nlabel L").is_err());
+        assert!(load("call Nowhere 0").is_err());
+        assert!(load("call Math.multiply 1").is_err());
+        assert!(load("function F 0\nlabel L\ngoto L").is_ok());
+        assert!(load("asm D=Q").is_err());
+        assert!(load("function F 0\nasm (L)\nasm @L\nasm @SCREEN\nasm 0;JMP").is_ok());
+        assert!(load("function F 0\nasm (L)\nfunction G 0\nasm @L").is_err());
+        assert!(load("function F 0\nasm @x").is_err());
+        // spaced out, the way the assembler allows
+        assert!(load("function F 0\nasm ( L )\nasm @ L").is_ok());
+        assert!(load("function F 0\nasm (L)\nasm ( L )").is_err());
+        assert!(load("function F 0\nasm ( L )\nfunction G 0\nasm @ L").is_err());
+        assert!(load("function F 0\nasm @ x").is_err());
+        let statics = |count: u16| {
+            (0..count)
+                .map(|i| format!("push static {}\n", i))
+                .collect::<String>()
+        };
+        assert!(load(&statics(240)).is_ok());
+        assert!(load(&statics(241)).is_err());
+    }
+}
@@ -0,0 +1,472 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use crate::backtrace;
+use crate::debug::{parse_value, Stop, Symbols};
+use crate::screen;
+use crate::vm::{self, Segment, Vm};
+
+// how long `continue` runs without being told otherwise
+const DEFAULT_CONTINUE: u64 = 1_000_000;
+// how much of `this` and `that` to show, since we can't know how big they are
+const POINTED_WORDS: u16 = 8;
+
+const HELP: &str = "\
+commands:
+  s, step [N]           run N VM commands (default 1)
+  c, continue [N]       run until a breakpoint, watchpoint, or the end of the
+                        program, or for at most N commands
+  b, break LOCATION     stop at a command number, a function, or FILE:LINE
+  watch ADDR [r|w|rw]   stop when a segment entry at a RAM address is read
+                        and/or written
+  info                  list breakpoints and watchpoints
+  delete N              remove breakpoint or watchpoint number N
+  stack                 show the current function's working stack
+  seg, segment NAME [N] show a segment (N words of this or that, default 8)
+  segments              show every segment of the current function
+  r, regs               show SP, LCL, ARG, THIS, THAT and the command count
+  x, ram ADDR [COUNT]   show COUNT words of RAM starting at ADDR
+  poke ADDR VALUE       change a word of RAM
+  screen                draw the screen
+  bt, backtrace         show the functions being called
+  l, list               show the current command and where it came from
+  h, help               show this message
+  q, quit               leave the debugger
+values may be decimal (including negative), 0x-prefixed hex, or 0b-prefixed binary;
+addresses may also be predefined symbols or statics such as Main.0";
+
+// steps through VM commands, showing RAM the way the program sees it:
+// as segments of the function that's running
+pub struct VmDebugger {
+    pub vm: Vm,
+    // numbered from 1, with deleted entries left as holes so numbers are stable
+    stops: Vec<Option<Stop>>,
+}
+
+impl VmDebugger {
+    pub fn new(vm: Vm) -> Self {
+        Self {
+            vm,
+            stops: Vec::new(),
+        }
+    }
+
+    fn function_name(&self) -> &str {
+        self.vm
+            .frame()
+            .map_or("", |frame| self.vm.program.function(frame.function).0)
+    }
+
+    // the current command, along with where it came from
+    pub fn location(&self) -> String {
+        let Some(line) = self.vm.line() else {
+            return format!("PC {:>5}: (past the end of the program)", self.vm.pc);
+        };
+        let mut out = format!(
+            "PC {:>5}: {}    {}:{}",
+            self.vm.pc,
+            line.command,
+            self.vm.program.files[line.file].path.display(),
+            line.number
+        );
+        let function = self.function_name();
+        if !function.is_empty() {
+            out.push_str(&format!(" in {}", function));
+        }
+        out
+    }
+
+    fn registers(&self) -> String {
+        let names = [
+            ("SP", vm::SP),
+            ("LCL", vm::LCL),
+            ("ARG", vm::ARG),
+            ("THIS", vm::THIS),
+            ("THAT", vm::THAT),
+        ];
+        let mut out = String::new();
+        for (name, address) in names {
+            out.push_str(&format!("{}={} ", name, self.vm.read(address)));
+        }
+        out.push_str(&format!("commands={}", self.vm.cycles));
+        out
+    }
+
+    fn ram(&self, name: &str) -> Result<u16, String> {
+        self.vm
+            .static_address(name)
+            .map_or_else(|| Symbols::default().ram(name), Ok)
+    }
+
+    // a command number, a function's first command, or the first command on
+    // or after a line of a file
+    fn command_index(&self, location: &str) -> Result<u16, String> {
+        let program = &self.vm.program;
+        if let Some(&index) = program.functions.get(location) {
+            return Ok(index as u16);
+        }
+        if let Some((file, line)) = location.rsplit_once(':') {
+            let line: usize = line
+                .parse()
+                .map_err(|_| format!("invalid line `{}`", line))?;
+            let matches = |index: usize| {
+                let path = &program.files[index].path;
+                program.file_name(index) == file || path.to_string_lossy() == file
+            };
+            return program
+                .lines
+                .iter()
+                .position(|vm_line| matches(vm_line.file) && vm_line.number >= line)
+                .map(|index| index as u16)
+                .ok_or_else(|| format!("no commands at {}", location));
+        }
+        parse_value(location)
+    }
+
+    // a RAM address, named by its segment entry if it has one
+    fn describe_address(&self, address: u16) -> String {
+        match self.vm.static_name(address) {
+            Some(name) => format!("RAM[{}] ({})", address, name),
+            None => format!("RAM[{}]", address),
+        }
+    }
+
+    fn segment(&self, segment: Segment, count: Option<u16>) -> Result<String, String> {
+        if segment == Segment::Constant {
+            Err("constant isn't stored anywhere")?;
+        }
+        let file = self.vm.line().map_or(0, |line| line.file);
+        let count = count
+            .or_else(|| self.vm.segment_len(segment, file))
+            .unwrap_or(POINTED_WORDS);
+        let base = self
+            .vm
+            .segment_address(segment, 0, file)
+            .map_or_else(String::new, |base| self.describe_address(base));
+        let mut out = match segment {
+            Segment::Argument | Segment::Local => {
+                format!("{} of {} at {}", segment, self.function_name(), base)
+            }
+            Segment::Static => format!("static of {}", self.vm.program.file_name(file)),
+            _ => format!("{} at {}", segment, base),
+        };
+        if count == 0 {
+            out.push_str(": (empty)");
+        }
+        out.push('\n');
+        for index in 0..count {
+            // statics the program never mentions don't have anywhere to live
+            let Some(address) = self.vm.segment_address(segment, index, file) else {
+                continue;
+            };
+            let value = self.vm.read(address);
+            out.push_str(&format!(
+                "  {:<12} {:<18} = {}\n",
+                format!("{} {}", segment, index),
+                self.describe_address(address),
+                value as i16
+            ));
+        }
+        Ok(out)
+    }
+
+    fn stack(&self) -> String {
+        let base = self.vm.stack_base();
+        let sp = self.vm.read(vm::SP);
+        let mut out = String::from("stack");
+        if !self.function_name().is_empty() {
+            out.push_str(&format!(" of {}", self.function_name()));
+        }
+        if sp <= base {
+            out.push_str(": (empty)");
+        }
+        out.push('\n');
+        for address in base..sp {
+            let top = if address + 1 == sp { "  <- top" } else { "" };
+            out.push_str(&format!(
+                "  RAM[{}] = {}{}\n",
+                address,
+                self.vm.read(address) as i16,
+                top
+            ));
+        }
+        out
+    }
+
+    // runs at most `count` commands, stopping early if the program ends or
+    // we hit a breakpoint or watchpoint, in which case we say why
+    fn run(&mut self, count: u64) -> Option<String> {
+        for _ in 0..count {
+            if self.vm.finished() {
+                return Some("the program has finished".to_owned());
+            }
+            if self.vm.halted() {
+                return Some("the program has halted".to_owned());
+            }
+            let access = self.vm.step();
+            let reason = self.stops.iter().enumerate().find_map(|(index, stop)| {
+                stop.as_ref()?
+                    .triggered(index + 1, self.vm.pc as u16, &access)
+            });
+            if reason.is_some() {
+                return reason;
+            }
+        }
+        None
+    }
+
+    fn add_stop(&mut self, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+        self.stops.push(Some(stop));
+        writeln!(
+            out,
+            "{}: {}",
+            self.stops.len(),
+            self.describe_stop(self.stops.len() - 1)
+        )
+    }
+
+    fn describe_stop(&self, index: usize) -> String {
+        match &self.stops[index] {
+            Some(Stop::Breakpoint(pc)) => match self.vm.program.lines.get(*pc as usize) {
+                Some(line) => format!("breakpoint at PC {}: {}", pc, line.command),
+                None => format!("breakpoint at PC {}", pc),
+            },
+            Some(Stop::Watchpoint {
+                address,
+                read,
+                write,
+            }) => {
+                let kind = match (read, write) {
+                    (true, true) => "read/write",
+                    (true, false) => "read",
+                    _ => "write",
+                };
+                format!("{} watchpoint on {}", kind, self.describe_address(*address))
+            }
+            None => "deleted".to_owned(),
+        }
+    }
+
+    // runs a single debugger command, returning whether to keep going
+    pub fn command(
+        &mut self,
+        line: &str,
+        out: &mut impl Write,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = |index: usize, default: u64| -> Result<u64, String> {
+            words.get(index).map_or(Ok(default), |word| {
+                word.parse()
+                    .map_err(|_| format!("invalid count `{}`", word))
+            })
+        };
+
+        match words[..] {
+            [] => {}
+            ["s" | "step", ..] | ["c" | "continue", ..] => {
+                let default = if words[0].starts_with('s') {
+                    1
+                } else {
+                    DEFAULT_CONTINUE
+                };
+                if let Some(reason) = self.run(count(1, default)?) {
+                    writeln!(out, "stopped: {}", reason)?;
+                    let backtrace = self.vm.backtrace();
+                    if !backtrace.is_empty() {
+                        writeln!(out, "in {}", backtrace::render(&backtrace))?;
+                    }
+                }
+                writeln!(out, "{}", self.location())?;
+            }
+            ["b" | "break", location] => {
+                let index = self.command_index(location)?;
+                self.add_stop(Stop::Breakpoint(index), out)?;
+            }
+            ["watch", address, ..] => {
+                let address = self.ram(address)?;
+                let (read, write) = match words.get(2).copied().unwrap_or("rw") {
+                    "r" => (true, false),
+                    "w" => (false, true),
+                    "rw" => (true, true),
+                    other => Err(format!(
+                        "invalid watch kind `{}` (expected r, w, or rw)",
+                        other
+                    ))?,
+                };
+                let stop = Stop::Watchpoint {
+                    address,
+                    read,
+                    write,
+                };
+                self.add_stop(stop, out)?;
+            }
+            ["info"] => {
+                for index in 0..self.stops.len() {
+                    if self.stops[index].is_some() {
+                        writeln!(out, "{}: {}", index + 1, self.describe_stop(index))?;
+                    }
+                }
+            }
+            ["delete", number] => {
+                let stop = number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| self.stops.get_mut(number.checked_sub(1)?))
+                    .filter(|stop| stop.is_some())
+                    .ok_or_else(|| format!("no breakpoint or watchpoint number `{}`", number))?;
+                *stop = None;
+            }
+            ["stack"] => write!(out, "{}", self.stack())?,
+            ["seg" | "segment", name, ..] => {
+                let segment =
+                    Segment::parse(name).ok_or_else(|| format!("unknown segment `{}`", name))?;
+                let count = match words.get(2) {
+                    Some(_) => Some(count(2, 0)? as u16),
+                    None => None,
+                };
+                write!(out, "{}", self.segment(segment, count)?)?;
+            }
+            ["segments"] => {
+                for segment in [
+                    Segment::Argument,
+                    Segment::Local,
+                    Segment::Static,
+                    Segment::Pointer,
+                    Segment::Temp,
+                ] {
+                    write!(out, "{}", self.segment(segment, None)?)?;
+                }
+            }
+            ["r" | "regs"] => writeln!(out, "{}", self.registers())?,
+            ["x" | "ram", address, ..] => {
+                let address = self.ram(address)?;
+                let count = count(2, 1)?;
+                for offset in 0..count {
+                    let address = address.wrapping_add(offset as u16);
+                    let value = self.vm.read(address);
+                    writeln!(
+                        out,
+                        "{} = {} ({:#06x})",
+                        self.describe_address(address),
+                        value as i16,
+                        value
+                    )?;
+                }
+            }
+            ["poke", address, value] => {
+                let (address, value) = (self.ram(address)?, parse_value(value)?);
+                self.vm.write(address, value);
+            }
+            ["screen"] => write!(out, "{}", screen::render(&self.vm.ram))?,
+            ["bt" | "backtrace"] => {
+                let backtrace = self.vm.backtrace();
+                if backtrace.is_empty() {
+                    writeln!(out, "not in a function")?;
+                } else {
+                    writeln!(out, "{}", backtrace::render(&backtrace))?;
+                }
+            }
+            ["l" | "list"] => writeln!(out, "{}", self.location())?,
+            ["h" | "help"] => writeln!(out, "{}", HELP)?,
+            ["q" | "quit"] => return Ok(false),
+            _ => Err(format!("unknown command `{}` (try `help`)", line.trim()))?,
+        }
+        Ok(true)
+    }
+
+    // the interactive loop: reads commands from `input` until it runs dry or
+    // the user quits
+    pub fn repl(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.location())?;
+        write!(out, "(vm) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            match self.command(&line?, out) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(err) => writeln!(out, "error: {}", err)?,
+            }
+            write!(out, "(vm) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Program;
+    use std::path::PathBuf;
+
+    const MAIN: &str = "\
+function Sys.init 0
+push constant 3
+push constant 4
+call Main.add 2
+pop static 0
+label END
+goto END
+function Main.add 1
+push argument 0
+push argument 1
+add
+pop local 0
+push local 0
+return
+";
+
+    fn session(commands: &str) -> String {
+        let program = Program::new(&[(PathBuf::from("Main.vm"), MAIN.to_owned())]).unwrap();
+        let mut debugger = VmDebugger::new(Vm::new(program));
+        let mut out = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn shows_segments() {
+        let transcript = session("break Main.add\nc\nstep 3\nsegments\nstack\nq\n");
+        let expected = "\
+PC     0: function Sys.init 0    Main.vm:1 in Sys.init
+(vm) 1: breakpoint at PC 7: function Main.add 1
+(vm) stopped: breakpoint 1: PC 7
+in Sys.init > Main.add
+PC     7: function Main.add 1    Main.vm:8 in Main.add
+(vm) PC    10: add    Main.vm:11 in Main.add
+(vm) argument of Main.add at RAM[261]
+  argument 0   RAM[261]           = 3
+  argument 1   RAM[262]           = 4
+local of Main.add at RAM[268]
+  local 0      RAM[268]           = 0
+static of Main
+  static 0     RAM[16] (Main.0)   = 0
+pointer at RAM[3]
+  pointer 0    RAM[3]             = 0
+  pointer 1    RAM[4]             = 0
+temp at RAM[5]
+  temp 0       RAM[5]             = 0
+  temp 1       RAM[6]             = 0
+  temp 2       RAM[7]             = 0
+  temp 3       RAM[8]             = 0
+  temp 4       RAM[9]             = 0
+  temp 5       RAM[10]            = 0
+  temp 6       RAM[11]            = 0
+  temp 7       RAM[12]            = 0
+(vm) stack of Main.add
+  RAM[269] = 3
+  RAM[270] = 4  <- top
+(vm) ";
+        assert_eq!(transcript, expected);
+    }
+
+    #[test]
+    fn watches_statics() {
+        let transcript = session("watch Main.0 w\nc\nx Main.0\nc\nfrobnicate\nq\n");
+        assert!(transcript.contains("1: write watchpoint on RAM[16] (Main.0)"));
+        assert!(transcript.contains("stopped: watchpoint 1: RAM[16] written (7)"));
+        assert!(transcript.contains("RAM[16] (Main.0) = 7 (0x0007)"));
+        assert!(transcript.contains("stopped: the program has halted\nin Sys.init\n"));
+        assert!(transcript.contains("error: unknown command `frobnicate`"));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// how often we check the inputs for changes; there's no portable file
+// notification API in std, and polling a handful of files is cheap
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// tracks the modification times of a set of files, reporting which ones have
+// changed (or appeared) since the last call
+#[derive(Default)]
+pub struct Watcher {
+    seen: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Watcher {
+    pub fn changed(&mut self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        // forget files that are no longer part of the input set, so that they
+        // count as changed if they come back
+        self.seen.retain(|path, _| paths.contains(path));
+
+        let mut changed = Vec::new();
+        for path in paths {
+            let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+            if self.seen.insert(path.clone(), modified) != Some(modified) {
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+// calls `rebuild` with every changed file, forever. `inputs` is re-evaluated
+// on every poll, so files added to a watched directory get picked up too
+pub fn watch(mut inputs: impl FnMut() -> Vec<PathBuf>, mut rebuild: impl FnMut(&[PathBuf])) -> ! {
+    let mut watcher = Watcher::default();
+    loop {
+        let changed = watcher.changed(&inputs());
+        if !changed.is_empty() {
+            rebuild(&changed);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_new_and_modified_files() {
+        let dir = std::env::temp_dir().join(format!("hack-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Watched.asm");
+        fs::write(&path, "@0\n").unwrap();
+        let paths = [path.clone()];
+
+        let mut watcher = Watcher::default();
+        assert_eq!(watcher.changed(&paths), paths);
+        assert!(watcher.changed(&paths).is_empty());
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(watcher.changed(&paths), paths);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
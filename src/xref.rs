@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::predefined::SymbolSet;
+use crate::{HackLine, SourceLine, SymbolTable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Label,
+    Variable,
+    Predefined,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Label => write!(f, "label"),
+            Kind::Variable => write!(f, "variable"),
+            Kind::Predefined => write!(f, "predefined"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    pub address: u16,
+    // the line of the label's definition (the last, if there are several,
+    // since that's the one that counts)
+    pub defined: Option<usize>,
+    pub references: Vec<usize>,
+}
+
+impl Entry {
+    // anything about the symbol worth a second look
+    pub fn flag(&self) -> Option<&'static str> {
+        match (self.kind, self.defined, self.references.first()) {
+            (Kind::Variable, _, _) => Some("never defined"),
+            (Kind::Label, _, None) => Some("never referenced"),
+            (Kind::Label, Some(defined), Some(first)) if *first < defined => {
+                Some("referenced before defined")
+            }
+            _ => None,
+        }
+    }
+}
+
+// every symbol of a program, where it's defined and every line using it,
+// sorted by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xref {
+    pub entries: Vec<Entry>,
+}
+
+impl Xref {
+    pub fn new(lines: &[SourceLine]) -> Self {
+        let mut table = SymbolTable::new(SymbolSet::standard(), lines.iter().map(|s| &s.line));
+        let mut defined: HashMap<&str, usize> = HashMap::new();
+        let mut names = Vec::new();
+        for source in lines {
+            match &source.line {
+                HackLine::Label(label) => {
+                    defined.insert(label, source.number);
+                    names.push(label.as_ref());
+                }
+                HackLine::ALocation(name) => {
+                    table.refer(name, source.number);
+                    names.push(name.as_ref());
+                }
+                _ => {}
+            }
+        }
+
+        let mut entries: Vec<Entry> = names
+            .into_iter()
+            .unique()
+            .map(|name| {
+                let kind = if defined.contains_key(name) {
+                    Kind::Label
+                } else if SymbolSet::standard().get(name).is_some() {
+                    Kind::Predefined
+                } else {
+                    Kind::Variable
+                };
+                // every symbol has been referred to by now, so no variable
+                // is allocated here
+                let address = match table.label(name) {
+                    Some(address) => address,
+                    None => table.variable(name),
+                };
+                Entry {
+                    name: name.to_owned(),
+                    kind,
+                    address,
+                    defined: defined.get(name).copied(),
+                    references: table.references(name).to_vec(),
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { entries }
+    }
+}
+
+impl fmt::Display for Xref {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = |column: &str, cell: &dyn Fn(&Entry) -> usize| {
+            self.entries
+                .iter()
+                .map(cell)
+                .max()
+                .unwrap_or(0)
+                .max(column.len())
+        };
+        let name = width("symbol", &|entry| entry.name.len());
+        let kind = width("kind", &|entry| entry.kind.to_string().len());
+        let address = width("address", &|entry| entry.address.to_string().len());
+        let defined = width("defined", &|entry| {
+            entry.defined.map_or(1, |line| line.to_string().len())
+        });
+        writeln!(
+            f,
+            "{:<name$}  {:<kind$}  {:>address$}  {:>defined$}  references",
+            "symbol", "kind", "address", "defined"
+        )?;
+        for entry in &self.entries {
+            let references = entry.references.iter().join(", ");
+            write!(
+                f,
+                "{:<name$}  {:<kind$}  {:>address$}  {:>defined$}  {}",
+                entry.name,
+                entry.kind.to_string(),
+                entry.address,
+                entry
+                    .defined
+                    .map_or("-".to_owned(), |line| line.to_string()),
+                if references.is_empty() {
+                    "-"
+                } else {
+                    &references
+                }
+            )?;
+            match entry.flag() {
+                Some(flag) => writeln!(f, "  ({})", flag)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_source;
+
+    #[test]
+    fn references() {
+        let source =
+            "@i\nM=1\n(LOOP)\n@i\nD=M\n@END\nD;JEQ\n@LOOP\n0;JMP\n(END)\n(UNUSED)\n@SCREEN\n";
+        let lines = parse_source(source).unwrap();
+        let xref = Xref::new(&lines);
+        assert_eq!(
+            xref.to_string(),
+            "symbol  kind        address  defined  references\n\
+             END     label             8       10  6  (referenced before defined)\n\
+             LOOP    label             2        3  8\n\
+             SCREEN  predefined    16384        -  12\n\
+             UNUSED  label             8       11  -  (never referenced)\n\
+             i       variable         16        -  1, 4  (never defined)\n"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+/* assembles and runs a program through hack.h, as a C caller would; see
+ * tests/ffi.rs. Exits non-zero, saying why, if anything's wrong */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "hack.h"
+
+#define CHECK(condition)                                   \
+    if (!(condition)) {                                    \
+        fprintf(stderr, "%d: %s\n", __LINE__, #condition); \
+        return 1;                                          \
+    }
+
+int main(void) {
+    const char *source = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n(END)\n@END\n0;JMP\n";
+    HackAssembly *assembly = hack_assemble_buffer((const uint8_t *)source, strlen(source));
+    CHECK(assembly->ok && assembly->length == 8 && assembly->diagnostic_count == 0);
+    HackEmulator *emulator = hack_emulator_new(assembly->words, assembly->length);
+    hack_assembly_free(assembly);
+    CHECK(hack_emulator_step(emulator, 100) == 6);
+    CHECK(hack_emulator_read_ram(emulator, 0) == 5);
+    hack_emulator_write_ram(emulator, 1, 7);
+    CHECK(hack_emulator_read_ram(emulator, 1) == 7 && hack_emulator_pc(emulator) == 6);
+    hack_emulator_free(emulator);
+
+    const char *bad = "D=Q\n";
+    assembly = hack_assemble_buffer((const uint8_t *)bad, strlen(bad));
+    CHECK(!assembly->ok && assembly->diagnostic_count == 1);
+    CHECK(assembly->diagnostics[0].severity == 1 && assembly->diagnostics[0].line == 1);
+    hack_assembly_free(assembly);
+
+    assembly = hack_assemble_buffer(NULL, 0);
+    CHECK(assembly->ok && assembly->words == NULL);
+    hack_assembly_free(assembly);
+    return 0;
+}
@@ -0,0 +1,40 @@
+// builds tests/ffi.c against include/hack.h and the static library, the way
+// a C program embedding us would, and runs it. Needs `--features ffi`, and a
+// C compiler called `cc`
+#![cfg(all(feature = "ffi", unix))]
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn links_from_c() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // the static library is built next to the test, without a hash in its name
+    let exe = env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    let library = deps.join("libhack.a");
+    assert!(library.exists(), "no {}", library.display());
+
+    let dir = env::temp_dir().join(format!("hack-ffi-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("ffi");
+    let status = Command::new("cc")
+        .arg(root.join("tests/ffi.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("couldn't run `cc`");
+    assert!(status.success(), "tests/ffi.c didn't compile and link");
+
+    let output = Command::new(&program).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
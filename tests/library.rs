@@ -0,0 +1,41 @@
+// the library as a program depending on it sees it, to make sure what it
+// needs is public
+
+use hack::builder::Builder;
+use hack::{assemble_lines, parse_program, Computation, Destination, HackLine, Jump, AM};
+
+#[test]
+fn builds_programs() {
+    let mut program = Builder::new();
+    program
+        .a(2)
+        .unwrap()
+        .assign(Destination::D, Computation::X(AM::A))
+        .a_sym("sum")
+        .assign(Destination::M, Computation::D)
+        .label("END")
+        .a_sym("END")
+        .jump(Computation::Zero, Jump::JMP);
+    assert_eq!(program.to_asm(), "@2\nD=A\n@sum\nM=D\n(END)\n@END\n0;JMP\n");
+    assert!(program.a(0x8000).is_err());
+}
+
+#[test]
+fn assembles_text() {
+    let program = parse_program("@i\nM=1 // start\n(LOOP)\n@LOOP\n0;JMP\n").unwrap();
+    let lines: Vec<HackLine> = program
+        .lines
+        .into_iter()
+        .map(|source| source.line)
+        .collect();
+    let mut binary = Vec::new();
+    assemble_lines(&lines, &mut binary).unwrap();
+    assert_eq!(
+        String::from_utf8(binary).unwrap(),
+        "0000000000010000\n1110111111001000\n0000000000000010\n1110101010000111\n"
+    );
+
+    let errors = parse_program("D=Q\n@i\n(\n").err().unwrap();
+    let lines: Vec<usize> = errors.iter().map(|err| err.line).collect();
+    assert_eq!(lines, [1, 3]);
+}